@@ -11,16 +11,23 @@ use crypto::buffer::{BufferResult, ReadBuffer, WriteBuffer};
 use crypto::{aes, blockmodes, buffer, symmetriccipher};
 
 // Encrypt a buffer with the given key and iv using
-// AES-256/CBC/Pkcs encryption.
-pub(super) fn encrypt(
+// AES/CBC encryption.
+//
+// 密钥长度与填充由调用方指定：小程序的加密数据为AES-128/PKCS#7，
+// 推送消息为AES-256且按32字节自行填充（NoPadding）
+pub(super) fn encrypt<P>(
     data: &[u8],
     key: &[u8],
     iv: &[u8],
-) -> Result<Vec<u8>, symmetriccipher::SymmetricCipherError> {
+    key_size: aes::KeySize,
+    padding: P,
+) -> Result<Vec<u8>, symmetriccipher::SymmetricCipherError>
+where
+    P: blockmodes::PaddingProcessor + Send + 'static,
+{
     // Create an encryptor instance of the best performing
     // type available for the platform.
-    let mut encryptor =
-        aes::cbc_encryptor(aes::KeySize::KeySize128, key, iv, blockmodes::PkcsPadding);
+    let mut encryptor = aes::cbc_encryptor(key_size, key, iv, padding);
 
     // Each encryption operation encrypts some data from
     // an input buffer into an output buffer. Those buffers
@@ -77,19 +84,23 @@ pub(super) fn encrypt(
 }
 
 // Decrypts a buffer with the given key and iv using
-// AES-256/CBC/Pkcs encryption.
+// AES/CBC encryption.
 //
 // This function is very similar to encrypt(), so, please reference
 // comments in that function. In non-example code, if desired, it is possible to
 // share much of the implementation using closures to hide the operation
 // being performed. However, such code would make this example less clear.
-pub(super) fn decrypt(
+pub(super) fn decrypt<P>(
     encrypted_data: &[u8],
     key: &[u8],
     iv: &[u8],
-) -> Result<Vec<u8>, symmetriccipher::SymmetricCipherError> {
-    let mut decryptor =
-        aes::cbc_decryptor(aes::KeySize::KeySize128, key, iv, blockmodes::PkcsPadding);
+    key_size: aes::KeySize,
+    padding: P,
+) -> Result<Vec<u8>, symmetriccipher::SymmetricCipherError>
+where
+    P: blockmodes::PaddingProcessor + Send + 'static,
+{
+    let mut decryptor = aes::cbc_decryptor(key_size, key, iv, padding);
 
     let mut final_result = Vec::<u8>::new();
     let mut read_buffer = buffer::RefReadBuffer::new(encrypted_data);
@@ -117,6 +128,8 @@ pub(super) fn decrypt(
 #[cfg(test)]
 mod tests {
     use super::{decrypt, encrypt};
+    use crypto::aes::KeySize;
+    use crypto::blockmodes::{NoPadding, PkcsPadding};
     use rand::{rngs::OsRng, RngCore};
 
     #[test]
@@ -135,9 +148,46 @@ mod tests {
         OsRng.fill_bytes(&mut key);
         OsRng.fill_bytes(&mut iv);
 
-        let encrypted_data = encrypt(message.as_bytes(), &key, &iv).ok().unwrap();
-        let decrypted_data = decrypt(&encrypted_data[..], &key, &iv).ok().unwrap();
+        let encrypted_data = encrypt(
+            message.as_bytes(),
+            &key[..16],
+            &iv,
+            KeySize::KeySize128,
+            PkcsPadding,
+        )
+        .ok()
+        .unwrap();
+        let decrypted_data = decrypt(
+            &encrypted_data[..],
+            &key[..16],
+            &iv,
+            KeySize::KeySize128,
+            PkcsPadding,
+        )
+        .ok()
+        .unwrap();
 
         assert!(message.as_bytes() == &decrypted_data[..]);
     }
+
+    #[test]
+    fn no_padding_256() {
+        let mut key: [u8; 32] = [0; 32];
+        let mut iv: [u8; 16] = [0; 16];
+        OsRng.fill_bytes(&mut key);
+        OsRng.fill_bytes(&mut iv);
+
+        let message = [7u8; 64];
+        let encrypted_data = encrypt(&message, &key, &iv, KeySize::KeySize256, NoPadding)
+            .ok()
+            .unwrap();
+        assert_eq!(encrypted_data.len(), 64);
+        let decrypted_data = decrypt(&encrypted_data, &key, &iv, KeySize::KeySize256, NoPadding)
+            .ok()
+            .unwrap();
+        assert_eq!(&decrypted_data[..], &message[..]);
+
+        // 长度须是16的倍数
+        assert!(encrypt(&message[..10], &key, &iv, KeySize::KeySize256, NoPadding).is_err());
+    }
 }
@@ -0,0 +1,352 @@
+//! 熔断
+//!
+//! 微信、钉钉故障时，请求会一直等到超时再重试，压力堆积在调用方。
+//! 按平台+接口统计失败率，超过阈值后熔断，在冷却期内直接返回`ClientError::CircuitOpen`：
+//! * Closed 正常放行，统计窗口内失败率达到`failure_ratio`时 => Open
+//! * Open 拒绝请求，`cool_down`之后 => HalfOpen
+//! * HalfOpen 每次只放行一个探测请求，成功 => Closed，失败 => Open
+//!
+//! 只有超时、网络错误、HTTP 5xx、系统繁忙计为失败，其它接口错误说明对方服务正常
+//!
+//! 熔断状态默认属于单个Client，同一平台的多个Client（例如小程序与公众号）可以共用：
+//!
+//! ```rs
+//! for status in client.circuit_states() {
+//!     info!("{} {:?}", status.endpoint, status.state);
+//! }
+//! let client = Client::new(Official::client_config(&cfg))?
+//!     .with_circuit_breaker(miniprogram.circuit_breaker());
+//! let official = Official::with_client(cfg, client);
+//! ```
+use super::{ClientError, ErrorKind, Platform};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct BreakerConfig {
+    pub enabled: bool,
+    /// 统计窗口
+    pub window: Duration,
+    /// 窗口内请求数达到此值才判断失败率，避免少量请求失败就熔断
+    pub min_requests: u32,
+    pub failure_ratio: f64,
+    /// 熔断后等待多久放行探测请求
+    pub cool_down: Duration,
+}
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window: Duration::from_secs(30),
+            min_requests: 10,
+            failure_ratio: 0.5,
+            cool_down: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// 某个接口的熔断状态
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CircuitStatus {
+    pub platform: Platform,
+    pub endpoint: String,
+    pub state: CircuitState,
+    /// 当前统计窗口内的请求数与失败数
+    pub requests: u32,
+    pub failures: u32,
+    /// Open时距离放行探测请求的时间
+    pub retry_after: Option<Duration>,
+}
+
+/// 是否计为失败
+pub fn is_failure(error: &ClientError) -> bool {
+    match error {
+        ClientError::Timeout | ClientError::Http(_) | ClientError::Io(_) => true,
+        ClientError::Status(status) => *status >= 500,
+        e => e.kind() == Some(ErrorKind::SystemBusy),
+    }
+}
+
+struct Circuit {
+    state: CircuitState,
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+    // Open的起始时间
+    opened_at: Instant,
+    // HalfOpen时正在进行的探测请求，超过cool_down未返回（例如调用方已放弃）则允许新的探测
+    probe_started: Option<Instant>,
+}
+impl Circuit {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            state: CircuitState::Closed,
+            window_start: now,
+            requests: 0,
+            failures: 0,
+            opened_at: now,
+            probe_started: None,
+        }
+    }
+
+    fn reset_window(&mut self, now: Instant) {
+        self.window_start = now;
+        self.requests = 0;
+        self.failures = 0;
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.state = CircuitState::Open;
+        self.opened_at = now;
+        self.probe_started = None;
+    }
+}
+
+pub struct CircuitBreaker {
+    cfg: BreakerConfig,
+    circuits: Mutex<HashMap<(Platform, String), Circuit>>,
+}
+impl CircuitBreaker {
+    pub fn new(cfg: BreakerConfig) -> Self {
+        Self {
+            cfg,
+            circuits: Default::default(),
+        }
+    }
+
+    /// 请求前调用，熔断时返回`ClientError::CircuitOpen`
+    pub fn acquire(&self, platform: Platform, endpoint: &str) -> Result<(), ClientError> {
+        if !self.cfg.enabled {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits
+            .entry((platform, endpoint.to_owned()))
+            .or_insert_with(Circuit::new);
+
+        if circuit.state == CircuitState::Open {
+            let elapsed = now.duration_since(circuit.opened_at);
+            if elapsed < self.cfg.cool_down {
+                return Err(ClientError::CircuitOpen {
+                    platform,
+                    endpoint: endpoint.to_owned(),
+                    retry_after: self.cfg.cool_down - elapsed,
+                });
+            }
+            info!("circuit half-open: {} {}", platform, endpoint);
+            circuit.state = CircuitState::HalfOpen;
+        }
+        if circuit.state == CircuitState::HalfOpen {
+            if let Some(started) = circuit.probe_started {
+                let elapsed = now.duration_since(started);
+                if elapsed < self.cfg.cool_down {
+                    return Err(ClientError::CircuitOpen {
+                        platform,
+                        endpoint: endpoint.to_owned(),
+                        retry_after: self.cfg.cool_down - elapsed,
+                    });
+                }
+            }
+            circuit.probe_started = Some(now);
+        }
+        Ok(())
+    }
+
+    /// 请求后调用，记录结果
+    pub fn record(&self, platform: Platform, endpoint: &str, failed: bool) {
+        if !self.cfg.enabled {
+            return;
+        }
+        let now = Instant::now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits
+            .entry((platform, endpoint.to_owned()))
+            .or_insert_with(Circuit::new);
+
+        match circuit.state {
+            CircuitState::Closed => {
+                if now.duration_since(circuit.window_start) >= self.cfg.window {
+                    circuit.reset_window(now);
+                }
+                circuit.requests += 1;
+                if failed {
+                    circuit.failures += 1;
+                }
+                let ratio = f64::from(circuit.failures) / f64::from(circuit.requests);
+                if circuit.requests >= self.cfg.min_requests && ratio >= self.cfg.failure_ratio {
+                    warn!(
+                        "circuit open: {} {} ({}/{} failed)",
+                        platform, endpoint, circuit.failures, circuit.requests
+                    );
+                    circuit.open(now);
+                }
+            }
+            CircuitState::HalfOpen if failed => {
+                warn!("circuit open again: {} {}", platform, endpoint);
+                circuit.open(now);
+            }
+            CircuitState::HalfOpen => {
+                info!("circuit closed: {} {}", platform, endpoint);
+                circuit.state = CircuitState::Closed;
+                circuit.probe_started = None;
+                circuit.reset_window(now);
+            }
+            // 熔断前发出的请求，结果不再统计
+            CircuitState::Open => {}
+        }
+    }
+
+    pub fn state(&self, platform: Platform, endpoint: &str) -> CircuitState {
+        let circuits = self.circuits.lock().unwrap();
+        circuits
+            .get(&(platform, endpoint.to_owned()))
+            .map_or(CircuitState::Closed, |circuit| circuit.state)
+    }
+
+    /// 所有接口的状态，按平台、接口排序
+    pub fn states(&self) -> Vec<CircuitStatus> {
+        let now = Instant::now();
+        let circuits = self.circuits.lock().unwrap();
+        let mut states: Vec<_> = circuits
+            .iter()
+            .map(|((platform, endpoint), circuit)| CircuitStatus {
+                platform: *platform,
+                endpoint: endpoint.clone(),
+                state: circuit.state,
+                requests: circuit.requests,
+                failures: circuit.failures,
+                retry_after: match circuit.state {
+                    CircuitState::Open => Some(
+                        self.cfg
+                            .cool_down
+                            .checked_sub(now.duration_since(circuit.opened_at))
+                            .unwrap_or_default(),
+                    ),
+                    _ => None,
+                },
+            })
+            .collect();
+        states.sort_by(|a, b| {
+            (a.platform.to_string(), &a.endpoint).cmp(&(b.platform.to_string(), &b.endpoint))
+        });
+        states
+    }
+
+    /// 手动恢复，例如确认对方故障已经排除
+    pub fn reset(&self, platform: Platform, endpoint: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        circuits.remove(&(platform, endpoint.to_owned()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ClientError, Platform};
+    use super::{is_failure, BreakerConfig, CircuitBreaker, CircuitState};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    const ENDPOINT: &str = "api.example.com/api";
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(BreakerConfig {
+            min_requests: 4,
+            failure_ratio: 0.5,
+            cool_down: Duration::from_millis(50),
+            ..Default::default()
+        })
+    }
+
+    fn call(breaker: &CircuitBreaker, failed: bool) -> Result<(), ClientError> {
+        breaker.acquire(Platform::Wechat, ENDPOINT)?;
+        breaker.record(Platform::Wechat, ENDPOINT, failed);
+        Ok(())
+    }
+
+    #[test]
+    fn failure_classes() {
+        assert!(is_failure(&ClientError::Timeout));
+        assert!(is_failure(&ClientError::Status(503)));
+        assert!(!is_failure(&ClientError::Status(404)));
+        let api_error = |code| ClientError::Api {
+            code,
+            message: Default::default(),
+            platform: Platform::Wechat,
+        };
+        assert!(is_failure(&api_error(-1)));
+        assert!(!is_failure(&api_error(40013)));
+    }
+
+    #[test]
+    fn open_half_open_closed() {
+        let breaker = breaker();
+        let state = || breaker.state(Platform::Wechat, ENDPOINT);
+
+        // 请求数不足时不熔断
+        call(&breaker, true).unwrap();
+        call(&breaker, false).unwrap();
+        call(&breaker, true).unwrap();
+        assert_eq!(state(), CircuitState::Closed);
+        call(&breaker, false).unwrap();
+        assert_eq!(state(), CircuitState::Open);
+
+        // 冷却期内直接失败
+        let err = call(&breaker, false).unwrap_err();
+        assert!(matches!(err, ClientError::CircuitOpen { .. }));
+        let states = breaker.states();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].state, CircuitState::Open);
+        assert!(states[0].retry_after.is_some());
+        // 其它平台、接口不受影响
+        assert!(breaker.acquire(Platform::Dingtalk, ENDPOINT).is_ok());
+        assert!(breaker
+            .acquire(Platform::Wechat, "api.example.com/b")
+            .is_ok());
+
+        // 探测失败，再次熔断
+        sleep(Duration::from_millis(60));
+        breaker.acquire(Platform::Wechat, ENDPOINT).unwrap();
+        assert_eq!(state(), CircuitState::HalfOpen);
+        // 同一时间只有一个探测请求
+        assert!(breaker.acquire(Platform::Wechat, ENDPOINT).is_err());
+        breaker.record(Platform::Wechat, ENDPOINT, true);
+        assert_eq!(state(), CircuitState::Open);
+
+        // 探测成功，恢复
+        sleep(Duration::from_millis(60));
+        call(&breaker, false).unwrap();
+        assert_eq!(state(), CircuitState::Closed);
+        call(&breaker, false).unwrap();
+
+        breaker.reset(Platform::Wechat, ENDPOINT);
+        assert_eq!(breaker.states().len(), 2);
+    }
+
+    #[test]
+    fn disabled() {
+        let breaker = CircuitBreaker::new(BreakerConfig {
+            enabled: false,
+            min_requests: 1,
+            ..Default::default()
+        });
+        for _ in 0..10 {
+            call(&breaker, true).unwrap();
+        }
+        assert_eq!(
+            breaker.state(Platform::Wechat, ENDPOINT),
+            CircuitState::Closed
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! 各平台的接口错误码
+//!
+//! 只收录需要区别处理的、或排查时常见的错误码，其余按`ErrorKind::Other`处理；
+//! 各平台的表里没有的，按`COMMON`识别
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 接口所属平台，决定错误码的含义
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    Wechat,
+    Dingtalk,
+    /// 企业微信
+    Wecom,
+    /// 未指定时，只识别各平台通用的错误码
+    Unknown,
+}
+impl Default for Platform {
+    fn default() -> Self {
+        Platform::Unknown
+    }
+}
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Platform::Wechat => write!(f, "wechat"),
+            Platform::Dingtalk => write!(f, "dingtalk"),
+            Platform::Wecom => write!(f, "wecom"),
+            Platform::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// 错误码的分类
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    /// 系统繁忙，稍后重试
+    SystemBusy,
+    /// access_token无效或过期，刷新后重试
+    InvalidCredential,
+    /// 调用频率或次数超过限制
+    RateLimited,
+    /// 内容安全检测未通过
+    ContentRisky,
+    Other,
+}
+
+use ErrorKind::*;
+
+// 各平台通用
+const COMMON: &[(i32, ErrorKind, &str)] = &[
+    (-1, SystemBusy, "系统繁忙"),
+    (40001, InvalidCredential, "access_token无效"),
+    (40014, InvalidCredential, "不合法的access_token"),
+    (41001, InvalidCredential, "缺少access_token参数"),
+    (42001, InvalidCredential, "access_token超时"),
+];
+
+const WECHAT: &[(i32, ErrorKind, &str)] = &[
+    (40013, Other, "不合法的AppID"),
+    (40125, Other, "不合法的AppSecret"),
+    (40029, Other, "不合法的code"),
+    (40163, Other, "code已被使用"),
+    (40226, Other, "高风险等级用户，登录被拦截"),
+    (40037, Other, "订阅模板id不正确"),
+    (43101, Other, "用户拒绝接受消息（未订阅或订阅次数已用完）"),
+    (47003, Other, "模板参数不准确"),
+    (41030, Other, "page路径不正确（须是已发布的小程序页面）"),
+    (45029, Other, "生成码个数总和到达最大个数限制"),
+    (45009, RateLimited, "接口调用超过每日限额"),
+    (45011, RateLimited, "接口调用过于频繁"),
+    (87014, ContentRisky, "内容含有违法违规内容"),
+];
+
+const DINGTALK: &[(i32, ErrorKind, &str)] = &[
+    (40089, Other, "不合法的corpid或corpsecret"),
+    (60121, Other, "找不到该用户"),
+    (90002, RateLimited, "调用频率超过限制"),
+    (90018, RateLimited, "接口QPS超过限制"),
+    (130101, RateLimited, "机器人发送太快，每分钟最多20条"),
+    (310000, Other, "机器人安全设置校验失败（关键词、加签或IP）"),
+];
+
+// 注意：企业微信的40001是secret不正确，刷新access_token也无济于事
+const WECOM: &[(i32, ErrorKind, &str)] = &[
+    (40001, Other, "不合法的secret参数"),
+    (40013, Other, "不合法的CorpID"),
+    (40029, Other, "不合法的oauth_code"),
+    (40056, Other, "不合法的agentid"),
+    (60011, Other, "指定的成员/部门/标签参数无权限"),
+    (60111, Other, "UserID不存在"),
+    (60123, Other, "无效的部门id"),
+    (81013, Other, "UserID、部门ID、标签ID全部非法或无权限"),
+    (45009, RateLimited, "接口调用超过限制"),
+    (45033, RateLimited, "接口并发调用超过限制"),
+];
+
+fn table(platform: Platform) -> &'static [(i32, ErrorKind, &'static str)] {
+    match platform {
+        Platform::Wechat => WECHAT,
+        Platform::Dingtalk => DINGTALK,
+        Platform::Wecom => WECOM,
+        Platform::Unknown => &[],
+    }
+}
+
+// 先查平台的表，再查通用的
+fn lookup(platform: Platform, code: i32) -> Option<&'static (i32, ErrorKind, &'static str)> {
+    table(platform)
+        .iter()
+        .chain(COMMON)
+        .find(|(c, _, _)| *c == code)
+}
+
+pub fn classify(platform: Platform, code: i32) -> ErrorKind {
+    lookup(platform, code).map_or(Other, |(_, kind, _)| *kind)
+}
+
+/// 错误码的说明
+pub fn describe(platform: Platform, code: i32) -> Option<&'static str> {
+    lookup(platform, code).map(|(_, _, description)| *description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify, describe, ErrorKind, Platform};
+
+    #[test]
+    fn classify_codes() {
+        assert_eq!(classify(Platform::Wechat, -1), ErrorKind::SystemBusy);
+        assert_eq!(classify(Platform::Dingtalk, -1), ErrorKind::SystemBusy);
+        assert_eq!(
+            classify(Platform::Dingtalk, 40014),
+            ErrorKind::InvalidCredential
+        );
+        assert_eq!(
+            classify(Platform::Wechat, 42001),
+            ErrorKind::InvalidCredential
+        );
+        assert_eq!(classify(Platform::Wechat, 45009), ErrorKind::RateLimited);
+        assert_eq!(classify(Platform::Wechat, 87014), ErrorKind::ContentRisky);
+        assert_eq!(classify(Platform::Dingtalk, 87014), ErrorKind::Other);
+        assert_eq!(classify(Platform::Dingtalk, 90018), ErrorKind::RateLimited);
+        assert_eq!(
+            classify(Platform::Wecom, 42001),
+            ErrorKind::InvalidCredential
+        );
+        assert_eq!(classify(Platform::Wecom, 40001), ErrorKind::Other);
+        assert_eq!(
+            classify(Platform::Unknown, 40014),
+            ErrorKind::InvalidCredential
+        );
+        assert_eq!(classify(Platform::Unknown, 45009), ErrorKind::Other);
+        assert_eq!(describe(Platform::Wechat, 40163), Some("code已被使用"));
+        assert_eq!(describe(Platform::Wecom, 40001), Some("不合法的secret参数"));
+        assert_eq!(describe(Platform::Wechat, 40001), Some("access_token无效"));
+        assert_eq!(describe(Platform::Wechat, 12345), None);
+    }
+}
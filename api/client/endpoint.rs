@@ -0,0 +1,192 @@
+//! 接口地址
+//!
+//! query参数逐个做url编码，用户输入（js_code、userid等）中的`&`、`#`不会注入额外的参数；
+//! access_token作为单独的参数，由`Client`在请求时填入。
+//!
+//! ```rs
+//! let endpoint = Endpoint::new("https://oapi.dingtalk.com/user/get")
+//!     .access_token()
+//!     .query("userid", user_id);
+//! let user: UserInfo = client.get(endpoint).await?;
+//! ```
+//!
+//! 兼容字符串写法：`"https://...?access_token=ACCESS_TOKEN"`，其中已有的query视为已编码
+use super::request::encode_component;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    // 不含query
+    url: String,
+    // (已编码的参数名, 参数值)
+    query: Vec<(String, Param)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Param {
+    // 已编码
+    Value(String),
+    AccessToken,
+}
+
+impl Endpoint {
+    pub fn new(url: &str) -> Self {
+        let url = url.split('#').next().unwrap_or_default();
+        let (path, query) = match url.find('?') {
+            Some(i) => (&url[..i], &url[i + 1..]),
+            None => (url, ""),
+        };
+        let query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = match pair.find('=') {
+                    Some(i) => (&pair[..i], &pair[i + 1..]),
+                    None => (pair, ""),
+                };
+                let value = if name == "access_token" && value == "ACCESS_TOKEN" {
+                    Param::AccessToken
+                } else {
+                    Param::Value(value.to_owned())
+                };
+                (name.to_owned(), value)
+            })
+            .collect();
+        Self {
+            url: path.to_owned(),
+            query,
+        }
+    }
+
+    /// 追加query参数（会做url编码）
+    pub fn query(mut self, name: &str, value: impl ToString) -> Self {
+        let value = encode_component(&value.to_string());
+        self.query
+            .push((encode_component(name), Param::Value(value)));
+        self
+    }
+
+    /// 追加`access_token`参数，请求时自动填入
+    pub fn access_token(mut self) -> Self {
+        self.query
+            .push(("access_token".to_owned(), Param::AccessToken));
+        self
+    }
+
+    pub fn requires_access_token(&self) -> bool {
+        self.query
+            .iter()
+            .any(|(_, value)| *value == Param::AccessToken)
+    }
+
+    /// 不含query的地址
+    pub fn path(&self) -> &str {
+        &self.url
+    }
+
+    /// 替换地址部分，query不变
+    pub(super) fn map_path(mut self, f: impl FnOnce(&str) -> String) -> Self {
+        self.url = f(&self.url);
+        self
+    }
+
+    /// 填入access_token后的完整地址
+    pub fn to_url(&self, access_token: &str) -> String {
+        if self.query.is_empty() {
+            return self.url.clone();
+        }
+        let access_token = encode_component(access_token);
+        let query: Vec<String> = self
+            .query
+            .iter()
+            .map(|(name, value)| match value {
+                Param::Value(value) => format!("{}={}", name, value),
+                Param::AccessToken => format!("{}={}", name, access_token),
+            })
+            .collect();
+        format!("{}?{}", self.url, query.join("&"))
+    }
+}
+
+/// access_token显示为`ACCESS_TOKEN`
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_url("ACCESS_TOKEN"))
+    }
+}
+
+impl From<&str> for Endpoint {
+    fn from(url: &str) -> Self {
+        Self::new(url)
+    }
+}
+impl From<&String> for Endpoint {
+    fn from(url: &String) -> Self {
+        Self::new(url)
+    }
+}
+impl From<String> for Endpoint {
+    fn from(url: String) -> Self {
+        Self::new(&url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Endpoint;
+
+    #[test]
+    fn encode_query() {
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/sns/jscode2session")
+            .query("appid", "APPID")
+            .query("js_code", "a&grant_type=x#y")
+            .query("name", "张 三");
+        assert!(!endpoint.requires_access_token());
+        assert_eq!(
+            endpoint.to_url("TOKEN"),
+            "https://api.weixin.qq.com/sns/jscode2session?appid=APPID\
+             &js_code=a%26grant_type%3Dx%23y&name=%E5%BC%A0%20%E4%B8%89"
+        );
+    }
+
+    #[test]
+    fn access_token() {
+        let endpoint = Endpoint::new("https://oapi.dingtalk.com/user/get")
+            .access_token()
+            .query("userid", "1&access_token=evil");
+        assert!(endpoint.requires_access_token());
+        assert_eq!(
+            endpoint.to_url("a+b/c="),
+            "https://oapi.dingtalk.com/user/get?access_token=a%2Bb%2Fc%3D\
+             &userid=1%26access_token%3Devil"
+        );
+        assert_eq!(
+            endpoint.to_string(),
+            "https://oapi.dingtalk.com/user/get?access_token=ACCESS_TOKEN\
+             &userid=1%26access_token%3Devil"
+        );
+        assert_eq!(endpoint.path(), "https://oapi.dingtalk.com/user/get");
+    }
+
+    #[test]
+    fn parse_url() {
+        let endpoint =
+            Endpoint::from("https://api.example.com/a?access_token=ACCESS_TOKEN&x=1%262#f");
+        assert!(endpoint.requires_access_token());
+        assert_eq!(
+            endpoint.to_url("TOKEN"),
+            "https://api.example.com/a?access_token=TOKEN&x=1%262"
+        );
+        assert_eq!(
+            Endpoint::from("https://api.example.com/a").to_url("TOKEN"),
+            "https://api.example.com/a"
+        );
+        // 其它位置的ACCESS_TOKEN不会被替换
+        let endpoint = Endpoint::from("https://api.example.com/a?name=ACCESS_TOKEN");
+        assert!(!endpoint.requires_access_token());
+        assert_eq!(
+            endpoint.to_url("TOKEN"),
+            "https://api.example.com/a?name=ACCESS_TOKEN"
+        );
+    }
+}
@@ -0,0 +1,31 @@
+//! 调用指标
+//!
+//! `Client`在每次调用（包括所有重试）结束时上报，实现`ClientMetrics`即可接入prometheus等：
+//! * `api_client_requests_total` 调用次数，标签`platform`、`endpoint`、`outcome`、`errcode`
+//! * `api_client_request_duration_seconds` 调用耗时，标签`platform`、`endpoint`
+//! * `api_client_retries_total` 重试次数（不包括第一次），标签`platform`、`endpoint`
+//!
+//! `endpoint`不含query，例如`api.weixin.qq.com/wxa/msg_sec_check`
+//!
+//! ```rs
+//! let client = Client::new(cfg)?.with_metrics(Arc::new(PrometheusMetrics::new(registry)));
+//! ```
+
+pub const REQUESTS_TOTAL: &str = "api_client_requests_total";
+pub const REQUEST_DURATION_SECONDS: &str = "api_client_request_duration_seconds";
+pub const RETRIES_TOTAL: &str = "api_client_retries_total";
+
+pub type Labels<'a> = [(&'static str, &'a str)];
+
+pub trait ClientMetrics: Send + Sync {
+    fn increment_counter(&self, name: &'static str, value: u64, labels: &Labels<'_>);
+    fn record_histogram(&self, name: &'static str, value: f64, labels: &Labels<'_>);
+}
+
+// 默认 不上报
+#[derive(Debug, Default)]
+pub struct NoopMetrics;
+impl ClientMetrics for NoopMetrics {
+    fn increment_counter(&self, _name: &'static str, _value: u64, _labels: &Labels<'_>) {}
+    fn record_histogram(&self, _name: &'static str, _value: f64, _labels: &Labels<'_>) {}
+}
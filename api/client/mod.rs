@@ -0,0 +1,1211 @@
+use async_std::sync::{Mutex, RwLock};
+use chrono::Duration;
+use futures::io::AsyncReadExt;
+use http::Method;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+use thiserror::Error as ThisError;
+use tracing::{field, Instrument, Span};
+
+pub mod breaker;
+pub mod codes;
+pub mod endpoint;
+pub mod metrics;
+pub mod redact;
+pub mod request;
+pub mod retry;
+pub mod token_store;
+pub mod transport;
+pub use breaker::{BreakerConfig, CircuitBreaker, CircuitState, CircuitStatus};
+pub use codes::{ErrorKind, Platform};
+pub use endpoint::Endpoint;
+pub use metrics::{ClientMetrics, NoopMetrics};
+pub use request::{Multipart, RequestBuilder};
+pub use retry::{RetryPolicy, Retryable};
+pub use token_store::{AccessTokenStore, CachedToken, MemoryTokenStore, PgTokenStore, TokenLock};
+pub use transport::HttpConfig;
+use transport::{BodyStream, HttpRequest, HttpTransport, IsahcTransport};
+
+// 配置
+// 1. access_token 的response 2. varify错误类型
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Config {
+    pub token_url: String,
+    /// 用于识别errcode
+    #[serde(default)]
+    pub platform: Platform,
+    /// access_token在共享存储里的key，例如`wechat_miniprogram:APPID`，
+    /// 为空时使用token_url的hash
+    #[serde(default)]
+    pub token_key: String,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// 超时、代理等
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// 熔断，见`breaker`
+    #[serde(default)]
+    pub breaker: BreakerConfig,
+    /// 替换接口地址（包括token_url）的scheme与host，
+    /// 例如指向内网网关`http://gateway/wechat`或本地stub`http://127.0.0.1:8080`
+    #[serde(default)]
+    pub base_url: Option<String>,
+}
+// token_url中含有secret
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("token_url", &redact::url(&self.token_url))
+            .field("platform", &self.platform)
+            .field("token_key", &self.token_key)
+            .field("retry", &self.retry)
+            .field("http", &self.http)
+            .field("breaker", &self.breaker)
+            .field("base_url", &self.base_url)
+            .finish()
+    }
+}
+impl Config {
+    fn rebase_url(&self, url: &str) -> String {
+        let base = match &self.base_url {
+            Some(base) => base.trim_end_matches('/'),
+            None => return url.to_owned(),
+        };
+        let path = url
+            .find("://")
+            .map(|i| i + 3)
+            .and_then(|i| {
+                url[i..]
+                    .find(|c| c == '/' || c == '?')
+                    .map(|j| &url[i + j..])
+            })
+            .unwrap_or("");
+        format!("{}{}", base, path)
+    }
+}
+
+// 等待其它副本刷新token时，轮询存储的间隔
+const TOKEN_LOCK_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+// Client 结构
+pub struct Client {
+    cfg: Config,
+    // 本地缓存，失效或无效时才读写token_store
+    token: RwLock<Option<CachedToken>>,
+    // 刷新token时持有
+    refresh: Mutex<()>,
+    token_key: String,
+    token_store: Arc<dyn AccessTokenStore>,
+    transport: Arc<dyn HttpTransport>,
+    metrics: Arc<dyn ClientMetrics>,
+    breaker: Arc<CircuitBreaker>,
+}
+impl Client {
+    /// 每个Client持有各自的连接池
+    ///
+    /// `cfg.http`无效（例如代理地址错误）时返回错误
+    pub fn new(cfg: Config) -> ClientResult<Client> {
+        let transport = IsahcTransport::new(&cfg.http)?;
+        Ok(Self::with_transport(cfg, Arc::new(transport)))
+    }
+
+    /// 注入传输层，例如测试时使用`MockTransport`
+    pub fn with_transport(cfg: Config, transport: Arc<dyn HttpTransport>) -> Client {
+        let token_key = if cfg.token_key.is_empty() {
+            format!("{:x}", Sha256::digest(cfg.token_url.as_bytes()))
+        } else {
+            cfg.token_key.clone()
+        };
+        let breaker = Arc::new(CircuitBreaker::new(cfg.breaker.clone()));
+        Client {
+            cfg,
+            token: Default::default(),
+            refresh: Default::default(),
+            token_key,
+            token_store: Arc::new(MemoryTokenStore::default()),
+            transport,
+            metrics: Arc::new(NoopMetrics),
+            breaker,
+        }
+    }
+
+    /// 多副本部署时，使用共享存储（例如`PgTokenStore`）
+    pub fn with_token_store(mut self, store: Arc<dyn AccessTokenStore>) -> Self {
+        self.token_store = store;
+        self
+    }
+
+    /// 上报调用次数、耗时、重试次数，见`metrics`
+    pub fn with_metrics(mut self, metrics: Arc<dyn ClientMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// 多个Client共用熔断状态（按平台+接口区分），默认使用`cfg.breaker`创建
+    pub fn with_circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    /// 当前使用的熔断器，可以交给同一平台的其它Client共用
+    pub fn circuit_breaker(&self) -> Arc<CircuitBreaker> {
+        self.breaker.clone()
+    }
+
+    /// 各接口的熔断状态
+    pub fn circuit_states(&self) -> Vec<CircuitStatus> {
+        self.breaker.states()
+    }
+
+    /// 构建任意请求，见`RequestBuilder`
+    pub fn request(&self, method: Method, endpoint: impl Into<Endpoint>) -> RequestBuilder<'_> {
+        RequestBuilder::new(self, method, endpoint.into())
+    }
+
+    pub async fn get<O>(&self, endpoint: impl Into<Endpoint>) -> ClientResult<O>
+    where
+        O: DeserializeOwned,
+    {
+        self.request(Method::GET, endpoint).send().await
+    }
+
+    pub async fn post<T, O>(
+        &self,
+        endpoint: impl Into<Endpoint>,
+        payload: Option<&T>,
+    ) -> ClientResult<O>
+    where
+        T: Serialize,
+        O: DeserializeOwned,
+    {
+        let request = self.request(Method::POST, endpoint);
+        match payload {
+            Some(payload) => request.json(payload).send().await,
+            None => request.send().await,
+        }
+    }
+
+    pub async fn access_token(&self) -> ClientResult<String> {
+        if let Some(token) = self.token.read().await.as_ref() {
+            if token.valid() {
+                return Ok(token.access_token.clone());
+            }
+        }
+        // 本地缓存失效，先读共享存储（可能已被其它副本刷新）
+        let old_token = match self.token_store.get(&self.token_key).await? {
+            Some(token) if token.valid() => {
+                *self.token.write().await = Some(token.clone());
+                return Ok(token.access_token);
+            }
+            Some(token) => token.access_token,
+            None => Default::default(),
+        };
+        let new_token = self.fetch_access_token(old_token).await?;
+        Ok(new_token.access_token)
+    }
+
+    #[cfg(test)]
+    pub(crate) async fn set_invalid_access_token(&self) {
+        let mut token = self.token.write().await;
+        // 仅仅将token的值设置为错误的，但是有效期不变，让sdk认为这是一个正确的token而发出去，知道接口方面返回这是invalid_token
+        if let Some(token) = token.as_mut() {
+            token.access_token = "invalid_access_token".to_owned();
+        }
+    }
+}
+
+// 期望的响应类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expect {
+    Json,
+    Bytes,
+    Stream,
+}
+enum Received {
+    Body(Vec<u8>),
+    Stream(BodyStream),
+}
+
+impl Client {
+    // 单次请求，并识别接口错误
+    //
+    // 日志中的url与响应均已脱敏，状态码与errcode记录到当前span
+    async fn raw_request(&self, request: HttpRequest, expect: Expect) -> ClientResult<Received> {
+        // request...
+        debug!(
+            "\t=> raw_request() send: {} {}",
+            request.method,
+            redact::url(&request.url)
+        );
+        let span = Span::current();
+        let (status, body) = match expect {
+            Expect::Json => {
+                let response = self.transport.send(request).await?;
+                span.record("status", &response.status);
+                (response.status, response.body)
+            }
+            // 文件类响应，只有返回json时才是接口错误
+            Expect::Bytes => {
+                let response = self.transport.send(request).await?;
+                span.record("status", &response.status);
+                if response.status < 400 && !transport::is_json(&response.headers) {
+                    return Ok(Received::Body(response.body));
+                }
+                (response.status, response.body)
+            }
+            Expect::Stream => {
+                let mut response = self.transport.send_streaming(request).await?;
+                span.record("status", &response.status);
+                if response.status < 400 && !transport::is_json(&response.headers) {
+                    return Ok(Received::Stream(response.body));
+                }
+                let mut body = vec![];
+                response.body.read_to_end(&mut body).await?;
+                (response.status, body)
+            }
+        };
+        let response = String::from_utf8_lossy(&body);
+        debug!(
+            "\t<= raw_request() response({}): {}",
+            status,
+            redact::body(&response)
+        );
+
+        // check error...
+        let checked = self.check_error(&response);
+        if let Err(ClientError::Api { code, .. }) = &checked {
+            span.record("errcode", code);
+        }
+        if !(200..300).contains(&status) {
+            // 优先使用接口返回的errcode
+            return Err(match checked {
+                Ok(_) | Err(ClientError::Serde(_)) => ClientError::Status(status),
+                Err(e) => e,
+            });
+        }
+        checked?;
+        Ok(Received::Body(body))
+    }
+
+    fn check_error(&self, response: &str) -> ClientResult<()> {
+        // api错误结构
+        #[derive(Serialize, Deserialize, Debug)]
+        struct ApiErrorResponse {
+            errcode: Option<i32>,
+            errmsg: Option<String>,
+        }
+
+        let error: ApiErrorResponse = serde_json::from_str(response)?;
+        match error.errcode {
+            None | Some(0) => Ok(()),
+            Some(code) => Err(ClientError::Api {
+                code,
+                message: error.errmsg.unwrap_or_default(),
+                platform: self.cfg.platform,
+            }),
+        }
+    }
+
+    // 自动处理access_token，并按策略重试
+    //
+    // 每次调用一个span，结束时上报metrics
+    async fn execute(
+        &self,
+        endpoint: Endpoint,
+        request: HttpRequest,
+        expect: Expect,
+        policy: &RetryPolicy,
+    ) -> ClientResult<Received> {
+        let endpoint = endpoint.map_path(|url| self.cfg.rebase_url(url));
+        let name = redact::endpoint(endpoint.path());
+        let span = tracing::info_span!(
+            "api_request",
+            platform = %self.cfg.platform,
+            method = %request.method,
+            endpoint = name,
+            status = field::Empty,
+            errcode = field::Empty,
+            latency_ms = field::Empty,
+            retries = field::Empty,
+        );
+        let started = Instant::now();
+        let mut attempts = 0;
+        let result = self
+            .execute_with_retry(&endpoint, request, expect, policy, &mut attempts)
+            .instrument(span.clone())
+            .await;
+
+        let latency = started.elapsed();
+        let retries = attempts.saturating_sub(1);
+        span.record("latency_ms", &(latency.as_millis() as u64));
+        span.record("retries", &retries);
+        self.report(name, &result, latency, retries);
+        result
+    }
+
+    fn report<T>(
+        &self,
+        endpoint: &str,
+        result: &ClientResult<T>,
+        latency: std::time::Duration,
+        retries: u32,
+    ) {
+        let platform = self.cfg.platform.to_string();
+        let errcode = result
+            .as_ref()
+            .err()
+            .and_then(ClientError::code)
+            .map(|code| code.to_string())
+            .unwrap_or_default();
+        let outcome = match result {
+            Ok(_) => "ok",
+            Err(e) => e.outcome(),
+        };
+        let labels = [("platform", platform.as_str()), ("endpoint", endpoint)];
+        self.metrics.increment_counter(
+            metrics::REQUESTS_TOTAL,
+            1,
+            &[
+                labels[0],
+                labels[1],
+                ("outcome", outcome),
+                ("errcode", errcode.as_str()),
+            ],
+        );
+        self.metrics.record_histogram(
+            metrics::REQUEST_DURATION_SECONDS,
+            latency.as_secs_f64(),
+            &labels,
+        );
+        if retries > 0 {
+            self.metrics
+                .increment_counter(metrics::RETRIES_TOTAL, retries as u64, &labels);
+        }
+    }
+
+    async fn execute_with_retry(
+        &self,
+        endpoint: &Endpoint,
+        request: HttpRequest,
+        expect: Expect,
+        policy: &RetryPolicy,
+        attempt: &mut u32,
+    ) -> ClientResult<Received> {
+        let started = Instant::now();
+        let log_url = redact::url(&endpoint.to_string());
+        let name = redact::endpoint(endpoint.path());
+
+        // auto retry...
+        let mut token_refreshed = false;
+        loop {
+            // get access_token string
+            let token_str = if endpoint.requires_access_token() {
+                within_deadline(policy, started, self.access_token()).await?
+            } else {
+                Default::default()
+            };
+
+            // 熔断时直接失败，不再重试；
+            // acquire之后必须record（HalfOpen时acquire即占用了探测名额），中间不能提前返回
+            self.breaker.acquire(self.cfg.platform, name)?;
+            let result = {
+                let mut request = request.clone();
+                request.url = endpoint.to_url(&token_str);
+                within_deadline(policy, started, self.raw_request(request, expect)).await
+            };
+            *attempt += 1;
+            let failed = result.as_ref().err().map_or(false, breaker::is_failure);
+            self.breaker.record(self.cfg.platform, name, failed);
+
+            let err = match &result {
+                Ok(_) => break result,
+                Err(err) => err,
+            };
+
+            // 无效token，刷新后立即重试（每次调用仅一次）
+            if err.is_invalid_credential() {
+                if token_refreshed {
+                    break result;
+                }
+                warn!("InvalidToken! refresh and retry <-{}", log_url);
+                token_refreshed = true;
+                within_deadline(policy, started, self.fetch_access_token(token_str)).await?;
+                continue;
+            }
+
+            if *attempt >= policy.max_attempts || !policy.should_retry(err) {
+                warn!("ApiError: {} <-{}", err, log_url);
+                break result;
+            }
+            // 等待之后会超出总时限，不再重试
+            let delay = policy.backoff(*attempt);
+            if let Some(deadline) = policy.deadline {
+                if started.elapsed() + delay >= deadline {
+                    warn!("ApiError: {}, deadline exceeded <-{}", err, log_url);
+                    break result;
+                }
+            }
+            warn!(
+                "{}! retry({}) after {:?} <-{}",
+                err, attempt, delay, log_url
+            );
+            async_std::task::sleep(delay).await;
+        }
+    }
+
+    async fn fetch_access_token(&self, old_token: String) -> ClientResult<CachedToken> {
+        let span = tracing::info_span!(
+            "api_fetch_token",
+            platform = %self.cfg.platform,
+            token_key = %self.token_key,
+            status = field::Empty,
+            errcode = field::Empty,
+        );
+        self.fetch_access_token_locked(old_token)
+            .instrument(span)
+            .await
+    }
+
+    // 等待超出总时限时由调用方（`within_deadline`）取消；
+    // 没有总时限时最多等待一次http超时（持有锁的副本的请求不会更久），之后自行刷新
+    async fn fetch_access_token_locked(&self, old_token: String) -> ClientResult<CachedToken> {
+        // 同一进程内只有一个请求在刷新
+        let _refresh = self.refresh.lock().await;
+        let started = Instant::now();
+        let _lock = loop {
+            // 再次判断，避免竞态fetch
+            // 如token已经变化，
+            // 说明等待期间有其他请求或其他进程(副本)fetch过这个token了，
+            // 则不再fetch
+            if let Some(token) = self.refreshed_token(&old_token).await? {
+                return Ok(token);
+            }
+            if let Some(lock) = self.token_store.try_lock(&self.token_key).await? {
+                // 取得锁之前其它副本可能刚刚写入
+                if let Some(token) = self.refreshed_token(&old_token).await? {
+                    return Ok(token);
+                }
+                break Some(lock);
+            }
+            if started.elapsed() >= self.cfg.http.timeout {
+                warn!(
+                    "token lock wait timeout, refresh anyway: {}",
+                    self.token_key
+                );
+                break None;
+            }
+            // 其它副本正在刷新
+            async_std::task::sleep(TOKEN_LOCK_POLL_INTERVAL).await;
+        };
+
+        // fetch...
+        #[derive(Serialize, Deserialize, Debug)]
+        struct ApiTokenResponse {
+            access_token: String,
+            expires_in: Option<i64>, // 钉钉没有这个字段
+        }
+
+        let request = HttpRequest {
+            method: Method::GET,
+            url: self.cfg.rebase_url(&self.cfg.token_url),
+            headers: vec![],
+            body: None,
+        };
+        let result: ApiTokenResponse = match self.raw_request(request, Expect::Json).await? {
+            Received::Body(body) => serde_json::from_slice(&body)?,
+            Received::Stream(_) => unreachable!("json response is never streamed"),
+        };
+        let expires_in = Duration::seconds(result.expires_in.unwrap_or(7200)); // todo 钉钉固定7200，其它平台须注意此处
+        info!(
+            "fetch_access_token() -> {}, expires in {}s",
+            redact::secret(&result.access_token),
+            expires_in.num_seconds()
+        );
+
+        // 锁意外丢失、其它副本抢先写入时，使用存储里的token
+        let new_token = CachedToken::new(result.access_token, expires_in);
+        let new_token = self
+            .token_store
+            .compare_and_set(&self.token_key, &old_token, new_token)
+            .await?;
+        *self.token.write().await = Some(new_token.clone());
+        Ok(new_token)
+    }
+
+    // 存储里已经是另一个有效的token
+    async fn refreshed_token(&self, old_token: &str) -> ClientResult<Option<CachedToken>> {
+        match self.token_store.get(&self.token_key).await? {
+            Some(token) if token.access_token != old_token && token.valid() => {
+                *self.token.write().await = Some(token.clone());
+                Ok(Some(token))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+// 剩余的总时限内完成，否则为`ClientError::Timeout`
+async fn within_deadline<T>(
+    policy: &RetryPolicy,
+    started: Instant,
+    future: impl std::future::Future<Output = ClientResult<T>>,
+) -> ClientResult<T> {
+    match policy.deadline {
+        Some(deadline) => {
+            let remaining = deadline.checked_sub(started.elapsed()).unwrap_or_default();
+            async_std::future::timeout(remaining, future)
+                .await
+                .unwrap_or(Err(ClientError::Timeout))
+        }
+        None => future.await,
+    }
+}
+
+// 错误类型
+#[derive(ThisError, Debug)]
+pub enum ClientError {
+    #[error("Request Error: {0}")]
+    Http(isahc::Error),
+    #[error(transparent)]
+    Serde(#[from] serde_json::error::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Token Store Error: {0}")]
+    Store(#[from] sqlx::Error),
+    #[error("Client Error Timeout")]
+    Timeout,
+    #[error("Client Error HTTP Status {0}")]
+    Status(u16),
+    /// 熔断中，`retry_after`之后再试
+    #[error("Circuit Open: {platform} {endpoint}, retry after {retry_after:?}")]
+    CircuitOpen {
+        platform: Platform,
+        endpoint: String,
+        retry_after: std::time::Duration,
+    },
+    /// 接口返回的errcode
+    #[error("Api Error({platform} {code}): {message}")]
+    Api {
+        code: i32,
+        message: String,
+        platform: Platform,
+    },
+    #[error("Client Error: {0}")]
+    Other(String),
+}
+pub type ClientResult<T> = Result<T, ClientError>;
+impl ClientError {
+    /// 接口错误的分类，非接口错误返回None
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            ClientError::Api { code, platform, .. } => Some(codes::classify(*platform, *code)),
+            _ => None,
+        }
+    }
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            ClientError::Api { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+    pub fn is_system_busy(&self) -> bool {
+        self.kind() == Some(ErrorKind::SystemBusy)
+    }
+    pub fn is_invalid_credential(&self) -> bool {
+        self.kind() == Some(ErrorKind::InvalidCredential)
+    }
+    pub fn is_rate_limited(&self) -> bool {
+        self.kind() == Some(ErrorKind::RateLimited)
+    }
+    pub fn is_content_risky(&self) -> bool {
+        self.kind() == Some(ErrorKind::ContentRisky)
+    }
+
+    /// 用于metrics的错误类别
+    pub fn outcome(&self) -> &'static str {
+        match self {
+            ClientError::Http(_) => "network",
+            ClientError::Serde(_) => "decode",
+            ClientError::Io(_) => "io",
+            ClientError::Store(_) => "token_store",
+            ClientError::Timeout => "timeout",
+            ClientError::Status(_) => "http_status",
+            ClientError::Api { .. } => "api_error",
+            ClientError::CircuitOpen { .. } => "circuit_open",
+            ClientError::Other(_) => "other",
+        }
+    }
+
+    fn from_isahc(e: isahc::Error) -> Self {
+        match e {
+            isahc::Error::Timeout => ClientError::Timeout,
+            e => ClientError::Http(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::metrics::{self, ClientMetrics, Labels};
+    use super::transport::MockTransport;
+    use super::{
+        AccessTokenStore, BreakerConfig, CachedToken, CircuitState, Client, ClientError,
+        ClientResult, Config, MemoryTokenStore, Platform, RetryPolicy, Retryable, TokenLock,
+    };
+    use crate::core::testing::TestResult;
+    use http::Method;
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    const TOKEN_URL: &str = "https://api.example.com/gettoken?appid=APPID";
+    const API_URL: &str = "https://api.example.com/api?access_token=ACCESS_TOKEN";
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    fn mock_client() -> (Client, Arc<MockTransport>) {
+        // 测试时缩短重试的等待时间
+        let retry = RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let mock = Arc::new(MockTransport::new());
+        let cfg = Config {
+            token_url: TOKEN_URL.to_owned(),
+            retry,
+            ..Default::default()
+        };
+        (Client::with_transport(cfg, mock.clone()), mock)
+    }
+
+    fn token_reply(token: &str) -> Value {
+        json!({"access_token": token, "expires_in": 7200})
+    }
+
+    #[async_std::test]
+    async fn access_token_cached() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        mock.reply_json("/gettoken", token_reply("TOKEN_1"));
+
+        assert_eq!(client.access_token().await?, "TOKEN_1");
+        assert_eq!(client.access_token().await?, "TOKEN_1");
+        assert_eq!(mock.requests().len(), 1);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn shared_token_store() -> TestResult<()> {
+        setup();
+
+        // 模拟两个副本
+        let store = Arc::new(MemoryTokenStore::default());
+        let (client_a, mock_a) = mock_client();
+        let client_a = client_a.with_token_store(store.clone());
+        let (client_b, mock_b) = mock_client();
+        let client_b = client_b.with_token_store(store.clone());
+
+        mock_a.reply_json("/gettoken", token_reply("TOKEN_1"));
+        assert_eq!(client_a.access_token().await?, "TOKEN_1");
+        assert_eq!(client_b.access_token().await?, "TOKEN_1");
+        assert!(mock_b.requests().is_empty());
+
+        // TOKEN_1失效，A刷新了token；
+        // B使用TOKEN_1失败后，从共享存储读取新token，而不是再次刷新
+        mock_a
+            .reply_json("/api", json!({"errcode": 40001}))
+            .reply_json("/gettoken", token_reply("TOKEN_2"))
+            .reply_json("/api", json!({"errcode": 0}));
+        client_a.get::<Value>(API_URL).await?;
+        mock_b
+            .reply_json("/api", json!({"errcode": 40001}))
+            .reply_json("/api", json!({"errcode": 0}));
+        client_b.get::<Value>(API_URL).await?;
+
+        let urls: Vec<_> = mock_b.requests().into_iter().map(|r| r.url).collect();
+        assert_eq!(urls.len(), 2);
+        assert!(urls[0].ends_with("access_token=TOKEN_1"));
+        assert!(urls[1].ends_with("access_token=TOKEN_2"));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn refresh_invalid_token() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        mock.reply_json("/gettoken", token_reply("TOKEN_1"))
+            .reply_json(
+                "/api",
+                json!({"errcode": 40001, "errmsg": "invalid credential"}),
+            )
+            .reply_json("/gettoken", token_reply("TOKEN_2"))
+            .reply_json("/api", json!({"errcode": 0, "value": 1}));
+
+        let result: Value = client.get(API_URL).await?;
+        assert_eq!(result["value"], 1);
+
+        let urls: Vec<_> = mock.requests().into_iter().map(|r| r.url).collect();
+        assert!(urls[1].ends_with("access_token=TOKEN_1"));
+        assert!(urls[3].ends_with("access_token=TOKEN_2"));
+        assert_eq!(mock.pending(), 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn retry_system_busy() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        let busy = json!({"errcode": -1, "errmsg": "system busy"});
+        mock.reply_json("/gettoken", token_reply("TOKEN"));
+        for _ in 0..3 {
+            mock.reply_json("/api", busy.clone());
+        }
+
+        // 重试2次后放弃
+        let result = client.get::<Value>(API_URL).await;
+        assert!(result.unwrap_err().is_system_busy());
+        assert_eq!(mock.requests().len(), 4);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn retry_server_error_and_quota() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        let quota = json!({"errcode": 45009, "errmsg": "reach max api daily quota limit"});
+        mock.reply("/api", 502, "Bad Gateway")
+            .reply_json("/api", quota.clone())
+            .reply_json("/api", quota)
+            .reply_json("/api", json!({"errcode": 0, "value": 1}));
+
+        // 超过限额默认不重试
+        let url = "https://api.example.com/api";
+        let result = client.get::<Value>(url).await;
+        assert!(result.unwrap_err().is_rate_limited());
+        assert_eq!(mock.requests().len(), 2);
+
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            retry_on: vec![Retryable::QuotaExceeded],
+            ..Default::default()
+        };
+        let result: Value = client
+            .request(Method::GET, url)
+            .retry(policy)
+            .send()
+            .await?;
+        assert_eq!(result["value"], 1);
+        assert_eq!(mock.requests().len(), 4);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn no_retry_for_other_errors() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        mock.reply("/api", 404, "Not Found")
+            .reply_json("/api", json!({"errcode": -1}))
+            .reply_json("/api", json!({"errcode": 0}));
+
+        let url = "https://api.example.com/api";
+        let result = client.get::<Value>(url).await;
+        assert!(matches!(result, Err(ClientError::Status(404))));
+
+        // 单次调用覆盖重试策略
+        let result = client
+            .request(Method::GET, url)
+            .retry(RetryPolicy::none())
+            .send::<Value>()
+            .await;
+        assert!(result.unwrap_err().is_system_busy());
+        assert_eq!(mock.requests().len(), 2);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn retry_post_only_when_idempotent() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        mock.reply_error("/api", ClientError::Timeout)
+            .reply_error("/api", ClientError::Timeout)
+            .reply_json("/api", json!({"errcode": 0}));
+
+        // 默认不重试，避免重复发送消息
+        let url = "https://api.example.com/api";
+        let result = client.post::<_, Value>(url, Some(&json!({}))).await;
+        assert!(matches!(result, Err(ClientError::Timeout)));
+        assert_eq!(mock.requests().len(), 1);
+
+        client
+            .request(Method::POST, url)
+            .json(&json!({}))
+            .idempotent()
+            .send::<Value>()
+            .await?;
+        assert_eq!(mock.requests().len(), 3);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn fetch_token_within_deadline() -> TestResult<()> {
+        setup();
+
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json("/gettoken", token_reply("TOKEN"));
+        let cfg = Config {
+            token_url: TOKEN_URL.to_owned(),
+            retry: RetryPolicy {
+                deadline: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let client =
+            Client::with_transport(cfg, mock.clone()).with_token_store(Arc::new(HangingStore));
+
+        let result = client.get::<Value>(API_URL).await;
+        assert!(matches!(result, Err(ClientError::Timeout)));
+        assert!(mock.requests().is_empty());
+        Ok(())
+    }
+
+    // 共享存储没有响应（例如数据库连接池耗尽）
+    struct HangingStore;
+    #[async_trait::async_trait]
+    impl AccessTokenStore for HangingStore {
+        async fn get(&self, _key: &str) -> ClientResult<Option<CachedToken>> {
+            futures::future::pending().await
+        }
+        async fn try_lock(&self, _key: &str) -> ClientResult<Option<Box<dyn TokenLock>>> {
+            futures::future::pending().await
+        }
+        async fn compare_and_set(
+            &self,
+            _key: &str,
+            _old_token: &str,
+            _token: CachedToken,
+        ) -> ClientResult<CachedToken> {
+            futures::future::pending().await
+        }
+    }
+
+    #[async_std::test]
+    async fn wait_for_other_replica() -> TestResult<()> {
+        setup();
+
+        let store = Arc::new(MemoryTokenStore::default());
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json("/api", json!({"errcode": 0}));
+        let cfg = Config {
+            token_url: TOKEN_URL.to_owned(),
+            token_key: "test".to_owned(),
+            ..Default::default()
+        };
+        let client = Client::with_transport(cfg, mock.clone()).with_token_store(store.clone());
+
+        // 其它副本正在刷新，刷新完成前不请求token_url
+        let lock = store.try_lock("test").await?.ok_or("locked")?;
+        let other_replica = async {
+            async_std::task::sleep(Duration::from_millis(150)).await;
+            let token = CachedToken::new("OTHER".to_owned(), chrono::Duration::seconds(7200));
+            store.compare_and_set("test", "", token).await?;
+            drop(lock);
+            ClientResult::Ok(())
+        };
+        let (result, refreshed) = futures::join!(client.get::<Value>(API_URL), other_replica);
+        refreshed?;
+        result?;
+
+        let urls: Vec<_> = mock.requests().into_iter().map(|r| r.url).collect();
+        assert_eq!(urls, vec!["https://api.example.com/api?access_token=OTHER"]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn retry_within_deadline() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        for _ in 0..10 {
+            mock.reply_json("/api", json!({"errcode": -1}));
+        }
+
+        // 第二次失败后需要等待100ms，超出了总时限
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(50),
+            jitter: false,
+            deadline: Some(Duration::from_millis(80)),
+            ..Default::default()
+        };
+        let result = client
+            .request(Method::GET, "https://api.example.com/api")
+            .retry(policy)
+            .send::<Value>()
+            .await;
+        assert!(result.unwrap_err().is_system_busy());
+        assert_eq!(mock.requests().len(), 2);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn custom_base_url() -> TestResult<()> {
+        setup();
+
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json("", token_reply("TOKEN"))
+            .reply_json("", json!({"errcode": 0}));
+        let cfg = Config {
+            token_url: TOKEN_URL.to_owned(),
+            base_url: Some("http://127.0.0.1:8080/wechat/".to_owned()),
+            ..Default::default()
+        };
+        let client = Client::with_transport(cfg, mock.clone());
+        client.get::<Value>(API_URL).await?;
+
+        let urls: Vec<_> = mock.requests().into_iter().map(|r| r.url).collect();
+        assert_eq!(urls[0], "http://127.0.0.1:8080/wechat/gettoken?appid=APPID");
+        assert_eq!(
+            urls[1],
+            "http://127.0.0.1:8080/wechat/api?access_token=TOKEN"
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn error_mapping() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        mock.reply_json("", json!({"errcode": 40013, "errmsg": "invalid appid"}))
+            .reply("", 200, "<html>not json</html>")
+            .reply_error("", ClientError::Other("connection reset".to_owned()));
+
+        let url = "https://api.example.com/public";
+        let result = client.get::<Value>(url).await;
+        assert!(matches!(
+            result,
+            Err(ClientError::Api { code: 40013, platform: Platform::Unknown, ref message }) if message == "invalid appid"
+        ));
+        let result = client.get::<Value>(url).await;
+        assert!(matches!(result, Err(ClientError::Serde(_))));
+        let result = client.get::<Value>(url).await;
+        assert!(matches!(result, Err(ClientError::Other(m)) if m == "connection reset"));
+
+        // 不需要access_token的接口不会获取token
+        assert!(mock.requests().iter().all(|r| r.url == url));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn classify_api_errors() -> TestResult<()> {
+        setup();
+
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json("", json!({"errcode": 87014, "errmsg": "risky content"}))
+            .reply_json(
+                "",
+                json!({"errcode": 45011, "errmsg": "api minute-quota reach limit"}),
+            );
+        let cfg = Config {
+            platform: Platform::Wechat,
+            ..Default::default()
+        };
+        let client = Client::with_transport(cfg, mock.clone());
+
+        let url = "https://api.example.com/public";
+        let call = || {
+            client
+                .request(Method::GET, url)
+                .retry(RetryPolicy::none())
+                .send::<Value>()
+        };
+        let err = call().await.unwrap_err();
+        assert!(err.is_content_risky());
+        assert_eq!(err.code(), Some(87014));
+        let err = call().await.unwrap_err();
+        assert!(err.is_rate_limited());
+        assert!(!err.is_invalid_credential());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn post_json_body() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        mock.reply_json("/gettoken", token_reply("TOKEN"))
+            .reply_json("/api", json!({"errcode": 0}));
+
+        let payload = json!({"content": "hello"});
+        client.post::<_, Value>(API_URL, Some(&payload)).await?;
+
+        let request = mock.requests().pop().expect("request");
+        assert_eq!(request.header("content-type"), Some("application/json"));
+        let body: Value = serde_json::from_slice(&request.body.unwrap_or_default())?;
+        assert_eq!(body, payload);
+        Ok(())
+    }
+
+    // 记录上报的metrics：(name, value, labels)
+    #[derive(Default)]
+    struct RecordingMetrics(Mutex<Vec<(&'static str, f64, Vec<String>)>>);
+    impl RecordingMetrics {
+        fn record(&self, name: &'static str, value: f64, labels: &Labels<'_>) {
+            let labels = labels.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            self.0.lock().unwrap().push((name, value, labels));
+        }
+        fn find(&self, name: &str) -> Vec<(f64, Vec<String>)> {
+            let records = self.0.lock().unwrap();
+            records
+                .iter()
+                .filter(|(n, _, _)| *n == name)
+                .map(|(_, value, labels)| (*value, labels.clone()))
+                .collect()
+        }
+    }
+    impl ClientMetrics for RecordingMetrics {
+        fn increment_counter(&self, name: &'static str, value: u64, labels: &Labels<'_>) {
+            self.record(name, value as f64, labels)
+        }
+        fn record_histogram(&self, name: &'static str, value: f64, labels: &Labels<'_>) {
+            self.record(name, value, labels)
+        }
+    }
+
+    #[async_std::test]
+    async fn report_metrics() -> TestResult<()> {
+        setup();
+
+        let recorder = Arc::new(RecordingMetrics::default());
+        let (client, mock) = mock_client();
+        let client = client.with_metrics(recorder.clone());
+        mock.reply_json("/gettoken", token_reply("TOKEN"))
+            .reply_json("/api", json!({"errcode": -1}))
+            .reply_json("/api", json!({"errcode": 0}))
+            .reply_json("/api", json!({"errcode": 40013}));
+
+        client.get::<Value>(API_URL).await?;
+        assert!(client.get::<Value>(API_URL).await.is_err());
+
+        // endpoint不含access_token
+        let labels = |outcome: &str, errcode: &str| {
+            vec![
+                "platform=unknown".to_owned(),
+                "endpoint=api.example.com/api".to_owned(),
+                format!("outcome={}", outcome),
+                format!("errcode={}", errcode),
+            ]
+        };
+        let requests = recorder.find(metrics::REQUESTS_TOTAL);
+        assert_eq!(
+            requests,
+            vec![(1.0, labels("ok", "")), (1.0, labels("api_error", "40013"))]
+        );
+        assert_eq!(recorder.find(metrics::REQUEST_DURATION_SECONDS).len(), 2);
+        let retries = recorder.find(metrics::RETRIES_TOTAL);
+        assert_eq!(retries.len(), 1);
+        assert_eq!(retries[0].0, 1.0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn circuit_open_fails_fast() -> TestResult<()> {
+        setup();
+
+        let mock = Arc::new(MockTransport::new());
+        let cfg = Config {
+            platform: Platform::Wechat,
+            retry: RetryPolicy::none(),
+            breaker: BreakerConfig {
+                min_requests: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let client = Client::with_transport(cfg, mock.clone());
+        mock.reply("/api", 502, "Bad Gateway")
+            .reply_error("/api", ClientError::Timeout)
+            .reply_json("/other", json!({"errcode": 0}));
+
+        let url = "https://api.example.com/api?id=1";
+        assert!(matches!(
+            client.get::<Value>(url).await,
+            Err(ClientError::Status(502))
+        ));
+        assert!(matches!(
+            client.get::<Value>(url).await,
+            Err(ClientError::Timeout)
+        ));
+
+        // 熔断后不再发出请求，其它接口不受影响
+        let err = client.get::<Value>(url).await.unwrap_err();
+        assert!(matches!(
+            err,
+            ClientError::CircuitOpen { platform: Platform::Wechat, ref endpoint, .. } if endpoint == "api.example.com/api"
+        ));
+        client.get::<Value>("https://api.example.com/other").await?;
+        assert_eq!(mock.requests().len(), 3);
+
+        let states = client.circuit_states();
+        assert_eq!(states[0].endpoint, "api.example.com/api");
+        assert_eq!(states[0].state, CircuitState::Open);
+        assert_eq!(states[1].state, CircuitState::Closed);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn token_error_releases_probe() -> TestResult<()> {
+        setup();
+
+        let mock = Arc::new(MockTransport::new());
+        let cfg = Config {
+            token_url: TOKEN_URL.to_owned(),
+            platform: Platform::Wechat,
+            retry: RetryPolicy::none(),
+            breaker: BreakerConfig {
+                min_requests: 2,
+                cool_down: Duration::from_millis(20),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let client = Client::with_transport(cfg, mock.clone());
+        // 同一平台的Client共用熔断状态
+        let other = Client::with_transport(Default::default(), mock.clone())
+            .with_circuit_breaker(client.circuit_breaker());
+        mock.reply("/api", 502, "Bad Gateway")
+            .reply("/api", 502, "Bad Gateway")
+            .reply("/gettoken", 500, "Internal Server Error")
+            .reply_json("/gettoken", token_reply("TOKEN"))
+            .reply_json("/api", json!({"errcode": 0}));
+
+        let url = "https://api.example.com/api";
+        assert!(client.get::<Value>(url).await.is_err());
+        assert!(client.get::<Value>(url).await.is_err());
+        assert_eq!(other.circuit_states()[0].state, CircuitState::Open);
+
+        // 冷却之后，获取token失败不占用探测名额，下一次调用可以正常探测
+        async_std::task::sleep(Duration::from_millis(30)).await;
+        assert!(matches!(
+            client.get::<Value>(API_URL).await,
+            Err(ClientError::Status(500))
+        ));
+        client.get::<Value>(API_URL).await?;
+        assert_eq!(client.circuit_states()[0].state, CircuitState::Closed);
+        assert_eq!(mock.pending(), 0);
+        Ok(())
+    }
+}
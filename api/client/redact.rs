@@ -0,0 +1,200 @@
+//! 日志脱敏
+//!
+//! access_token、secret、session_key等凭证替换为`***`，手机号只保留前3位和后4位
+use serde_json::Value;
+
+// url参数中的凭证
+const SECRET_PARAMS: &[&str] = &[
+    "access_token",
+    "secret",
+    "appsecret",
+    "corpsecret",
+    "js_code",
+    "session_key",
+    "refresh_token",
+    // 网页授权的code、jsapi_ticket
+    "code",
+    "ticket",
+];
+
+// json中的凭证字段（忽略大小写和下划线）
+const SECRET_KEYS: &[&str] = &[
+    "accesstoken",
+    "refreshtoken",
+    "sessionkey",
+    "secret",
+    "appsecret",
+    "corpsecret",
+    "ticket",
+    "userticket",
+];
+
+// json中的手机号字段（忽略大小写和下划线）
+const PHONE_KEYS: &[&str] = &["phonenumber", "purephonenumber", "mobile", "tel"];
+
+pub fn secret(_value: &str) -> &'static str {
+    "***"
+}
+
+/// `13812345678` => `138****5678`
+pub fn phone(value: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    if chars.len() < 7 {
+        return "***".to_owned();
+    }
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}****{}", head, tail)
+}
+
+/// 去掉query，用于日志与metrics的接口名，例如`api.weixin.qq.com/wxa/msg_sec_check`
+pub fn endpoint(url: &str) -> &str {
+    let url = url.split(|c| c == '?' || c == '#').next().unwrap_or("");
+    url.find("://").map_or(url, |i| &url[i + 3..])
+}
+
+/// 替换url参数中的凭证
+pub fn url(url: &str) -> String {
+    let (path, query) = match url.find('?') {
+        Some(i) => (&url[..i], &url[i + 1..]),
+        None => return url.to_owned(),
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.find('=') {
+            Some(i) if SECRET_PARAMS.contains(&pair[..i].to_ascii_lowercase().as_str()) => {
+                format!("{}={}", &pair[..i], secret(&pair[i + 1..]))
+            }
+            _ => pair.to_owned(),
+        })
+        .collect();
+    format!("{}?{}", path, query.join("&"))
+}
+
+/// 响应内容：json按字段脱敏，其它内容只处理手机号
+pub fn body(body: &str) -> String {
+    match serde_json::from_str::<Value>(body) {
+        Ok(mut value) => {
+            redact_value(&mut value);
+            value.to_string()
+        }
+        Err(_) => phones_in_text(body),
+    }
+}
+
+fn normalize(key: &str) -> String {
+    key.chars()
+        .filter(|c| *c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = normalize(key);
+                match value {
+                    Value::String(s) if SECRET_KEYS.contains(&key.as_str()) => {
+                        *s = secret(s).to_owned()
+                    }
+                    Value::String(s) if PHONE_KEYS.contains(&key.as_str()) => *s = phone(s),
+                    value => redact_value(value),
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_value),
+        Value::String(s) => *s = phones_in_text(s),
+        _ => {}
+    }
+}
+
+// 文本中的大陆手机号（1开头的11位数字）
+fn phones_in_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut digits = String::new();
+    let flush = |digits: &mut String, result: &mut String| {
+        if digits.len() == 11 && digits.starts_with('1') {
+            result.push_str(&phone(digits));
+        } else {
+            result.push_str(digits);
+        }
+        digits.clear();
+    };
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+        } else {
+            flush(&mut digits, &mut result);
+            result.push(c);
+        }
+    }
+    flush(&mut digits, &mut result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{body, endpoint, phone, url};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn redact_url() {
+        assert_eq!(
+            url("https://api.weixin.qq.com/sns/jscode2session?appid=APPID&secret=S&js_code=C"),
+            "https://api.weixin.qq.com/sns/jscode2session?appid=APPID&secret=***&js_code=***"
+        );
+        assert_eq!(
+            url("https://oapi.dingtalk.com/user/get?access_token=TOKEN&userid=1"),
+            "https://oapi.dingtalk.com/user/get?access_token=***&userid=1"
+        );
+        assert_eq!(
+            url("https://api.weixin.qq.com/sns/oauth2/refresh_token?appid=APPID&refresh_token=R&grant_type=refresh_token"),
+            "https://api.weixin.qq.com/sns/oauth2/refresh_token?appid=APPID&refresh_token=***&grant_type=refresh_token"
+        );
+        assert_eq!(
+            url("https://api.weixin.qq.com/sns/oauth2/access_token?appid=APPID&secret=S&code=C"),
+            "https://api.weixin.qq.com/sns/oauth2/access_token?appid=APPID&secret=***&code=***"
+        );
+        assert_eq!(
+            url("https://mp.weixin.qq.com/cgi-bin/showqrcode?ticket=T"),
+            "https://mp.weixin.qq.com/cgi-bin/showqrcode?ticket=***"
+        );
+        assert_eq!(url("https://example.com/a"), "https://example.com/a");
+        assert_eq!(
+            endpoint("https://api.weixin.qq.com/wxa/msg_sec_check?access_token=TOKEN"),
+            "api.weixin.qq.com/wxa/msg_sec_check"
+        );
+    }
+
+    #[test]
+    fn redact_body() {
+        assert_eq!(phone("13812345678"), "138****5678");
+        assert_eq!(phone("123"), "***");
+
+        let response = json!({
+            "access_token": "TOKEN",
+            "session_key": "KEY",
+            "user_ticket": "TICKET",
+            "openid": "OPENID",
+            "phoneNumber": "+8613812345678",
+            "user": {"mobile": "13812345678", "remark": "call 13812345678"},
+            "errcode": 0,
+        });
+        let redacted: Value = serde_json::from_str(&body(&response.to_string())).unwrap();
+        assert_eq!(
+            redacted,
+            json!({
+                "access_token": "***",
+                "session_key": "***",
+                "user_ticket": "***",
+                "openid": "OPENID",
+                "phoneNumber": "+86****5678",
+                "user": {"mobile": "138****5678", "remark": "call 138****5678"},
+                "errcode": 0,
+            })
+        );
+        assert_eq!(body("tel:13812345678;"), "tel:138****5678;");
+        assert_eq!(body("id 1234567890123"), "id 1234567890123");
+    }
+}
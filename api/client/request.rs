@@ -0,0 +1,415 @@
+//! 请求构建
+//!
+//! 支持任意method、query参数、自定义header，以及json、表单、multipart等body；
+//! 响应可以按json解析，也可以读取为字节（图片）或流（下载素材）。
+//! 接口地址见`Endpoint`，access_token会自动填入。
+//!
+//! ```rs
+//! let endpoint = Endpoint::new("https://api.weixin.qq.com/wxa/getwxacodeunlimit").access_token();
+//! let image: Vec<u8> = client
+//!     .request(Method::POST, endpoint)
+//!     .json(&payload)
+//!     .send_bytes()
+//!     .await?;
+//!
+//! let endpoint = Endpoint::new("https://api.weixin.qq.com/cgi-bin/media/upload").access_token();
+//! let media: UploadResult = client
+//!     .request(Method::POST, endpoint)
+//!     .query("type", "image")
+//!     .multipart(Multipart::new().file("media", "a.jpg", "image/jpeg", bytes))
+//!     .send()
+//!     .await?;
+//! ```
+use super::endpoint::Endpoint;
+use super::retry::RetryPolicy;
+use super::transport::{BodyStream, HttpRequest};
+use super::{Client, ClientResult, Expect, Received};
+use http::Method;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Write;
+
+pub struct RequestBuilder<'a> {
+    client: &'a Client,
+    method: Method,
+    endpoint: Endpoint,
+    headers: Vec<(String, String)>,
+    // (Content-Type, 数据)；序列化错误留到send时返回
+    body: ClientResult<Option<(String, Vec<u8>)>>,
+    retry: Option<RetryPolicy>,
+    idempotent: bool,
+}
+impl<'a> RequestBuilder<'a> {
+    pub(super) fn new(client: &'a Client, method: Method, endpoint: Endpoint) -> Self {
+        let idempotent = method == Method::GET || method == Method::HEAD;
+        Self {
+            client,
+            method,
+            endpoint,
+            headers: vec![],
+            body: Ok(None),
+            retry: None,
+            idempotent,
+        }
+    }
+
+    /// 追加query参数（会做url编码）
+    pub fn query(mut self, name: &str, value: impl ToString) -> Self {
+        self.endpoint = self.endpoint.query(name, value);
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn json<T: Serialize + ?Sized>(mut self, payload: &T) -> Self {
+        self.body = serde_json::to_vec(payload)
+            .map(|data| Some(("application/json".to_owned(), data)))
+            .map_err(Into::into);
+        self
+    }
+
+    /// application/x-www-form-urlencoded
+    pub fn form(mut self, fields: &[(&str, &str)]) -> Self {
+        let data = encode_pairs(fields.iter().map(|(k, v)| (*k, *v))).into_bytes();
+        let content_type = "application/x-www-form-urlencoded".to_owned();
+        self.body = Ok(Some((content_type, data)));
+        self
+    }
+
+    pub fn multipart(mut self, form: Multipart) -> Self {
+        self.body = Ok(Some((form.content_type(), form.encode())));
+        self
+    }
+
+    pub fn body(mut self, content_type: &str, data: Vec<u8>) -> Self {
+        self.body = Ok(Some((content_type.to_owned(), data)));
+        self
+    }
+
+    /// 覆盖`Config::retry`
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// 声明重复执行没有副作用（例如查询类的POST接口），按重试策略重试
+    ///
+    /// 默认只重试GET/HEAD：POST超时后对方可能已经执行，重试会重复发送消息等
+    pub fn idempotent(mut self) -> Self {
+        self.idempotent = true;
+        self
+    }
+
+    /// 按json解析响应
+    pub async fn send<O: DeserializeOwned>(self) -> ClientResult<O> {
+        let (client, endpoint, request, policy) = self.build()?;
+        match client
+            .execute(endpoint, request, Expect::Json, &policy)
+            .await?
+        {
+            Received::Body(body) => serde_json::from_slice(&body).map_err(|e| {
+                let response = String::from_utf8_lossy(&body);
+                warn!("serde_json解析错误：{:?}，response: {}", e, response);
+                e.into()
+            }),
+            Received::Stream(_) => unreachable!("json response is never streamed"),
+        }
+    }
+
+    /// 读取为字节，例如小程序码图片
+    pub async fn send_bytes(self) -> ClientResult<Vec<u8>> {
+        let (client, endpoint, request, policy) = self.build()?;
+        match client
+            .execute(endpoint, request, Expect::Bytes, &policy)
+            .await?
+        {
+            Received::Body(body) => Ok(body),
+            Received::Stream(_) => unreachable!("bytes response is never streamed"),
+        }
+    }
+
+    /// 读取为流，适合较大的文件
+    pub async fn send_stream(self) -> ClientResult<BodyStream> {
+        let (client, endpoint, request, policy) = self.build()?;
+        match client
+            .execute(endpoint, request, Expect::Stream, &policy)
+            .await?
+        {
+            Received::Stream(stream) => Ok(stream),
+            // 接口返回了json（成功但非文件的情况）
+            Received::Body(body) => Ok(Box::new(futures::io::Cursor::new(body))),
+        }
+    }
+
+    fn build(self) -> ClientResult<(&'a Client, Endpoint, HttpRequest, RetryPolicy)> {
+        let mut headers = self.headers;
+        let body = match self.body? {
+            Some((content_type, data)) => {
+                if !headers
+                    .iter()
+                    .any(|(k, _)| k.eq_ignore_ascii_case("Content-Type"))
+                {
+                    headers.push(("Content-Type".to_owned(), content_type));
+                }
+                Some(data)
+            }
+            None => None,
+        };
+
+        let request = HttpRequest {
+            method: self.method,
+            // 每次请求时由endpoint生成（填入access_token）
+            url: Default::default(),
+            headers,
+            body,
+        };
+        let mut policy = self.retry.unwrap_or_else(|| self.client.cfg.retry.clone());
+        if !self.idempotent {
+            // 无效token时的刷新重试不受影响（请求没有被执行）
+            policy.max_attempts = 1;
+        }
+        Ok((self.client, self.endpoint, request, policy))
+    }
+}
+
+// multipart/form-data
+pub struct Multipart {
+    boundary: String,
+    parts: Vec<Part>,
+}
+struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    data: Vec<u8>,
+}
+impl Default for Multipart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Multipart {
+    pub fn new() -> Self {
+        Self {
+            boundary: format!("----RustxBoundary{:016x}", rand::random::<u64>()),
+            parts: vec![],
+        }
+    }
+
+    pub fn text(mut self, name: &str, value: &str) -> Self {
+        self.parts.push(Part {
+            name: name.to_owned(),
+            filename: None,
+            content_type: None,
+            data: value.as_bytes().to_vec(),
+        });
+        self
+    }
+
+    pub fn file(mut self, name: &str, filename: &str, content_type: &str, data: Vec<u8>) -> Self {
+        self.parts.push(Part {
+            name: name.to_owned(),
+            filename: Some(filename.to_owned()),
+            content_type: Some(content_type.to_owned()),
+            data,
+        });
+        self
+    }
+
+    fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    fn encode(self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for part in self.parts {
+            let mut head = format!("--{}\r\n", self.boundary);
+            // 引号与换行不能出现在header里
+            let quote = |s: &str| s.replace('"', "%22").replace('\r', "").replace('\n', "");
+            write!(
+                head,
+                "Content-Disposition: form-data; name=\"{}\"",
+                quote(&part.name)
+            )
+            .ok();
+            if let Some(filename) = &part.filename {
+                write!(head, "; filename=\"{}\"", quote(filename)).ok();
+            }
+            head.push_str("\r\n");
+            if let Some(content_type) = &part.content_type {
+                write!(head, "Content-Type: {}\r\n", content_type).ok();
+            }
+            head.push_str("\r\n");
+
+            out.extend_from_slice(head.as_bytes());
+            out.extend_from_slice(&part.data);
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        out
+    }
+}
+
+// url编码：保留 A-Z a-z 0-9 - _ . ~，其余按字节编码为%XX
+pub(crate) fn encode_component(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => {
+                write!(out, "%{:02X}", b).ok();
+            }
+        }
+    }
+    out
+}
+
+fn encode_pairs<'s>(pairs: impl Iterator<Item = (&'s str, &'s str)>) -> String {
+    pairs
+        .map(|(k, v)| format!("{}={}", encode_component(k), encode_component(v)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::transport::MockTransport;
+    use super::super::{Client, Config, RetryPolicy};
+    use super::{encode_component, Multipart};
+    use crate::core::testing::TestResult;
+    use futures::io::AsyncReadExt;
+    use http::Method;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    const TOKEN_URL: &str = "https://api.example.com/gettoken";
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    fn mock_client() -> (Client, Arc<MockTransport>) {
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json(
+            "/gettoken",
+            json!({"access_token": "TOKEN", "expires_in": 7200}),
+        );
+        let cfg = Config {
+            token_url: TOKEN_URL.to_owned(),
+            ..Default::default()
+        };
+        (Client::with_transport(cfg, mock.clone()), mock)
+    }
+
+    #[test]
+    fn url_encode() {
+        assert_eq!(encode_component("abc-_.~123"), "abc-_.~123");
+        assert_eq!(encode_component("a&b=c#d e"), "a%26b%3Dc%23d%20e");
+        assert_eq!(encode_component("中"), "%E4%B8%AD");
+    }
+
+    #[async_std::test]
+    async fn query_form_and_headers() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        mock.reply_json("/user", json!({"errcode": 0}));
+        client
+            .request(
+                Method::PUT,
+                "https://api.example.com/user?access_token=ACCESS_TOKEN",
+            )
+            .query("userid", "a&b")
+            .header("X-Request-Id", "1")
+            .form(&[("name", "张三"), ("tag", "a b")])
+            .send::<Value>()
+            .await?;
+
+        let request = mock.requests().pop().expect("request");
+        assert_eq!(request.method, Method::PUT);
+        assert_eq!(
+            request.url,
+            "https://api.example.com/user?access_token=TOKEN&userid=a%26b"
+        );
+        assert_eq!(request.header("x-request-id"), Some("1"));
+        assert_eq!(
+            request.header("Content-Type"),
+            Some("application/x-www-form-urlencoded")
+        );
+        assert_eq!(
+            request.body.unwrap_or_default(),
+            b"name=%E5%BC%A0%E4%B8%89&tag=a%20b".to_vec()
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn multipart_body() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        mock.reply_json("/upload", json!({"media_id": "MEDIA_ID"}));
+        let form = Multipart::new().text("type", "image").file(
+            "media",
+            "a.jpg",
+            "image/jpeg",
+            vec![0xff, 0xd8],
+        );
+        let boundary = form.boundary.clone();
+        let result: Value = client
+            .request(Method::POST, "https://api.example.com/upload")
+            .multipart(form)
+            .send()
+            .await?;
+        assert_eq!(result["media_id"], "MEDIA_ID");
+
+        let request = mock.requests().pop().expect("request");
+        let content_type = request.header("Content-Type").unwrap_or_default();
+        assert!(content_type.starts_with("multipart/form-data; boundary="));
+        let body = request.body.unwrap_or_default();
+        let mut expected = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"type\"\r\n\r\nimage\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"media\"; filename=\"a.jpg\"\r\n\
+             Content-Type: image/jpeg\r\n\r\n",
+            b = boundary
+        )
+        .into_bytes();
+        expected.extend_from_slice(&[0xff, 0xd8]);
+        expected.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+        assert_eq!(body, expected);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn bytes_and_stream_response() -> TestResult<()> {
+        setup();
+
+        let (client, mock) = mock_client();
+        let url = "https://api.example.com/wxacode?access_token=ACCESS_TOKEN";
+        mock.reply_bytes("/wxacode", "image/jpeg", vec![1, 2, 3])
+            .reply_json("/wxacode", json!({"errcode": 45009, "errmsg": "quota"}))
+            .reply_bytes("/wxacode", "image/jpeg", vec![4, 5, 6]);
+
+        let image = client.request(Method::POST, url).send_bytes().await?;
+        assert_eq!(image, vec![1, 2, 3]);
+
+        // 返回json时识别为接口错误
+        let result = client
+            .request(Method::POST, url)
+            .retry(RetryPolicy::none())
+            .send_bytes()
+            .await;
+        assert_eq!(result.unwrap_err().code(), Some(45009));
+
+        let mut stream = client.request(Method::GET, url).send_stream().await?;
+        let mut data = vec![];
+        stream.read_to_end(&mut data).await?;
+        assert_eq!(data, vec![4, 5, 6]);
+        Ok(())
+    }
+}
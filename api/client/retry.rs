@@ -0,0 +1,171 @@
+//! 重试策略
+//!
+//! 指数退避 + 随机抖动，避免在对方繁忙时立即重试；所有重试（包括获取access_token）受总时限(deadline)约束
+//!
+//! 只有GET/HEAD按策略重试，POST等须在单次调用时用`RequestBuilder::idempotent`声明
+//!
+//! ```rs
+//! // 全局配置
+//! let cfg = Config { token_url, retry: RetryPolicy::default(), ..Default::default() };
+//! // 单次调用覆盖
+//! client.request(Method::GET, url).retry(RetryPolicy::none()).send().await?;
+//! // 查询类的POST接口
+//! client.request(Method::POST, url).json(&query).idempotent().send().await?;
+//! ```
+use super::{ClientError, ErrorKind};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// 可重试的错误类别
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Retryable {
+    /// 请求超时
+    Timeout,
+    /// HTTP 5xx
+    ServerError,
+    /// errcode -1，系统繁忙
+    SystemBusy,
+    /// errcode 45009等，接口调用超过限额（见`codes::ErrorKind::RateLimited`）
+    ///
+    /// 默认不重试：每日限额几秒内不会恢复，频率限制时重试只会加重
+    QuotaExceeded,
+    /// 连接失败等网络错误
+    Network,
+}
+impl Retryable {
+    pub fn of(error: &ClientError) -> Option<Retryable> {
+        match error {
+            ClientError::Timeout => Some(Retryable::Timeout),
+            ClientError::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                Some(Retryable::Timeout)
+            }
+            ClientError::Status(status) if *status >= 500 => Some(Retryable::ServerError),
+            ClientError::Http(_) => Some(Retryable::Network),
+            e => match e.kind() {
+                Some(ErrorKind::SystemBusy) => Some(Retryable::SystemBusy),
+                Some(ErrorKind::RateLimited) => Some(Retryable::QuotaExceeded),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// 最多尝试次数（包括第一次）
+    pub max_attempts: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// 等待时间随机取[delay/2, delay]，避免多个请求同时重试
+    pub jitter: bool,
+    pub retry_on: Vec<Retryable>,
+    /// 整个调用（包括所有重试）的总时限
+    pub deadline: Option<Duration>,
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+            retry_on: vec![
+                Retryable::Timeout,
+                Retryable::ServerError,
+                Retryable::SystemBusy,
+            ],
+            deadline: Some(Duration::from_secs(30)),
+        }
+    }
+}
+impl RetryPolicy {
+    /// 不重试（无效token时仍会刷新后重试一次）
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn should_retry(&self, error: &ClientError) -> bool {
+        Retryable::of(error).map_or(false, |class| self.retry_on.contains(&class))
+    }
+
+    /// 第`attempt`次失败后的等待时间（`attempt`从1开始）
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |d| d.min(self.max_delay));
+        if self.jitter {
+            let half = delay / 2;
+            half + half.mul_f64(rand::thread_rng().gen::<f64>())
+        } else {
+            delay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ClientError, Platform};
+    use super::{RetryPolicy, Retryable};
+    use std::time::Duration;
+
+    fn api_error(code: i32) -> ClientError {
+        ClientError::Api {
+            code,
+            message: Default::default(),
+            platform: Platform::Wechat,
+        }
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: false,
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff(40), Duration::from_millis(500));
+
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = policy.backoff(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn retryable_errors() {
+        let policy = RetryPolicy::default();
+        assert!(policy.should_retry(&ClientError::Timeout));
+        assert!(policy.should_retry(&ClientError::Status(502)));
+        assert!(policy.should_retry(&api_error(-1)));
+        assert!(!policy.should_retry(&api_error(45009)));
+        assert!(!policy.should_retry(&ClientError::Status(404)));
+        assert!(!policy.should_retry(&api_error(40001)));
+        assert!(!policy.should_retry(&api_error(40013)));
+
+        let policy = RetryPolicy {
+            retry_on: vec![Retryable::Timeout, Retryable::QuotaExceeded],
+            ..Default::default()
+        };
+        assert!(!policy.should_retry(&api_error(-1)));
+        assert!(policy.should_retry(&api_error(45009)));
+    }
+}
@@ -0,0 +1,314 @@
+//! access_token 的存储
+//!
+//! 多个副本各自刷新token时，每次刷新都会让其它副本的token失效，引起InvalidToken重试风暴。
+//! 使用共享存储后，所有副本共用一个token：
+//! * `MemoryTokenStore` 默认实现，仅限单进程
+//! * `PgTokenStore` 保存到postgres
+//!
+//! 刷新时先用`try_lock`取得该key的锁，持有期间重新读取、请求token_url、写入，
+//! 同一时间只有一个副本刷新；没有取得锁的副本轮询存储，等待新的token，而不是自己请求。
+//! 写入仍用`compare_and_set`，锁意外丢失（例如数据库连接断开）时以存储里的为准
+//!
+//! ```rs
+//! let store = Arc::new(PgTokenStore::new(pool));
+//! let client = Client::new(cfg)?.with_token_store(store);
+//! ```
+use super::ClientResult;
+use async_std::sync::RwLock;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::FromRow;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct CachedToken {
+    pub access_token: String,
+    pub expired_at: DateTime<Utc>,
+}
+impl CachedToken {
+    pub fn new(access_token: String, ttl: Duration) -> CachedToken {
+        CachedToken {
+            access_token,
+            expired_at: Utc::now() + ttl,
+        }
+    }
+    /// 提前10秒视为过期
+    pub fn valid(&self) -> bool {
+        self.expired_at > Utc::now() + Duration::seconds(10)
+    }
+}
+
+// 存储里仍是刷新前的token（或已过期），可以替换
+fn replaceable(current: &CachedToken, old_token: &str) -> bool {
+    current.access_token == old_token || !current.valid()
+}
+
+/// `try_lock`取得的刷新锁，drop时释放
+pub trait TokenLock: Send + Sync {}
+
+#[async_trait]
+pub trait AccessTokenStore: Send + Sync {
+    async fn get(&self, key: &str) -> ClientResult<Option<CachedToken>>;
+    /// 取得`key`的刷新锁，不等待；已被其它副本持有时返回None
+    async fn try_lock(&self, key: &str) -> ClientResult<Option<Box<dyn TokenLock>>>;
+    /// 存储里仍是`old_token`（或为空、已过期）时写入`token`，
+    /// 否则说明其它副本已经刷新过，保持不变；返回存储里最终的token
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        old_token: &str,
+        token: CachedToken,
+    ) -> ClientResult<CachedToken>;
+}
+
+// 内存 实现
+#[derive(Default)]
+pub struct MemoryTokenStore {
+    tokens: RwLock<HashMap<String, CachedToken>>,
+    locked: Arc<Mutex<HashSet<String>>>,
+}
+
+struct MemoryTokenLock {
+    locked: Arc<Mutex<HashSet<String>>>,
+    key: String,
+}
+impl TokenLock for MemoryTokenLock {}
+impl Drop for MemoryTokenLock {
+    fn drop(&mut self) {
+        self.locked.lock().unwrap().remove(&self.key);
+    }
+}
+
+#[async_trait]
+impl AccessTokenStore for MemoryTokenStore {
+    async fn get(&self, key: &str) -> ClientResult<Option<CachedToken>> {
+        Ok(self.tokens.read().await.get(key).cloned())
+    }
+
+    async fn try_lock(&self, key: &str) -> ClientResult<Option<Box<dyn TokenLock>>> {
+        if !self.locked.lock().unwrap().insert(key.to_owned()) {
+            return Ok(None);
+        }
+        Ok(Some(Box::new(MemoryTokenLock {
+            locked: self.locked.clone(),
+            key: key.to_owned(),
+        })))
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        old_token: &str,
+        token: CachedToken,
+    ) -> ClientResult<CachedToken> {
+        let mut tokens = self.tokens.write().await;
+        match tokens.get(key) {
+            Some(current) if !replaceable(current, old_token) => Ok(current.clone()),
+            _ => {
+                tokens.insert(key.to_owned(), token.clone());
+                Ok(token)
+            }
+        }
+    }
+}
+
+// postgres 实现
+//
+// 表结构见migrations/2026-10-18-000002_create_api_access_tokens
+pub struct PgTokenStore {
+    pool: PgPool,
+}
+impl PgTokenStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn lock_key(key: &str) -> String {
+    format!("api_access_tokens:{}", key)
+}
+
+// 会话级的advisory lock，持有一个独占的连接直到释放（行可能还不存在，无法用行锁）
+struct PgTokenLock {
+    conn: Option<PoolConnection<Postgres>>,
+    lock_key: String,
+}
+impl TokenLock for PgTokenLock {}
+impl Drop for PgTokenLock {
+    fn drop(&mut self) {
+        // 连接归还连接池之前必须解锁；drop不能等待，交给后台任务
+        if let Some(mut conn) = self.conn.take() {
+            let lock_key = std::mem::take(&mut self.lock_key);
+            async_std::task::spawn(async move {
+                let unlocked = sqlx::query("SELECT pg_advisory_unlock(hashtext($1))")
+                    .bind(&lock_key)
+                    .execute(&mut *conn)
+                    .await;
+                if let Err(e) = unlocked {
+                    warn!("pg_advisory_unlock({}) failed: {}", lock_key, e);
+                }
+            });
+        }
+    }
+}
+
+mod queries {
+    use super::CachedToken;
+    use super::ClientResult;
+    use sqlx::{postgres::Postgres, Executor};
+
+    pub async fn find<'e, E>(executor: E, key: &str) -> ClientResult<Option<CachedToken>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, CachedToken>(
+            "SELECT access_token, expired_at FROM api_access_tokens WHERE key = $1",
+        )
+        .bind(key)
+        .fetch_optional(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    // 与`replaceable`一致：仍是old_token或即将过期时才覆盖
+    pub async fn replace<'e, E>(
+        executor: E,
+        key: &str,
+        old_token: &str,
+        token: &CachedToken,
+    ) -> ClientResult<()>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query(
+            "INSERT INTO api_access_tokens (key, access_token, expired_at, updated_at) \
+             VALUES ($1, $2, $3, now()) \
+             ON CONFLICT (key) DO UPDATE SET \
+             access_token = EXCLUDED.access_token, expired_at = EXCLUDED.expired_at, \
+             updated_at = EXCLUDED.updated_at \
+             WHERE api_access_tokens.access_token = $4 \
+             OR api_access_tokens.expired_at <= now() + interval '10 seconds'",
+        )
+        .bind(key)
+        .bind(&token.access_token)
+        .bind(token.expired_at)
+        .bind(old_token)
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AccessTokenStore for PgTokenStore {
+    async fn get(&self, key: &str) -> ClientResult<Option<CachedToken>> {
+        queries::find(&self.pool, key).await
+    }
+
+    async fn try_lock(&self, key: &str) -> ClientResult<Option<Box<dyn TokenLock>>> {
+        let lock_key = lock_key(key);
+        let mut conn = self.pool.acquire().await?;
+        let (locked,) = sqlx::query_as::<_, (bool,)>("SELECT pg_try_advisory_lock(hashtext($1))")
+            .bind(&lock_key)
+            .fetch_one(&mut *conn)
+            .await?;
+        if !locked {
+            return Ok(None);
+        }
+        Ok(Some(Box::new(PgTokenLock {
+            conn: Some(conn),
+            lock_key,
+        })))
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        old_token: &str,
+        token: CachedToken,
+    ) -> ClientResult<CachedToken> {
+        // 比较与写入是同一条语句（ON CONFLICT会锁住已有的行），
+        // 不能再用`try_lock`的advisory lock：持有锁的连接正等待这里写入
+        queries::replace(&self.pool, key, old_token, &token).await?;
+        let current = queries::find(&self.pool, key).await?;
+        Ok(current.unwrap_or(token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessTokenStore, CachedToken, MemoryTokenStore, PgTokenStore};
+    use crate::core::testing::TestResult;
+    use crate::db_connection::tests as db_tests;
+    use chrono::Duration;
+
+    async fn compare_and_set(store: &dyn AccessTokenStore, key: &str) -> TestResult<()> {
+        let token = |value: &str| CachedToken::new(value.to_owned(), Duration::seconds(7200));
+        assert_eq!(store.get(key).await?, None);
+
+        let first = store.compare_and_set(key, "", token("TOKEN_1")).await?;
+        assert_eq!(first.access_token, "TOKEN_1");
+        assert_eq!(store.get(key).await?, Some(first.clone()));
+
+        // 两个副本同时刷新TOKEN_1，后写入的使用先写入的token
+        let second = store
+            .compare_and_set(key, "TOKEN_1", token("TOKEN_2"))
+            .await?;
+        assert_eq!(second.access_token, "TOKEN_2");
+        let third = store
+            .compare_and_set(key, "TOKEN_1", token("TOKEN_3"))
+            .await?;
+        assert_eq!(third, second);
+        assert_eq!(store.get(key).await?, Some(second));
+
+        // 已过期的可以替换
+        let expired = CachedToken::new("EXPIRED".to_owned(), Duration::seconds(-1));
+        store.compare_and_set(key, "TOKEN_2", expired).await?;
+        let fourth = store.compare_and_set(key, "", token("TOKEN_4")).await?;
+        assert_eq!(fourth.access_token, "TOKEN_4");
+        Ok(())
+    }
+
+    async fn try_lock(store: &dyn AccessTokenStore, key: &str) -> TestResult<()> {
+        let lock = store.try_lock(key).await?;
+        assert!(lock.is_some());
+        // 其它副本取不到锁
+        assert!(store.try_lock(key).await?.is_none());
+        assert!(store.try_lock(&format!("{}:other", key)).await?.is_some());
+
+        // drop后释放（pg在后台任务里解锁）
+        drop(lock);
+        for _ in 0..50 {
+            if store.try_lock(key).await?.is_some() {
+                return Ok(());
+            }
+            async_std::task::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        Err("lock not released".into())
+    }
+
+    #[async_std::test]
+    async fn memory_store() -> TestResult<()> {
+        let store = MemoryTokenStore::default();
+        compare_and_set(&store, "test").await?;
+        try_lock(&store, "test").await
+    }
+
+    #[async_std::test]
+    #[ignore = "需要数据库"]
+    async fn pg_store() -> TestResult<()> {
+        let pool = db_tests::sqlx_pool().await;
+        let key = "test:pg_store";
+        sqlx::query("DELETE FROM api_access_tokens WHERE key = $1")
+            .bind(key)
+            .execute(&pool)
+            .await?;
+
+        let store = PgTokenStore::new(pool);
+        compare_and_set(&store, key).await?;
+        try_lock(&store, key).await
+    }
+}
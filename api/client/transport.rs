@@ -0,0 +1,300 @@
+//! HTTP 传输层
+//!
+//! `Client`只负责access_token、重试、错误识别，真正的网络请求交给`HttpTransport`：
+//! * `IsahcTransport` 默认实现，支持超时、连接池、代理（见`HttpConfig`）
+//! * `MockTransport` 按脚本回放响应，并记录收到的请求，用于离线测试
+//!
+//! ```rs
+//! let mock = Arc::new(MockTransport::new());
+//! mock.reply_json("/gettoken", json!({"access_token": "TOKEN", "expires_in": 7200}));
+//! let client = Client::with_transport(cfg, mock.clone());
+//! ```
+use super::{ClientError, ClientResult};
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncReadExt, Cursor};
+use http::Method;
+use isahc::config::Configurable;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// 传输层配置
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// 建立连接的超时时间
+    pub connect_timeout: Duration,
+    /// 单次请求（包括读取响应）的超时时间
+    pub timeout: Duration,
+    /// 代理，例如`http://127.0.0.1:8080`、`socks5://127.0.0.1:1080`
+    pub proxy: Option<String>,
+    /// 每个host的最大连接数，0为不限制
+    pub max_connections_per_host: usize,
+}
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(15),
+            proxy: None,
+            max_connections_per_host: 0,
+        }
+    }
+}
+
+// 请求
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+// 响应
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+pub type BodyStream = Box<dyn AsyncRead + Send + Unpin>;
+
+// 流式响应
+pub struct StreamResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: BodyStream,
+}
+
+#[async_trait]
+pub trait HttpTransport: Send + Sync {
+    async fn send(&self, request: HttpRequest) -> ClientResult<HttpResponse>;
+
+    /// 默认读取完整的body后再包装为流
+    async fn send_streaming(&self, request: HttpRequest) -> ClientResult<StreamResponse> {
+        let response = self.send(request).await?;
+        Ok(StreamResponse {
+            status: response.status,
+            headers: response.headers,
+            body: Box::new(Cursor::new(response.body)),
+        })
+    }
+}
+
+pub(super) fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+// 接口错误一般以json返回（例如请求图片时）
+pub(super) fn is_json(headers: &[(String, String)]) -> bool {
+    find_header(headers, "Content-Type").map_or(false, |t| t.contains("json"))
+}
+
+// isahc 实现
+//
+// 每个`IsahcTransport`持有一个连接池
+pub struct IsahcTransport {
+    client: isahc::HttpClient,
+}
+impl IsahcTransport {
+    pub fn new(cfg: &HttpConfig) -> ClientResult<Self> {
+        let mut builder = isahc::HttpClient::builder()
+            .connect_timeout(cfg.connect_timeout)
+            .timeout(cfg.timeout)
+            .max_connections_per_host(cfg.max_connections_per_host);
+        if let Some(proxy) = &cfg.proxy {
+            let uri = proxy
+                .parse::<http::Uri>()
+                .map_err(|e| ClientError::Other(format!("invalid proxy {}: {}", proxy, e)))?;
+            builder = builder.proxy(Some(uri));
+        }
+        let client = builder.build().map_err(ClientError::from_isahc)?;
+        Ok(Self { client })
+    }
+
+    async fn fetch(&self, request: HttpRequest) -> ClientResult<http::Response<isahc::Body>> {
+        let mut builder = http::Request::builder()
+            .method(request.method)
+            .uri(request.url.as_str());
+        for (name, value) in request.headers.iter() {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        let body = request
+            .body
+            .map_or_else(isahc::Body::empty, isahc::Body::from);
+        let request = builder
+            .body(body)
+            .map_err(|e| ClientError::Other(format!("invalid request: {}", e)))?;
+        self.client
+            .send_async(request)
+            .await
+            .map_err(ClientError::from_isahc)
+    }
+
+    fn headers<B>(response: &http::Response<B>) -> Vec<(String, String)> {
+        response
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                let value = value.to_str().unwrap_or_default();
+                (name.as_str().to_owned(), value.to_owned())
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl HttpTransport for IsahcTransport {
+    async fn send(&self, request: HttpRequest) -> ClientResult<HttpResponse> {
+        let mut response = self.fetch(request).await?;
+        let status = response.status().as_u16();
+        let headers = Self::headers(&response);
+        let mut body = vec![];
+        response.body_mut().read_to_end(&mut body).await?;
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    async fn send_streaming(&self, request: HttpRequest) -> ClientResult<StreamResponse> {
+        let response = self.fetch(request).await?;
+        Ok(StreamResponse {
+            status: response.status().as_u16(),
+            headers: Self::headers(&response),
+            body: Box::new(response.into_body()),
+        })
+    }
+}
+
+// 模拟 实现
+//
+// 按添加的顺序，取第一个url匹配的脚本响应（匹配后即移除）
+#[derive(Default)]
+pub struct MockTransport {
+    replies: Mutex<VecDeque<(String, MockReply)>>,
+    requests: Mutex<Vec<HttpRequest>>,
+}
+enum MockReply {
+    Response(HttpResponse),
+    Error(ClientError),
+}
+impl MockTransport {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// url包含`pattern`时回放该响应，`pattern`为空则匹配任意请求
+    pub fn reply(&self, pattern: &str, status: u16, body: impl Into<Vec<u8>>) -> &Self {
+        let response = HttpResponse {
+            status,
+            headers: vec![],
+            body: body.into(),
+        };
+        self.reply_with(pattern, response)
+    }
+
+    pub fn reply_with(&self, pattern: &str, response: HttpResponse) -> &Self {
+        self.push(pattern, MockReply::Response(response))
+    }
+
+    pub fn reply_json(&self, pattern: &str, body: serde_json::Value) -> &Self {
+        self.reply_bytes(pattern, "application/json", body.to_string())
+    }
+
+    pub fn reply_bytes(
+        &self,
+        pattern: &str,
+        content_type: &str,
+        body: impl Into<Vec<u8>>,
+    ) -> &Self {
+        let response = HttpResponse {
+            status: 200,
+            headers: vec![("Content-Type".to_owned(), content_type.to_owned())],
+            body: body.into(),
+        };
+        self.reply_with(pattern, response)
+    }
+
+    /// 模拟网络错误等
+    pub fn reply_error(&self, pattern: &str, error: ClientError) -> &Self {
+        self.push(pattern, MockReply::Error(error))
+    }
+
+    /// 已收到的请求
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// 剩余未被使用的脚本响应数量
+    pub fn pending(&self) -> usize {
+        self.replies.lock().unwrap().len()
+    }
+
+    fn push(&self, pattern: &str, reply: MockReply) -> &Self {
+        let mut replies = self.replies.lock().unwrap();
+        replies.push_back((pattern.to_owned(), reply));
+        self
+    }
+}
+
+#[async_trait]
+impl HttpTransport for MockTransport {
+    async fn send(&self, request: HttpRequest) -> ClientResult<HttpResponse> {
+        let url = request.url.clone();
+        self.requests.lock().unwrap().push(request);
+
+        let mut replies = self.replies.lock().unwrap();
+        let index = replies
+            .iter()
+            .position(|(pattern, _)| url.contains(pattern.as_str()));
+        match index.and_then(|i| replies.remove(i)) {
+            Some((_, MockReply::Response(response))) => Ok(response),
+            Some((_, MockReply::Error(error))) => Err(error),
+            None => Err(ClientError::Other(format!("mock: no reply for {}", url))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpConfig, IsahcTransport};
+    use std::time::Duration;
+
+    #[test]
+    fn http_config() {
+        let cfg: HttpConfig =
+            serde_json::from_str(r#"{"timeout": {"secs": 3, "nanos": 0}}"#).unwrap();
+        assert_eq!(cfg.timeout, Duration::from_secs(3));
+        assert_eq!(cfg.connect_timeout, HttpConfig::default().connect_timeout);
+
+        let cfg = HttpConfig {
+            proxy: Some("socks5://127.0.0.1:1080".to_owned()),
+            ..Default::default()
+        };
+        assert!(IsahcTransport::new(&cfg).is_ok());
+
+        let cfg = HttpConfig {
+            proxy: Some("not a proxy".to_owned()),
+            ..Default::default()
+        };
+        assert!(IsahcTransport::new(&cfg).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! 推送的事件
+//!
+//! 解密后为json，字段名保持钉钉的写法（通讯录事件为`UserId`，审批事件为`processInstanceId`），
+//! 常用的事件解析为`CallbackEvent`，其它的可以用`CallbackMessage::parse`自行解析
+use super::super::super::wechat_push::de;
+use super::{CallbackError, CallbackResult};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallbackMessage {
+    /// 例如user_add_org，分发用
+    pub event_type: String,
+    /// 审批事件没有时为空
+    pub corp_id: String,
+    pub event: CallbackEvent,
+    /// 解密后的原始内容
+    pub raw: Value,
+}
+
+impl CallbackMessage {
+    pub(super) fn from_json(plain: &str) -> CallbackResult<Self> {
+        #[derive(Deserialize)]
+        struct Header {
+            #[serde(rename = "EventType")]
+            event_type: String,
+            // 通讯录事件为CorpId，审批事件为corpId
+            #[serde(rename = "CorpId", alias = "corpId", default)]
+            corp_id: String,
+        }
+
+        let raw: Value =
+            serde_json::from_str(plain).map_err(|e| CallbackError::Parse(e.to_string()))?;
+        let header: Header =
+            serde_json::from_value(raw.clone()).map_err(|e| CallbackError::Parse(e.to_string()))?;
+        let event = CallbackEvent::from_value(&header.event_type, &raw)?;
+        Ok(Self {
+            event_type: header.event_type,
+            corp_id: header.corp_id,
+            event,
+            raw,
+        })
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self) -> CallbackResult<T> {
+        serde_json::from_value(self.raw.clone()).map_err(|e| CallbackError::Parse(e.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallbackEvent {
+    /// 保存请求网址时的验证
+    CheckUrl,
+    /// 通讯录：员工入职
+    UserAddOrg(UserChange),
+    /// 通讯录：员工资料变更
+    UserModifyOrg(UserChange),
+    /// 通讯录：员工离职
+    UserLeaveOrg(UserChange),
+    /// 通讯录：员工激活钉钉
+    UserActiveOrg(UserChange),
+    /// 通讯录：部门创建
+    DeptCreate(DeptChange),
+    /// 通讯录：部门修改
+    DeptModify(DeptChange),
+    /// 通讯录：部门删除
+    DeptRemove(DeptChange),
+    /// 审批实例开始、结束、撤销
+    BpmsInstanceChange(BpmsInstance),
+    /// 审批任务开始、结束、转交
+    BpmsTaskChange(BpmsTask),
+    /// 考勤打卡
+    AttendanceCheckRecord(Vec<CheckRecord>),
+    /// 签到
+    CheckIn(CheckIn),
+    /// 其它事件，见`CallbackMessage::raw`
+    Other,
+}
+
+impl CallbackEvent {
+    fn from_value(event_type: &str, raw: &Value) -> CallbackResult<Self> {
+        fn parse<T: DeserializeOwned>(raw: &Value) -> CallbackResult<T> {
+            serde_json::from_value(raw.clone()).map_err(|e| CallbackError::Parse(e.to_string()))
+        }
+
+        Ok(match event_type {
+            "check_url" => CallbackEvent::CheckUrl,
+            "user_add_org" => CallbackEvent::UserAddOrg(parse(raw)?),
+            "user_modify_org" => CallbackEvent::UserModifyOrg(parse(raw)?),
+            "user_leave_org" => CallbackEvent::UserLeaveOrg(parse(raw)?),
+            "user_active_org" => CallbackEvent::UserActiveOrg(parse(raw)?),
+            "org_dept_create" => CallbackEvent::DeptCreate(parse(raw)?),
+            "org_dept_modify" => CallbackEvent::DeptModify(parse(raw)?),
+            "org_dept_remove" => CallbackEvent::DeptRemove(parse(raw)?),
+            "bpms_instance_change" => CallbackEvent::BpmsInstanceChange(parse(raw)?),
+            "bpms_task_change" => CallbackEvent::BpmsTaskChange(parse(raw)?),
+            "attendance_check_record" => {
+                let list = raw.get("DataList").cloned().unwrap_or_default();
+                CallbackEvent::AttendanceCheckRecord(
+                    de::into_vec(list).map_err(|e| CallbackError::Parse(e.to_string()))?,
+                )
+            }
+            "check_in" => CallbackEvent::CheckIn(parse(raw)?),
+            _ => CallbackEvent::Other,
+        })
+    }
+}
+
+/// 通讯录的员工变更，一次推送可以包含多人
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UserChange {
+    #[serde(rename = "UserId", default, deserialize_with = "de::one_or_many")]
+    pub user_ids: Vec<String>,
+    /// 毫秒
+    #[serde(rename = "TimeStamp", default, deserialize_with = "de::opt_int")]
+    pub time_stamp: Option<i64>,
+}
+
+/// 通讯录的部门变更
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeptChange {
+    #[serde(rename = "DeptId", default, deserialize_with = "de::one_or_many")]
+    pub dept_ids: Vec<i64>,
+    #[serde(rename = "TimeStamp", default, deserialize_with = "de::opt_int")]
+    pub time_stamp: Option<i64>,
+}
+
+/// 审批实例
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BpmsInstance {
+    pub process_instance_id: String,
+    /// 审批模板
+    #[serde(default)]
+    pub process_code: String,
+    #[serde(default)]
+    pub title: String,
+    /// start开始、finish结束、terminate撤销
+    #[serde(rename = "type")]
+    pub change_type: String,
+    /// 结束时的结果：agree同意、refuse拒绝
+    pub result: Option<String>,
+    /// 发起人的userid
+    #[serde(default)]
+    pub staff_id: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default, deserialize_with = "de::opt_int")]
+    pub create_time: Option<i64>,
+    #[serde(default, deserialize_with = "de::opt_int")]
+    pub finish_time: Option<i64>,
+}
+impl BpmsInstance {
+    /// 审批结束并且同意
+    pub fn is_approved(&self) -> bool {
+        self.change_type == "finish" && self.result.as_deref() == Some("agree")
+    }
+}
+
+/// 审批任务（某个审批人的节点）
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BpmsTask {
+    pub process_instance_id: String,
+    #[serde(default)]
+    pub process_code: String,
+    #[serde(default)]
+    pub title: String,
+    /// start开始、finish结束、cancel转交
+    #[serde(rename = "type")]
+    pub change_type: String,
+    /// 结束时的结果：agree同意、refuse拒绝、redirect转交
+    pub result: Option<String>,
+    /// 审批人的userid
+    #[serde(default)]
+    pub staff_id: String,
+    /// 审批意见
+    pub remark: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_int")]
+    pub create_time: Option<i64>,
+    #[serde(default, deserialize_with = "de::opt_int")]
+    pub finish_time: Option<i64>,
+}
+
+/// 考勤打卡记录
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckRecord {
+    pub user_id: String,
+    /// 毫秒
+    #[serde(deserialize_with = "de::int")]
+    pub check_time: i64,
+    #[serde(default)]
+    pub address: String,
+    /// 打卡方式，例如ATM（考勤机）、MAP（定位）
+    pub location_method: Option<String>,
+    pub biz_id: Option<String>,
+}
+
+/// 签到
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CheckIn {
+    #[serde(rename = "StaffId")]
+    pub staff_id: String,
+    #[serde(rename = "TimeStamp", default, deserialize_with = "de::opt_int")]
+    pub time_stamp: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CallbackEvent, CallbackMessage};
+    use crate::core::testing::TestResult;
+    use serde_json::json;
+
+    #[test]
+    fn parse_events() -> TestResult<()> {
+        let task = json!({
+            "EventType": "bpms_task_change",
+            "processInstanceId": "ad253df6-e175caf-68085c60ba8a",
+            "corpId": "dingcorp",
+            "createTime": 1700000000000i64,
+            "title": "张三提交的请假",
+            "type": "start",
+            "staffId": "lisi",
+            "processCode": "PROC-EF6Y4J2N"
+        });
+        let message = CallbackMessage::from_json(&task.to_string())?;
+        match &message.event {
+            CallbackEvent::BpmsTaskChange(task) => {
+                assert_eq!(task.staff_id, "lisi");
+                assert_eq!(task.result, None);
+                assert_eq!(task.create_time, Some(1700000000000));
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+
+        let records = json!({
+            "EventType": "attendance_check_record",
+            "CorpId": "dingcorp",
+            "DataList": [{
+                "address": "杭州市余杭区",
+                "bizId": "12345",
+                "checkTime": 1700000000000i64,
+                "corpId": "dingcorp",
+                "locationMethod": "MAP",
+                "userId": "zhangsan"
+            }]
+        });
+        let message = CallbackMessage::from_json(&records.to_string())?;
+        match &message.event {
+            CallbackEvent::AttendanceCheckRecord(records) => {
+                assert_eq!(records[0].user_id, "zhangsan");
+                assert_eq!(records[0].location_method.as_deref(), Some("MAP"));
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+
+        let dept =
+            json!({"EventType": "org_dept_create", "DeptId": [3, 4], "TimeStamp": "1700000000000"});
+        let message = CallbackMessage::from_json(&dept.to_string())?;
+        assert!(matches!(&message.event, CallbackEvent::DeptCreate(d) if d.dept_ids == vec![3, 4]));
+
+        let other = json!({"EventType": "label_user_change", "CorpId": "dingcorp"});
+        let message = CallbackMessage::from_json(&other.to_string())?;
+        assert_eq!(message.event, CallbackEvent::Other);
+        assert_eq!(message.corp_id, "dingcorp");
+
+        assert!(CallbackMessage::from_json("{}").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+/// 集成到tide
+///
+/// 处理成功返回加密的`success`（json），否则钉钉会重试
+///
+/// ```rs
+/// use crate::core::api::dingtalk::callback::integrate_with_tide::CallbackEndpoint;
+/// app.at("/dingtalk/callback").post(CallbackEndpoint::new(receiver));
+/// ```
+use super::{CallbackError, CallbackQuery, CallbackReceiver};
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use tide::{Request, Response, StatusCode};
+
+#[derive(Clone)]
+pub struct CallbackEndpoint {
+    receiver: Arc<CallbackReceiver>,
+}
+impl CallbackEndpoint {
+    pub fn new(receiver: CallbackReceiver) -> Self {
+        Self {
+            receiver: Arc::new(receiver),
+        }
+    }
+}
+
+impl<State: Send + Sync + 'static> tide::Endpoint<State> for CallbackEndpoint {
+    fn call<'a>(&'a self, mut req: Request<State>) -> BoxFuture<'a, tide::Result> {
+        Box::pin(async move {
+            let query: CallbackQuery = req.query()?;
+            let bytes = req.body_bytes().await?;
+            let reply = self
+                .receiver
+                .receive(&query, &bytes)
+                .await
+                .map_err(into_tide_error)?;
+
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(serde_json::to_value(&reply)?);
+            Ok(res)
+        })
+    }
+}
+
+/// 签名错误为401，内容错误为400，配置与handler错误为500（钉钉会重试）
+pub fn into_tide_error(e: CallbackError) -> tide::Error {
+    let status = match e {
+        CallbackError::Signature | CallbackError::Expired(_) => StatusCode::Unauthorized,
+        CallbackError::Decrypt(_) | CallbackError::AppKey(_) | CallbackError::Parse(_) => {
+            StatusCode::BadRequest
+        }
+        CallbackError::Config(_) | CallbackError::Handler(_) => StatusCode::InternalServerError,
+    };
+    warn!("dingtalk callback rejected: {}", e);
+    tide::Error::from_str(status, e.to_string())
+}
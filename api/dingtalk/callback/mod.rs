@@ -0,0 +1,423 @@
+//! 事件订阅（HTTP推送）
+//!
+//! 通讯录变更、审批、考勤等事件由钉钉加密后推送到开发者后台配置的请求网址：
+//! * 验证`msg_signature`/`timestamp`/`nonce`，用aes_key解密`{"encrypt": ".."}`
+//! * 加解密与微信的安全模式相同（见`wechat_push::Cipher`），receive_id为应用的AppKey
+//! * 解析为`CallbackMessage`，按`EventType`分发给注册的handler
+//! * 处理成功后返回加密的`success`，否则钉钉会重试
+//!
+//! ```rs
+//! use crate::core::api::dingtalk::callback::{integrate_with_tide::CallbackEndpoint, CallbackReceiver};
+//! let receiver = CallbackReceiver::new(&dingtalk::Config::from_env())?
+//!     .on("user_leave_org", Arc::new(LeaveHandler::new(repos.clone())))
+//!     .on("bpms_instance_change", Arc::new(ApprovalHandler::new(db.clone())));
+//! app.at("/dingtalk/callback").post(CallbackEndpoint::new(receiver));
+//! ```
+use super::super::client::redact;
+use super::super::wechat_push::{signature, Cipher, PushError};
+use super::Config;
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use thiserror::Error as ThisError;
+
+mod event;
+pub mod integrate_with_tide;
+
+pub use event::{
+    BpmsInstance, BpmsTask, CallbackEvent, CallbackMessage, CheckIn, CheckRecord, DeptChange,
+    UserChange,
+};
+
+// timestamp（毫秒）与服务器时间相差超过5分钟的推送视为重放
+const MAX_CLOCK_SKEW_MILLIS: i64 = 300_000;
+
+#[derive(ThisError, Debug)]
+pub enum CallbackError {
+    #[error("Callback Config Error: {0}")]
+    Config(String),
+    #[error("Callback Signature Mismatch")]
+    Signature,
+    #[error("Callback Timestamp Expired: {0}")]
+    Expired(String),
+    #[error("Callback Decrypt Error: {0}")]
+    Decrypt(String),
+    #[error("Callback AppKey Mismatch: {0}")]
+    AppKey(String),
+    #[error("Callback Parse Error: {0}")]
+    Parse(String),
+    #[error("Callback Handler Error: {0}")]
+    Handler(Box<dyn std::error::Error + Send + Sync>),
+}
+pub type CallbackResult<T> = Result<T, CallbackError>;
+
+/// 推送的url参数
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct CallbackQuery {
+    pub msg_signature: String,
+    /// 与`msg_signature`相同，旧版的参数名
+    pub signature: String,
+    /// 毫秒
+    pub timestamp: String,
+    pub nonce: String,
+}
+
+/// 响应内容，`encrypt`为加密后的`success`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CallbackReply {
+    pub msg_signature: String,
+    #[serde(rename = "timeStamp")]
+    pub time_stamp: String,
+    pub nonce: String,
+    pub encrypt: String,
+}
+
+pub type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+/// 返回错误时响应500，钉钉会重试，handler须能处理重复的推送
+#[async_trait]
+pub trait CallbackHandler: Send + Sync {
+    async fn handle(&self, message: &CallbackMessage) -> HandlerResult;
+}
+
+pub struct CallbackReceiver {
+    token: String,
+    cipher: Cipher,
+    handlers: HashMap<String, Arc<dyn CallbackHandler>>,
+    fallback: Option<Arc<dyn CallbackHandler>>,
+}
+
+impl CallbackReceiver {
+    /// 须配置`callback_token`与`callback_aes_key`（43位）
+    pub fn new(cfg: &Config) -> CallbackResult<Self> {
+        let token = cfg.callback_token.clone().ok_or_else(|| {
+            CallbackError::Config("DINGTALK_CALLBACK_TOKEN is missing!".to_owned())
+        })?;
+        let aes_key = cfg.callback_aes_key.as_deref().ok_or_else(|| {
+            CallbackError::Config("DINGTALK_CALLBACK_AES_KEY is missing!".to_owned())
+        })?;
+        let cipher = Cipher::new(aes_key, &cfg.app_key).map_err(|_| {
+            CallbackError::Config("DINGTALK_CALLBACK_AES_KEY must be 43 chars base64".to_owned())
+        })?;
+        Ok(Self {
+            token,
+            cipher,
+            handlers: HashMap::new(),
+            fallback: None,
+        })
+    }
+
+    /// `event_type`为推送的`EventType`，例如user_add_org、bpms_instance_change
+    pub fn on(mut self, event_type: &str, handler: Arc<dyn CallbackHandler>) -> Self {
+        self.handlers.insert(event_type.to_owned(), handler);
+        self
+    }
+
+    /// 没有注册handler的推送
+    pub fn fallback(mut self, handler: Arc<dyn CallbackHandler>) -> Self {
+        self.fallback = Some(handler);
+        self
+    }
+
+    /// 验证签名、解密并解析
+    pub fn parse(&self, query: &CallbackQuery, body: &[u8]) -> CallbackResult<CallbackMessage> {
+        #[derive(Deserialize)]
+        struct Body {
+            encrypt: String,
+        }
+        let body: Body =
+            serde_json::from_slice(body).map_err(|e| CallbackError::Parse(e.to_string()))?;
+
+        self.verify(query, &body.encrypt)?;
+        let plain = self.cipher.decrypt(&body.encrypt).map_err(|e| match e {
+            PushError::AppId(app_key) => CallbackError::AppKey(app_key),
+            e => CallbackError::Decrypt(e.to_string()),
+        })?;
+        debug!("dingtalk callback <= {}", redact::body(&plain));
+        CallbackMessage::from_json(&plain)
+    }
+
+    /// 处理一条推送，返回加密的`success`；没有对应的handler时忽略
+    pub async fn receive(
+        &self,
+        query: &CallbackQuery,
+        body: &[u8],
+    ) -> CallbackResult<CallbackReply> {
+        let message = self.parse(query, body)?;
+        // 保存请求网址时的验证推送，不需要分发
+        if message.event == CallbackEvent::CheckUrl {
+            return self.reply();
+        }
+
+        let handler = self
+            .handlers
+            .get(&message.event_type)
+            .or_else(|| self.fallback.as_ref());
+        match handler {
+            Some(handler) => handler
+                .handle(&message)
+                .await
+                .map_err(CallbackError::Handler)?,
+            None => debug!("dingtalk callback ignored: {}", message.event_type),
+        }
+        self.reply()
+    }
+
+    /// 加密的`success`，告知钉钉已处理
+    pub fn reply(&self) -> CallbackResult<CallbackReply> {
+        let encrypt = self
+            .cipher
+            .encrypt("success")
+            .map_err(|e| CallbackError::Decrypt(e.to_string()))?;
+        let time_stamp = Utc::now().timestamp_millis().to_string();
+        let nonce = format!("{:016x}", rand::random::<u64>());
+        Ok(CallbackReply {
+            msg_signature: signature(&self.token, &time_stamp, &nonce, Some(&encrypt)),
+            time_stamp,
+            nonce,
+            encrypt,
+        })
+    }
+
+    fn verify(&self, query: &CallbackQuery, encrypt: &str) -> CallbackResult<()> {
+        let expected = if query.msg_signature.is_empty() {
+            &query.signature
+        } else {
+            &query.msg_signature
+        };
+        let actual = signature(&self.token, &query.timestamp, &query.nonce, Some(encrypt));
+        if !bool::from(actual.as_bytes().ct_eq(expected.as_bytes())) {
+            return Err(CallbackError::Signature);
+        }
+        let timestamp: i64 = query
+            .timestamp
+            .parse()
+            .map_err(|_| CallbackError::Expired(query.timestamp.clone()))?;
+        if (Utc::now().timestamp_millis() - timestamp).abs() > MAX_CLOCK_SKEW_MILLIS {
+            return Err(CallbackError::Expired(query.timestamp.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::wechat_push::{signature, Cipher};
+    use super::super::Config;
+    use super::{
+        CallbackError, CallbackEvent, CallbackHandler, CallbackMessage, CallbackQuery,
+        CallbackReceiver, CallbackResult, HandlerResult,
+    };
+    use crate::core::testing::TestResult;
+    use async_std::sync::Mutex;
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    const TOKEN: &str = "TOKEN";
+    const AES_KEY: &str = "abcdefghijklmnopqrstuvwxyz0123456789ABCDEFG";
+    const APP_KEY: &str = "dingxxxxxxxxxxxxxxxx";
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    fn config() -> Config {
+        Config {
+            app_key: APP_KEY.to_owned(),
+            callback_token: Some(TOKEN.to_owned()),
+            callback_aes_key: Some(AES_KEY.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn receiver() -> CallbackResult<CallbackReceiver> {
+        CallbackReceiver::new(&config())
+    }
+
+    // 模拟钉钉加密一条推送
+    fn push(plain: &serde_json::Value, app_key: &str) -> TestResult<(CallbackQuery, Vec<u8>)> {
+        let encrypt = Cipher::new(AES_KEY, app_key)?.encrypt(&plain.to_string())?;
+        let timestamp = chrono::Utc::now().timestamp_millis().to_string();
+        let nonce = "nEXhMP4r".to_owned();
+        let query = CallbackQuery {
+            msg_signature: signature(TOKEN, &timestamp, &nonce, Some(&encrypt)),
+            signature: signature(TOKEN, &timestamp, &nonce, Some(&encrypt)),
+            timestamp,
+            nonce,
+        };
+        Ok((
+            query,
+            json!({ "encrypt": encrypt }).to_string().into_bytes(),
+        ))
+    }
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<CallbackMessage>>);
+    #[async_trait]
+    impl CallbackHandler for Recorder {
+        async fn handle(&self, message: &CallbackMessage) -> HandlerResult {
+            self.0.lock().await.push(message.clone());
+            Ok(())
+        }
+    }
+
+    struct Failing;
+    #[async_trait]
+    impl CallbackHandler for Failing {
+        async fn handle(&self, _: &CallbackMessage) -> HandlerResult {
+            Err("database is down".into())
+        }
+    }
+
+    #[async_std::test]
+    async fn receive_events() -> TestResult<()> {
+        setup();
+
+        let recorder = Arc::new(Recorder::default());
+        let receiver = receiver()?
+            .on("user_leave_org", recorder.clone())
+            .on("bpms_instance_change", recorder.clone())
+            .on("org_dept_remove", Arc::new(Failing));
+
+        // 保存请求网址时的验证
+        let (query, body) = push(&json!({"EventType": "check_url"}), APP_KEY)?;
+        let reply = receiver.receive(&query, &body).await?;
+        let cipher = Cipher::new(AES_KEY, APP_KEY)?;
+        assert_eq!(cipher.decrypt(&reply.encrypt)?, "success");
+        assert_eq!(
+            reply.msg_signature,
+            signature(TOKEN, &reply.time_stamp, &reply.nonce, Some(&reply.encrypt))
+        );
+
+        let leave = json!({
+            "EventType": "user_leave_org",
+            "TimeStamp": "1700000000000",
+            "UserId": ["manager7140"],
+            "CorpId": "dingcorp"
+        });
+        let (query, body) = push(&leave, APP_KEY)?;
+        receiver.receive(&query, &body).await?;
+
+        let approval = json!({
+            "EventType": "bpms_instance_change",
+            "processInstanceId": "ad253df6-e175caf-68085c60ba8a",
+            "corpId": "dingcorp",
+            "createTime": 1700000000000i64,
+            "finishTime": 1700000360000i64,
+            "title": "张三提交的请假",
+            "type": "finish",
+            "result": "agree",
+            "staffId": "manager7140",
+            "url": "https://aflow.dingtalk.com/dingtalk/mobile/homepage.htm",
+            "processCode": "PROC-EF6Y4J2N"
+        });
+        let (query, body) = push(&approval, APP_KEY)?;
+        receiver.receive(&query, &body).await?;
+
+        // 没有handler的忽略
+        let (query, body) = push(&json!({"EventType": "label_user_change"}), APP_KEY)?;
+        receiver.receive(&query, &body).await?;
+
+        // handler出错时钉钉会重试
+        let (query, body) = push(
+            &json!({"EventType": "org_dept_remove", "DeptId": [2]}),
+            APP_KEY,
+        )?;
+        assert!(matches!(
+            receiver.receive(&query, &body).await,
+            Err(CallbackError::Handler(_))
+        ));
+
+        let messages = recorder.0.lock().await;
+        assert_eq!(messages.len(), 2);
+        match &messages[0].event {
+            CallbackEvent::UserLeaveOrg(change) => {
+                assert_eq!(change.user_ids, vec!["manager7140"]);
+                assert_eq!(change.time_stamp, Some(1700000000000));
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+        assert_eq!(messages[1].corp_id, "dingcorp");
+        assert!(
+            matches!(&messages[1].event, CallbackEvent::BpmsInstanceChange(i) if i.is_approved())
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn reject_forged() -> TestResult<()> {
+        setup();
+
+        let receiver = receiver()?;
+        let event = json!({"EventType": "user_add_org", "UserId": ["zhangsan"]});
+
+        let (mut query, body) = push(&event, APP_KEY)?;
+        query.msg_signature = "0".repeat(40);
+        assert!(matches!(
+            receiver.receive(&query, &body).await,
+            Err(CallbackError::Signature)
+        ));
+
+        // 旧版只有signature参数
+        let (mut query, body) = push(&event, APP_KEY)?;
+        query.msg_signature = String::new();
+        receiver.receive(&query, &body).await?;
+
+        let (query, _) = push(&event, APP_KEY)?;
+        let mut expired = query.clone();
+        expired.timestamp = "1700000000000".to_owned();
+        let encrypt = Cipher::new(AES_KEY, APP_KEY)?.encrypt(&event.to_string())?;
+        expired.msg_signature =
+            signature(TOKEN, &expired.timestamp, &expired.nonce, Some(&encrypt));
+        let body = json!({ "encrypt": encrypt }).to_string();
+        assert!(matches!(
+            receiver.receive(&expired, body.as_bytes()).await,
+            Err(CallbackError::Expired(_))
+        ));
+
+        // 其它应用的推送
+        let (query, body) = push(&event, "dingotherapp")?;
+        assert!(matches!(
+            receiver.receive(&query, &body).await,
+            Err(CallbackError::AppKey(key)) if key == "dingotherapp"
+        ));
+
+        assert!(matches!(
+            receiver.receive(&query, b"not json").await,
+            Err(CallbackError::Parse(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_config() {
+        let mut cfg = config();
+        cfg.callback_token = None;
+        assert!(matches!(
+            CallbackReceiver::new(&cfg),
+            Err(CallbackError::Config(_))
+        ));
+
+        let mut cfg = config();
+        cfg.callback_aes_key = None;
+        assert!(matches!(
+            CallbackReceiver::new(&cfg),
+            Err(CallbackError::Config(_))
+        ));
+
+        let mut cfg = config();
+        cfg.callback_aes_key = Some("too short".to_owned());
+        assert!(matches!(
+            CallbackReceiver::new(&cfg),
+            Err(CallbackError::Config(_))
+        ));
+
+        assert!(receiver().is_ok());
+    }
+}
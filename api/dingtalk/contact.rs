@@ -0,0 +1,155 @@
+//! 通讯录读取，用于同步组织架构（见`dingtalk::sync`）
+use super::super::client::{redact, ClientResult, Endpoint};
+use super::Dingtalk;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 根部门的id
+pub const ROOT_DEPARTMENT_ID: i64 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Department {
+    pub id: i64,
+    #[serde(default)]
+    pub name: String,
+    /// 根部门没有
+    pub parentid: Option<i64>,
+}
+
+/// 部门成员（`user/listbypage`），比`UserInfo`少了角色等字段
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DepartmentMember {
+    pub userid: String,
+    pub unionid: String,
+    pub name: String,
+    /// 没有通讯录手机号权限时为空
+    #[serde(default)]
+    pub mobile: String,
+    #[serde(default)]
+    pub avatar: String,
+    #[serde(default)]
+    pub position: String,
+    #[serde(default)]
+    pub jobnumber: String,
+    /// 是否已激活钉钉
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub department: Vec<i64>,
+}
+// 手机号脱敏
+impl fmt::Debug for DepartmentMember {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DepartmentMember")
+            .field("userid", &self.userid)
+            .field("unionid", &self.unionid)
+            .field("name", &self.name)
+            .field("mobile", &redact::phone(&self.mobile))
+            .field("avatar", &self.avatar)
+            .field("position", &self.position)
+            .field("jobnumber", &self.jobnumber)
+            .field("active", &self.active)
+            .field("department", &self.department)
+            .finish()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberPage {
+    #[serde(default)]
+    pub has_more: bool,
+    #[serde(default)]
+    pub userlist: Vec<DepartmentMember>,
+}
+
+impl Dingtalk {
+    /// 子部门列表，`fetch_child`为true时递归返回全部下级部门（不含`id`本身）
+    pub async fn department_list(
+        &self,
+        id: i64,
+        fetch_child: bool,
+    ) -> ClientResult<Vec<Department>> {
+        let endpoint = Endpoint::new("https://oapi.dingtalk.com/department/list")
+            .access_token()
+            .query("id", id)
+            .query("fetch_child", fetch_child);
+
+        #[derive(Deserialize)]
+        struct ApiResult {
+            #[serde(default)]
+            department: Vec<Department>,
+        }
+        let resp: ApiResult = self.client.get(endpoint).await?;
+        Ok(resp.department)
+    }
+
+    /// 部门的直属成员，分页获取，`size`最大100
+    pub async fn department_members(
+        &self,
+        department_id: i64,
+        offset: u32,
+        size: u32,
+    ) -> ClientResult<MemberPage> {
+        let endpoint = Endpoint::new("https://oapi.dingtalk.com/user/listbypage")
+            .access_token()
+            .query("department_id", department_id)
+            .query("offset", offset)
+            .query("size", size);
+        self.client.get(endpoint).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock_dingtalk;
+    use crate::core::testing::TestResult;
+    use serde_json::json;
+
+    #[async_std::test]
+    async fn department_members_offline() -> TestResult<()> {
+        let (dd, mock) = mock_dingtalk();
+        mock.reply_json(
+            "/department/list",
+            json!({
+                "errcode": 0,
+                "errmsg": "ok",
+                "department": [{"id": 2, "name": "研发部", "parentid": 1, "createDeptGroup": true}]
+            }),
+        )
+        .reply_json(
+            "/user/listbypage",
+            json!({
+                "errcode": 0,
+                "errmsg": "ok",
+                "hasMore": true,
+                "userlist": [{
+                    "userid": "manager7140",
+                    "unionid": "PiiiPyQqBNBii0HnCJ3zljcuAiEiE",
+                    "name": "张三",
+                    "mobile": "13800000000",
+                    "active": true,
+                    "department": [1, 2],
+                    "isAdmin": true
+                }]
+            }),
+        );
+
+        let departments = dd.department_list(1, true).await?;
+        assert_eq!(departments[0].parentid, Some(1));
+        let page = dd.department_members(2, 0, 100).await?;
+        assert!(page.has_more);
+        assert_eq!(page.userlist[0].department, vec![1, 2]);
+        assert!(!format!("{:?}", page).contains("13800000000"));
+
+        let requests = mock.requests();
+        assert!(requests[1]
+            .url
+            .ends_with("access_token=TOKEN&id=1&fetch_child=true"));
+        assert!(requests[2]
+            .url
+            .ends_with("access_token=TOKEN&department_id=2&offset=0&size=100"));
+        Ok(())
+    }
+}
@@ -0,0 +1,479 @@
+//! 工作通知
+//!
+//! 以微应用（`Config.agent_id`）的身份发给员工，异步发送，返回task_id，
+//! 之后可以用`send_progress`/`send_result`查询进度与结果
+//!
+//! ```rs
+//! let message = Message::oa(
+//!     Oa::new("https://h5.example.com/approval?id=1", "审批")
+//!         .title("请假申请")
+//!         .field("申请人：", "张三")
+//!         .field("天数：", "3"),
+//! );
+//! let task_id = dd.send_work_notification(&Recipients::users(&["manager7140"]), &message).await?;
+//! ```
+use super::super::client::{ClientResult, Endpoint};
+use super::Dingtalk;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Text {
+    pub content: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Markdown {
+    /// 会话列表里显示的标题
+    pub title: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Link {
+    pub title: String,
+    pub text: String,
+    pub message_url: String,
+    /// 可以是图片的media_id
+    pub pic_url: String,
+}
+
+/// 卡片消息，`single_*`（整体跳转）与`btn_json_list`（独立跳转）二选一
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ActionCard {
+    pub title: String,
+    pub markdown: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub single_title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub single_url: Option<String>,
+    /// "0"竖直排列 "1"横向排列
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub btn_orientation: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub btn_json_list: Vec<ActionButton>,
+}
+impl ActionCard {
+    /// 整体跳转
+    pub fn single(title: &str, markdown: &str, single_title: &str, single_url: &str) -> Self {
+        Self {
+            title: title.to_owned(),
+            markdown: markdown.to_owned(),
+            single_title: Some(single_title.to_owned()),
+            single_url: Some(single_url.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    /// 独立跳转，按钮用`button`添加
+    pub fn buttons(title: &str, markdown: &str) -> Self {
+        Self {
+            title: title.to_owned(),
+            markdown: markdown.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn button(mut self, title: &str, action_url: &str) -> Self {
+        self.btn_json_list.push(ActionButton {
+            title: title.to_owned(),
+            action_url: action_url.to_owned(),
+        });
+        self
+    }
+
+    pub fn horizontal(mut self) -> Self {
+        self.btn_orientation = Some("1".to_owned());
+        self
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ActionButton {
+    pub title: String,
+    pub action_url: String,
+}
+
+/// OA消息，适合审批、待办等带表单的通知
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Oa {
+    pub message_url: String,
+    /// PC端打开的地址，不设置时使用`message_url`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pc_message_url: Option<String>,
+    pub head: OaHead,
+    pub body: OaBody,
+}
+impl Oa {
+    pub fn new(message_url: &str, head_text: &str) -> Self {
+        Self {
+            message_url: message_url.to_owned(),
+            head: OaHead {
+                bgcolor: "FFBBBBBB".to_owned(),
+                text: head_text.to_owned(),
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn pc_message_url(mut self, url: &str) -> Self {
+        self.pc_message_url = Some(url.to_owned());
+        self
+    }
+
+    /// 标题栏的颜色，ARGB，例如`FFBBBBBB`
+    pub fn bgcolor(mut self, bgcolor: &str) -> Self {
+        self.head.bgcolor = bgcolor.to_owned();
+        self
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.body.title = Some(title.to_owned());
+        self
+    }
+
+    /// 表单的一行，最多6行
+    pub fn field(mut self, key: &str, value: impl ToString) -> Self {
+        self.body.form.push(OaForm {
+            key: key.to_owned(),
+            value: value.to_string(),
+        });
+        self
+    }
+
+    pub fn content(mut self, content: &str) -> Self {
+        self.body.content = Some(content.to_owned());
+        self
+    }
+
+    pub fn author(mut self, author: &str) -> Self {
+        self.body.author = Some(author.to_owned());
+        self
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct OaHead {
+    pub bgcolor: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct OaBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub form: Vec<OaForm>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OaForm {
+    pub key: String,
+    pub value: String,
+}
+
+/// 工作通知的消息内容，序列化为`{"msgtype": "text", "text": {...}}`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "msgtype", rename_all = "snake_case")]
+pub enum Message {
+    Text { text: Text },
+    Markdown { markdown: Markdown },
+    Link { link: Link },
+    ActionCard { action_card: ActionCard },
+    Oa { oa: Oa },
+}
+impl Message {
+    pub fn text(content: &str) -> Self {
+        Message::Text {
+            text: Text {
+                content: content.to_owned(),
+            },
+        }
+    }
+
+    pub fn markdown(title: &str, text: &str) -> Self {
+        Message::Markdown {
+            markdown: Markdown {
+                title: title.to_owned(),
+                text: text.to_owned(),
+            },
+        }
+    }
+
+    pub fn link(title: &str, text: &str, message_url: &str, pic_url: &str) -> Self {
+        Message::Link {
+            link: Link {
+                title: title.to_owned(),
+                text: text.to_owned(),
+                message_url: message_url.to_owned(),
+                pic_url: pic_url.to_owned(),
+            },
+        }
+    }
+
+    pub fn action_card(action_card: ActionCard) -> Self {
+        Message::ActionCard { action_card }
+    }
+
+    pub fn oa(oa: Oa) -> Self {
+        Message::Oa { oa }
+    }
+}
+
+/// 接收人，三者可以同时使用
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recipients {
+    /// 最多100个
+    pub user_ids: Vec<String>,
+    /// 最多20个
+    pub dept_ids: Vec<i64>,
+    pub to_all: bool,
+}
+impl Recipients {
+    pub fn users(user_ids: &[&str]) -> Self {
+        Self {
+            user_ids: user_ids.iter().map(|s| (*s).to_owned()).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn departments(dept_ids: &[i64]) -> Self {
+        Self {
+            dept_ids: dept_ids.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// 全员，同一应用每天只能发送一次
+    pub fn all() -> Self {
+        Self {
+            to_all: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SendProgress {
+    #[serde(default)]
+    pub progress_in_percent: i32,
+    /// 0未开始 1处理中 2处理完毕
+    #[serde(default)]
+    pub status: i32,
+}
+impl SendProgress {
+    pub fn is_done(&self) -> bool {
+        self.status == 2
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SendResult {
+    /// 无效的userid
+    #[serde(default)]
+    pub invalid_user_id_list: Vec<String>,
+    /// 因发送消息过于频繁或超量而被流控的userid
+    #[serde(default)]
+    pub forbidden_user_id_list: Vec<String>,
+    #[serde(default)]
+    pub failed_user_id_list: Vec<String>,
+    #[serde(default)]
+    pub read_user_id_list: Vec<String>,
+    #[serde(default)]
+    pub unread_user_id_list: Vec<String>,
+    #[serde(default)]
+    pub invalid_dept_id_list: Vec<i64>,
+}
+
+impl Dingtalk {
+    /// 发送工作通知，返回task_id；同一接收人每天最多500条，相同内容每天只能一次
+    pub async fn send_work_notification(
+        &self,
+        to: &Recipients,
+        message: &Message,
+    ) -> ClientResult<i64> {
+        let endpoint =
+            Endpoint::new("https://oapi.dingtalk.com/topapi/message/corpconversation/asyncsend_v2")
+                .access_token();
+
+        #[derive(Serialize)]
+        struct SendRequest<'a> {
+            agent_id: u64,
+            #[serde(skip_serializing_if = "String::is_empty")]
+            userid_list: String,
+            #[serde(skip_serializing_if = "String::is_empty")]
+            dept_id_list: String,
+            to_all_user: bool,
+            msg: &'a Message,
+        }
+        #[derive(Deserialize)]
+        struct SendResponse {
+            task_id: i64,
+        }
+
+        let dept_ids: Vec<String> = to.dept_ids.iter().map(ToString::to_string).collect();
+        let payload = SendRequest {
+            agent_id: self.cfg.agent_id,
+            userid_list: to.user_ids.join(","),
+            dept_id_list: dept_ids.join(","),
+            to_all_user: to.to_all,
+            msg: message,
+        };
+        let resp: SendResponse = self.client.post(endpoint, Some(&payload)).await?;
+        Ok(resp.task_id)
+    }
+
+    pub async fn send_progress(&self, task_id: i64) -> ClientResult<SendProgress> {
+        let endpoint = Endpoint::new(
+            "https://oapi.dingtalk.com/topapi/message/corpconversation/getsendprogress",
+        )
+        .access_token();
+
+        #[derive(Deserialize)]
+        struct ProgressResponse {
+            progress: SendProgress,
+        }
+
+        let payload = TaskRequest {
+            agent_id: self.cfg.agent_id,
+            task_id,
+        };
+        let resp: ProgressResponse = self.client.post(endpoint, Some(&payload)).await?;
+        Ok(resp.progress)
+    }
+
+    pub async fn send_result(&self, task_id: i64) -> ClientResult<SendResult> {
+        let endpoint = Endpoint::new(
+            "https://oapi.dingtalk.com/topapi/message/corpconversation/getsendresult",
+        )
+        .access_token();
+
+        #[derive(Deserialize)]
+        struct ResultResponse {
+            send_result: SendResult,
+        }
+
+        let payload = TaskRequest {
+            agent_id: self.cfg.agent_id,
+            task_id,
+        };
+        let resp: ResultResponse = self.client.post(endpoint, Some(&payload)).await?;
+        Ok(resp.send_result)
+    }
+}
+
+#[derive(Serialize)]
+struct TaskRequest {
+    agent_id: u64,
+    task_id: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock_dingtalk;
+    use super::{ActionCard, Message, Oa, Recipients};
+    use crate::core::testing::TestResult;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn serialize_message() -> TestResult<()> {
+        let card = ActionCard::buttons("审批", "### 请假申请")
+            .button("同意", "https://h5.example.com/approve")
+            .button("拒绝", "https://h5.example.com/reject")
+            .horizontal();
+        assert_eq!(
+            serde_json::to_value(&Message::action_card(card))?,
+            json!({
+                "msgtype": "action_card",
+                "action_card": {
+                    "title": "审批",
+                    "markdown": "### 请假申请",
+                    "btn_orientation": "1",
+                    "btn_json_list": [
+                        {"title": "同意", "action_url": "https://h5.example.com/approve"},
+                        {"title": "拒绝", "action_url": "https://h5.example.com/reject"}
+                    ]
+                }
+            })
+        );
+
+        let oa = Oa::new("https://h5.example.com/approval?id=1", "审批")
+            .title("请假申请")
+            .field("天数：", 3);
+        assert_eq!(
+            serde_json::to_value(&Message::oa(oa))?,
+            json!({
+                "msgtype": "oa",
+                "oa": {
+                    "message_url": "https://h5.example.com/approval?id=1",
+                    "head": {"bgcolor": "FFBBBBBB", "text": "审批"},
+                    "body": {"title": "请假申请", "form": [{"key": "天数：", "value": "3"}]}
+                }
+            })
+        );
+
+        let link = Message::link(
+            "标题",
+            "内容",
+            "https://h5.example.com",
+            "@lADOADmaWMzazQKA",
+        );
+        assert_eq!(
+            serde_json::to_value(&link)?["link"]["messageUrl"],
+            "https://h5.example.com"
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn send_work_notification_offline() -> TestResult<()> {
+        let (dd, mock) = mock_dingtalk();
+        mock.reply_json(
+            "/corpconversation/asyncsend_v2",
+            json!({"errcode": 0, "errmsg": "ok", "task_id": 256271667526, "request_id": "4jzllmte0wau"}),
+        )
+        .reply_json(
+            "/corpconversation/getsendprogress",
+            json!({"errcode": 0, "progress": {"progress_in_percent": 100, "status": 2}}),
+        )
+        .reply_json(
+            "/corpconversation/getsendresult",
+            json!({
+                "errcode": 0,
+                "send_result": {
+                    "invalid_user_id_list": ["lisi"],
+                    "read_user_id_list": ["manager7140"],
+                    "forbidden_list": []
+                }
+            }),
+        );
+
+        let to = Recipients::users(&["manager7140", "lisi"]);
+        let task_id = dd
+            .send_work_notification(&to, &Message::markdown("提醒", "#### 明天开会"))
+            .await?;
+        assert_eq!(task_id, 256271667526);
+        assert!(dd.send_progress(task_id).await?.is_done());
+        let result = dd.send_result(task_id).await?;
+        assert_eq!(result.invalid_user_id_list, vec!["lisi"]);
+
+        let requests = mock.requests();
+        let body: Value = serde_json::from_slice(requests[1].body.as_deref().unwrap_or_default())?;
+        assert_eq!(
+            body,
+            json!({
+                "agent_id": 10086,
+                "userid_list": "manager7140,lisi",
+                "to_all_user": false,
+                "msg": {"msgtype": "markdown", "markdown": {"title": "提醒", "text": "#### 明天开会"}}
+            })
+        );
+        let body: Value = serde_json::from_slice(requests[3].body.as_deref().unwrap_or_default())?;
+        assert_eq!(body, json!({"agent_id": 10086, "task_id": 256271667526i64}));
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+//! 钉钉（企业内部应用）
+//!
+//! * 通讯录读取（部门、成员），见`contact`
+//! * 工作通知，见`message`
+//! * 群自定义机器人，见`robot`
+//! * 事件订阅（HTTP推送），见`callback`
+use super::client::{
+    redact, CircuitBreaker, CircuitStatus, Client, ClientResult, Config as ClientConfig, Endpoint,
+    Platform,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+pub mod callback;
+mod contact;
+pub use contact::{Department, DepartmentMember, MemberPage, ROOT_DEPARTMENT_ID};
+mod message;
+pub use message::{
+    ActionButton, ActionCard, Link, Markdown, Message, Oa, OaBody, OaForm, OaHead, Recipients,
+    SendProgress, SendResult, Text,
+};
+mod robot;
+pub use robot::{At, Robot, RobotActionCard, RobotButton, RobotMessage};
+
+// dingtalk 配置
+#[derive(Serialize, Deserialize, Default)]
+pub struct Config {
+    pub corp_id: String,
+    /// 微应用的AgentId，发送工作通知时使用
+    pub agent_id: u64,
+    pub app_key: String,
+    pub app_secret: String,
+    /// 事件订阅的签名token，不接收推送时为None
+    #[serde(default)]
+    pub callback_token: Option<String>,
+    /// 事件订阅的加密aes_key（43位）
+    #[serde(default)]
+    pub callback_aes_key: Option<String>,
+    /// 超时、代理、重试等（token_url由此处自动设置）
+    #[serde(default)]
+    pub client: ClientConfig,
+}
+impl Config {
+    pub fn from_env() -> Config {
+        use dotenv::dotenv;
+        use std::env;
+
+        dotenv().ok();
+        let corp_id = env::var("DINGTALK_CORP_ID")
+            .expect("value `DINGTALK_CORP_ID` not presented in .env file");
+        let agent_id = env::var("DINGTALK_AGENT_ID")
+            .expect("value `DINGTALK_AGENT_ID` not presented in .env file")
+            .parse()
+            .expect("value `DINGTALK_AGENT_ID` in .env file is not a valid integer");
+        let app_key = env::var("DINGTALK_APP_KEY")
+            .expect("value `DINGTALK_APP_KEY` not presented in .env file");
+        let app_secret = env::var("DINGTALK_APP_SECRET")
+            .expect("value `DINGTALK_APP_SECRET` not presented in .env file");
+        let callback_token = env::var("DINGTALK_CALLBACK_TOKEN").ok();
+        let callback_aes_key = env::var("DINGTALK_CALLBACK_AES_KEY").ok();
+
+        Config {
+            corp_id,
+            agent_id,
+            app_key,
+            app_secret,
+            callback_token,
+            callback_aes_key,
+            ..Default::default()
+        }
+    }
+}
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("corp_id", &self.corp_id)
+            .field("agent_id", &self.agent_id)
+            .field("app_key", &self.app_key)
+            .field("app_secret", &redact::secret(&self.app_secret))
+            .field(
+                "callback_token",
+                &self.callback_token.as_deref().map(redact::secret),
+            )
+            .field(
+                "callback_aes_key",
+                &self.callback_aes_key.as_deref().map(redact::secret),
+            )
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+// dingtalk 结构
+pub struct Dingtalk {
+    cfg: Config,
+    client: Client,
+}
+impl Dingtalk {
+    pub fn new(cfg: Config) -> ClientResult<Dingtalk> {
+        let client = Client::new(Self::client_config(&cfg))?;
+        Ok(Self::with_client(cfg, client))
+    }
+
+    /// 使用自行构建的Client，可以同时设置token_store、metrics、breaker，测试时注入`MockTransport`
+    pub fn with_client(cfg: Config, client: Client) -> Dingtalk {
+        Dingtalk { cfg, client }
+    }
+
+    /// Client的配置，token_url等由`cfg`生成
+    pub fn client_config(cfg: &Config) -> ClientConfig {
+        let token_url = Endpoint::new("https://oapi.dingtalk.com/gettoken")
+            .query("appkey", &cfg.app_key)
+            .query("appsecret", &cfg.app_secret);
+        ClientConfig {
+            token_url: token_url.to_string(),
+            platform: Platform::Dingtalk,
+            token_key: format!("dingtalk:{}", cfg.app_key),
+            ..cfg.client.clone()
+        }
+    }
+
+    /// 各接口的熔断状态，见`client::breaker`
+    pub fn circuit_states(&self) -> Vec<CircuitStatus> {
+        self.client.circuit_states()
+    }
+
+    /// 熔断器，同一平台的其它Client可以共用，见`with_client`
+    pub fn circuit_breaker(&self) -> Arc<CircuitBreaker> {
+        self.client.circuit_breaker()
+    }
+
+    pub async fn access_token(&self) -> ClientResult<String> {
+        self.client.access_token().await
+    }
+
+    #[cfg(test)]
+    pub(crate) async fn set_invalid_access_token(&self) {
+        self.client.set_invalid_access_token().await
+    }
+}
+
+// 钉钉接口返回的类型定义
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInfo {
+    userid: String,             // "zhangsan"，创建后不可修改
+    unionid: String,            // "PiiiPyQqBNBii0HnCJ3zljcuAiEiE"，不会改变
+    name: String,               // "张三",
+    tel: Option<String>,        // "xxx-xxxxxxxx", 分机号（仅限企业内部开发调用）
+    work_place: Option<String>, // "place",
+    remark: Option<String>,     // "remark",
+    mobile: String,             // "1xxxxxxxxxx", 手机号码
+    email: Option<String>,      // "test@xxx.com",
+    org_email: Option<String>,  // "test@xxx.com",
+    active: bool,               // false,
+    order_in_depts: String,     // "{1:71738366882504}",
+    is_admin: bool,             // false, 是否为企业的管理员
+    is_boss: bool,              // false,
+    is_leader_in_depts: String, // "{1:false}",
+    is_hide: bool,              // false,
+    department: Vec<i32>,       // [1,2],
+    position: String,           // "manager",
+    avatar: String,             // "xxx",
+    hired_date: u64,            // 1520265600000,
+    jobnumber: String,          // "001",
+    // extattr: HashMap<String, String>, // {}, 扩展属性，可以设置多种属性
+    is_senior: bool,      // false,
+    state_code: String,   // "86",
+    roles: Vec<UserRole>, // [{"id": 149507744, "name": "总监", "groupName": "职务"}]
+}
+// 手机号、分机号脱敏
+impl fmt::Debug for UserInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserInfo")
+            .field("userid", &self.userid)
+            .field("unionid", &self.unionid)
+            .field("name", &self.name)
+            .field("tel", &self.tel.as_deref().map(redact::phone))
+            .field("work_place", &self.work_place)
+            .field("remark", &self.remark)
+            .field("mobile", &redact::phone(&self.mobile))
+            .field("email", &self.email)
+            .field("org_email", &self.org_email)
+            .field("active", &self.active)
+            .field("order_in_depts", &self.order_in_depts)
+            .field("is_admin", &self.is_admin)
+            .field("is_boss", &self.is_boss)
+            .field("is_leader_in_depts", &self.is_leader_in_depts)
+            .field("is_hide", &self.is_hide)
+            .field("department", &self.department)
+            .field("position", &self.position)
+            .field("avatar", &self.avatar)
+            .field("hired_date", &self.hired_date)
+            .field("jobnumber", &self.jobnumber)
+            .field("is_senior", &self.is_senior)
+            .field("state_code", &self.state_code)
+            .field("roles", &self.roles)
+            .finish()
+    }
+}
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserRole {
+    id: u32,            //
+    name: String,       // 角色名称
+    group_name: String, // 角色组名称
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Microapp {
+    pub app_icon: String,                 // 应用图标
+    pub agent_id: i32,                    // 应用实例化id
+    pub app_desc: String,                 // 应用描述
+    pub is_self: bool,                    // 表示是否是自建应用
+    pub name: String,                     // 应用名称
+    pub homepage_link: Option<String>,    // 应用的移动端主页
+    pub pc_homepage_link: Option<String>, // 应用的pc端主页
+    pub app_status: i32,                  // 应用状态， 1表示启用 0表示停用
+    pub omp_link: Option<String>,         // 应用的OA后台管理主页
+}
+impl Dingtalk {
+    pub async fn user_info(&self, user_id: String) -> ClientResult<UserInfo> {
+        let endpoint = Endpoint::new("https://oapi.dingtalk.com/user/get")
+            .access_token()
+            .query("userid", user_id);
+        self.client.get(endpoint).await
+    }
+    pub async fn microapp_list(&self) -> ClientResult<Vec<Microapp>> {
+        let endpoint = Endpoint::new("https://oapi.dingtalk.com/microapp/list").access_token();
+
+        #[derive(Serialize, Deserialize, Debug)]
+        #[serde(rename_all = "camelCase")]
+        struct ApiResult {
+            app_list: Vec<Microapp>,
+        }
+        let resp = self.client.post::<(), ApiResult>(endpoint, None).await?;
+        Ok(resp.app_list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::client::{transport::MockTransport, Client};
+    use super::{Config, Dingtalk};
+    use crate::core::testing::TestResult;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    pub(super) fn mock_dingtalk() -> (Dingtalk, Arc<MockTransport>) {
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json("/gettoken", json!({"errcode": 0, "access_token": "TOKEN"}));
+        let cfg = Config {
+            corp_id: "CORPID".to_owned(),
+            agent_id: 10086,
+            app_key: "APPKEY".to_owned(),
+            app_secret: "APPSECRET".to_owned(),
+            ..Default::default()
+        };
+        let client = Client::with_transport(Dingtalk::client_config(&cfg), mock.clone());
+        (Dingtalk::with_client(cfg, client), mock)
+    }
+
+    #[test]
+    fn read_config_from_env() {
+        setup();
+
+        let cfg = Config::from_env();
+        info!("dingtalk cfg: {:#?}", cfg);
+    }
+
+    #[async_std::test]
+    async fn get_access_token() -> TestResult<()> {
+        setup();
+
+        let cfg = Config::from_env();
+        let dd = Dingtalk::new(cfg)?;
+
+        dbg!(dd.access_token().await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    #[ignore = "需要钉钉后台设置白名单ip才能测试"]
+    async fn auto_refresh_access_token() -> TestResult<()> {
+        setup();
+
+        let dd = Dingtalk::new(Config::from_env())?;
+        dd.access_token().await?;
+        dd.set_invalid_access_token().await;
+        let app_list = dd.microapp_list().await?;
+        dbg!(app_list);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn auto_refresh_access_token_offline() -> TestResult<()> {
+        setup();
+
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json(
+            "/gettoken",
+            json!({"errcode": 0, "access_token": "TOKEN_1"}),
+        )
+        .reply_json(
+            "/microapp/list",
+            json!({"errcode": 40014, "errmsg": "不合法的access_token"}),
+        )
+        .reply_json(
+            "/gettoken",
+            json!({"errcode": 0, "access_token": "TOKEN_2"}),
+        )
+        .reply_json("/microapp/list", json!({"errcode": 0, "appList": []}));
+        let cfg = Default::default();
+        let client = Client::with_transport(Dingtalk::client_config(&cfg), mock.clone());
+        let dd = Dingtalk::with_client(cfg, client);
+
+        let app_list = dd.microapp_list().await?;
+        assert!(app_list.is_empty());
+        assert!(mock.requests()[3].url.ends_with("access_token=TOKEN_2"));
+        Ok(())
+    }
+
+    #[async_std::test]
+    #[ignore = "需要钉钉后台设置白名单ip才能测试"]
+    async fn get_user_info() -> TestResult<()> {
+        setup();
+
+        let dd = Dingtalk::new(Config::from_env())?;
+        let user_info = dd.user_info("manager7140".to_string()).await?;
+        debug!("user_info: {:#?}", user_info);
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+//! 群自定义机器人
+//!
+//! 通过webhook发到群聊，例如运维告警；与企业内部应用无关，不需要`Dingtalk`的access_token。
+//! 安全设置选择“加签”时需提供secret，每分钟最多发送20条
+//!
+//! ```rs
+//! let robot = Robot::new(&webhook, Some(&secret))?;
+//! robot
+//!     .send(&RobotMessage::markdown("告警", "#### 订单服务异常\n> 5分钟内失败32次").at_all())
+//!     .await?;
+//! ```
+use super::super::client::transport::HttpTransport;
+use super::super::client::{Client, ClientResult, Config as ClientConfig, Endpoint, Platform};
+use super::message::{Link, Markdown, Text};
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Arc;
+
+/// 被@的人，markdown消息需要在text里包含`@手机号`才会显示
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct At {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub at_mobiles: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub at_user_ids: Vec<String>,
+    pub is_at_all: bool,
+}
+
+/// 卡片消息，`single_*`（整体跳转）与`btns`（独立跳转）二选一
+///
+/// 字段名与工作通知的`ActionCard`不同
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RobotActionCard {
+    pub title: String,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub single_title: Option<String>,
+    #[serde(rename = "singleURL", skip_serializing_if = "Option::is_none")]
+    pub single_url: Option<String>,
+    /// "0"竖直排列 "1"横向排列
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub btn_orientation: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub btns: Vec<RobotButton>,
+}
+impl RobotActionCard {
+    /// 整体跳转
+    pub fn single(title: &str, text: &str, single_title: &str, single_url: &str) -> Self {
+        Self {
+            title: title.to_owned(),
+            text: text.to_owned(),
+            single_title: Some(single_title.to_owned()),
+            single_url: Some(single_url.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    /// 独立跳转，按钮用`button`添加
+    pub fn buttons(title: &str, text: &str) -> Self {
+        Self {
+            title: title.to_owned(),
+            text: text.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn button(mut self, title: &str, action_url: &str) -> Self {
+        self.btns.push(RobotButton {
+            title: title.to_owned(),
+            action_url: action_url.to_owned(),
+        });
+        self
+    }
+
+    pub fn horizontal(mut self) -> Self {
+        self.btn_orientation = Some("1".to_owned());
+        self
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RobotButton {
+    pub title: String,
+    #[serde(rename = "actionURL")]
+    pub action_url: String,
+}
+
+/// 机器人消息，序列化为`{"msgtype": "text", "text": {...}, "at": {...}}`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "msgtype", rename_all = "camelCase")]
+pub enum RobotMessage {
+    Text {
+        text: Text,
+        at: At,
+    },
+    Markdown {
+        markdown: Markdown,
+        at: At,
+    },
+    Link {
+        link: Link,
+    },
+    ActionCard {
+        #[serde(rename = "actionCard")]
+        action_card: RobotActionCard,
+    },
+}
+impl RobotMessage {
+    pub fn text(content: &str) -> Self {
+        RobotMessage::Text {
+            text: Text {
+                content: content.to_owned(),
+            },
+            at: Default::default(),
+        }
+    }
+
+    pub fn markdown(title: &str, text: &str) -> Self {
+        RobotMessage::Markdown {
+            markdown: Markdown {
+                title: title.to_owned(),
+                text: text.to_owned(),
+            },
+            at: Default::default(),
+        }
+    }
+
+    pub fn link(title: &str, text: &str, message_url: &str, pic_url: &str) -> Self {
+        RobotMessage::Link {
+            link: Link {
+                title: title.to_owned(),
+                text: text.to_owned(),
+                message_url: message_url.to_owned(),
+                pic_url: pic_url.to_owned(),
+            },
+        }
+    }
+
+    pub fn action_card(action_card: RobotActionCard) -> Self {
+        RobotMessage::ActionCard { action_card }
+    }
+
+    /// 按手机号@群成员，只对text、markdown有效
+    pub fn at_mobiles(mut self, mobiles: &[&str]) -> Self {
+        if let Some(at) = self.at_mut() {
+            at.at_mobiles = mobiles.iter().map(|s| (*s).to_owned()).collect();
+        }
+        self
+    }
+
+    /// 按userid@群成员，只对text、markdown有效
+    pub fn at_user_ids(mut self, user_ids: &[&str]) -> Self {
+        if let Some(at) = self.at_mut() {
+            at.at_user_ids = user_ids.iter().map(|s| (*s).to_owned()).collect();
+        }
+        self
+    }
+
+    /// @所有人，只对text、markdown有效
+    pub fn at_all(mut self) -> Self {
+        if let Some(at) = self.at_mut() {
+            at.is_at_all = true;
+        }
+        self
+    }
+
+    fn at_mut(&mut self) -> Option<&mut At> {
+        match self {
+            RobotMessage::Text { at, .. } | RobotMessage::Markdown { at, .. } => Some(at),
+            _ => None,
+        }
+    }
+}
+
+/// 群自定义机器人
+pub struct Robot {
+    webhook: String,
+    secret: Option<String>,
+    client: Client,
+}
+impl Robot {
+    /// `webhook`为机器人设置里的完整地址（含access_token），`secret`为加签的密钥（SEC开头）
+    pub fn new(webhook: &str, secret: Option<&str>) -> ClientResult<Self> {
+        Ok(Self {
+            webhook: webhook.to_owned(),
+            secret: secret.map(ToOwned::to_owned),
+            client: Client::new(Self::client_config())?,
+        })
+    }
+
+    /// 注入传输层，例如测试时使用`MockTransport`
+    pub fn with_transport(
+        webhook: &str,
+        secret: Option<&str>,
+        transport: Arc<dyn HttpTransport>,
+    ) -> Self {
+        Self {
+            webhook: webhook.to_owned(),
+            secret: secret.map(ToOwned::to_owned),
+            client: Client::with_transport(Self::client_config(), transport),
+        }
+    }
+
+    // webhook自带access_token，不需要token_url
+    fn client_config() -> ClientConfig {
+        ClientConfig {
+            platform: Platform::Dingtalk,
+            token_key: "dingtalk_robot".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub async fn send(&self, message: &RobotMessage) -> ClientResult<()> {
+        let mut endpoint = Endpoint::new(&self.webhook);
+        if let Some(secret) = &self.secret {
+            let timestamp = Utc::now().timestamp_millis();
+            endpoint = endpoint
+                .query("timestamp", timestamp)
+                .query("sign", sign(secret, timestamp));
+        }
+
+        #[derive(Deserialize)]
+        struct SendResponse {}
+
+        self.client
+            .post::<_, SendResponse>(endpoint, Some(message))
+            .await?;
+        Ok(())
+    }
+}
+
+/// `base64(hmac_sha256(secret, "{timestamp}\n{secret}"))`，timestamp为毫秒，1小时内有效
+pub fn sign(secret: &str, timestamp: i64) -> String {
+    // HMAC可以接受任意长度的key，这里不会出错
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}\n{}", timestamp, secret).as_bytes());
+    base64::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::client::transport::MockTransport;
+    use super::{sign, Robot, RobotActionCard, RobotMessage};
+    use crate::core::testing::TestResult;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    const WEBHOOK: &str = "https://oapi.dingtalk.com/robot/send?access_token=ROBOT_TOKEN";
+    const SECRET: &str = "SEC000000000000000000000";
+
+    #[test]
+    fn sign_message() {
+        assert_eq!(
+            sign(SECRET, 1700000000000),
+            "1zJ/w34EOSVAYr7cu7Vo8LnebmK2/GrCgegtr8mQrqM="
+        );
+    }
+
+    #[test]
+    fn serialize_message() -> TestResult<()> {
+        let card = RobotActionCard::single(
+            "发布",
+            "### v1.2.0 已上线",
+            "查看",
+            "https://ci.example.com/1",
+        );
+        assert_eq!(
+            serde_json::to_value(&RobotMessage::action_card(card).at_all())?,
+            json!({
+                "msgtype": "actionCard",
+                "actionCard": {
+                    "title": "发布",
+                    "text": "### v1.2.0 已上线",
+                    "singleTitle": "查看",
+                    "singleURL": "https://ci.example.com/1"
+                }
+            })
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn send_offline() -> TestResult<()> {
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json("/robot/send", json!({"errcode": 0, "errmsg": "ok"}))
+            .reply_json(
+                "/robot/send",
+                json!({"errcode": 310000, "errmsg": "sign not match"}),
+            );
+        let robot = Robot::with_transport(WEBHOOK, Some(SECRET), mock.clone());
+
+        let message = RobotMessage::text("订单服务异常").at_mobiles(&["13800000000"]);
+        robot.send(&message).await?;
+        let err = robot.send(&message).await.unwrap_err();
+        assert_eq!(err.code(), Some(310000));
+
+        let requests = mock.requests();
+        // 不需要获取access_token
+        assert_eq!(requests.len(), 2);
+        let url = &requests[0].url;
+        assert!(url.starts_with(&format!("{}&timestamp=", WEBHOOK)));
+        assert!(url.contains("&sign="));
+        let body: Value = serde_json::from_slice(requests[0].body.as_deref().unwrap_or_default())?;
+        assert_eq!(
+            body,
+            json!({
+                "msgtype": "text",
+                "text": {"content": "订单服务异常"},
+                "at": {"atMobiles": ["13800000000"], "isAtAll": false}
+            })
+        );
+        Ok(())
+    }
+}
@@ -4,3 +4,7 @@ mod aes_cbc_128;
 pub mod client;
 pub mod dingtalk;
 pub mod wechat_miniprogram;
+pub mod wechat_official;
+pub mod wechat_pay;
+pub mod wechat_push;
+pub mod wecom;
@@ -1,18 +1,39 @@
-use super::client::{Client, ClientResult, Config as ClientConfig};
+use super::client::{
+    redact, CircuitBreaker, CircuitStatus, Client, ClientResult, Config as ClientConfig, Endpoint,
+    Platform,
+};
 //use anyhow::Result as AnyhowResult;
 use super::aes_cbc_128;
 use base64;
+use crypto::aes::KeySize;
+use crypto::blockmodes::PkcsPadding;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::str;
 use std::sync::Arc;
 
+mod security;
+pub use security::{
+    MediaCheckEvent, MediaCheckHandler, MediaType, MsgSecCheckResponse, SecCheckDetail,
+    SecCheckResult, SecScene, Suggest,
+};
+mod subscribe_message;
+pub use subscribe_message::{
+    Category, DataValue, Field, MiniprogramState, SubscribeMessage, Template, TemplateKeyword,
+};
+mod wxacode;
+pub use wxacode::{Color, Scene, WxacodeOptions, SCENE_MAX_LEN};
+
 type AnyhowResult<O> = Result<O, Box<dyn std::error::Error + Send + Sync>>;
 
 // miniprogram 配置
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct Config {
     pub appid: String,
     pub secret: String,
+    /// 超时、代理、重试等（token_url由此处自动设置）
+    #[serde(default)]
+    pub client: ClientConfig,
 }
 impl Config {
     pub fn from_env() -> Config {
@@ -25,7 +46,20 @@ impl Config {
         let secret = env::var("WECHAT_WEAPP_SECRET")
             .expect("value `WECHAT_WEAPP_SECRET` not presented in .env file");
 
-        Config { appid, secret }
+        Config {
+            appid,
+            secret,
+            ..Default::default()
+        }
+    }
+}
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("appid", &self.appid)
+            .field("secret", &redact::secret(&self.secret))
+            .field("client", &self.client)
+            .finish()
     }
 }
 
@@ -43,16 +77,40 @@ struct MiniprogramInner {
     client: Client,
 }
 impl Miniprogram {
-    pub fn new(cfg: Config) -> Self {
-        let token_url = "https://api.weixin.qq.com/cgi-bin/token?grant_type=client_credential&appid=APPID&secret=APPSECRET";
-        let token_url = token_url
-            .replace("APPID", &cfg.appid)
-            .replace("APPSECRET", &cfg.secret);
-        let client = Client::new(ClientConfig { token_url });
+    pub fn new(cfg: Config) -> ClientResult<Self> {
+        let client = Client::new(Self::client_config(&cfg))?;
+        Ok(Self::with_client(cfg, client))
+    }
 
+    /// 使用自行构建的Client，可以同时设置token_store、metrics、breaker，测试时注入`MockTransport`
+    pub fn with_client(cfg: Config, client: Client) -> Self {
         Self(Arc::new(MiniprogramInner { cfg, client }))
     }
 
+    /// Client的配置，token_url等由`cfg`生成
+    pub fn client_config(cfg: &Config) -> ClientConfig {
+        let token_url = Endpoint::new("https://api.weixin.qq.com/cgi-bin/token")
+            .query("grant_type", "client_credential")
+            .query("appid", &cfg.appid)
+            .query("secret", &cfg.secret);
+        ClientConfig {
+            token_url: token_url.to_string(),
+            platform: Platform::Wechat,
+            token_key: format!("wechat_miniprogram:{}", cfg.appid),
+            ..cfg.client.clone()
+        }
+    }
+
+    /// 各接口的熔断状态，见`client::breaker`
+    pub fn circuit_states(&self) -> Vec<CircuitStatus> {
+        self.0.client.circuit_states()
+    }
+
+    /// 熔断器，同一平台的其它Client可以共用，见`with_client`
+    pub fn circuit_breaker(&self) -> Arc<CircuitBreaker> {
+        self.0.client.circuit_breaker()
+    }
+
     pub async fn access_token(&self) -> ClientResult<String> {
         self.0.client.access_token().await
     }
@@ -64,33 +122,13 @@ impl Miniprogram {
 }
 
 impl Miniprogram {
-    pub async fn msg_sec_check(&self, content: &str) -> ClientResult<()> {
-        let url = "https://api.weixin.qq.com/wxa/msg_sec_check?access_token=ACCESS_TOKEN";
-
-        #[derive(Serialize)]
-        struct MsgSecCheckRequest<'a> {
-            content: &'a str,
-        }
-        #[derive(Deserialize)]
-        struct ApiErrorResponse {
-            errcode: Option<i32>,
-            errmsg: Option<String>,
-        }
-
-        let payload = MsgSecCheckRequest { content };
-        self.0
-            .client
-            .post::<_, ApiErrorResponse>(url, Some(&payload))
-            .await?;
-        Ok(())
-    }
     pub async fn code_to_session(&self, code: &str) -> ClientResult<Code2SessionResponse> {
-        let url = "https://api.weixin.qq.com/sns/jscode2session?appid=APPID&secret=SECRET&js_code=JSCODE&grant_type=authorization_code";
-        let url = url
-            .replace("APPID", &self.0.cfg.appid)
-            .replace("SECRET", &self.0.cfg.secret)
-            .replace("JSCODE", code);
-        self.0.client.get::<Code2SessionResponse>(&url).await
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/sns/jscode2session")
+            .query("appid", &self.0.cfg.appid)
+            .query("secret", &self.0.cfg.secret)
+            .query("js_code", code)
+            .query("grant_type", "authorization_code");
+        self.0.client.get::<Code2SessionResponse>(endpoint).await
     }
 
     pub fn get_phone_number(
@@ -102,8 +140,9 @@ impl Miniprogram {
         let iv = base64::decode(iv)?;
         let data = base64::decode(data)?;
 
-        let decrypted_data = aes_cbc_128::decrypt(&data, &session_key, &iv)
-            .map_err(|e| format!("解密失败：{:?}", e))?;
+        let decrypted_data =
+            aes_cbc_128::decrypt(&data, &session_key, &iv, KeySize::KeySize128, PkcsPadding)
+                .map_err(|e| format!("解密失败：{:?}", e))?;
 
         let data = str::from_utf8(&decrypted_data)?;
         serde_json::from_str::<PhoneNumberResult>(data).map_err(Into::into)
@@ -114,27 +153,46 @@ impl Miniprogram {
         let iv = base64::decode(iv)?;
         let data = base64::decode(data)?;
 
-        let decrypted_data = aes_cbc_128::decrypt(&data, &session_key, &iv)
-            .map_err(|e| format!("解密失败：{:?}", e))?;
+        let decrypted_data =
+            aes_cbc_128::decrypt(&data, &session_key, &iv, KeySize::KeySize128, PkcsPadding)
+                .map_err(|e| format!("解密失败：{:?}", e))?;
 
         let data = str::from_utf8(&decrypted_data)?;
         serde_json::from_str::<UserInfo>(data).map_err(Into::into)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize)]
 pub struct Code2SessionResponse {
     pub openid: String,          //	用户唯一标识
     pub session_key: String,     //	会话密钥
     pub unionid: Option<String>, //	用户在开放平台的唯一标识符，在满足 UnionID 下发条件的情况下会返回，详见 UnionID 机制说明。
 }
-#[derive(Serialize, Deserialize, Debug)]
+impl fmt::Debug for Code2SessionResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Code2SessionResponse")
+            .field("openid", &self.openid)
+            .field("session_key", &redact::secret(&self.session_key))
+            .field("unionid", &self.unionid)
+            .finish()
+    }
+}
+#[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PhoneNumberResult {
     pub phone_number: String,      // 用户绑定的手机号（国外手机号会有区号）
     pub pure_phone_number: String, // 没有区号的手机号
     pub country_code: String,      //
 }
+impl fmt::Debug for PhoneNumberResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PhoneNumberResult")
+            .field("phone_number", &redact::phone(&self.phone_number))
+            .field("pure_phone_number", &redact::phone(&self.pure_phone_number))
+            .field("country_code", &self.country_code)
+            .finish()
+    }
+}
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct UserInfo {
@@ -150,9 +208,12 @@ pub struct UserInfo {
 
 #[cfg(test)]
 mod tests {
+    use super::super::client::{transport::MockTransport, Client};
     use super::{Config, Miniprogram};
+    use crate::core::testing::TestResult;
+    use serde_json::json;
     use std::env;
-    type TestResult<O> = Result<O, Box<dyn std::error::Error + Send + Sync>>;
+    use std::sync::Arc;
 
     fn setup() {
         // 为了在testing下看到logging
@@ -172,7 +233,7 @@ mod tests {
         setup();
 
         let cfg = Config::from_env();
-        let app = Miniprogram::new(cfg);
+        let app = Miniprogram::new(cfg)?;
 
         debug!("access_token: {}", app.access_token().await?);
         Ok(())
@@ -182,22 +243,40 @@ mod tests {
     async fn auto_refresh_access_token() -> TestResult<()> {
         setup();
 
-        let app = Miniprogram::new(Config::from_env());
+        let app = Miniprogram::new(Config::from_env())?;
         app.access_token().await?;
         app.set_invalid_access_token().await;
 
-        app.msg_sec_check("平安顺利").await?;
+        app.template_categories().await?;
         Ok(())
     }
 
     #[async_std::test]
-    async fn msg_sec_check() {
+    async fn code_to_session_offline() -> TestResult<()> {
         setup();
 
-        let app = Miniprogram::new(Config::from_env());
-
-        assert!(app.msg_sec_check("法轮功").await.is_err());
-        assert!(app.msg_sec_check("没毛病").await.is_ok());
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json(
+            "/sns/jscode2session",
+            json!({"openid": "OPENID", "session_key": "KEY"}),
+        );
+        let cfg = Config {
+            appid: "APPID".to_owned(),
+            secret: "SECRET".to_owned(),
+            ..Default::default()
+        };
+        let client = Client::with_transport(Miniprogram::client_config(&cfg), mock.clone());
+        let app = Miniprogram::with_client(cfg, client);
+
+        // code中的特殊字符不会注入额外的参数
+        let session = app.code_to_session("CODE&appid=OTHER#").await?;
+        assert_eq!(session.openid, "OPENID");
+        assert_eq!(
+            mock.requests()[0].url,
+            "https://api.weixin.qq.com/sns/jscode2session?appid=APPID&secret=SECRET\
+             &js_code=CODE%26appid%3DOTHER%23&grant_type=authorization_code"
+        );
+        Ok(())
     }
 
     #[async_std::test]
@@ -206,7 +285,7 @@ mod tests {
         setup();
 
         // check invalid code
-        let app = Miniprogram::new(Config::from_env());
+        let app = Miniprogram::new(Config::from_env())?;
         let invalid_result = app.code_to_session("").await;
         assert!(invalid_result.is_err());
 
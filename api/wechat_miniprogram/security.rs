@@ -0,0 +1,453 @@
+//! 内容安全
+//!
+//! 用户的头像、昵称、帖子等，发布前须经过检测：
+//! * `msg_sec_check` 文本（2.0版本），同步返回建议与标签
+//! * `img_sec_check` 图片（不超过1M），有风险时返回的错误`is_content_risky()`为true
+//! * `media_check_async` 图片、音频（url），结果通过消息推送异步返回，见`MediaCheckHandler`
+//!
+//! ```rs
+//! let result = app.msg_sec_check(&open_id, SecScene::Forum, &post.content).await?;
+//! if !result.is_pass() {
+//!     // 拒绝发布或转人工审核
+//! }
+//!
+//! let trace_id = app.media_check_async(&open_id, SecScene::Profile, &avatar_url, MediaType::Image).await?;
+//! // 消息推送收到wxa_media_check事件后
+//! app.handle_media_check_callback(&body, &handler).await?;
+//! ```
+use super::super::client::{ClientError, ClientResult, Endpoint, Multipart};
+use super::super::wechat_push::de;
+use super::Miniprogram;
+use async_trait::async_trait;
+use http::Method;
+use serde::{Deserialize, Serialize};
+
+/// 检测场景
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SecScene {
+    /// 资料
+    Profile,
+    /// 评论
+    Comment,
+    /// 论坛
+    Forum,
+    /// 社交日志
+    SocialLog,
+}
+impl SecScene {
+    fn code(self) -> u8 {
+        match self {
+            SecScene::Profile => 1,
+            SecScene::Comment => 2,
+            SecScene::Forum => 3,
+            SecScene::SocialLog => 4,
+        }
+    }
+}
+
+/// 异步检测的媒体类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaType {
+    Audio,
+    Image,
+}
+impl MediaType {
+    fn code(self) -> u8 {
+        match self {
+            MediaType::Audio => 1,
+            MediaType::Image => 2,
+        }
+    }
+}
+
+/// 处理建议
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Suggest {
+    Pass,
+    /// 建议人工审核
+    Review,
+    Risky,
+}
+
+/// 综合结果
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SecCheckResult {
+    pub suggest: Suggest,
+    /// 命中的主标签，见`SecCheckResult::label_name`
+    #[serde(deserialize_with = "de::int")]
+    pub label: i32,
+}
+impl SecCheckResult {
+    pub fn is_pass(&self) -> bool {
+        self.suggest == Suggest::Pass
+    }
+
+    pub fn label_name(&self) -> &'static str {
+        match self.label {
+            100 => "正常",
+            10001 => "广告",
+            20001 => "时政",
+            20002 => "色情",
+            20003 => "辱骂",
+            20006 => "违法犯罪",
+            20008 => "欺诈",
+            20012 => "低俗",
+            20013 => "版权",
+            21000 => "其他",
+            _ => "未知",
+        }
+    }
+}
+
+/// 各检测策略的详细结果
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SecCheckDetail {
+    pub strategy: String,
+    #[serde(deserialize_with = "de::int")]
+    pub errcode: i32,
+    pub suggest: Option<Suggest>,
+    #[serde(default, deserialize_with = "de::opt_int")]
+    pub label: Option<i32>,
+    /// 命中的关键词
+    pub keyword: Option<String>,
+    /// 0-100，越高越可能命中
+    #[serde(default, deserialize_with = "de::opt_int")]
+    pub prob: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MsgSecCheckResponse {
+    pub trace_id: String,
+    pub result: SecCheckResult,
+    #[serde(default)]
+    pub detail: Vec<SecCheckDetail>,
+}
+impl MsgSecCheckResponse {
+    pub fn is_pass(&self) -> bool {
+        self.result.is_pass()
+    }
+}
+
+/// 异步检测的结果（消息推送的`wxa_media_check`事件），兼容json与xml格式
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MediaCheckEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime", deserialize_with = "de::int")]
+    pub create_time: i64,
+    #[serde(rename = "Event")]
+    pub event: String,
+    pub appid: String,
+    /// 与`media_check_async`返回的trace_id对应
+    pub trace_id: String,
+    pub result: SecCheckResult,
+    #[serde(default, deserialize_with = "de::one_or_many")]
+    pub detail: Vec<SecCheckDetail>,
+}
+
+/// 处理异步检测的结果，例如更新帖子的审核状态
+#[async_trait]
+pub trait MediaCheckHandler: Send + Sync {
+    async fn on_media_check(&self, event: MediaCheckEvent) -> ClientResult<()>;
+}
+
+impl Miniprogram {
+    /// 文本检测，`openid`须是近两小时访问过小程序的用户
+    pub async fn msg_sec_check(
+        &self,
+        openid: &str,
+        scene: SecScene,
+        content: &str,
+    ) -> ClientResult<MsgSecCheckResponse> {
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/wxa/msg_sec_check").access_token();
+
+        #[derive(Serialize)]
+        struct MsgSecCheckRequest<'a> {
+            content: &'a str,
+            version: u8,
+            scene: u8,
+            openid: &'a str,
+        }
+
+        let payload = MsgSecCheckRequest {
+            content,
+            version: 2,
+            scene: scene.code(),
+            openid,
+        };
+        self.0.client.post(endpoint, Some(&payload)).await
+    }
+
+    /// 图片检测（1.0版本，同步），有风险时返回的错误`is_content_risky()`为true
+    pub async fn img_sec_check(
+        &self,
+        filename: &str,
+        content_type: &str,
+        data: Vec<u8>,
+    ) -> ClientResult<()> {
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/wxa/img_sec_check").access_token();
+
+        #[derive(Deserialize)]
+        struct ImgSecCheckResponse {}
+
+        let form = Multipart::new().file("media", filename, content_type, data);
+        self.0
+            .client
+            .request(Method::POST, endpoint)
+            .multipart(form)
+            .send::<ImgSecCheckResponse>()
+            .await?;
+        Ok(())
+    }
+
+    /// 异步检测图片、音频，返回trace_id；结果在30分钟内通过消息推送返回
+    pub async fn media_check_async(
+        &self,
+        openid: &str,
+        scene: SecScene,
+        media_url: &str,
+        media_type: MediaType,
+    ) -> ClientResult<String> {
+        let endpoint =
+            Endpoint::new("https://api.weixin.qq.com/wxa/media_check_async").access_token();
+
+        #[derive(Serialize)]
+        struct MediaCheckRequest<'a> {
+            media_url: &'a str,
+            media_type: u8,
+            version: u8,
+            scene: u8,
+            openid: &'a str,
+        }
+        #[derive(Deserialize)]
+        struct MediaCheckResponse {
+            trace_id: String,
+        }
+
+        let payload = MediaCheckRequest {
+            media_url,
+            media_type: media_type.code(),
+            version: 2,
+            scene: scene.code(),
+            openid,
+        };
+        let resp: MediaCheckResponse = self.0.client.post(endpoint, Some(&payload)).await?;
+        Ok(resp.trace_id)
+    }
+
+    /// 处理消息推送（json格式）中的`wxa_media_check`事件，其它事件返回false
+    ///
+    /// 推送的签名须已验证；接收推送见`wechat_push::PushReceiver::on_media_check`
+    pub async fn handle_media_check_callback(
+        &self,
+        body: &[u8],
+        handler: &dyn MediaCheckHandler,
+    ) -> ClientResult<bool> {
+        #[derive(Deserialize)]
+        struct EventType {
+            #[serde(rename = "Event")]
+            event: Option<String>,
+        }
+
+        let event_type: EventType = serde_json::from_slice(body)?;
+        if event_type.event.as_deref() != Some("wxa_media_check") {
+            return Ok(false);
+        }
+        let event: MediaCheckEvent = serde_json::from_slice(body)?;
+        if event.appid != self.0.cfg.appid {
+            return Err(ClientError::Other(format!(
+                "media check event for another appid: {}",
+                event.appid
+            )));
+        }
+        handler.on_media_check(event).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::client::{transport::MockTransport, Client, ClientResult};
+    use super::super::{Config, Miniprogram};
+    use super::{MediaCheckEvent, MediaCheckHandler, MediaType, SecScene, Suggest};
+    use crate::core::testing::TestResult;
+    use async_std::sync::Mutex;
+    use async_trait::async_trait;
+    use serde_json::{json, Value};
+    use std::env;
+    use std::sync::Arc;
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    fn mock_app() -> (Miniprogram, Arc<MockTransport>) {
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json(
+            "/cgi-bin/token",
+            json!({"access_token": "TOKEN", "expires_in": 7200}),
+        );
+        let cfg = Config {
+            appid: "APPID".to_owned(),
+            secret: "SECRET".to_owned(),
+            ..Default::default()
+        };
+        let client = Client::with_transport(Miniprogram::client_config(&cfg), mock.clone());
+        (Miniprogram::with_client(cfg, client), mock)
+    }
+
+    #[async_std::test]
+    #[ignore = "需要提供OPENID=xxx（近两小时访问过小程序）"]
+    async fn msg_sec_check() -> TestResult<()> {
+        setup();
+
+        let app = Miniprogram::new(Config::from_env())?;
+        let openid = env::var("OPENID").expect("OPENID is missing!");
+
+        let result = app
+            .msg_sec_check(&openid, SecScene::Forum, "法轮功")
+            .await?;
+        assert!(!result.is_pass());
+        let result = app
+            .msg_sec_check(&openid, SecScene::Forum, "没毛病")
+            .await?;
+        assert!(result.is_pass());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn msg_sec_check_offline() -> TestResult<()> {
+        setup();
+
+        let (app, mock) = mock_app();
+        mock.reply_json(
+            "/wxa/msg_sec_check",
+            json!({
+                "errcode": 0,
+                "errmsg": "ok",
+                "trace_id": "TRACE_ID",
+                "result": {"suggest": "risky", "label": 20001},
+                "detail": [
+                    {"strategy": "content_model", "errcode": 0, "suggest": "risky", "label": 20001, "prob": 90},
+                    {"strategy": "keyword", "errcode": 0, "suggest": "risky", "label": 20001, "level": 20, "keyword": "法轮功"}
+                ]
+            }),
+        );
+
+        let result = app
+            .msg_sec_check("OPENID", SecScene::Comment, "法轮功")
+            .await?;
+        assert!(!result.is_pass());
+        assert_eq!(result.result.suggest, Suggest::Risky);
+        assert_eq!(result.result.label_name(), "时政");
+        assert_eq!(result.detail[1].keyword.as_deref(), Some("法轮功"));
+
+        let request = mock.requests().pop().expect("request");
+        let body: Value = serde_json::from_slice(&request.body.unwrap_or_default())?;
+        assert_eq!(
+            body,
+            json!({"content": "法轮功", "version": 2, "scene": 2, "openid": "OPENID"})
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn img_sec_check_offline() -> TestResult<()> {
+        setup();
+
+        let (app, mock) = mock_app();
+        mock.reply_json("/wxa/img_sec_check", json!({"errcode": 0, "errmsg": "ok"}))
+            .reply_json(
+                "/wxa/img_sec_check",
+                json!({"errcode": 87014, "errmsg": "risky content"}),
+            );
+
+        app.img_sec_check("a.jpg", "image/jpeg", vec![0xff, 0xd8])
+            .await?;
+        let err = app
+            .img_sec_check("b.jpg", "image/jpeg", vec![0xff, 0xd8])
+            .await
+            .unwrap_err();
+        assert!(err.is_content_risky());
+
+        let request = mock.requests().pop().expect("request");
+        let content_type = request.header("Content-Type").unwrap_or_default();
+        assert!(content_type.starts_with("multipart/form-data"));
+        let body =
+            String::from_utf8_lossy(request.body.as_deref().unwrap_or_default()).into_owned();
+        assert!(body.contains("name=\"media\"; filename=\"b.jpg\""));
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct RecordingHandler(Mutex<Vec<MediaCheckEvent>>);
+    #[async_trait]
+    impl MediaCheckHandler for RecordingHandler {
+        async fn on_media_check(&self, event: MediaCheckEvent) -> ClientResult<()> {
+            self.0.lock().await.push(event);
+            Ok(())
+        }
+    }
+
+    #[async_std::test]
+    async fn media_check_async_offline() -> TestResult<()> {
+        setup();
+
+        let (app, mock) = mock_app();
+        mock.reply_json(
+            "/wxa/media_check_async",
+            json!({"errcode": 0, "errmsg": "ok", "trace_id": "TRACE_ID"}),
+        );
+        let trace_id = app
+            .media_check_async(
+                "OPENID",
+                SecScene::Profile,
+                "https://example.com/avatar.jpg",
+                MediaType::Image,
+            )
+            .await?;
+        assert_eq!(trace_id, "TRACE_ID");
+
+        let handler = RecordingHandler::default();
+        let callback = |appid: &str| {
+            json!({
+                "ToUserName": "gh_38cc49f9733b",
+                "FromUserName": "oH1fu0FdHqpToe2T6gBj0WyB8iS1",
+                "CreateTime": 1626959646,
+                "MsgType": "event",
+                "Event": "wxa_media_check",
+                "appid": appid,
+                "trace_id": "TRACE_ID",
+                "version": 2,
+                "detail": [{"strategy": "content_model", "errcode": 0, "suggest": "pass", "label": 100, "prob": 90}],
+                "errcode": 0,
+                "errmsg": "ok",
+                "result": {"suggest": "pass", "label": 100}
+            })
+            .to_string()
+        };
+        assert!(
+            app.handle_media_check_callback(callback("APPID").as_bytes(), &handler)
+                .await?
+        );
+        assert!(app
+            .handle_media_check_callback(callback("OTHER").as_bytes(), &handler)
+            .await
+            .is_err());
+        let other_event = json!({"MsgType": "event", "Event": "user_enter_tempsession"});
+        assert!(
+            !app.handle_media_check_callback(other_event.to_string().as_bytes(), &handler)
+                .await?
+        );
+
+        let events = handler.0.lock().await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].trace_id, "TRACE_ID");
+        assert!(events[0].result.is_pass());
+        Ok(())
+    }
+}
@@ -0,0 +1,475 @@
+//! 订阅消息
+//!
+//! 用户在小程序里订阅后，才能下发对应模板的消息（一次订阅只能下发一条）
+//!
+//! ```rs
+//! let message = SubscribeMessage::new("TEMPLATE_ID")
+//!     .page("pages/order/detail?id=1")
+//!     .field(Field::CharacterString, 1, &order.no)
+//!     .field(Field::Thing, 2, "预约成功")
+//!     .time(3, order.appointed_at.naive_local())
+//!     .state(MiniprogramState::Trial);
+//! app.send_subscribe_message(&open_id, &message).await?;
+//! ```
+use super::super::client::{ClientError, ClientResult, Endpoint};
+use super::Miniprogram;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// 跳转的小程序版本
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MiniprogramState {
+    /// 开发版
+    Developer,
+    /// 体验版
+    Trial,
+    /// 正式版
+    Formal,
+}
+impl Default for MiniprogramState {
+    fn default() -> Self {
+        MiniprogramState::Formal
+    }
+}
+
+/// 模板字段的类型，决定字段名前缀与取值限制
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    /// 20个以内字符
+    Thing,
+    /// 32位以内数字，可带小数
+    Number,
+    /// 32位以内字母
+    Letter,
+    /// 5位以内符号
+    Symbol,
+    /// 32位以内数字、字母或符号
+    CharacterString,
+    /// 24小时制时间，见`SubscribeMessage::time`
+    Time,
+    /// 年月日，见`SubscribeMessage::date`
+    Date,
+    /// 1个币种符号+10位以内纯数字，可带小数
+    Amount,
+    /// 17位以内数字、符号
+    PhoneNumber,
+    /// 8位以内车牌号
+    CarNumber,
+    /// 10个以内纯汉字或20个以内纯字母/符号
+    Name,
+    /// 5个以内汉字
+    Phrase,
+}
+impl Field {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Field::Thing => "thing",
+            Field::Number => "number",
+            Field::Letter => "letter",
+            Field::Symbol => "symbol",
+            Field::CharacterString => "character_string",
+            Field::Time => "time",
+            Field::Date => "date",
+            Field::Amount => "amount",
+            Field::PhoneNumber => "phone_number",
+            Field::CarNumber => "car_number",
+            Field::Name => "name",
+            Field::Phrase => "phrase",
+        }
+    }
+
+    // 最大字符数，超出时微信返回47003
+    fn max_chars(self) -> Option<usize> {
+        match self {
+            Field::Thing => Some(20),
+            Field::Number | Field::Letter | Field::CharacterString => Some(32),
+            Field::Symbol | Field::Phrase => Some(5),
+            Field::Amount => Some(11),
+            Field::PhoneNumber => Some(17),
+            Field::CarNumber => Some(8),
+            Field::Name => Some(20),
+            Field::Time | Field::Date => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DataValue {
+    pub value: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SubscribeMessage {
+    pub template_id: String,
+    /// 点击消息后跳转的页面，可带参数，不填则没有跳转
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<String>,
+    /// 字段名（例如`thing1`）=> 值
+    pub data: BTreeMap<String, DataValue>,
+    pub miniprogram_state: MiniprogramState,
+    /// 进入小程序查看的语言类型，默认zh_CN
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    // 校验字段时发现的错误，发送时返回
+    #[serde(skip)]
+    invalid: Vec<String>,
+}
+impl SubscribeMessage {
+    pub fn new(template_id: &str) -> Self {
+        Self {
+            template_id: template_id.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn page(mut self, page: &str) -> Self {
+        self.page = Some(page.to_owned());
+        self
+    }
+
+    pub fn state(mut self, state: MiniprogramState) -> Self {
+        self.miniprogram_state = state;
+        self
+    }
+
+    pub fn lang(mut self, lang: &str) -> Self {
+        self.lang = Some(lang.to_owned());
+        self
+    }
+
+    /// 模板字段，例如`field(Field::Thing, 1, "预约成功")`对应模板里的`{{thing1.DATA}}`
+    pub fn field(mut self, kind: Field, index: u32, value: impl ToString) -> Self {
+        let name = format!("{}{}", kind.prefix(), index);
+        let value = value.to_string();
+        if let Some(max) = kind.max_chars() {
+            let count = value.chars().count();
+            if count > max {
+                self.invalid
+                    .push(format!("{}: {} chars exceeds {}", name, count, max));
+            }
+        }
+        self.data.insert(name, DataValue { value });
+        self
+    }
+
+    /// `time{index}`，格式为`2019年10月01日 15:01`
+    pub fn time(self, index: u32, time: NaiveDateTime) -> Self {
+        let value = time.format("%Y年%m月%d日 %H:%M");
+        self.field(Field::Time, index, value)
+    }
+
+    /// `date{index}`，格式为`2019年10月01日`
+    pub fn date(self, index: u32, date: NaiveDate) -> Self {
+        let value = date.format("%Y年%m月%d日");
+        self.field(Field::Date, index, value)
+    }
+
+    fn validate(&self) -> ClientResult<()> {
+        if self.invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(ClientError::Other(format!(
+                "invalid subscribe message: {}",
+                self.invalid.join(", ")
+            )))
+        }
+    }
+}
+
+/// 个人模板
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Template {
+    pub pri_tmpl_id: String,
+    pub title: String,
+    /// 例如`订单号:{{character_string1.DATA}}\n预约时间:{{time2.DATA}}`
+    pub content: String,
+    pub example: String,
+    /// 2为一次性订阅，3为长期订阅
+    #[serde(rename = "type")]
+    pub kind: i32,
+}
+
+/// 公共模板库中的关键词
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TemplateKeyword {
+    pub kid: i32,
+    pub name: String,
+    pub example: String,
+    /// 字段类型，例如`thing`、`time`
+    pub rule: String,
+}
+
+/// 小程序账号的类目
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Category {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+struct DataResponse<T> {
+    data: Vec<T>,
+}
+
+impl Miniprogram {
+    /// 发送订阅消息，用户未订阅时返回errcode 43101
+    pub async fn send_subscribe_message(
+        &self,
+        open_id: &str,
+        message: &SubscribeMessage,
+    ) -> ClientResult<()> {
+        message.validate()?;
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/cgi-bin/message/subscribe/send")
+            .access_token();
+
+        #[derive(Serialize)]
+        struct SendRequest<'a> {
+            touser: &'a str,
+            #[serde(flatten)]
+            message: &'a SubscribeMessage,
+        }
+        #[derive(Deserialize)]
+        struct SendResponse {}
+
+        let payload = SendRequest {
+            touser: open_id,
+            message,
+        };
+        self.0
+            .client
+            .post::<_, SendResponse>(endpoint, Some(&payload))
+            .await?;
+        Ok(())
+    }
+
+    /// 个人模板列表
+    pub async fn templates(&self) -> ClientResult<Vec<Template>> {
+        let endpoint =
+            Endpoint::new("https://api.weixin.qq.com/wxaapi/newtmpl/gettemplate").access_token();
+        let resp: DataResponse<Template> = self.0.client.get(endpoint).await?;
+        Ok(resp.data)
+    }
+
+    /// 从公共模板库选用模板，返回个人模板id
+    ///
+    /// `kid_list`为选用的关键词（见`pub_template_keywords`），按顺序对应模板字段
+    pub async fn add_template(
+        &self,
+        tid: &str,
+        kid_list: &[i32],
+        scene_desc: &str,
+    ) -> ClientResult<String> {
+        let endpoint =
+            Endpoint::new("https://api.weixin.qq.com/wxaapi/newtmpl/addtemplate").access_token();
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AddRequest<'a> {
+            tid: &'a str,
+            kid_list: &'a [i32],
+            scene_desc: &'a str,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AddResponse {
+            pri_tmpl_id: String,
+        }
+
+        let payload = AddRequest {
+            tid,
+            kid_list,
+            scene_desc,
+        };
+        let resp: AddResponse = self.0.client.post(endpoint, Some(&payload)).await?;
+        Ok(resp.pri_tmpl_id)
+    }
+
+    pub async fn delete_template(&self, pri_tmpl_id: &str) -> ClientResult<()> {
+        let endpoint =
+            Endpoint::new("https://api.weixin.qq.com/wxaapi/newtmpl/deltemplate").access_token();
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct DeleteRequest<'a> {
+            pri_tmpl_id: &'a str,
+        }
+        #[derive(Deserialize)]
+        struct DeleteResponse {}
+
+        let payload = DeleteRequest { pri_tmpl_id };
+        self.0
+            .client
+            .post::<_, DeleteResponse>(endpoint, Some(&payload))
+            .await?;
+        Ok(())
+    }
+
+    /// 小程序账号的类目，选用公共模板时需要
+    pub async fn template_categories(&self) -> ClientResult<Vec<Category>> {
+        let endpoint =
+            Endpoint::new("https://api.weixin.qq.com/wxaapi/newtmpl/getcategory").access_token();
+        let resp: DataResponse<Category> = self.0.client.get(endpoint).await?;
+        Ok(resp.data)
+    }
+
+    /// 公共模板的关键词
+    pub async fn pub_template_keywords(&self, tid: &str) -> ClientResult<Vec<TemplateKeyword>> {
+        let endpoint =
+            Endpoint::new("https://api.weixin.qq.com/wxaapi/newtmpl/getpubtemplatekeywords")
+                .access_token()
+                .query("tid", tid);
+        let resp: DataResponse<TemplateKeyword> = self.0.client.get(endpoint).await?;
+        Ok(resp.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::client::{transport::MockTransport, Client};
+    use super::super::{Config, Miniprogram};
+    use super::{Field, MiniprogramState, SubscribeMessage};
+    use crate::core::testing::TestResult;
+    use chrono::NaiveDate;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    fn mock_app() -> (Miniprogram, Arc<MockTransport>) {
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json(
+            "/cgi-bin/token",
+            json!({"access_token": "TOKEN", "expires_in": 7200}),
+        );
+        let cfg = Config {
+            appid: "APPID".to_owned(),
+            secret: "SECRET".to_owned(),
+            ..Default::default()
+        };
+        let client = Client::with_transport(Miniprogram::client_config(&cfg), mock.clone());
+        (Miniprogram::with_client(cfg, client), mock)
+    }
+
+    #[async_std::test]
+    async fn send_subscribe_message() -> TestResult<()> {
+        setup();
+
+        let (app, mock) = mock_app();
+        mock.reply_json(
+            "/message/subscribe/send",
+            json!({"errcode": 0, "errmsg": "ok"}),
+        )
+        .reply_json(
+            "/message/subscribe/send",
+            json!({"errcode": 43101, "errmsg": "user refuse to accept the msg"}),
+        );
+
+        let time = NaiveDate::from_ymd(2020, 10, 1).and_hms(15, 1, 0);
+        let message = SubscribeMessage::new("TEMPLATE_ID")
+            .page("pages/order/detail?id=1")
+            .field(Field::CharacterString, 1, "NO.001")
+            .field(Field::Thing, 2, "预约成功")
+            .time(3, time)
+            .state(MiniprogramState::Trial);
+        app.send_subscribe_message("OPENID", &message).await?;
+
+        let request = mock.requests().pop().expect("request");
+        let body: Value = serde_json::from_slice(&request.body.unwrap_or_default())?;
+        assert_eq!(
+            body,
+            json!({
+                "touser": "OPENID",
+                "template_id": "TEMPLATE_ID",
+                "page": "pages/order/detail?id=1",
+                "data": {
+                    "character_string1": {"value": "NO.001"},
+                    "thing2": {"value": "预约成功"},
+                    "time3": {"value": "2020年10月01日 15:01"},
+                },
+                "miniprogram_state": "trial",
+            })
+        );
+
+        let err = app
+            .send_subscribe_message("OPENID", &message)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Some(43101));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn invalid_field() -> TestResult<()> {
+        setup();
+
+        let (app, mock) = mock_app();
+        let message = SubscribeMessage::new("TEMPLATE_ID").field(
+            Field::Thing,
+            1,
+            "这是一段超过二十个字的内容所以不能发送给用户",
+        );
+        assert!(app
+            .send_subscribe_message("OPENID", &message)
+            .await
+            .is_err());
+        // 校验失败不会发出请求
+        assert!(mock.requests().is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn manage_templates() -> TestResult<()> {
+        setup();
+
+        let (app, mock) = mock_app();
+        mock.reply_json(
+            "/newtmpl/getcategory",
+            json!({"errcode": 0, "data": [{"id": 616, "name": "公交"}]}),
+        )
+        .reply_json(
+            "/newtmpl/getpubtemplatekeywords",
+            json!({"errcode": 0, "data": [{"kid": 1, "name": "物品名称", "example": "名称", "rule": "thing"}]}),
+        )
+        .reply_json(
+            "/newtmpl/addtemplate",
+            json!({"errcode": 0, "priTmplId": "PRI_ID"}),
+        )
+        .reply_json(
+            "/newtmpl/gettemplate",
+            json!({"errcode": 0, "data": [{
+                "priTmplId": "PRI_ID",
+                "title": "预约成功通知",
+                "content": "物品名称:{{thing1.DATA}}\n",
+                "example": "物品名称:名称\n",
+                "type": 2
+            }]}),
+        )
+        .reply_json("/newtmpl/deltemplate", json!({"errcode": 0}));
+
+        let categories = app.template_categories().await?;
+        assert_eq!(categories[0].id, 616);
+        let keywords = app.pub_template_keywords("99").await?;
+        assert_eq!(keywords[0].rule, "thing");
+        let id = app.add_template("99", &[1], "预约通知").await?;
+        assert_eq!(id, "PRI_ID");
+        let templates = app.templates().await?;
+        assert_eq!(templates[0].pri_tmpl_id, "PRI_ID");
+        assert_eq!(templates[0].kind, 2);
+        app.delete_template(&id).await?;
+
+        let requests = mock.requests();
+        assert!(requests[2].url.ends_with("access_token=TOKEN&tid=99"));
+        let body: Value = serde_json::from_slice(requests[3].body.as_deref().unwrap_or_default())?;
+        assert_eq!(
+            body,
+            json!({"tid": "99", "kidList": [1], "sceneDesc": "预约通知"})
+        );
+        Ok(())
+    }
+}
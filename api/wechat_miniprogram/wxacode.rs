@@ -0,0 +1,419 @@
+//! 小程序码
+//!
+//! * `wxacode_unlimited` 数量不限，通过`scene`传参（最多32个字符），适合门店、活动等
+//! * `wxacode` 数量有限（与`wxa_qrcode`共10万个），可直接带页面参数
+//! * `wxa_qrcode` 普通小程序二维码
+//!
+//! 成功时返回图片，失败时返回json（识别为`ClientError::Api`）
+//!
+//! ```rs
+//! let scene = Scene::new().id("s", store.id).encode()?;
+//! let options = WxacodeOptions { width: Some(430), transparent: true, ..Default::default() };
+//! let image = app.wxacode_unlimited(&scene, Some("pages/store/index"), &options).await?;
+//!
+//! // 小程序页面onLoad时将options.scene传回后台
+//! let store_id = Scene::decode(&scene)?.get_id("s");
+//! ```
+use super::super::client::{ClientError, ClientResult, Endpoint};
+use super::{Miniprogram, MiniprogramState};
+use http::Method;
+use serde::Serialize;
+use std::convert::TryFrom;
+
+/// scene的最大长度
+pub const SCENE_MAX_LEN: usize = 32;
+
+/// 线条颜色
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    /// 自动配置线条颜色
+    Auto,
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WxacodeOptions {
+    /// 二维码的宽度（px），最小280，最大1280，默认430
+    pub width: Option<u32>,
+    /// 默认黑色
+    pub color: Option<Color>,
+    /// 透明底色
+    pub transparent: bool,
+    /// 打开的版本，仅`wxacode_unlimited`有效，默认正式版
+    pub env_version: Option<MiniprogramState>,
+    /// 是否检查page存在（须已发布），仅`wxacode_unlimited`有效，默认检查
+    pub check_path: Option<bool>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+struct LineColor {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+// 请求参数，未设置的字段使用微信的默认值
+#[derive(Serialize, Default)]
+struct WxacodeRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scene: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auto_color: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line_color: Option<LineColor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_hyaline: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env_version: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    check_path: Option<bool>,
+}
+impl<'a> WxacodeRequest<'a> {
+    fn with_options(mut self, options: &WxacodeOptions) -> Self {
+        self.width = options.width;
+        match options.color {
+            Some(Color::Auto) => self.auto_color = Some(true),
+            Some(Color::Rgb(r, g, b)) => {
+                self.auto_color = Some(false);
+                self.line_color = Some(LineColor { r, g, b });
+            }
+            None => {}
+        }
+        if options.transparent {
+            self.is_hyaline = Some(true);
+        }
+        self
+    }
+}
+
+impl Miniprogram {
+    /// 数量不限的小程序码，`page`须是已发布的页面，不填则为首页
+    pub async fn wxacode_unlimited(
+        &self,
+        scene: &str,
+        page: Option<&str>,
+        options: &WxacodeOptions,
+    ) -> ClientResult<Vec<u8>> {
+        validate_scene(scene)?;
+        let endpoint =
+            Endpoint::new("https://api.weixin.qq.com/wxa/getwxacodeunlimit").access_token();
+        let env_version = options.env_version.map(|state| match state {
+            MiniprogramState::Developer => "develop",
+            MiniprogramState::Trial => "trial",
+            MiniprogramState::Formal => "release",
+        });
+        let payload = WxacodeRequest {
+            scene: Some(scene),
+            page,
+            env_version,
+            check_path: options.check_path,
+            ..Default::default()
+        }
+        .with_options(options);
+        self.wxacode_request(endpoint, &payload).await
+    }
+
+    /// 数量有限的小程序码，`path`可以带参数，例如`pages/index?query=1`，最多128字节
+    pub async fn wxacode(&self, path: &str, options: &WxacodeOptions) -> ClientResult<Vec<u8>> {
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/wxa/getwxacode").access_token();
+        let payload = WxacodeRequest {
+            path: Some(path),
+            ..Default::default()
+        }
+        .with_options(options);
+        self.wxacode_request(endpoint, &payload).await
+    }
+
+    /// 小程序二维码，数量有限
+    pub async fn wxa_qrcode(&self, path: &str, width: Option<u32>) -> ClientResult<Vec<u8>> {
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/cgi-bin/wxaapp/createwxaqrcode")
+            .access_token();
+        let payload = WxacodeRequest {
+            path: Some(path),
+            width,
+            ..Default::default()
+        };
+        self.wxacode_request(endpoint, &payload).await
+    }
+
+    async fn wxacode_request(
+        &self,
+        endpoint: Endpoint,
+        payload: &WxacodeRequest<'_>,
+    ) -> ClientResult<Vec<u8>> {
+        self.0
+            .client
+            .request(Method::POST, endpoint)
+            .json(payload)
+            .send_bytes()
+            .await
+    }
+}
+
+// scene只支持 数字、大小写英文以及部分特殊字符：!#$&'()*+,/:;=?@-._~
+// （与getUnlimited接口文档一致，含逗号）
+fn is_scene_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$&'()*+,/:;=?@-._~".contains(c)
+}
+
+fn validate_scene(scene: &str) -> ClientResult<()> {
+    if scene.is_empty() || scene.len() > SCENE_MAX_LEN {
+        return Err(ClientError::Other(format!(
+            "scene must be 1~{} characters: {}",
+            SCENE_MAX_LEN, scene
+        )));
+    }
+    if let Some(c) = scene.chars().find(|c| !is_scene_char(*c)) {
+        return Err(ClientError::Other(format!(
+            "invalid character {:?} in scene: {}",
+            c, scene
+        )));
+    }
+    Ok(())
+}
+
+/// scene参数的编解码
+///
+/// 编码为`key=value&key=value`，实体id使用36进制以节省长度，例如`s=2n9c&e=1`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scene(Vec<(String, String)>);
+impl Scene {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// 实体id，使用36进制
+    pub fn id(self, key: &str, id: i64) -> Self {
+        let value = if id < 0 {
+            format!("-{}", to_base36(id.unsigned_abs()))
+        } else {
+            to_base36(id as u64)
+        };
+        self.param(key, &value)
+    }
+
+    /// 其它参数，不能含有`&`、`=`以及scene不支持的字符
+    pub fn param(mut self, key: &str, value: &str) -> Self {
+        self.0.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_id(&self, key: &str) -> Option<i64> {
+        let value = self.get(key)?;
+        match value.strip_prefix('-') {
+            // i64::MIN的绝对值超出i64，按u64比较后取反
+            Some(abs) => from_base36(abs)
+                .filter(|v| *v <= i64::MIN.unsigned_abs())
+                .map(|v| (v as i64).wrapping_neg()),
+            None => from_base36(value).and_then(|v| i64::try_from(v).ok()),
+        }
+    }
+
+    /// 超过32个字符或含有不支持的字符时返回错误
+    pub fn encode(&self) -> ClientResult<String> {
+        let separator = |c: char| c == '&' || c == '=';
+        if let Some((k, v)) = self
+            .0
+            .iter()
+            .find(|(k, v)| k.contains(separator) || v.contains(separator))
+        {
+            return Err(ClientError::Other(format!(
+                "scene param must not contain '&' or '=': {}={}",
+                k, v
+            )));
+        }
+        let scene = self
+            .0
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+        validate_scene(&scene)?;
+        Ok(scene)
+    }
+
+    /// 小程序收到的`options.scene`是url编码的，未解码时这里先解码
+    pub fn decode(scene: &str) -> ClientResult<Scene> {
+        let scene = if scene.contains('%') {
+            decode_component(scene)?
+        } else {
+            scene.to_owned()
+        };
+        let pairs = scene
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.find('=') {
+                Some(i) => (pair[..i].to_owned(), pair[i + 1..].to_owned()),
+                None => (pair.to_owned(), String::new()),
+            })
+            .collect();
+        Ok(Scene(pairs))
+    }
+}
+
+fn to_base36(mut n: u64) -> String {
+    const DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    let mut out = vec![];
+    loop {
+        out.push(DIGITS[(n % 36) as usize]);
+        n /= 36;
+        if n == 0 {
+            break;
+        }
+    }
+    out.reverse();
+    String::from_utf8(out).unwrap_or_default()
+}
+
+fn from_base36(s: &str) -> Option<u64> {
+    if s.is_empty() {
+        return None;
+    }
+    u64::from_str_radix(&s.to_ascii_lowercase(), 36).ok()
+}
+
+fn decode_component(s: &str) -> ClientResult<String> {
+    let invalid = || ClientError::Other(format!("invalid scene encoding: {}", s));
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3).ok_or_else(invalid)?;
+            out.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::client::{transport::MockTransport, Client};
+    use super::super::{Config, Miniprogram, MiniprogramState};
+    use super::{Color, Scene, WxacodeOptions};
+    use crate::core::testing::TestResult;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    #[test]
+    fn scene_encode_decode() -> TestResult<()> {
+        let scene = Scene::new().id("s", 123456).id("e", -1).param("c", "A.b");
+        let encoded = scene.encode()?;
+        assert_eq!(encoded, "s=2n9c&e=-1&c=A.b");
+        let decoded = Scene::decode(&encoded)?;
+        assert_eq!(decoded, scene);
+        assert_eq!(decoded.get_id("s"), Some(123456));
+        assert_eq!(decoded.get_id("e"), Some(-1));
+        assert_eq!(decoded.get("c"), Some("A.b"));
+        assert_eq!(decoded.get_id("c"), None);
+        assert!(Scene::new().param("c", "a&b").encode().is_err());
+        assert!(Scene::new().param("c", "中文").encode().is_err());
+
+        // 小程序端未解码的scene
+        let decoded = Scene::decode("s%3D2n9c%26e%3D-1")?;
+        assert_eq!(decoded.get_id("s"), Some(123456));
+
+        // i64::MAX 只需要13个字符
+        let scene = Scene::new().id("a", i64::MAX).id("b", i64::MAX);
+        assert_eq!(Scene::decode(&scene.encode()?)?.get_id("b"), Some(i64::MAX));
+        let scene = scene.id("c", i64::MAX);
+        assert!(scene.encode().is_err());
+
+        // 负数的范围比正数多一个
+        for id in &[i64::MIN, i64::MIN + 1] {
+            let scene = Scene::new().id("a", *id);
+            assert_eq!(Scene::decode(&scene.encode()?)?.get_id("a"), Some(*id));
+        }
+        let too_small = format!("-{}", super::to_base36(i64::MIN.unsigned_abs() + 1));
+        assert_eq!(Scene::new().param("a", &too_small).get_id("a"), None);
+
+        assert_eq!(Scene::new().param("t", "1,2").encode()?, "t=1,2");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn wxacode_images() -> TestResult<()> {
+        setup();
+
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json(
+            "/cgi-bin/token",
+            json!({"access_token": "TOKEN", "expires_in": 7200}),
+        )
+        .reply_bytes("/wxa/getwxacodeunlimit", "image/jpeg", vec![1, 2, 3])
+        .reply_json(
+            "/wxa/getwxacode",
+            json!({"errcode": 41030, "errmsg": "invalid page"}),
+        )
+        .reply_bytes("/wxaapp/createwxaqrcode", "image/jpeg", vec![4, 5, 6]);
+        let cfg = Config::default();
+        let client = Client::with_transport(Miniprogram::client_config(&cfg), mock.clone());
+        let app = Miniprogram::with_client(cfg, client);
+
+        let options = WxacodeOptions {
+            width: Some(280),
+            color: Some(Color::Rgb(255, 0, 0)),
+            transparent: true,
+            env_version: Some(MiniprogramState::Trial),
+            check_path: Some(false),
+        };
+        let image = app
+            .wxacode_unlimited("s=2n9c", Some("pages/store/index"), &options)
+            .await?;
+        assert_eq!(image, vec![1, 2, 3]);
+
+        let err = app
+            .wxacode("pages/none", &WxacodeOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Some(41030));
+
+        let image = app.wxa_qrcode("pages/index?id=1", Some(430)).await?;
+        assert_eq!(image, vec![4, 5, 6]);
+
+        // scene不合法时不发出请求
+        assert!(app.wxacode_unlimited("中文", None, &options).await.is_err());
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 4);
+        let body: Value = serde_json::from_slice(requests[1].body.as_deref().unwrap_or_default())?;
+        assert_eq!(
+            body,
+            json!({
+                "scene": "s=2n9c",
+                "page": "pages/store/index",
+                "width": 280,
+                "auto_color": false,
+                "line_color": {"r": 255, "g": 0, "b": 0},
+                "is_hyaline": true,
+                "env_version": "trial",
+                "check_path": false,
+            })
+        );
+        let body: Value = serde_json::from_slice(requests[3].body.as_deref().unwrap_or_default())?;
+        assert_eq!(body, json!({"path": "pages/index?id=1", "width": 430}));
+        Ok(())
+    }
+}
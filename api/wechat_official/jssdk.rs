@@ -0,0 +1,136 @@
+//! JS-SDK
+//!
+//! 网页里调用`wx.config`需要的签名，jsapi_ticket有效期2小时，调用次数有限，必须缓存。
+//! 这里只做进程内缓存，每个副本各自获取，互不影响（与access_token不同，重新获取不会让旧的失效）
+use super::super::client::{CachedToken, ClientResult, Endpoint};
+use super::Official;
+use async_std::sync::RwLock;
+use chrono::{Duration, Utc};
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+pub(super) type TicketCache = RwLock<Option<CachedToken>>;
+
+/// `wx.config`的参数（`debug`、`jsApiList`由前端补充）
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct JsSdkConfig {
+    pub app_id: String,
+    pub timestamp: i64,
+    pub nonce_str: String,
+    pub signature: String,
+}
+
+impl Official {
+    pub async fn jsapi_ticket(&self) -> ClientResult<String> {
+        if let Some(ticket) = self.0.ticket.read().await.as_ref() {
+            if ticket.valid() {
+                return Ok(ticket.access_token.clone());
+            }
+        }
+
+        let mut cache = self.0.ticket.write().await;
+        // 等锁期间可能已被其它请求刷新
+        if let Some(ticket) = cache.as_ref() {
+            if ticket.valid() {
+                return Ok(ticket.access_token.clone());
+            }
+        }
+
+        #[derive(Deserialize)]
+        struct TicketResponse {
+            ticket: String,
+            expires_in: i64,
+        }
+
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/cgi-bin/ticket/getticket")
+            .access_token()
+            .query("type", "jsapi");
+        let resp: TicketResponse = self.0.client.get(endpoint).await?;
+        *cache = Some(CachedToken::new(
+            resp.ticket.clone(),
+            Duration::seconds(resp.expires_in),
+        ));
+        Ok(resp.ticket)
+    }
+
+    /// `url`为调用JS-SDK的页面地址（`#`及其后面的部分会被去掉）
+    pub async fn js_sdk_config(&self, url: &str) -> ClientResult<JsSdkConfig> {
+        let ticket = self.jsapi_ticket().await?;
+        let timestamp = Utc::now().timestamp();
+        let nonce_str = nonce_str();
+        let signature = signature(&ticket, &nonce_str, timestamp, url);
+        Ok(JsSdkConfig {
+            app_id: self.0.cfg.appid.clone(),
+            timestamp,
+            nonce_str,
+            signature,
+        })
+    }
+}
+
+/// `sha1("jsapi_ticket=..&noncestr=..&timestamp=..&url=..")`
+pub fn signature(ticket: &str, nonce_str: &str, timestamp: i64, url: &str) -> String {
+    let url = url.split('#').next().unwrap_or_default();
+    let mut hasher = Sha1::new();
+    hasher.input_str(&format!(
+        "jsapi_ticket={}&noncestr={}&timestamp={}&url={}",
+        ticket, nonce_str, timestamp, url
+    ));
+    hasher.result_str()
+}
+
+fn nonce_str() -> String {
+    let mut bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock_official;
+    use super::signature;
+    use crate::core::testing::TestResult;
+    use serde_json::json;
+
+    #[test]
+    fn sign() {
+        // 官方文档的示例
+        let ticket = "sM4AOVdWfPE4DxkXGEs8VMCPGGVi4C3VM0P37wVUCFvkVAy_90u5h9nbSlYy3-Sl-HhTdfl2fzFy1AOcHKP7qg";
+        assert_eq!(
+            signature(
+                ticket,
+                "Wm3WZYTPz0wzccnW",
+                1414587457,
+                "http://mp.weixin.qq.com?params=value#top"
+            ),
+            "0f9de62fce790f9a083d5c99e95740ceb90c27ed"
+        );
+    }
+
+    #[async_std::test]
+    async fn js_sdk_config_offline() -> TestResult<()> {
+        let (app, mock) = mock_official();
+        mock.reply_json(
+            "/cgi-bin/ticket/getticket",
+            json!({"errcode": 0, "errmsg": "ok", "ticket": "TICKET", "expires_in": 7200}),
+        );
+
+        let url = "https://h5.example.com/order?id=1";
+        let config = app.js_sdk_config(url).await?;
+        assert_eq!(config.app_id, "APPID");
+        assert_eq!(
+            config.signature,
+            signature("TICKET", &config.nonce_str, config.timestamp, url)
+        );
+
+        // ticket已缓存
+        app.js_sdk_config(url).await?;
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].url.ends_with("access_token=TOKEN&type=jsapi"));
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+//! 公众号
+//!
+//! * 网页授权（H5登录），见`oauth`
+//! * 模板消息，见`template_message`
+//! * JS-SDK的jsapi_ticket与签名，见`jssdk`
+use super::client::{
+    redact, CircuitBreaker, CircuitStatus, Client, ClientResult, Config as ClientConfig, Endpoint,
+    Platform,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+mod jssdk;
+pub use jssdk::JsSdkConfig;
+mod oauth;
+pub use oauth::{OauthScope, OauthToken, OauthUserInfo};
+mod template_message;
+pub use template_message::{MiniprogramPage, TemplateMessage};
+
+// official 配置
+#[derive(Serialize, Deserialize, Default)]
+pub struct Config {
+    pub appid: String,
+    pub secret: String,
+    /// 超时、代理、重试等（token_url由此处自动设置）
+    #[serde(default)]
+    pub client: ClientConfig,
+}
+impl Config {
+    pub fn from_env() -> Config {
+        use dotenv::dotenv;
+        use std::env;
+
+        dotenv().ok();
+        let appid = env::var("WECHAT_OFFICIAL_APPID")
+            .expect("value `WECHAT_OFFICIAL_APPID` not presented in .env file");
+        let secret = env::var("WECHAT_OFFICIAL_SECRET")
+            .expect("value `WECHAT_OFFICIAL_SECRET` not presented in .env file");
+
+        Config {
+            appid,
+            secret,
+            ..Default::default()
+        }
+    }
+}
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("appid", &self.appid)
+            .field("secret", &redact::secret(&self.secret))
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+// official 结构
+#[derive(Clone)]
+pub struct Official(Arc<OfficialInner>);
+
+struct OfficialInner {
+    cfg: Config,
+    client: Client,
+    // jsapi_ticket的本地缓存，见`jssdk`
+    ticket: jssdk::TicketCache,
+}
+impl Official {
+    pub fn new(cfg: Config) -> ClientResult<Self> {
+        let client = Client::new(Self::client_config(&cfg))?;
+        Ok(Self::with_client(cfg, client))
+    }
+
+    /// 使用自行构建的Client，可以同时设置token_store、metrics、breaker，测试时注入`MockTransport`
+    pub fn with_client(cfg: Config, client: Client) -> Self {
+        Self(Arc::new(OfficialInner {
+            cfg,
+            client,
+            ticket: Default::default(),
+        }))
+    }
+
+    /// Client的配置，token_url等由`cfg`生成
+    pub fn client_config(cfg: &Config) -> ClientConfig {
+        let token_url = Endpoint::new("https://api.weixin.qq.com/cgi-bin/token")
+            .query("grant_type", "client_credential")
+            .query("appid", &cfg.appid)
+            .query("secret", &cfg.secret);
+        ClientConfig {
+            token_url: token_url.to_string(),
+            platform: Platform::Wechat,
+            token_key: format!("wechat_official:{}", cfg.appid),
+            ..cfg.client.clone()
+        }
+    }
+
+    pub fn appid(&self) -> &str {
+        &self.0.cfg.appid
+    }
+
+    /// 各接口的熔断状态，见`client::breaker`
+    pub fn circuit_states(&self) -> Vec<CircuitStatus> {
+        self.0.client.circuit_states()
+    }
+
+    /// 熔断器，同一平台的其它Client可以共用，见`with_client`
+    pub fn circuit_breaker(&self) -> Arc<CircuitBreaker> {
+        self.0.client.circuit_breaker()
+    }
+
+    pub async fn access_token(&self) -> ClientResult<String> {
+        self.0.client.access_token().await
+    }
+}
+
+impl Official {
+    /// 用户的基本信息，未关注时只有openid（与unionid）
+    pub async fn user_info(&self, open_id: &str) -> ClientResult<UserInfo> {
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/cgi-bin/user/info")
+            .access_token()
+            .query("openid", open_id)
+            .query("lang", "zh_CN");
+        self.0.client.get(endpoint).await
+    }
+}
+
+/// 关注公众号的用户信息
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UserInfo {
+    /// 0为未关注
+    pub subscribe: i32,
+    pub openid: String,
+    pub unionid: Option<String>,
+    pub language: Option<String>,
+    /// 关注时间
+    pub subscribe_time: Option<i64>,
+    pub remark: Option<String>,
+    #[serde(default)]
+    pub tagid_list: Vec<i32>,
+    /// 例如ADD_SCENE_QR_CODE
+    pub subscribe_scene: Option<String>,
+    pub qr_scene_str: Option<String>,
+}
+impl UserInfo {
+    pub fn is_subscribed(&self) -> bool {
+        self.subscribe == 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::client::{transport::MockTransport, Client};
+    use super::{Config, Official};
+    use crate::core::testing::TestResult;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    pub(super) fn mock_official() -> (Official, Arc<MockTransport>) {
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json(
+            "/cgi-bin/token",
+            json!({"access_token": "TOKEN", "expires_in": 7200}),
+        );
+        let cfg = Config {
+            appid: "APPID".to_owned(),
+            secret: "SECRET".to_owned(),
+            ..Default::default()
+        };
+        let client = Client::with_transport(Official::client_config(&cfg), mock.clone());
+        (Official::with_client(cfg, client), mock)
+    }
+
+    #[test]
+    #[ignore = "需要提供WECHAT_OFFICIAL_APPID与WECHAT_OFFICIAL_SECRET"]
+    fn read_config_from_env() {
+        setup();
+
+        let cfg = Config::from_env();
+        info!("wechat_official cfg: {:#?}", cfg);
+    }
+
+    #[async_std::test]
+    async fn user_info_offline() -> TestResult<()> {
+        setup();
+
+        let (app, mock) = mock_official();
+        mock.reply_json(
+            "/cgi-bin/user/info",
+            json!({
+                "subscribe": 1,
+                "openid": "OPENID",
+                "language": "zh_CN",
+                "subscribe_time": 1382694957,
+                "unionid": "UNIONID",
+                "remark": "",
+                "groupid": 0,
+                "tagid_list": [128, 2],
+                "subscribe_scene": "ADD_SCENE_QR_CODE",
+                "qr_scene": 98765,
+                "qr_scene_str": ""
+            }),
+        );
+
+        let user = app.user_info("OPENID").await?;
+        assert!(user.is_subscribed());
+        assert_eq!(user.unionid.as_deref(), Some("UNIONID"));
+        assert_eq!(
+            mock.requests()[1].url,
+            "https://api.weixin.qq.com/cgi-bin/user/info?access_token=TOKEN&openid=OPENID&lang=zh_CN"
+        );
+        Ok(())
+    }
+}
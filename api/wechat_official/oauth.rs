@@ -0,0 +1,188 @@
+//! 网页授权
+//!
+//! 1. 跳转到`authorize_url`，用户同意后带着code回到redirect_uri
+//! 2. `oauth_access_token(code)`换取openid（`snsapi_userinfo`时还有unionid）
+//! 3. `snsapi_userinfo`时可以再用`oauth_user_info`获取昵称、头像
+//!
+//! 这里的access_token只用于获取该用户的信息，与`Official::access_token`无关
+use super::super::client::{redact, ClientResult, Endpoint};
+use super::Official;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OauthScope {
+    /// 静默授权，只能获取openid
+    Base,
+    /// 需要用户同意，可以获取unionid、昵称与头像
+    UserInfo,
+}
+impl OauthScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OauthScope::Base => "snsapi_base",
+            OauthScope::UserInfo => "snsapi_userinfo",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OauthToken {
+    pub access_token: String,
+    pub expires_in: i64,
+    pub refresh_token: String,
+    pub openid: String,
+    /// 逗号分隔，例如`snsapi_userinfo`
+    pub scope: String,
+    /// 仅`snsapi_userinfo`时返回
+    pub unionid: Option<String>,
+    /// 1为快照页模式的虚拟账号，此时拿不到真实的openid
+    #[serde(default)]
+    pub is_snapshotuser: i32,
+}
+impl OauthToken {
+    pub fn has_scope(&self, scope: OauthScope) -> bool {
+        self.scope.split(',').any(|s| s == scope.as_str())
+    }
+}
+impl fmt::Debug for OauthToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OauthToken")
+            .field("access_token", &redact::secret(&self.access_token))
+            .field("expires_in", &self.expires_in)
+            .field("refresh_token", &redact::secret(&self.refresh_token))
+            .field("openid", &self.openid)
+            .field("scope", &self.scope)
+            .field("unionid", &self.unionid)
+            .field("is_snapshotuser", &self.is_snapshotuser)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OauthUserInfo {
+    pub openid: String,
+    pub nickname: String,
+    /// 0未知 1男性 2女性
+    #[serde(default)]
+    pub sex: i16,
+    #[serde(default)]
+    pub province: String,
+    #[serde(default)]
+    pub city: String,
+    #[serde(default)]
+    pub country: String,
+    /// 头像，用户更换后原地址会失效
+    #[serde(default)]
+    pub headimgurl: String,
+    pub unionid: Option<String>,
+}
+
+impl Official {
+    /// 授权页地址，`state`会原样带回redirect_uri（a-zA-Z0-9，最多128字节），用于防CSRF
+    pub fn authorize_url(&self, redirect_uri: &str, scope: OauthScope, state: &str) -> String {
+        let endpoint = Endpoint::new("https://open.weixin.qq.com/connect/oauth2/authorize")
+            .query("appid", &self.0.cfg.appid)
+            .query("redirect_uri", redirect_uri)
+            .query("response_type", "code")
+            .query("scope", scope.as_str())
+            .query("state", state);
+        format!("{}#wechat_redirect", endpoint)
+    }
+
+    /// 用code换取openid，code只能使用一次，5分钟内有效
+    pub async fn oauth_access_token(&self, code: &str) -> ClientResult<OauthToken> {
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/sns/oauth2/access_token")
+            .query("appid", &self.0.cfg.appid)
+            .query("secret", &self.0.cfg.secret)
+            .query("code", code)
+            .query("grant_type", "authorization_code");
+        self.0.client.get(endpoint).await
+    }
+
+    /// access_token有效期2小时，refresh_token有效期30天
+    pub async fn refresh_oauth_token(&self, refresh_token: &str) -> ClientResult<OauthToken> {
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/sns/oauth2/refresh_token")
+            .query("appid", &self.0.cfg.appid)
+            .query("grant_type", "refresh_token")
+            .query("refresh_token", refresh_token);
+        self.0.client.get(endpoint).await
+    }
+
+    /// 需要`snsapi_userinfo`授权
+    pub async fn oauth_user_info(&self, token: &OauthToken) -> ClientResult<OauthUserInfo> {
+        let endpoint = Endpoint::new("https://api.weixin.qq.com/sns/userinfo")
+            .query("access_token", &token.access_token)
+            .query("openid", &token.openid)
+            .query("lang", "zh_CN");
+        self.0.client.get(endpoint).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock_official;
+    use super::OauthScope;
+    use crate::core::testing::TestResult;
+    use serde_json::json;
+
+    #[test]
+    fn authorize_url() {
+        let (app, _) = mock_official();
+        assert_eq!(
+            app.authorize_url(
+                "https://h5.example.com/login?from=menu",
+                OauthScope::UserInfo,
+                "STATE"
+            ),
+            "https://open.weixin.qq.com/connect/oauth2/authorize?appid=APPID\
+             &redirect_uri=https%3A%2F%2Fh5.example.com%2Flogin%3Ffrom%3Dmenu\
+             &response_type=code&scope=snsapi_userinfo&state=STATE#wechat_redirect"
+        );
+    }
+
+    #[async_std::test]
+    async fn oauth_offline() -> TestResult<()> {
+        let (app, mock) = mock_official();
+        mock.reply_json(
+            "/sns/oauth2/access_token",
+            json!({
+                "access_token": "OAUTH_TOKEN",
+                "expires_in": 7200,
+                "refresh_token": "REFRESH_TOKEN",
+                "openid": "OPENID",
+                "scope": "snsapi_userinfo",
+                "unionid": "UNIONID"
+            }),
+        )
+        .reply_json(
+            "/sns/userinfo",
+            json!({
+                "openid": "OPENID",
+                "nickname": "NICKNAME",
+                "sex": 1,
+                "province": "PROVINCE",
+                "city": "CITY",
+                "country": "COUNTRY",
+                "headimgurl": "https://thirdwx.qlogo.cn/mmopen/46/0",
+                "privilege": [],
+                "unionid": "UNIONID"
+            }),
+        );
+
+        let token = app.oauth_access_token("CODE").await?;
+        assert!(token.has_scope(OauthScope::UserInfo));
+        assert!(!format!("{:?}", token).contains("OAUTH_TOKEN"));
+        let user = app.oauth_user_info(&token).await?;
+        assert_eq!(user.unionid.as_deref(), Some("UNIONID"));
+
+        // 不需要公众号的access_token
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[1].url,
+            "https://api.weixin.qq.com/sns/userinfo?access_token=OAUTH_TOKEN&openid=OPENID&lang=zh_CN"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! 模板消息
+//!
+//! 只能发给已关注公众号的用户
+//!
+//! ```rs
+//! let message = TemplateMessage::new("TEMPLATE_ID")
+//!     .field("character_string1", &order.no)
+//!     .field("thing2", "预约成功")
+//!     .miniprogram(&miniprogram_appid, "pages/order/detail?id=1");
+//! official.send_template_message(&open_id, &message).await?;
+//! ```
+use super::super::client::{ClientResult, Endpoint};
+use super::super::wechat_miniprogram::DataValue;
+use super::Official;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// 点击消息后打开的小程序页面，小程序需要与公众号关联
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MiniprogramPage {
+    pub appid: String,
+    pub pagepath: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct TemplateMessage {
+    pub template_id: String,
+    /// 点击消息后打开的网页，与`miniprogram`同时设置时优先打开小程序
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub miniprogram: Option<MiniprogramPage>,
+    /// 防重入，同一个id只发送一次
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_msg_id: Option<String>,
+    /// 字段名（例如`thing2`）=> 值
+    pub data: BTreeMap<String, DataValue>,
+}
+impl TemplateMessage {
+    pub fn new(template_id: &str) -> Self {
+        Self {
+            template_id: template_id.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn url(mut self, url: &str) -> Self {
+        self.url = Some(url.to_owned());
+        self
+    }
+
+    pub fn miniprogram(mut self, appid: &str, pagepath: &str) -> Self {
+        self.miniprogram = Some(MiniprogramPage {
+            appid: appid.to_owned(),
+            pagepath: pagepath.to_owned(),
+        });
+        self
+    }
+
+    pub fn client_msg_id(mut self, id: &str) -> Self {
+        self.client_msg_id = Some(id.to_owned());
+        self
+    }
+
+    /// 模板字段，例如`field("thing2", "预约成功")`对应模板里的`{{thing2.DATA}}`
+    pub fn field(mut self, name: &str, value: impl ToString) -> Self {
+        let value = value.to_string();
+        self.data.insert(name.to_owned(), DataValue { value });
+        self
+    }
+}
+
+impl Official {
+    /// 发送模板消息，返回msgid；用户未关注时返回errcode 43004
+    pub async fn send_template_message(
+        &self,
+        open_id: &str,
+        message: &TemplateMessage,
+    ) -> ClientResult<i64> {
+        let endpoint =
+            Endpoint::new("https://api.weixin.qq.com/cgi-bin/message/template/send").access_token();
+
+        #[derive(Serialize)]
+        struct SendRequest<'a> {
+            touser: &'a str,
+            #[serde(flatten)]
+            message: &'a TemplateMessage,
+        }
+        #[derive(Deserialize)]
+        struct SendResponse {
+            msgid: i64,
+        }
+
+        let payload = SendRequest {
+            touser: open_id,
+            message,
+        };
+        let resp: SendResponse = self.0.client.post(endpoint, Some(&payload)).await?;
+        Ok(resp.msgid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock_official;
+    use super::TemplateMessage;
+    use crate::core::testing::TestResult;
+    use serde_json::{json, Value};
+
+    #[async_std::test]
+    async fn send_template_message_offline() -> TestResult<()> {
+        let (app, mock) = mock_official();
+        mock.reply_json(
+            "/cgi-bin/message/template/send",
+            json!({"errcode": 0, "errmsg": "ok", "msgid": 200228332}),
+        )
+        .reply_json(
+            "/cgi-bin/message/template/send",
+            json!({"errcode": 43004, "errmsg": "require subscribe"}),
+        );
+
+        let message = TemplateMessage::new("TEMPLATE_ID")
+            .field("character_string1", "20301217001")
+            .field("thing2", "预约成功")
+            .miniprogram("MINIPROGRAM_APPID", "pages/order/detail?id=1");
+        assert_eq!(
+            app.send_template_message("OPENID", &message).await?,
+            200228332
+        );
+        let err = app
+            .send_template_message("OPENID", &message)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Some(43004));
+
+        let body: Value =
+            serde_json::from_slice(mock.requests()[1].body.as_deref().unwrap_or_default())?;
+        assert_eq!(
+            body,
+            json!({
+                "touser": "OPENID",
+                "template_id": "TEMPLATE_ID",
+                "miniprogram": {"appid": "MINIPROGRAM_APPID", "pagepath": "pages/order/detail?id=1"},
+                "data": {
+                    "character_string1": {"value": "20301217001"},
+                    "thing2": {"value": "预约成功"}
+                }
+            })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+/// 集成到tide
+///
+/// 处理成功返回204，否则返回`{"code":"FAIL","message":".."}`，微信支付会按策略重新通知
+///
+/// ```rs
+/// use crate::core::api::wechat_pay::integrate_with_tide::PayNotifyEndpoint;
+/// app.at("/wechat/pay/notify").post(PayNotifyEndpoint::new(pay, Arc::new(handler)));
+/// ```
+use super::{PayError, PayNotifyHandler, WechatPay};
+use futures::future::BoxFuture;
+use serde_json::json;
+use std::sync::Arc;
+use tide::{Request, Response, StatusCode};
+
+const SIGNATURE_HEADERS: [&str; 4] = [
+    "Wechatpay-Timestamp",
+    "Wechatpay-Nonce",
+    "Wechatpay-Signature",
+    "Wechatpay-Serial",
+];
+
+#[derive(Clone)]
+pub struct PayNotifyEndpoint {
+    pay: WechatPay,
+    handler: Arc<dyn PayNotifyHandler>,
+}
+impl PayNotifyEndpoint {
+    pub fn new(pay: WechatPay, handler: Arc<dyn PayNotifyHandler>) -> Self {
+        Self { pay, handler }
+    }
+}
+
+impl<State: Send + Sync + 'static> tide::Endpoint<State> for PayNotifyEndpoint {
+    fn call<'a>(&'a self, mut req: Request<State>) -> BoxFuture<'a, tide::Result> {
+        Box::pin(async move {
+            let headers: Vec<(String, String)> = SIGNATURE_HEADERS
+                .iter()
+                .filter_map(|&name| {
+                    req.header(name)
+                        .map(|v| (name.to_owned(), v.as_str().to_owned()))
+                })
+                .collect();
+            let body = req.body_bytes().await?;
+            let result = self
+                .pay
+                .handle_notification(&headers, &body, self.handler.as_ref())
+                .await;
+
+            Ok(match result {
+                Ok(()) => Response::new(StatusCode::NoContent),
+                Err(e) => into_response(e),
+            })
+        })
+    }
+}
+
+/// 签名错误为401，内容错误为400，handler错误为500
+fn into_response(e: PayError) -> Response {
+    let status = match e {
+        PayError::Signature(_) => StatusCode::Unauthorized,
+        PayError::Decrypt(_) | PayError::Serde(_) | PayError::Mismatch(_) => StatusCode::BadRequest,
+        _ => StatusCode::InternalServerError,
+    };
+    warn!("pay notification rejected: {}", e);
+    let mut res = Response::new(status);
+    res.set_body(json!({"code": "FAIL", "message": e.to_string()}));
+    res
+}
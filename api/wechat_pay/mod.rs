@@ -0,0 +1,381 @@
+//! 微信支付 APIv3（小程序JSAPI支付）
+//!
+//! * 下单、生成`wx.requestPayment`的参数、查单、关单，见`order`
+//! * 退款、查询退款，见`refund`
+//! * 支付、退款结果的回调，见`notify`与`integrate_with_tide::PayNotifyEndpoint`
+//!
+//! 请求用商户私钥签名，应答与回调用平台公钥验签（见`sign`）。
+//!
+//! ```rs
+//! let pay = WechatPay::new(wechat_pay::Config::from_env())?;
+//! let order = JsapiOrder::new(&out_trade_no, "体检套餐", 19900, &open_id);
+//! let params = pay.create_jsapi_payment(&order).await?;
+//! // params 原样交给小程序调用 wx.requestPayment
+//! ```
+use super::client::transport::{HttpRequest, HttpTransport, IsahcTransport};
+use super::client::{redact, ClientError, HttpConfig};
+use chrono::Utc;
+use http::Method;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+use thiserror::Error as ThisError;
+
+pub mod integrate_with_tide;
+mod notify;
+mod order;
+mod refund;
+mod sign;
+#[cfg(test)]
+pub(crate) mod testing;
+
+pub use notify::{PayNotification, PayNotifyHandler, RefundNotification};
+pub use order::{Amount, JsapiOrder, Payer, RequestPayment, TradeState, Transaction};
+pub use refund::{Refund, RefundAmount, RefundResult, RefundStatus};
+pub use sign::{MerchantSigner, PlatformVerifier};
+
+const BASE_URL: &str = "https://api.mch.weixin.qq.com";
+// 应答、回调的时间戳与服务器时间相差超过5分钟视为重放
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Config {
+    /// 商户号
+    pub mchid: String,
+    /// 小程序的appid
+    pub appid: String,
+    /// 商户API证书的序列号
+    pub serial_no: String,
+    /// 商户API私钥（apiclient_key.pem的内容）
+    pub private_key: String,
+    /// APIv3密钥，解密回调用
+    pub api_v3_key: String,
+    /// 支付、退款结果的回调地址
+    pub notify_url: String,
+    /// 平台证书序列号（或微信支付公钥ID） => 公钥PEM
+    #[serde(default)]
+    pub platform_keys: HashMap<String, String>,
+    /// 默认为`https://api.mch.weixin.qq.com`
+    #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub http: HttpConfig,
+}
+impl Config {
+    pub fn from_env() -> Self {
+        let var = |name: &str| env::var(name).unwrap_or_else(|_| panic!("{} is missing!", name));
+        let read = |name: &str| {
+            let path = var(name);
+            fs::read_to_string(&path).unwrap_or_else(|e| panic!("{} {}: {}", name, path, e))
+        };
+
+        let mut platform_keys = HashMap::new();
+        platform_keys.insert(
+            var("WECHAT_PAY_PUBLIC_KEY_ID"),
+            read("WECHAT_PAY_PUBLIC_KEY_PATH"),
+        );
+        Self {
+            mchid: var("WECHAT_PAY_MCHID"),
+            appid: var("WECHAT_WEAPP_APPID"),
+            serial_no: var("WECHAT_PAY_SERIAL_NO"),
+            private_key: read("WECHAT_PAY_PRIVATE_KEY_PATH"),
+            api_v3_key: var("WECHAT_PAY_API_V3_KEY"),
+            notify_url: var("WECHAT_PAY_NOTIFY_URL"),
+            platform_keys,
+            base_url: None,
+            http: HttpConfig::default(),
+        }
+    }
+}
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("mchid", &self.mchid)
+            .field("appid", &self.appid)
+            .field("serial_no", &self.serial_no)
+            .field("private_key", &redact::secret(&self.private_key))
+            .field("api_v3_key", &redact::secret(&self.api_v3_key))
+            .field("notify_url", &self.notify_url)
+            .field("platform_keys", &self.platform_keys.keys())
+            .field("base_url", &self.base_url)
+            .finish()
+    }
+}
+
+#[derive(ThisError, Debug)]
+pub enum PayError {
+    #[error(transparent)]
+    Client(#[from] ClientError),
+    #[error(transparent)]
+    Serde(#[from] serde_json::error::Error),
+    /// 接口返回的错误码，例如ORDERPAID、ORDER_NOT_EXIST
+    #[error("Pay Api Error({status} {code}): {message}")]
+    Api {
+        status: u16,
+        code: String,
+        message: String,
+    },
+    #[error("Pay Signature Error: {0}")]
+    Signature(String),
+    #[error("Pay Decrypt Error: {0}")]
+    Decrypt(String),
+    #[error("Pay Key Error: {0}")]
+    Key(String),
+    /// 回调的商户号、appid与配置不一致
+    #[error("Pay Merchant Mismatch: {0}")]
+    Mismatch(String),
+    /// `PayNotifyHandler`返回的错误
+    #[error("Pay Handler Error: {0}")]
+    Handler(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Pay Error: {0}")]
+    Other(String),
+}
+pub type PayResult<T> = Result<T, PayError>;
+impl PayError {
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            PayError::Api { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WechatPay(Arc<WechatPayInner>);
+
+struct WechatPayInner {
+    cfg: Config,
+    signer: MerchantSigner,
+    verifier: PlatformVerifier,
+    transport: Arc<dyn HttpTransport>,
+}
+
+impl WechatPay {
+    pub fn new(cfg: Config) -> PayResult<Self> {
+        let transport = IsahcTransport::new(&cfg.http)?;
+        Self::with_transport(cfg, Arc::new(transport))
+    }
+
+    /// 注入传输层，例如测试时使用`MockTransport`
+    pub fn with_transport(cfg: Config, transport: Arc<dyn HttpTransport>) -> PayResult<Self> {
+        let signer = MerchantSigner::from_pem(&cfg.serial_no, &cfg.private_key)?;
+        let mut verifier = PlatformVerifier::default();
+        for (serial_no, pem) in &cfg.platform_keys {
+            verifier.add_pem(serial_no, pem)?;
+        }
+        Ok(Self(Arc::new(WechatPayInner {
+            cfg,
+            signer,
+            verifier,
+            transport,
+        })))
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.0.cfg
+    }
+
+    pub(crate) async fn get<O: DeserializeOwned>(&self, path: &str) -> PayResult<O> {
+        let body = self.send(Method::GET, path, None).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    pub(crate) async fn post<I: Serialize, O: DeserializeOwned>(
+        &self,
+        path: &str,
+        payload: &I,
+    ) -> PayResult<O> {
+        let body = self
+            .send(Method::POST, path, Some(serde_json::to_string(payload)?))
+            .await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// 返回验签后的body（204时为空）
+    pub(crate) async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<String>,
+    ) -> PayResult<Vec<u8>> {
+        let cfg = &self.0.cfg;
+        let url = format!("{}{}", cfg.base_url.as_deref().unwrap_or(BASE_URL), path);
+        let timestamp = Utc::now().timestamp().to_string();
+        let nonce = sign::nonce_str();
+        let message = format!(
+            "{}\n{}\n{}\n{}\n{}\n",
+            method.as_str(),
+            path,
+            timestamp,
+            nonce,
+            body.as_deref().unwrap_or_default()
+        );
+        let authorization = format!(
+            "{} mchid=\"{}\",nonce_str=\"{}\",signature=\"{}\",timestamp=\"{}\",serial_no=\"{}\"",
+            sign::AUTH_SCHEMA,
+            cfg.mchid,
+            nonce,
+            self.0.signer.sign(&message)?,
+            timestamp,
+            self.0.signer.serial_no()
+        );
+
+        let mut headers = vec![
+            ("Authorization".to_owned(), authorization),
+            ("Accept".to_owned(), "application/json".to_owned()),
+        ];
+        if body.is_some() {
+            headers.push(("Content-Type".to_owned(), "application/json".to_owned()));
+        }
+        debug!(
+            "pay {} {} <= {}",
+            method,
+            path,
+            redact::body(body.as_deref().unwrap_or_default())
+        );
+        let response = self
+            .0
+            .transport
+            .send(HttpRequest {
+                method,
+                url,
+                headers,
+                body: body.map(String::into_bytes),
+            })
+            .await?;
+        debug!(
+            "pay {} => {} {}",
+            path,
+            response.status,
+            redact::body(&String::from_utf8_lossy(&response.body))
+        );
+
+        if !(200..300).contains(&response.status) {
+            #[derive(Deserialize)]
+            struct ErrorResponse {
+                code: String,
+                message: String,
+            }
+            return Err(
+                match serde_json::from_slice::<ErrorResponse>(&response.body) {
+                    Ok(e) => PayError::Api {
+                        status: response.status,
+                        code: e.code,
+                        message: e.message,
+                    },
+                    Err(_) => ClientError::Status(response.status).into(),
+                },
+            );
+        }
+        self.verify(&response.headers, &response.body)?;
+        Ok(response.body)
+    }
+
+    /// 验证应答、回调的平台签名
+    pub(crate) fn verify(&self, headers: &[(String, String)], body: &[u8]) -> PayResult<()> {
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+                .ok_or_else(|| PayError::Signature(format!("{} is missing", name)))
+        };
+        let timestamp = header("Wechatpay-Timestamp")?;
+        let nonce = header("Wechatpay-Nonce")?;
+        let signature = header("Wechatpay-Signature")?;
+        let serial_no = header("Wechatpay-Serial")?;
+
+        let body = std::str::from_utf8(body).map_err(|e| PayError::Other(e.to_string()))?;
+        let message = format!("{}\n{}\n{}\n", timestamp, nonce, body);
+        self.0.verifier.verify(serial_no, &message, signature)?;
+
+        let timestamp: i64 = timestamp
+            .parse()
+            .map_err(|_| PayError::Signature(format!("invalid timestamp {}", timestamp)))?;
+        if (Utc::now().timestamp() - timestamp).abs() > MAX_CLOCK_SKEW_SECS {
+            return Err(PayError::Signature(format!(
+                "expired timestamp {}",
+                timestamp
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{self, mock_pay, reply_signed, MCHID, SERIAL_NO};
+    use super::PayError;
+    use crate::core::api::client::transport::HttpResponse;
+    use crate::core::testing::TestResult;
+    use serde_json::{json, Value};
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    #[async_std::test]
+    async fn sign_request_verify_response() -> TestResult<()> {
+        setup();
+
+        let (pay, mock) = mock_pay();
+        reply_signed(&mock, "/v3/ok", 200, r#"{"a":1}"#);
+        let result: Value = pay.get("/v3/ok?x=1").await?;
+        assert_eq!(result, json!({"a": 1}));
+
+        let request = mock.requests().pop().expect("request");
+        assert_eq!(request.url, "https://api.mch.weixin.qq.com/v3/ok?x=1");
+        let authorization = request.header("Authorization").unwrap_or_default();
+        assert!(authorization.starts_with("WECHATPAY2-SHA256-RSA2048 "));
+        assert!(authorization.contains(&format!("mchid=\"{}\"", MCHID)));
+        assert!(authorization.contains(&format!("serial_no=\"{}\"", SERIAL_NO)));
+
+        // 签名不对
+        let mut headers = testing::signed_headers(r#"{"a":1}"#);
+        headers.retain(|(k, _)| k != "Wechatpay-Signature");
+        headers.push((
+            "Wechatpay-Signature".to_owned(),
+            testing::signed_headers(r#"{"a":2}"#)[3].1.clone(),
+        ));
+        mock.reply_with(
+            "/v3/forged",
+            HttpResponse {
+                status: 200,
+                headers,
+                body: br#"{"a":1}"#.to_vec(),
+            },
+        );
+        let result: Result<Value, _> = pay.get("/v3/forged").await;
+        assert!(matches!(result, Err(PayError::Signature(_))));
+
+        // 没有签名
+        mock.reply_json("/v3/unsigned", json!({"a": 1}));
+        let result: Result<Value, _> = pay.get("/v3/unsigned").await;
+        assert!(matches!(result, Err(PayError::Signature(_))));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn api_error() -> TestResult<()> {
+        setup();
+
+        let (pay, mock) = mock_pay();
+        mock.reply(
+            "/v3/pay",
+            400,
+            r#"{"code":"ORDERPAID","message":"该订单已支付"}"#,
+        )
+        .reply("/v3/pay", 502, "Bad Gateway");
+
+        let err = pay.get::<Value>("/v3/pay").await.unwrap_err();
+        assert_eq!(err.code(), Some("ORDERPAID"));
+        let err = pay.get::<Value>("/v3/pay").await.unwrap_err();
+        assert!(err.code().is_none());
+        Ok(())
+    }
+}
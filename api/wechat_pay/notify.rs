@@ -0,0 +1,283 @@
+//! 支付、退款结果的回调
+//!
+//! 先用平台公钥验签，再用APIv3密钥解密resource，最后核对商户号与appid。
+//! 同一个回调可能推送多次，handler需要幂等
+use super::order::Transaction;
+use super::refund::{RefundAmount, RefundStatus};
+use super::sign;
+use super::{PayError, PayResult, WechatPay};
+use crate::core::api::wechat_push::HandlerResult;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// 退款结果的回调内容
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RefundNotification {
+    pub mchid: String,
+    pub out_trade_no: String,
+    pub transaction_id: String,
+    pub out_refund_no: String,
+    pub refund_id: String,
+    pub refund_status: RefundStatus,
+    pub success_time: Option<String>,
+    pub user_received_account: Option<String>,
+    pub amount: RefundAmount,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayNotification {
+    /// TRANSACTION.SUCCESS
+    Transaction(Transaction),
+    /// REFUND.SUCCESS、REFUND.ABNORMAL、REFUND.CLOSED
+    Refund(RefundNotification),
+    /// 其它事件，resource为解密后的内容
+    Other { event_type: String, resource: Value },
+}
+
+#[async_trait]
+pub trait PayNotifyHandler: Send + Sync {
+    async fn handle(&self, notification: &PayNotification) -> HandlerResult;
+}
+
+#[derive(Deserialize)]
+struct NotifyBody {
+    event_type: String,
+    resource: Resource,
+}
+
+#[derive(Deserialize)]
+struct Resource {
+    algorithm: String,
+    ciphertext: String,
+    #[serde(default)]
+    associated_data: String,
+    nonce: String,
+}
+
+impl WechatPay {
+    /// 验签、解密回调
+    pub fn parse_notification(
+        &self,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> PayResult<PayNotification> {
+        self.verify(headers, body)?;
+        let body: NotifyBody = serde_json::from_slice(body)?;
+        if body.resource.algorithm != "AEAD_AES_256_GCM" {
+            return Err(PayError::Decrypt(format!(
+                "unsupported algorithm {}",
+                body.resource.algorithm
+            )));
+        }
+        let plain = sign::decrypt_resource(
+            &self.config().api_v3_key,
+            &body.resource.nonce,
+            &body.resource.associated_data,
+            &body.resource.ciphertext,
+        )?;
+
+        let notification = match body.event_type.as_str() {
+            "TRANSACTION.SUCCESS" => PayNotification::Transaction(serde_json::from_slice(&plain)?),
+            t if t.starts_with("REFUND.") => {
+                PayNotification::Refund(serde_json::from_slice(&plain)?)
+            }
+            _ => PayNotification::Other {
+                resource: serde_json::from_slice(&plain)?,
+                event_type: body.event_type,
+            },
+        };
+        self.check_merchant(&notification)?;
+        Ok(notification)
+    }
+
+    // 验签只能说明回调来自微信支付，还要确认是本商户、本小程序的订单
+    fn check_merchant(&self, notification: &PayNotification) -> PayResult<()> {
+        let (mchid, appid) = match notification {
+            PayNotification::Transaction(t) => (Some(t.mchid.as_str()), Some(t.appid.as_str())),
+            // 退款结果没有appid
+            PayNotification::Refund(r) => (Some(r.mchid.as_str()), None),
+            PayNotification::Other { resource, .. } => (
+                resource.get("mchid").and_then(Value::as_str),
+                resource.get("appid").and_then(Value::as_str),
+            ),
+        };
+        let cfg = self.config();
+        if let Some(mchid) = mchid.filter(|mchid| *mchid != cfg.mchid) {
+            return Err(PayError::Mismatch(format!("mchid {}", mchid)));
+        }
+        if let Some(appid) = appid.filter(|appid| *appid != cfg.appid) {
+            return Err(PayError::Mismatch(format!("appid {}", appid)));
+        }
+        Ok(())
+    }
+
+    /// 验签、解密后交给handler
+    pub async fn handle_notification(
+        &self,
+        headers: &[(String, String)],
+        body: &[u8],
+        handler: &dyn PayNotifyHandler,
+    ) -> PayResult<()> {
+        let notification = self.parse_notification(headers, body)?;
+        debug!("pay notification: {:?}", notification);
+        handler
+            .handle(&notification)
+            .await
+            .map_err(PayError::Handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sign;
+    use super::super::testing::{self, mock_pay, API_V3_KEY};
+    use super::super::{PayError, TradeState};
+    use super::PayNotification;
+    use serde_json::json;
+
+    /// 构造带签名的回调
+    fn notification(
+        event_type: &str,
+        original_type: &str,
+        resource: serde_json::Value,
+    ) -> (Vec<(String, String)>, Vec<u8>) {
+        let nonce = "fdasflkja484";
+        let ciphertext = sign::encrypt_resource(
+            API_V3_KEY,
+            nonce,
+            original_type,
+            resource.to_string().as_bytes(),
+        )
+        .unwrap();
+        let body = json!({
+            "id": "EV-2018022511223320873",
+            "create_time": "2015-05-20T13:29:35+08:00",
+            "resource_type": "encrypt-resource",
+            "event_type": event_type,
+            "summary": "支付成功",
+            "resource": {
+                "original_type": original_type,
+                "algorithm": "AEAD_AES_256_GCM",
+                "ciphertext": ciphertext,
+                "associated_data": original_type,
+                "nonce": nonce
+            }
+        })
+        .to_string();
+        (testing::signed_headers(&body), body.into_bytes())
+    }
+
+    #[test]
+    fn parse_notification() {
+        let (pay, _) = mock_pay();
+        let (headers, body) = notification(
+            "TRANSACTION.SUCCESS",
+            "transaction",
+            json!({
+                "appid": testing::APPID,
+                "mchid": testing::MCHID,
+                "out_trade_no": "20301217001",
+                "transaction_id": "1217752501201407033233368018",
+                "trade_type": "JSAPI",
+                "trade_state": "SUCCESS",
+                "trade_state_desc": "支付成功",
+                "success_time": "2030-12-17T13:29:35+08:00",
+                "payer": {"openid": "OPENID"},
+                "amount": {"total": 100, "payer_total": 100, "currency": "CNY", "payer_currency": "CNY"}
+            }),
+        );
+        match pay.parse_notification(&headers, &body).unwrap() {
+            PayNotification::Transaction(t) => {
+                assert_eq!(t.trade_state, TradeState::Success);
+                assert_eq!(t.payer.unwrap().openid, "OPENID");
+            }
+            n => panic!("unexpected {:?}", n),
+        }
+
+        let (headers, body) = notification(
+            "REFUND.SUCCESS",
+            "refund",
+            json!({
+                "mchid": testing::MCHID,
+                "out_trade_no": "20301217001",
+                "transaction_id": "1217752501201407033233368018",
+                "out_refund_no": "R20301217001",
+                "refund_id": "50000000382019052709732678859",
+                "refund_status": "SUCCESS",
+                "success_time": "2030-12-17T13:29:35+08:00",
+                "user_received_account": "支付用户零钱",
+                "amount": {"total": 100, "refund": 50, "payer_total": 100, "payer_refund": 50}
+            }),
+        );
+        assert!(matches!(
+            pay.parse_notification(&headers, &body).unwrap(),
+            PayNotification::Refund(ref r) if r.amount.refund == 50
+        ));
+
+        // 篡改的body
+        let forged = String::from_utf8(body).unwrap().replace("REFUND", "REFUNX");
+        assert!(matches!(
+            pay.parse_notification(&headers, forged.as_bytes()),
+            Err(PayError::Signature(_))
+        ));
+    }
+
+    #[test]
+    fn other_merchant() {
+        let (pay, _) = mock_pay();
+        let transaction = |mchid: &str, appid: &str| {
+            json!({
+                "appid": appid,
+                "mchid": mchid,
+                "out_trade_no": "20301217001",
+                "trade_state": "SUCCESS",
+                "trade_state_desc": "支付成功"
+            })
+        };
+
+        // 签名正确但不是本商户、本小程序的订单
+        let (headers, body) = notification(
+            "TRANSACTION.SUCCESS",
+            "transaction",
+            transaction("1900000002", testing::APPID),
+        );
+        assert!(matches!(
+            pay.parse_notification(&headers, &body),
+            Err(PayError::Mismatch(_))
+        ));
+        let (headers, body) = notification(
+            "TRANSACTION.SUCCESS",
+            "transaction",
+            transaction(testing::MCHID, "wx0000000000000000"),
+        );
+        assert!(matches!(
+            pay.parse_notification(&headers, &body),
+            Err(PayError::Mismatch(_))
+        ));
+        let (headers, body) = notification(
+            "REFUND.SUCCESS",
+            "refund",
+            json!({
+                "mchid": "1900000002",
+                "out_trade_no": "20301217001",
+                "transaction_id": "1217752501201407033233368018",
+                "out_refund_no": "R20301217001",
+                "refund_id": "50000000382019052709732678859",
+                "refund_status": "SUCCESS",
+                "amount": {"total": 100, "refund": 50, "payer_total": 100, "payer_refund": 50}
+            }),
+        );
+        assert!(matches!(
+            pay.parse_notification(&headers, &body),
+            Err(PayError::Mismatch(_))
+        ));
+
+        let (headers, body) = notification(
+            "TRANSACTION.SUCCESS",
+            "transaction",
+            transaction(testing::MCHID, testing::APPID),
+        );
+        assert!(pay.parse_notification(&headers, &body).is_ok());
+    }
+}
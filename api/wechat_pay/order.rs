@@ -0,0 +1,347 @@
+//! JSAPI（小程序）下单、查单、关单
+use super::sign;
+use super::{PayError, PayResult, WechatPay};
+use crate::core::api::client::request::encode_component;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use http::Method;
+use serde::{Deserialize, Serialize};
+
+/// 下单参数，金额单位为分
+#[derive(Debug, Clone)]
+pub struct JsapiOrder {
+    pub out_trade_no: String,
+    pub description: String,
+    pub total: i64,
+    /// 付款用户在该小程序下的openid
+    pub openid: String,
+    /// 附加数据，回调时原样返回
+    pub attach: Option<String>,
+    pub time_expire: Option<DateTime<Local>>,
+}
+
+impl JsapiOrder {
+    pub fn new(out_trade_no: &str, description: &str, total: i64, openid: &str) -> Self {
+        Self {
+            out_trade_no: out_trade_no.to_owned(),
+            description: description.to_owned(),
+            total,
+            openid: openid.to_owned(),
+            attach: None,
+            time_expire: None,
+        }
+    }
+
+    pub fn attach(mut self, attach: &str) -> Self {
+        self.attach = Some(attach.to_owned());
+        self
+    }
+
+    pub fn time_expire(mut self, time_expire: DateTime<Local>) -> Self {
+        self.time_expire = Some(time_expire);
+        self
+    }
+
+    fn validate(&self) -> PayResult<()> {
+        validate_out_no("out_trade_no", &self.out_trade_no)?;
+        if self.total <= 0 {
+            return Err(PayError::Other(format!("invalid total: {}", self.total)));
+        }
+        if self.description.is_empty() || self.description.chars().count() > 127 {
+            return Err(PayError::Other(
+                "description must be 1-127 chars".to_owned(),
+            ));
+        }
+        if self
+            .attach
+            .as_ref()
+            .map_or(false, |a| a.chars().count() > 128)
+        {
+            return Err(PayError::Other(
+                "attach must be at most 128 chars".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 商户订单号、退款单号：6-32个字符，只能是数字、大小写字母与`_-|*`
+pub(super) fn validate_out_no(name: &str, no: &str) -> PayResult<()> {
+    let valid = (6..=32).contains(&no.len())
+        && no
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-|*".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(PayError::Other(format!("invalid {}: {}", name, no)))
+    }
+}
+
+/// 小程序`wx.requestPayment`的参数
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestPayment {
+    pub time_stamp: String,
+    pub nonce_str: String,
+    /// `prepay_id=xxx`
+    pub package: String,
+    pub sign_type: String,
+    pub pay_sign: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TradeState {
+    Success,
+    /// 转入退款
+    Refund,
+    NotPay,
+    Closed,
+    /// 已撤销（仅付款码支付）
+    Revoked,
+    /// 用户支付中（仅付款码支付）
+    UserPaying,
+    PayError,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Payer {
+    pub openid: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Amount {
+    pub total: i64,
+    /// 用户实际支付金额，未支付时没有
+    pub payer_total: Option<i64>,
+    pub currency: Option<String>,
+}
+
+/// 订单（查单的结果、支付成功的回调）
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub appid: String,
+    pub mchid: String,
+    pub out_trade_no: String,
+    /// 微信支付订单号，未支付时没有
+    pub transaction_id: Option<String>,
+    pub trade_type: Option<String>,
+    pub trade_state: TradeState,
+    pub trade_state_desc: String,
+    /// 例如`2018-06-08T10:34:56+08:00`
+    pub success_time: Option<String>,
+    pub payer: Option<Payer>,
+    pub amount: Option<Amount>,
+    pub attach: Option<String>,
+}
+impl Transaction {
+    pub fn is_paid(&self) -> bool {
+        self.trade_state == TradeState::Success
+    }
+}
+
+impl WechatPay {
+    /// 下单，返回prepay_id（有效期2小时）
+    pub async fn create_jsapi_order(&self, order: &JsapiOrder) -> PayResult<String> {
+        order.validate()?;
+        let cfg = self.config();
+
+        #[derive(Serialize)]
+        struct OrderAmount {
+            total: i64,
+            currency: &'static str,
+        }
+        #[derive(Serialize)]
+        struct OrderRequest<'a> {
+            appid: &'a str,
+            mchid: &'a str,
+            description: &'a str,
+            out_trade_no: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            time_expire: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            attach: Option<&'a str>,
+            notify_url: &'a str,
+            amount: OrderAmount,
+            payer: Payer,
+        }
+        #[derive(Deserialize)]
+        struct OrderResponse {
+            prepay_id: String,
+        }
+
+        let payload = OrderRequest {
+            appid: &cfg.appid,
+            mchid: &cfg.mchid,
+            description: &order.description,
+            out_trade_no: &order.out_trade_no,
+            time_expire: order
+                .time_expire
+                .map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, false)),
+            attach: order.attach.as_deref(),
+            notify_url: &cfg.notify_url,
+            amount: OrderAmount {
+                total: order.total,
+                currency: "CNY",
+            },
+            payer: Payer {
+                openid: order.openid.clone(),
+            },
+        };
+        let resp: OrderResponse = self.post("/v3/pay/transactions/jsapi", &payload).await?;
+        Ok(resp.prepay_id)
+    }
+
+    /// 用prepay_id生成小程序调起支付的参数
+    pub fn request_payment(&self, prepay_id: &str) -> PayResult<RequestPayment> {
+        let time_stamp = Utc::now().timestamp().to_string();
+        let nonce_str = sign::nonce_str();
+        let package = format!("prepay_id={}", prepay_id);
+        let pay_sign = self.0.signer.sign(&format!(
+            "{}\n{}\n{}\n{}\n",
+            self.config().appid,
+            time_stamp,
+            nonce_str,
+            package
+        ))?;
+        Ok(RequestPayment {
+            time_stamp,
+            nonce_str,
+            package,
+            sign_type: "RSA".to_owned(),
+            pay_sign,
+        })
+    }
+
+    /// 下单并生成调起支付的参数
+    pub async fn create_jsapi_payment(&self, order: &JsapiOrder) -> PayResult<RequestPayment> {
+        let prepay_id = self.create_jsapi_order(order).await?;
+        self.request_payment(&prepay_id)
+    }
+
+    /// 按商户订单号查单
+    pub async fn query_order(&self, out_trade_no: &str) -> PayResult<Transaction> {
+        validate_out_no("out_trade_no", out_trade_no)?;
+        let path = format!(
+            "/v3/pay/transactions/out-trade-no/{}?mchid={}",
+            out_trade_no,
+            encode_component(&self.config().mchid)
+        );
+        self.get(&path).await
+    }
+
+    /// 关单，已支付的订单返回ORDERPAID
+    pub async fn close_order(&self, out_trade_no: &str) -> PayResult<()> {
+        validate_out_no("out_trade_no", out_trade_no)?;
+
+        #[derive(Serialize)]
+        struct CloseRequest<'a> {
+            mchid: &'a str,
+        }
+
+        let path = format!("/v3/pay/transactions/out-trade-no/{}/close", out_trade_no);
+        let payload = CloseRequest {
+            mchid: &self.config().mchid,
+        };
+        self.send(Method::POST, &path, Some(serde_json::to_string(&payload)?))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{mock_pay, reply_signed, APPID, MCHID, PUBLIC_KEY, SERIAL_NO};
+    use super::super::PlatformVerifier;
+    use super::{JsapiOrder, TradeState};
+    use crate::core::testing::TestResult;
+    use chrono::{Local, TimeZone};
+    use serde_json::{json, Value};
+
+    #[async_std::test]
+    async fn create_jsapi_payment() -> TestResult<()> {
+        let (pay, mock) = mock_pay();
+        reply_signed(
+            &mock,
+            "/v3/pay/transactions/jsapi",
+            200,
+            r#"{"prepay_id":"wx201410272009395522657a690389285100"}"#,
+        );
+
+        let order = JsapiOrder::new("1217752501201407033233368018", "体检套餐", 19900, "OPENID")
+            .attach("user_id=1")
+            .time_expire(Local.ymd(2030, 6, 8).and_hms(10, 34, 56));
+        let params = pay.create_jsapi_payment(&order).await?;
+        assert_eq!(
+            params.package,
+            "prepay_id=wx201410272009395522657a690389285100"
+        );
+        assert_eq!(params.sign_type, "RSA");
+
+        // paySign用商户私钥签名，测试中与平台公钥是同一对
+        let mut verifier = PlatformVerifier::default();
+        verifier.add_pem(SERIAL_NO, PUBLIC_KEY)?;
+        let message = format!(
+            "{}\n{}\n{}\n{}\n",
+            APPID, params.time_stamp, params.nonce_str, params.package
+        );
+        verifier.verify(SERIAL_NO, &message, &params.pay_sign)?;
+
+        let request = mock.requests().pop().expect("request");
+        let body: Value = serde_json::from_slice(&request.body.unwrap_or_default())?;
+        assert_eq!(body["appid"], APPID);
+        assert_eq!(body["mchid"], MCHID);
+        assert_eq!(body["amount"], json!({"total": 19900, "currency": "CNY"}));
+        assert_eq!(body["payer"], json!({"openid": "OPENID"}));
+        assert_eq!(body["attach"], "user_id=1");
+        assert!(body["time_expire"]
+            .as_str()
+            .unwrap_or_default()
+            .starts_with("2030-06-08T10:34:56"));
+
+        // 参数不合法时不请求
+        assert!(pay
+            .create_jsapi_order(&JsapiOrder::new("1&x=1", "体检套餐", 19900, "OPENID"))
+            .await
+            .is_err());
+        assert!(pay
+            .create_jsapi_order(&JsapiOrder::new("20301217001", "体检套餐", 0, "OPENID"))
+            .await
+            .is_err());
+        assert_eq!(mock.requests().len(), 1);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn query_and_close() -> TestResult<()> {
+        let (pay, mock) = mock_pay();
+        reply_signed(
+            &mock,
+            "/out-trade-no/20301217001?",
+            200,
+            &json!({
+                "appid": APPID,
+                "mchid": MCHID,
+                "out_trade_no": "20301217001",
+                "trade_state": "NOTPAY",
+                "trade_state_desc": "订单未支付",
+                "amount": {"total": 100, "currency": "CNY"}
+            })
+            .to_string(),
+        );
+        reply_signed(&mock, "/out-trade-no/20301217001/close", 204, "");
+
+        let transaction = pay.query_order("20301217001").await?;
+        assert_eq!(transaction.trade_state, TradeState::NotPay);
+        assert!(!transaction.is_paid());
+        pay.close_order("20301217001").await?;
+
+        let requests = mock.requests();
+        assert!(requests[0]
+            .url
+            .ends_with("/v3/pay/transactions/out-trade-no/20301217001?mchid=1900000001"));
+        let body: Value = serde_json::from_slice(requests[1].body.as_deref().unwrap_or_default())?;
+        assert_eq!(body, json!({"mchid": MCHID}));
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! 退款、查询退款
+use super::order::validate_out_no;
+use super::{PayError, PayResult, WechatPay};
+use serde::{Deserialize, Serialize};
+
+/// 退款参数，金额单位为分
+#[derive(Debug, Clone)]
+pub struct Refund {
+    pub out_trade_no: String,
+    pub out_refund_no: String,
+    /// 退款金额
+    pub refund: i64,
+    /// 原订单金额
+    pub total: i64,
+    pub reason: Option<String>,
+}
+
+impl Refund {
+    pub fn new(out_trade_no: &str, out_refund_no: &str, refund: i64, total: i64) -> Self {
+        Self {
+            out_trade_no: out_trade_no.to_owned(),
+            out_refund_no: out_refund_no.to_owned(),
+            refund,
+            total,
+            reason: None,
+        }
+    }
+
+    /// 退款原因，会展示在用户收到的退款消息中
+    pub fn reason(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_owned());
+        self
+    }
+
+    fn validate(&self) -> PayResult<()> {
+        validate_out_no("out_trade_no", &self.out_trade_no)?;
+        validate_out_no("out_refund_no", &self.out_refund_no)?;
+        if self.refund <= 0 || self.refund > self.total {
+            return Err(PayError::Other(format!(
+                "invalid refund: {} of {}",
+                self.refund, self.total
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RefundStatus {
+    Success,
+    Closed,
+    Processing,
+    /// 退款异常，需要在商户平台处理
+    Abnormal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RefundAmount {
+    pub total: i64,
+    pub refund: i64,
+    /// 用户实际收到的退款金额
+    pub payer_refund: Option<i64>,
+    pub currency: Option<String>,
+}
+
+/// 退款（申请、查询的结果）
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RefundResult {
+    /// 微信支付退款单号
+    pub refund_id: String,
+    pub out_refund_no: String,
+    pub transaction_id: String,
+    pub out_trade_no: String,
+    /// 退款入账账户，例如`支付用户零钱`
+    pub user_received_account: Option<String>,
+    pub success_time: Option<String>,
+    pub create_time: Option<String>,
+    pub status: RefundStatus,
+    pub amount: RefundAmount,
+}
+
+impl WechatPay {
+    /// 申请退款，结果以回调或`query_refund`为准
+    pub async fn refund(&self, refund: &Refund) -> PayResult<RefundResult> {
+        refund.validate()?;
+
+        #[derive(Serialize)]
+        struct Amount {
+            refund: i64,
+            total: i64,
+            currency: &'static str,
+        }
+        #[derive(Serialize)]
+        struct RefundRequest<'a> {
+            out_trade_no: &'a str,
+            out_refund_no: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            reason: Option<&'a str>,
+            notify_url: &'a str,
+            amount: Amount,
+        }
+
+        let payload = RefundRequest {
+            out_trade_no: &refund.out_trade_no,
+            out_refund_no: &refund.out_refund_no,
+            reason: refund.reason.as_deref(),
+            notify_url: &self.config().notify_url,
+            amount: Amount {
+                refund: refund.refund,
+                total: refund.total,
+                currency: "CNY",
+            },
+        };
+        self.post("/v3/refund/domestic/refunds", &payload).await
+    }
+
+    /// 按商户退款单号查询退款
+    pub async fn query_refund(&self, out_refund_no: &str) -> PayResult<RefundResult> {
+        validate_out_no("out_refund_no", out_refund_no)?;
+        self.get(&format!("/v3/refund/domestic/refunds/{}", out_refund_no))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{mock_pay, reply_signed};
+    use super::{Refund, RefundStatus};
+    use crate::core::testing::TestResult;
+    use serde_json::{json, Value};
+
+    #[async_std::test]
+    async fn refund() -> TestResult<()> {
+        let (pay, mock) = mock_pay();
+        let result = json!({
+            "refund_id": "50000000382019052709732678859",
+            "out_refund_no": "R20301217001",
+            "transaction_id": "1217752501201407033233368018",
+            "out_trade_no": "20301217001",
+            "channel": "ORIGINAL",
+            "user_received_account": "支付用户零钱",
+            "create_time": "2030-12-17T13:29:35+08:00",
+            "status": "PROCESSING",
+            "amount": {"total": 100, "refund": 50, "payer_refund": 50, "currency": "CNY"}
+        });
+        reply_signed(
+            &mock,
+            "/v3/refund/domestic/refunds",
+            200,
+            &result.to_string(),
+        );
+        let mut success = result.clone();
+        success["status"] = json!("SUCCESS");
+        reply_signed(
+            &mock,
+            "/v3/refund/domestic/refunds/R20301217001",
+            200,
+            &success.to_string(),
+        );
+
+        let refund = Refund::new("20301217001", "R20301217001", 50, 100).reason("商品已售完");
+        let result = pay.refund(&refund).await?;
+        assert_eq!(result.status, RefundStatus::Processing);
+        assert_eq!(result.amount.refund, 50);
+        let result = pay.query_refund("R20301217001").await?;
+        assert_eq!(result.status, RefundStatus::Success);
+
+        let request = &mock.requests()[0];
+        let body: Value = serde_json::from_slice(request.body.as_deref().unwrap_or_default())?;
+        assert_eq!(body["reason"], "商品已售完");
+        assert_eq!(
+            body["amount"],
+            json!({"refund": 50, "total": 100, "currency": "CNY"})
+        );
+
+        // 退款金额超过订单金额
+        assert!(pay
+            .refund(&Refund::new("20301217001", "R20301217002", 101, 100))
+            .await
+            .is_err());
+        assert_eq!(mock.requests().len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! 签名、验签与回调解密
+//!
+//! * 请求：`METHOD\nURL\n时间戳\n随机串\nbody\n`，用商户私钥做SHA256-RSA签名，放在Authorization中
+//! * 应答与回调：`时间戳\n随机串\nbody\n`，按`Wechatpay-Serial`选择平台公钥验签
+//! * 回调的resource：AEAD_AES_256_GCM，密钥为APIv3密钥
+use super::{PayError, PayResult};
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::aes::KeySize;
+use crypto::aes_gcm::AesGcm;
+use rand::{rngs::OsRng, RngCore};
+use rsa::{Hash, PaddingScheme, PublicKey, RSAPrivateKey, RSAPublicKey};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+pub(super) const AUTH_SCHEMA: &str = "WECHATPAY2-SHA256-RSA2048";
+const TAG_LENGTH: usize = 16;
+
+/// 商户API私钥（apiclient_key.pem）
+pub struct MerchantSigner {
+    serial_no: String,
+    key: RSAPrivateKey,
+}
+
+impl MerchantSigner {
+    /// `serial_no`为商户API证书的序列号，`pem`为PKCS#8格式的私钥
+    pub fn from_pem(serial_no: &str, pem: &str) -> PayResult<Self> {
+        let key = RSAPrivateKey::from_pkcs8(&pem_to_der(pem)?)
+            .map_err(|e| PayError::Key(format!("merchant private key: {}", e)))?;
+        Ok(Self {
+            serial_no: serial_no.to_owned(),
+            key,
+        })
+    }
+
+    pub fn serial_no(&self) -> &str {
+        &self.serial_no
+    }
+
+    /// `base64(SHA256-RSA(message))`
+    pub fn sign(&self, message: &str) -> PayResult<String> {
+        let digest = Sha256::digest(message.as_bytes());
+        let signature = self
+            .key
+            .sign(
+                PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256)),
+                &digest,
+            )
+            .map_err(|e| PayError::Key(e.to_string()))?;
+        Ok(base64::encode(&signature))
+    }
+}
+
+/// 平台证书的公钥（或微信支付公钥），按序列号（公钥ID）查找
+///
+/// 平台证书可以用`openssl x509 -in cert.pem -pubkey -noout`导出公钥
+#[derive(Default)]
+pub struct PlatformVerifier {
+    keys: HashMap<String, RSAPublicKey>,
+}
+
+impl PlatformVerifier {
+    pub fn add_pem(&mut self, serial_no: &str, pem: &str) -> PayResult<()> {
+        let key = RSAPublicKey::from_pkcs8(&pem_to_der(pem)?)
+            .map_err(|e| PayError::Key(format!("platform public key {}: {}", serial_no, e)))?;
+        self.keys.insert(serial_no.to_owned(), key);
+        Ok(())
+    }
+
+    pub fn verify(&self, serial_no: &str, message: &str, signature: &str) -> PayResult<()> {
+        let key = self
+            .keys
+            .get(serial_no)
+            .ok_or_else(|| PayError::Signature(format!("unknown serial {}", serial_no)))?;
+        let signature = base64::decode(signature)
+            .map_err(|_| PayError::Signature("invalid base64".to_owned()))?;
+        let digest = Sha256::digest(message.as_bytes());
+        key.verify(
+            PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256)),
+            &digest,
+            &signature,
+        )
+        .map_err(|_| PayError::Signature("mismatch".to_owned()))
+    }
+}
+
+/// 解密回调的resource
+pub(super) fn decrypt_resource(
+    api_v3_key: &str,
+    nonce: &str,
+    associated_data: &str,
+    ciphertext: &str,
+) -> PayResult<Vec<u8>> {
+    check_aead(api_v3_key, nonce)?;
+    let data =
+        base64::decode(ciphertext).map_err(|_| PayError::Decrypt("invalid base64".to_owned()))?;
+    if data.len() < TAG_LENGTH {
+        return Err(PayError::Decrypt("too short".to_owned()));
+    }
+    let (data, tag) = data.split_at(data.len() - TAG_LENGTH);
+
+    let mut cipher = AesGcm::new(
+        KeySize::KeySize256,
+        api_v3_key.as_bytes(),
+        nonce.as_bytes(),
+        associated_data.as_bytes(),
+    );
+    let mut plain = vec![0u8; data.len()];
+    if !cipher.decrypt(data, &mut plain, tag) {
+        return Err(PayError::Decrypt("authentication failed".to_owned()));
+    }
+    Ok(plain)
+}
+
+/// 与`decrypt_resource`相反，测试时构造回调
+pub(super) fn encrypt_resource(
+    api_v3_key: &str,
+    nonce: &str,
+    associated_data: &str,
+    plain: &[u8],
+) -> PayResult<String> {
+    check_aead(api_v3_key, nonce)?;
+    let mut cipher = AesGcm::new(
+        KeySize::KeySize256,
+        api_v3_key.as_bytes(),
+        nonce.as_bytes(),
+        associated_data.as_bytes(),
+    );
+    let mut data = vec![0u8; plain.len()];
+    let mut tag = [0u8; TAG_LENGTH];
+    cipher.encrypt(plain, &mut data, &mut tag);
+    data.extend_from_slice(&tag);
+    Ok(base64::encode(&data))
+}
+
+// AesGcm::new在长度不对时会panic
+fn check_aead(api_v3_key: &str, nonce: &str) -> PayResult<()> {
+    if api_v3_key.len() != 32 {
+        return Err(PayError::Key("api v3 key must be 32 bytes".to_owned()));
+    }
+    if nonce.len() != 12 {
+        return Err(PayError::Decrypt("nonce must be 12 bytes".to_owned()));
+    }
+    Ok(())
+}
+
+/// 32位随机串
+pub(super) fn nonce_str() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn pem_to_der(pem: &str) -> PayResult<Vec<u8>> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    base64::decode(&body).map_err(|e| PayError::Key(format!("invalid pem: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{PRIVATE_KEY, PUBLIC_KEY};
+    use super::{decrypt_resource, encrypt_resource, nonce_str, MerchantSigner, PlatformVerifier};
+
+    #[test]
+    fn sign_verify() {
+        let signer = MerchantSigner::from_pem("SERIAL", PRIVATE_KEY).unwrap();
+        let mut verifier = PlatformVerifier::default();
+        verifier.add_pem("SERIAL", PUBLIC_KEY).unwrap();
+
+        let message = "GET\n/v3/certificates\n1554208460\n593BEC0C930BF1AFEB40B4A08C8FB242\n\n";
+        let signature = signer.sign(message).unwrap();
+        verifier.verify("SERIAL", message, &signature).unwrap();
+        assert!(verifier.verify("SERIAL", "other", &signature).is_err());
+        assert!(verifier.verify("OTHER", message, &signature).is_err());
+        assert!(verifier.verify("SERIAL", message, "not base64!").is_err());
+
+        assert!(MerchantSigner::from_pem("SERIAL", PUBLIC_KEY).is_err());
+        assert!(PlatformVerifier::default().add_pem("SERIAL", "").is_err());
+    }
+
+    #[test]
+    fn aead() {
+        let key = "0123456789abcdef0123456789abcdef";
+        let data = encrypt_resource(key, "0123456789ab", "transaction", b"{\"a\":1}").unwrap();
+        assert_eq!(
+            decrypt_resource(key, "0123456789ab", "transaction", &data).unwrap(),
+            b"{\"a\":1}"
+        );
+        assert!(decrypt_resource(key, "0123456789ab", "refund", &data).is_err());
+        assert!(decrypt_resource(key, "short", "transaction", &data).is_err());
+        assert!(decrypt_resource("short", "0123456789ab", "transaction", &data).is_err());
+        assert_eq!(nonce_str().len(), 32);
+    }
+}
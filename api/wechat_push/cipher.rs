@@ -0,0 +1,139 @@
+//! 消息加解密（安全模式）
+//!
+//! * 密钥：`base64_decode(EncodingAESKey + "=")`，32字节；iv为密钥的前16字节
+//! * 明文：`random(16) + msg_len(4字节，网络字节序) + msg + appid`，按32字节做PKCS#7填充
+//! * 密文：`base64(AES-256-CBC(明文))`
+use super::super::aes_cbc_128;
+use super::{PushError, PushResult};
+use crypto::aes::KeySize;
+use crypto::blockmodes::NoPadding;
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use rand::{rngs::OsRng, RngCore};
+use std::convert::TryInto;
+
+const BLOCK_SIZE: usize = 32;
+
+pub struct Cipher {
+    key: [u8; 32],
+    // 公众号、小程序为appid，企业微信为corpid，钉钉为应用的AppKey
+    receive_id: String,
+}
+
+impl Cipher {
+    pub fn new(encoding_aes_key: &str, receive_id: &str) -> PushResult<Self> {
+        let key = base64::decode(format!("{}=", encoding_aes_key))
+            .ok()
+            .and_then(|key| key[..].try_into().ok())
+            .ok_or_else(|| PushError::Decrypt("invalid EncodingAESKey".to_owned()))?;
+        Ok(Self {
+            key,
+            receive_id: receive_id.to_owned(),
+        })
+    }
+
+    pub fn decrypt(&self, encrypted: &str) -> PushResult<String> {
+        let data =
+            base64::decode(encrypted).map_err(|e| PushError::Decrypt(format!("base64: {}", e)))?;
+        let plain = aes_cbc_128::decrypt(
+            &data,
+            &self.key,
+            &self.key[..16],
+            KeySize::KeySize256,
+            NoPadding,
+        )
+        .map_err(|_| PushError::Decrypt("aes".to_owned()))?;
+
+        let pad = *plain.last().unwrap_or(&0) as usize;
+        if pad == 0 || pad > BLOCK_SIZE || pad > plain.len() {
+            return Err(PushError::Decrypt("padding".to_owned()));
+        }
+        let plain = &plain[..plain.len() - pad];
+        if plain.len() < 20 {
+            return Err(PushError::Decrypt("too short".to_owned()));
+        }
+        let len = u32::from_be_bytes([plain[16], plain[17], plain[18], plain[19]]) as usize;
+        let content = &plain[20..];
+        if len > content.len() {
+            return Err(PushError::Decrypt("length".to_owned()));
+        }
+        let (msg, receive_id) = content.split_at(len);
+        if receive_id != self.receive_id.as_bytes() {
+            return Err(PushError::AppId(
+                String::from_utf8_lossy(receive_id).into_owned(),
+            ));
+        }
+        String::from_utf8(msg.to_vec()).map_err(|_| PushError::Decrypt("utf8".to_owned()))
+    }
+
+    pub fn encrypt(&self, msg: &str) -> PushResult<String> {
+        let mut plain = vec![0u8; 16];
+        OsRng.fill_bytes(&mut plain);
+        plain.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+        plain.extend_from_slice(msg.as_bytes());
+        plain.extend_from_slice(self.receive_id.as_bytes());
+        let pad = BLOCK_SIZE - plain.len() % BLOCK_SIZE;
+        plain.extend(std::iter::repeat(pad as u8).take(pad));
+
+        let data = aes_cbc_128::encrypt(
+            &plain,
+            &self.key,
+            &self.key[..16],
+            KeySize::KeySize256,
+            NoPadding,
+        )
+        .map_err(|_| PushError::Decrypt("aes".to_owned()))?;
+        Ok(base64::encode(&data))
+    }
+}
+
+/// `sha1(sort([token, timestamp, nonce, encrypt]).join(""))`，明文模式不传`encrypt`
+pub fn signature(token: &str, timestamp: &str, nonce: &str, encrypt: Option<&str>) -> String {
+    let mut parts = vec![token, timestamp, nonce];
+    parts.extend(encrypt);
+    parts.sort_unstable();
+
+    let mut hasher = Sha1::new();
+    hasher.input_str(&parts.concat());
+    hasher.result_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{signature, Cipher};
+    use crate::core::api::wechat_push::PushError;
+
+    const AES_KEY: &str = "abcdefghijklmnopqrstuvwxyz0123456789ABCDEFG";
+
+    #[test]
+    fn encrypt_decrypt() {
+        let cipher = Cipher::new(AES_KEY, "wx5823bf96d3bd56c7").unwrap();
+        for msg in &[
+            "",
+            "hello",
+            "<xml><Content><![CDATA[你好]]></Content></xml>",
+        ] {
+            let encrypted = cipher.encrypt(msg).unwrap();
+            assert_eq!(&cipher.decrypt(&encrypted).unwrap(), msg);
+        }
+
+        let other = Cipher::new(AES_KEY, "wx0000000000000000").unwrap();
+        let encrypted = other.encrypt("hello").unwrap();
+        assert!(
+            matches!(cipher.decrypt(&encrypted), Err(PushError::AppId(id)) if id == "wx0000000000000000")
+        );
+        assert!(cipher.decrypt("not base64!").is_err());
+        assert!(cipher.decrypt(&base64::encode(&[0u8; 20])).is_err());
+        assert!(Cipher::new("short", "wx5823bf96d3bd56c7").is_err());
+    }
+
+    #[test]
+    fn sign() {
+        assert_eq!(
+            signature("QDG6eK", "1409659813", "1372623149", None),
+            "d2157f2f9079f4d6257b45edf665c43c62e60a0a"
+        );
+        let msg_signature = signature("token", "1409659813", "1372623149", Some("ENCRYPT"));
+        assert_eq!(msg_signature, "630e5cb482d910c76c514183ca2d0fbc8cfeb063");
+    }
+}
@@ -0,0 +1,96 @@
+//! xml转换后的值都是字符串，单个子元素也不是数组；这里兼容json与xml两种写法
+use serde::de::{DeserializeOwned, Deserializer, Error};
+use serde::Deserialize;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// 数字，或数字字符串
+pub(crate) fn int<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + FromStr,
+{
+    match Value::deserialize(deserializer)? {
+        Value::String(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| D::Error::custom(format!("invalid number: {}", s))),
+        v => serde_json::from_value(v).map_err(D::Error::custom),
+    }
+}
+
+/// 同`int`，缺失、null与空字符串为None；字段须加`#[serde(default)]`
+pub(crate) fn opt_int<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + FromStr,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Null => Ok(None),
+        Value::String(s) if s.trim().is_empty() => Ok(None),
+        Value::String(s) => s
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| D::Error::custom(format!("invalid number: {}", s))),
+        v => serde_json::from_value(v)
+            .map(Some)
+            .map_err(D::Error::custom),
+    }
+}
+
+/// 数组，或单个元素；字段须加`#[serde(default)]`
+pub(crate) fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    into_vec(Value::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+pub(crate) fn into_vec<T: DeserializeOwned>(value: Value) -> Result<Vec<T>, serde_json::Error> {
+    match value {
+        Value::Null => Ok(vec![]),
+        Value::String(s) if s.is_empty() => Ok(vec![]),
+        Value::Array(items) => items.into_iter().map(serde_json::from_value).collect(),
+        v => Ok(vec![serde_json::from_value(v)?]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Item {
+        #[serde(deserialize_with = "super::int")]
+        id: i64,
+        #[serde(default, deserialize_with = "super::opt_int")]
+        code: Option<i32>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct List {
+        #[serde(default, deserialize_with = "super::one_or_many")]
+        items: Vec<Item>,
+    }
+
+    #[test]
+    fn json_or_xml() {
+        let xml: List = serde_json::from_value(json!({"items": {"id": "1", "code": ""}})).unwrap();
+        let json: List = serde_json::from_value(json!({"items": [{"id": 1}]})).unwrap();
+        assert_eq!(xml, json);
+        assert_eq!(xml.items, vec![Item { id: 1, code: None }]);
+
+        let list: List =
+            serde_json::from_value(json!({"items": [{"id": 1, "code": "2"}, {"id": "3"}]}))
+                .unwrap();
+        assert_eq!(list.items[0].code, Some(2));
+        assert_eq!(list.items[1].id, 3);
+
+        let empty: List = serde_json::from_value(json!({})).unwrap();
+        assert!(empty.items.is_empty());
+        assert!(serde_json::from_value::<List>(json!({"items": {"id": "x"}})).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! 推送消息
+//!
+//! xml与json格式的推送先转换为`serde_json::Value`，字段名保持微信的写法（`ToUserName`等），
+//! 常用的消息、事件解析为`PushEvent`，其它的可以用`PushMessage::parse`自行解析
+use super::super::wechat_miniprogram::MediaCheckEvent;
+use super::{de, PushError, PushResult};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushMessage {
+    pub to_user_name: String,
+    /// 发送方的openid，事件推送时为微信的系统帐号
+    pub from_user_name: String,
+    pub create_time: i64,
+    pub msg_type: String,
+    /// `msg_type`为event时的事件类型
+    pub event_name: Option<String>,
+    pub event: PushEvent,
+    /// 解密、转换后的原始内容
+    pub raw: Value,
+}
+
+impl PushMessage {
+    pub(super) fn from_value(raw: Value) -> PushResult<Self> {
+        #[derive(Deserialize)]
+        struct Header {
+            #[serde(rename = "ToUserName", default)]
+            to_user_name: String,
+            #[serde(rename = "FromUserName", default)]
+            from_user_name: String,
+            #[serde(rename = "CreateTime", default, deserialize_with = "de::opt_int")]
+            create_time: Option<i64>,
+            #[serde(rename = "MsgType")]
+            msg_type: String,
+            #[serde(rename = "Event")]
+            event: Option<String>,
+        }
+
+        let header: Header =
+            serde_json::from_value(raw.clone()).map_err(|e| PushError::Parse(e.to_string()))?;
+        let event = PushEvent::from_value(&header.msg_type, header.event.as_deref(), &raw)?;
+        Ok(Self {
+            to_user_name: header.to_user_name,
+            from_user_name: header.from_user_name,
+            create_time: header.create_time.unwrap_or_default(),
+            msg_type: header.msg_type,
+            event_name: header.event,
+            event,
+            raw,
+        })
+    }
+
+    /// 分发用的类型：事件为`Event`，其它为`MsgType`
+    pub fn kind(&self) -> &str {
+        self.event_name.as_deref().unwrap_or(&self.msg_type)
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self) -> PushResult<T> {
+        serde_json::from_value(self.raw.clone()).map_err(|e| PushError::Parse(e.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PushEvent {
+    /// 客服消息：文本
+    Text(TextMessage),
+    /// 客服消息：图片
+    Image(ImageMessage),
+    /// `media_check_async`的检测结果
+    MediaCheck(MediaCheckEvent),
+    /// 用户在弹窗中操作了订阅消息
+    SubscribeMsgPopup(Vec<SubscribeStatus>),
+    /// 用户在设置页改变了订阅状态
+    SubscribeMsgChange(Vec<SubscribeStatus>),
+    /// 订阅消息的发送结果
+    SubscribeMsgSent(Vec<SubscribeSent>),
+    /// 其它消息、事件，见`PushMessage::raw`
+    Other,
+}
+
+impl PushEvent {
+    fn from_value(msg_type: &str, event: Option<&str>, raw: &Value) -> PushResult<Self> {
+        fn parse<T: DeserializeOwned>(raw: &Value) -> PushResult<T> {
+            serde_json::from_value(raw.clone()).map_err(|e| PushError::Parse(e.to_string()))
+        }
+        // xml：<SubscribeMsgPopupEvent><List>..</List></SubscribeMsgPopupEvent>
+        // json："List": [..]
+        fn list<T: DeserializeOwned>(raw: &Value, wrapper: &str) -> PushResult<Vec<T>> {
+            let list = raw
+                .get(wrapper)
+                .and_then(|v| v.get("List"))
+                .or_else(|| raw.get("List"))
+                .cloned()
+                .unwrap_or_default();
+            de::into_vec(list).map_err(|e| PushError::Parse(e.to_string()))
+        }
+
+        Ok(match (msg_type, event) {
+            ("text", _) => PushEvent::Text(parse(raw)?),
+            ("image", _) => PushEvent::Image(parse(raw)?),
+            ("event", Some("wxa_media_check")) => PushEvent::MediaCheck(parse(raw)?),
+            ("event", Some("subscribe_msg_popup_event")) => {
+                PushEvent::SubscribeMsgPopup(list(raw, "SubscribeMsgPopupEvent")?)
+            }
+            ("event", Some("subscribe_msg_change_event")) => {
+                PushEvent::SubscribeMsgChange(list(raw, "SubscribeMsgChangeEvent")?)
+            }
+            ("event", Some("subscribe_msg_sent_event")) => {
+                PushEvent::SubscribeMsgSent(list(raw, "SubscribeMsgSentEvent")?)
+            }
+            _ => PushEvent::Other,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TextMessage {
+    #[serde(rename = "Content")]
+    pub content: String,
+    #[serde(rename = "MsgId", deserialize_with = "de::int")]
+    pub msg_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageMessage {
+    #[serde(rename = "PicUrl")]
+    pub pic_url: String,
+    #[serde(rename = "MediaId")]
+    pub media_id: String,
+    #[serde(rename = "MsgId", deserialize_with = "de::int")]
+    pub msg_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubscribeStatus {
+    #[serde(rename = "TemplateId")]
+    pub template_id: String,
+    /// accept、reject、ban（被封禁）、filter（标题同名被过滤）
+    #[serde(rename = "SubscribeStatusString")]
+    pub status: String,
+    /// 弹窗场景：0小程序页面，1设置页，2支付完成页
+    #[serde(rename = "PopupScene", default, deserialize_with = "de::opt_int")]
+    pub popup_scene: Option<i32>,
+}
+impl SubscribeStatus {
+    pub fn is_accepted(&self) -> bool {
+        self.status == "accept"
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubscribeSent {
+    #[serde(rename = "TemplateId")]
+    pub template_id: String,
+    #[serde(rename = "MsgID")]
+    pub msg_id: String,
+    /// 0为成功
+    #[serde(rename = "ErrorCode", deserialize_with = "de::int")]
+    pub error_code: i32,
+    #[serde(rename = "ErrorStatus")]
+    pub error_status: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::xml;
+    use super::{PushEvent, PushMessage};
+    use serde_json::json;
+
+    #[test]
+    fn subscribe_events() {
+        let from_xml = xml::to_json(
+            "<xml><ToUserName><![CDATA[gh_123]]></ToUserName>\
+             <FromUserName><![CDATA[OPENID]]></FromUserName>\
+             <CreateTime>1620963428</CreateTime>\
+             <MsgType><![CDATA[event]]></MsgType>\
+             <Event><![CDATA[subscribe_msg_sent_event]]></Event>\
+             <SubscribeMsgSentEvent><List>\
+             <TemplateId><![CDATA[T1]]></TemplateId>\
+             <MsgID>1700827132819554304</MsgID>\
+             <ErrorCode>0</ErrorCode>\
+             <ErrorStatus><![CDATA[success]]></ErrorStatus>\
+             </List></SubscribeMsgSentEvent></xml>",
+        )
+        .unwrap();
+        let from_json = json!({
+            "ToUserName": "gh_123",
+            "FromUserName": "OPENID",
+            "CreateTime": 1620963428,
+            "MsgType": "event",
+            "Event": "subscribe_msg_sent_event",
+            "List": [{
+                "TemplateId": "T1",
+                "MsgID": "1700827132819554304",
+                "ErrorCode": 0,
+                "ErrorStatus": "success"
+            }]
+        });
+        let a = PushMessage::from_value(from_xml).unwrap();
+        let b = PushMessage::from_value(from_json).unwrap();
+        assert_eq!(a.event, b.event);
+        assert_eq!(a.kind(), "subscribe_msg_sent_event");
+        assert_eq!(a.create_time, 1620963428);
+        match a.event {
+            PushEvent::SubscribeMsgSent(sent) => {
+                assert_eq!(sent.len(), 1);
+                assert_eq!(sent[0].error_code, 0);
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+
+        let popup = PushMessage::from_value(json!({
+            "ToUserName": "gh_123",
+            "FromUserName": "OPENID",
+            "CreateTime": "1620963428",
+            "MsgType": "event",
+            "Event": "subscribe_msg_popup_event",
+            "List": {"TemplateId": "T2", "SubscribeStatusString": "accept", "PopupScene": "2"}
+        }))
+        .unwrap();
+        match popup.event {
+            PushEvent::SubscribeMsgPopup(list) => {
+                assert!(list[0].is_accepted());
+                assert_eq!(list[0].popup_scene, Some(2));
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn messages() {
+        let text = PushMessage::from_value(json!({
+            "ToUserName": "gh_123",
+            "FromUserName": "OPENID",
+            "CreateTime": 1482048670,
+            "MsgType": "text",
+            "Content": "this is a test",
+            "MsgId": 1234567890123456i64
+        }))
+        .unwrap();
+        assert_eq!(text.kind(), "text");
+        assert!(matches!(text.event, PushEvent::Text(ref m) if m.content == "this is a test"));
+
+        let other =
+            PushMessage::from_value(json!({"MsgType": "event", "Event": "user_enter_tempsession"}))
+                .unwrap();
+        assert_eq!(other.event, PushEvent::Other);
+        assert_eq!(other.kind(), "user_enter_tempsession");
+
+        assert!(PushMessage::from_value(json!({"ToUserName": "gh_123"})).is_err());
+        assert!(PushMessage::from_value(json!({"MsgType": "text"})).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+/// 集成到tide
+///
+/// GET为服务器配置的验证请求，POST为推送；处理成功返回`success`
+///
+/// ```rs
+/// use crate::core::api::wechat_push::integrate_with_tide::PushEndpoint;
+/// app.at("/wechat/push").all(PushEndpoint::new(receiver));
+/// ```
+use super::{PushError, PushQuery, PushReceiver};
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use tide::{http::Method, Request, Response, StatusCode};
+
+#[derive(Clone)]
+pub struct PushEndpoint {
+    receiver: Arc<PushReceiver>,
+}
+impl PushEndpoint {
+    pub fn new(receiver: PushReceiver) -> Self {
+        Self {
+            receiver: Arc::new(receiver),
+        }
+    }
+}
+
+impl<State: Send + Sync + 'static> tide::Endpoint<State> for PushEndpoint {
+    fn call<'a>(&'a self, mut req: Request<State>) -> BoxFuture<'a, tide::Result> {
+        Box::pin(async move {
+            let query: PushQuery = req.query()?;
+            let result = if req.method() == Method::Get {
+                self.receiver.echo(&query)
+            } else {
+                let bytes = req.body_bytes().await?;
+                self.receiver
+                    .receive(&query, &bytes)
+                    .await
+                    .map(|_| "success".to_owned())
+            };
+            let body = result.map_err(into_tide_error)?;
+
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_body(body);
+            Ok(res)
+        })
+    }
+}
+
+/// 签名错误为401，内容错误为400，配置与handler错误为500（微信会重试）
+pub fn into_tide_error(e: PushError) -> tide::Error {
+    let status = match e {
+        PushError::Signature | PushError::Expired(_) => StatusCode::Unauthorized,
+        PushError::Decrypt(_) | PushError::AppId(_) | PushError::Parse(_) => StatusCode::BadRequest,
+        PushError::Config(_) | PushError::Handler(_) => StatusCode::InternalServerError,
+    };
+    warn!("push rejected: {}", e);
+    tide::Error::from_str(status, e.to_string())
+}
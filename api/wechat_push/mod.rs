@@ -0,0 +1,604 @@
+//! 接收微信服务器的消息推送（小程序、公众号）
+//!
+//! * 验证`signature`/`timestamp`/`nonce`，GET请求原样返回`echostr`完成服务器配置
+//! * 先验证`signature`再解析body；安全模式下验证`msg_signature`并用EncodingAESKey解密，
+//!   拒绝明文的推送（`PushMode`须与服务器配置中的消息加解密方式一致）
+//! * xml与json格式的推送都解析为`PushMessage`，按`Event`或`MsgType`分发给注册的handler
+//!
+//! ```rs
+//! use crate::core::api::wechat_push::{integrate_with_tide::PushEndpoint, Config, PushReceiver};
+//! let receiver = PushReceiver::new(Config::from_env()?)?
+//!     .on("subscribe_msg_popup_event", Arc::new(SubscribeStatusHandler::new(db.clone())))
+//!     .on_media_check(Arc::new(PostAuditHandler::new(db.clone())));
+//! app.at("/wechat/push").all(PushEndpoint::new(receiver));
+//! ```
+use super::client::redact;
+use super::wechat_miniprogram::{MediaCheckEvent, MediaCheckHandler};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use thiserror::Error as ThisError;
+
+mod cipher;
+pub(crate) mod de;
+mod event;
+pub mod integrate_with_tide;
+mod xml;
+
+pub use cipher::{signature, Cipher};
+pub use event::{
+    ImageMessage, PushEvent, PushMessage, SubscribeSent, SubscribeStatus, TextMessage,
+};
+
+// timestamp与服务器时间相差超过5分钟的推送视为重放
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// 服务器配置中的消息加解密方式
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PushMode {
+    /// 明文模式
+    Plain,
+    /// 兼容模式：明文与密文都接受，切换到安全模式前的过渡
+    Compatible,
+    /// 安全模式：只接受密文
+    Safe,
+}
+impl Default for PushMode {
+    fn default() -> Self {
+        PushMode::Plain
+    }
+}
+impl std::str::FromStr for PushMode {
+    type Err = PushError;
+    fn from_str(s: &str) -> PushResult<Self> {
+        match s {
+            "plain" => Ok(PushMode::Plain),
+            "compatible" => Ok(PushMode::Compatible),
+            "safe" => Ok(PushMode::Safe),
+            _ => Err(PushError::Config(format!("unknown push mode: {}", s))),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Config {
+    /// 服务器配置中的Token
+    pub token: String,
+    #[serde(default)]
+    pub mode: PushMode,
+    /// 安全模式、兼容模式下的EncodingAESKey（43位）
+    pub encoding_aes_key: Option<String>,
+    /// 解密后校验
+    pub appid: String,
+}
+impl Config {
+    /// `WECHAT_PUSH_MODE`为plain、compatible或safe，没有设置时按是否有`WECHAT_PUSH_AES_KEY`
+    /// 取safe或plain
+    pub fn from_env() -> PushResult<Self> {
+        fn var(key: &str) -> PushResult<String> {
+            env::var(key).map_err(|_| PushError::Config(format!("{} is missing!", key)))
+        }
+        let token = var("WECHAT_PUSH_TOKEN")?;
+        let encoding_aes_key = env::var("WECHAT_PUSH_AES_KEY").ok();
+        let mode = match env::var("WECHAT_PUSH_MODE") {
+            Ok(mode) => mode.parse()?,
+            Err(_) if encoding_aes_key.is_some() => PushMode::Safe,
+            Err(_) => PushMode::Plain,
+        };
+        let appid = var("WECHAT_PUSH_APPID")?;
+        Ok(Self {
+            token,
+            mode,
+            encoding_aes_key,
+            appid,
+        })
+    }
+}
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("token", &redact::secret(&self.token))
+            .field("mode", &self.mode)
+            .field(
+                "encoding_aes_key",
+                &self.encoding_aes_key.as_deref().map(redact::secret),
+            )
+            .field("appid", &self.appid)
+            .finish()
+    }
+}
+
+#[derive(ThisError, Debug)]
+pub enum PushError {
+    #[error("Push Config Error: {0}")]
+    Config(String),
+    #[error("Push Signature Mismatch")]
+    Signature,
+    #[error("Push Timestamp Expired: {0}")]
+    Expired(String),
+    #[error("Push Decrypt Error: {0}")]
+    Decrypt(String),
+    #[error("Push AppId Mismatch: {0}")]
+    AppId(String),
+    #[error("Push Parse Error: {0}")]
+    Parse(String),
+    #[error("Push Handler Error: {0}")]
+    Handler(Box<dyn std::error::Error + Send + Sync>),
+}
+pub type PushResult<T> = Result<T, PushError>;
+
+/// 推送的url参数
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct PushQuery {
+    pub signature: String,
+    pub timestamp: String,
+    pub nonce: String,
+    /// 服务器配置时的GET请求
+    pub echostr: Option<String>,
+    /// 安全模式为aes
+    pub encrypt_type: Option<String>,
+    pub msg_signature: Option<String>,
+}
+
+pub type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+/// 返回错误时响应500，微信会重试（最多3次），handler须能处理重复的推送
+#[async_trait]
+pub trait PushHandler: Send + Sync {
+    async fn handle(&self, message: &PushMessage) -> HandlerResult;
+}
+
+pub struct PushReceiver {
+    cfg: Config,
+    cipher: Option<Cipher>,
+    handlers: HashMap<String, Arc<dyn PushHandler>>,
+    fallback: Option<Arc<dyn PushHandler>>,
+}
+
+impl PushReceiver {
+    /// 兼容模式、安全模式须配置EncodingAESKey，明文模式不能配置
+    pub fn new(cfg: Config) -> PushResult<Self> {
+        let cipher = match (cfg.mode, cfg.encoding_aes_key.as_deref()) {
+            (PushMode::Plain, None) => None,
+            (PushMode::Plain, Some(_)) => {
+                return Err(PushError::Config(
+                    "EncodingAESKey is configured in plain mode".to_owned(),
+                ))
+            }
+            (_, Some(key)) => Some(Cipher::new(key, &cfg.appid)?),
+            (mode, None) => {
+                return Err(PushError::Config(format!(
+                    "EncodingAESKey is missing in {:?} mode",
+                    mode
+                )))
+            }
+        };
+        Ok(Self {
+            cfg,
+            cipher,
+            handlers: HashMap::new(),
+            fallback: None,
+        })
+    }
+
+    /// `kind`为事件的`Event`（例如subscribe_msg_popup_event），或消息的`MsgType`（例如text）
+    pub fn on(mut self, kind: &str, handler: Arc<dyn PushHandler>) -> Self {
+        self.handlers.insert(kind.to_owned(), handler);
+        self
+    }
+
+    /// `media_check_async`的检测结果
+    pub fn on_media_check(self, handler: Arc<dyn MediaCheckHandler>) -> Self {
+        let appid = self.cfg.appid.clone();
+        self.on(
+            "wxa_media_check",
+            Arc::new(MediaCheckDispatcher { appid, handler }),
+        )
+    }
+
+    /// 没有注册handler的推送
+    pub fn fallback(mut self, handler: Arc<dyn PushHandler>) -> Self {
+        self.fallback = Some(handler);
+        self
+    }
+
+    /// 服务器配置的验证请求，返回`echostr`
+    pub fn echo(&self, query: &PushQuery) -> PushResult<String> {
+        self.verify(query, &query.signature, None)?;
+        query
+            .echostr
+            .clone()
+            .ok_or_else(|| PushError::Parse("echostr is missing".to_owned()))
+    }
+
+    /// 验证签名、解密并解析
+    pub fn parse(&self, query: &PushQuery, body: &[u8]) -> PushResult<PushMessage> {
+        // 各种模式下都带有signature，先验证再解析body
+        self.verify(query, &query.signature, None)?;
+        let safe = self.cfg.mode == PushMode::Safe;
+        if safe && query.encrypt_type.as_deref() != Some("aes") {
+            return Err(PushError::Decrypt("plaintext push in safe mode".to_owned()));
+        }
+
+        let body = std::str::from_utf8(body).map_err(|e| PushError::Parse(e.to_string()))?;
+        let is_xml = body.trim_start().starts_with('<');
+        let value = to_value(body, is_xml)?;
+
+        let value = match value.get("Encrypt").and_then(|v| v.as_str()) {
+            Some(encrypted) => {
+                let cipher = self
+                    .cipher
+                    .as_ref()
+                    .ok_or_else(|| PushError::Decrypt("EncodingAESKey is missing".to_owned()))?;
+                // signature不覆盖body，密文须再验证msg_signature
+                let msg_signature = query.msg_signature.as_deref().unwrap_or_default();
+                self.verify(query, msg_signature, Some(encrypted))?;
+                let plain = cipher.decrypt(encrypted)?;
+                // 解密后与外层的格式相同
+                to_value(&plain, plain.trim_start().starts_with('<'))?
+            }
+            None if safe => {
+                return Err(PushError::Decrypt("Encrypt is missing".to_owned()));
+            }
+            // 明文模式、兼容模式
+            None => value,
+        };
+        debug!("push <= {}", redact::body(&value.to_string()));
+        PushMessage::from_value(value)
+    }
+
+    /// 处理一条推送；没有对应的handler时忽略
+    pub async fn receive(&self, query: &PushQuery, body: &[u8]) -> PushResult<()> {
+        let message = self.parse(query, body)?;
+        let handler = self
+            .handlers
+            .get(message.kind())
+            .or_else(|| self.fallback.as_ref());
+        match handler {
+            Some(handler) => handler.handle(&message).await.map_err(PushError::Handler),
+            None => {
+                debug!("push ignored: {}", message.kind());
+                Ok(())
+            }
+        }
+    }
+
+    fn verify(&self, query: &PushQuery, expected: &str, encrypted: Option<&str>) -> PushResult<()> {
+        let actual = signature(&self.cfg.token, &query.timestamp, &query.nonce, encrypted);
+        // 比较签名时不提前返回
+        if !bool::from(actual.as_bytes().ct_eq(expected.as_bytes())) {
+            return Err(PushError::Signature);
+        }
+        let timestamp: i64 = query
+            .timestamp
+            .parse()
+            .map_err(|_| PushError::Expired(query.timestamp.clone()))?;
+        if (chrono::Utc::now().timestamp() - timestamp).abs() > MAX_CLOCK_SKEW_SECS {
+            return Err(PushError::Expired(query.timestamp.clone()));
+        }
+        Ok(())
+    }
+}
+
+fn to_value(body: &str, is_xml: bool) -> PushResult<serde_json::Value> {
+    if is_xml {
+        xml::to_json(body).map_err(PushError::Parse)
+    } else {
+        serde_json::from_str(body).map_err(|e| PushError::Parse(e.to_string()))
+    }
+}
+
+struct MediaCheckDispatcher {
+    appid: String,
+    handler: Arc<dyn MediaCheckHandler>,
+}
+#[async_trait]
+impl PushHandler for MediaCheckDispatcher {
+    async fn handle(&self, message: &PushMessage) -> HandlerResult {
+        let event: MediaCheckEvent = match &message.event {
+            PushEvent::MediaCheck(event) => event.clone(),
+            _ => return Ok(()),
+        };
+        if event.appid != self.appid {
+            return Err(PushError::AppId(event.appid).into());
+        }
+        self.handler.on_media_check(event).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::client::ClientResult;
+    use super::super::wechat_miniprogram::{MediaCheckEvent, MediaCheckHandler};
+    use super::{
+        signature, Cipher, Config, HandlerResult, PushError, PushEvent, PushHandler, PushMessage,
+        PushMode, PushQuery, PushReceiver,
+    };
+    use crate::core::testing::TestResult;
+    use async_std::sync::Mutex;
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    const TOKEN: &str = "TOKEN";
+    const AES_KEY: &str = "abcdefghijklmnopqrstuvwxyz0123456789ABCDEFG";
+    const APPID: &str = "wx5823bf96d3bd56c7";
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    fn config(mode: PushMode, encoding_aes_key: Option<&str>) -> Config {
+        Config {
+            token: TOKEN.to_owned(),
+            mode,
+            encoding_aes_key: encoding_aes_key.map(ToOwned::to_owned),
+            appid: APPID.to_owned(),
+        }
+    }
+
+    fn query(encrypted: Option<&str>) -> PushQuery {
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let nonce = "1372623149".to_owned();
+        PushQuery {
+            signature: signature(TOKEN, &timestamp, &nonce, None),
+            msg_signature: encrypted.map(|e| signature(TOKEN, &timestamp, &nonce, Some(e))),
+            encrypt_type: encrypted.map(|_| "aes".to_owned()),
+            timestamp,
+            nonce,
+            echostr: None,
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<PushMessage>>);
+    #[async_trait]
+    impl PushHandler for Recorder {
+        async fn handle(&self, message: &PushMessage) -> HandlerResult {
+            self.0.lock().await.push(message.clone());
+            Ok(())
+        }
+    }
+    #[async_trait]
+    impl MediaCheckHandler for Recorder {
+        async fn on_media_check(&self, event: MediaCheckEvent) -> ClientResult<()> {
+            self.0.lock().await.push(PushMessage {
+                to_user_name: event.to_user_name.clone(),
+                from_user_name: event.from_user_name.clone(),
+                create_time: event.create_time,
+                msg_type: "event".to_owned(),
+                event_name: Some(event.event.clone()),
+                event: PushEvent::MediaCheck(event),
+                raw: json!({}),
+            });
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn echo() {
+        setup();
+
+        let receiver = PushReceiver::new(config(PushMode::Plain, None)).unwrap();
+        let mut q = query(None);
+        q.echostr = Some("ECHOSTR".to_owned());
+        assert_eq!(receiver.echo(&q).unwrap(), "ECHOSTR");
+
+        let mut forged = q.clone();
+        forged.nonce = "0".to_owned();
+        assert!(matches!(receiver.echo(&forged), Err(PushError::Signature)));
+
+        let mut expired = q.clone();
+        expired.timestamp = "1409659813".to_owned();
+        expired.signature = signature(TOKEN, &expired.timestamp, &expired.nonce, None);
+        assert!(matches!(
+            receiver.echo(&expired),
+            Err(PushError::Expired(_))
+        ));
+    }
+
+    #[test]
+    fn mode_requires_key() {
+        assert!(PushReceiver::new(config(PushMode::Compatible, Some(AES_KEY))).is_ok());
+        assert!(matches!(
+            PushReceiver::new(config(PushMode::Safe, None)),
+            Err(PushError::Config(_))
+        ));
+        assert!(matches!(
+            PushReceiver::new(config(PushMode::Plain, Some(AES_KEY))),
+            Err(PushError::Config(_))
+        ));
+        assert!(matches!(
+            PushReceiver::new(config(PushMode::Safe, Some("short"))),
+            Err(PushError::Decrypt(_))
+        ));
+        assert_eq!("safe".parse::<PushMode>().unwrap(), PushMode::Safe);
+        assert!("aes".parse::<PushMode>().is_err());
+    }
+
+    #[async_std::test]
+    async fn receive_plain() -> TestResult<()> {
+        setup();
+
+        let recorder = Arc::new(Recorder::default());
+        let receiver = PushReceiver::new(config(PushMode::Plain, None))?
+            .on("subscribe_msg_change_event", recorder.clone())
+            .on("text", recorder.clone());
+
+        let xml = "<xml><ToUserName><![CDATA[gh_123]]></ToUserName>\
+            <FromUserName><![CDATA[OPENID]]></FromUserName>\
+            <CreateTime>1610969440</CreateTime>\
+            <MsgType><![CDATA[event]]></MsgType>\
+            <Event><![CDATA[subscribe_msg_change_event]]></Event>\
+            <SubscribeMsgChangeEvent><List>\
+            <TemplateId><![CDATA[T1]]></TemplateId>\
+            <SubscribeStatusString><![CDATA[reject]]></SubscribeStatusString>\
+            </List></SubscribeMsgChangeEvent></xml>";
+        receiver.receive(&query(None), xml.as_bytes()).await?;
+
+        let text = json!({
+            "ToUserName": "gh_123",
+            "FromUserName": "OPENID",
+            "CreateTime": 1610969440,
+            "MsgType": "text",
+            "Content": "你好",
+            "MsgId": 23
+        });
+        receiver
+            .receive(&query(None), text.to_string().as_bytes())
+            .await?;
+        // 没有handler的忽略
+        let other = json!({"MsgType": "event", "Event": "user_enter_tempsession"});
+        receiver
+            .receive(&query(None), other.to_string().as_bytes())
+            .await?;
+
+        let mut forged = query(None);
+        forged.signature = "0".repeat(40);
+        assert!(matches!(
+            receiver.receive(&forged, xml.as_bytes()).await,
+            Err(PushError::Signature)
+        ));
+        // 签名不对时不解析body
+        let nested = "<a>".repeat(100_000);
+        assert!(matches!(
+            receiver.receive(&forged, nested.as_bytes()).await,
+            Err(PushError::Signature)
+        ));
+        assert!(matches!(
+            receiver.receive(&query(None), nested.as_bytes()).await,
+            Err(PushError::Parse(_))
+        ));
+        // 没有配置EncodingAESKey时不能解密
+        let encrypted = json!({"ToUserName": "gh_123", "Encrypt": "xxx"}).to_string();
+        assert!(matches!(
+            receiver
+                .receive(&query(Some("xxx")), encrypted.as_bytes())
+                .await,
+            Err(PushError::Decrypt(_))
+        ));
+
+        let messages = recorder.0.lock().await;
+        assert_eq!(messages.len(), 2);
+        match &messages[0].event {
+            PushEvent::SubscribeMsgChange(list) => {
+                assert_eq!(list[0].template_id, "T1");
+                assert!(!list[0].is_accepted());
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+        assert!(matches!(&messages[1].event, PushEvent::Text(m) if m.content == "你好"));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn receive_encrypted() -> TestResult<()> {
+        setup();
+
+        let recorder = Arc::new(Recorder::default());
+        let receiver = PushReceiver::new(config(PushMode::Safe, Some(AES_KEY)))?
+            .on_media_check(recorder.clone())
+            .fallback(recorder.clone());
+        let cipher = Cipher::new(AES_KEY, APPID)?;
+
+        // json格式
+        let event = json!({
+            "ToUserName": "gh_123",
+            "FromUserName": "OPENID",
+            "CreateTime": 1626959646,
+            "MsgType": "event",
+            "Event": "wxa_media_check",
+            "appid": APPID,
+            "trace_id": "TRACE_ID",
+            "version": 2,
+            "detail": [{"strategy": "content_model", "errcode": 0, "suggest": "risky", "label": 20002, "prob": 90}],
+            "errcode": 0,
+            "errmsg": "ok",
+            "result": {"suggest": "risky", "label": 20002}
+        });
+        let encrypted = cipher.encrypt(&event.to_string())?;
+        let body = json!({"ToUserName": "gh_123", "Encrypt": encrypted}).to_string();
+        receiver
+            .receive(&query(Some(&encrypted)), body.as_bytes())
+            .await?;
+
+        // xml格式
+        let event = "<xml><ToUserName><![CDATA[gh_123]]></ToUserName>\
+            <FromUserName><![CDATA[OPENID]]></FromUserName>\
+            <CreateTime>1626959646</CreateTime>\
+            <MsgType><![CDATA[event]]></MsgType>\
+            <Event><![CDATA[wxa_media_check]]></Event>\
+            <appid><![CDATA[wx5823bf96d3bd56c7]]></appid>\
+            <trace_id><![CDATA[TRACE_ID_2]]></trace_id>\
+            <version>2</version>\
+            <detail><strategy><![CDATA[content_model]]></strategy><errcode>0</errcode>\
+            <suggest><![CDATA[pass]]></suggest><label>100</label><prob>90</prob></detail>\
+            <errcode>0</errcode><errmsg><![CDATA[ok]]></errmsg>\
+            <result><suggest><![CDATA[pass]]></suggest><label>100</label></result></xml>";
+        let encrypted = cipher.encrypt(event)?;
+        let body = format!(
+            "<xml><ToUserName><![CDATA[gh_123]]></ToUserName><Encrypt><![CDATA[{}]]></Encrypt></xml>",
+            encrypted
+        );
+        receiver
+            .receive(&query(Some(&encrypted)), body.as_bytes())
+            .await?;
+
+        // msg_signature不对
+        let mut forged = query(Some(&encrypted));
+        forged.msg_signature = Some(forged.signature.clone());
+        assert!(matches!(
+            receiver.receive(&forged, body.as_bytes()).await,
+            Err(PushError::Signature)
+        ));
+
+        // 安全模式拒绝明文，即使签名正确
+        let other = json!({"MsgType": "event", "Event": "user_enter_tempsession"});
+        assert!(matches!(
+            receiver
+                .receive(&query(None), other.to_string().as_bytes())
+                .await,
+            Err(PushError::Decrypt(_))
+        ));
+        let mut plain = query(None);
+        plain.encrypt_type = Some("aes".to_owned());
+        assert!(matches!(
+            receiver.receive(&plain, other.to_string().as_bytes()).await,
+            Err(PushError::Decrypt(_))
+        ));
+
+        // 兼容模式的明文走fallback
+        let compatible = PushReceiver::new(config(PushMode::Compatible, Some(AES_KEY)))?
+            .fallback(recorder.clone());
+        compatible
+            .receive(&query(None), other.to_string().as_bytes())
+            .await?;
+        compatible
+            .receive(&query(Some(&encrypted)), body.as_bytes())
+            .await?;
+
+        let messages = recorder.0.lock().await;
+        assert_eq!(messages.len(), 4);
+        match (&messages[0].event, &messages[1].event) {
+            (PushEvent::MediaCheck(a), PushEvent::MediaCheck(b)) => {
+                assert!(!a.result.is_pass());
+                assert_eq!(a.result.label_name(), "色情");
+                assert_eq!(b.trace_id, "TRACE_ID_2");
+                assert!(b.result.is_pass());
+                assert_eq!(b.detail[0].prob, Some(90));
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+        assert_eq!(messages[2].kind(), "user_enter_tempsession");
+        assert_eq!(messages[3].kind(), "wxa_media_check");
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+//! 推送消息的xml转换为json，之后与json格式的推送共用反序列化
+//!
+//! 只支持推送用到的子集：元素、文本、CDATA、注释，忽略属性；
+//! 有子元素的转换为对象，同名子元素转换为数组，其它转换为字符串
+use serde_json::{Map, Value};
+
+// 推送的嵌套不超过4层，限制深度避免恶意的深层嵌套耗尽栈
+const MAX_DEPTH: usize = 16;
+
+pub(super) fn to_json(xml: &str) -> Result<Value, String> {
+    let mut parser = Parser { rest: xml };
+    parser.skip_misc();
+    if parser.rest.starts_with("<?") {
+        parser.skip_past("?>")?;
+        parser.skip_misc();
+    }
+    let (_, value) = parser.element(0)?;
+    parser.skip_misc();
+    if !parser.rest.is_empty() {
+        return Err("unexpected content after root element".to_owned());
+    }
+    // 根元素`<xml>`没有内容时为空对象
+    Ok(match value {
+        Value::String(s) if s.trim().is_empty() => Value::Object(Map::new()),
+        v => v,
+    })
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    // 跳过空白与注释
+    fn skip_misc(&mut self) {
+        loop {
+            self.rest = self.rest.trim_start();
+            if self.rest.starts_with("<!--") {
+                if self.skip_past("-->").is_err() {
+                    return;
+                }
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn skip_past(&mut self, end: &str) -> Result<&'a str, String> {
+        let i = self
+            .rest
+            .find(end)
+            .ok_or_else(|| format!("missing {}", end))?;
+        let skipped = &self.rest[..i];
+        self.rest = &self.rest[i + end.len()..];
+        Ok(skipped)
+    }
+
+    fn element(&mut self, depth: usize) -> Result<(String, Value), String> {
+        if depth >= MAX_DEPTH {
+            return Err(format!("nested deeper than {}", MAX_DEPTH));
+        }
+        if !self.rest.starts_with('<') {
+            return Err("expected element".to_owned());
+        }
+        self.rest = &self.rest[1..];
+        let tag = self.skip_past(">")?;
+        let (tag, empty) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let name = tag.split_whitespace().next().unwrap_or_default().to_owned();
+        if name.is_empty() {
+            return Err("empty element name".to_owned());
+        }
+        if empty {
+            return Ok((name, Value::String(String::new())));
+        }
+
+        let mut text = String::new();
+        let mut children: Map<String, Value> = Map::new();
+        loop {
+            if self.rest.starts_with("<![CDATA[") {
+                self.rest = &self.rest["<![CDATA[".len()..];
+                text.push_str(self.skip_past("]]>")?);
+            } else if self.rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest.starts_with("</") {
+                self.rest = &self.rest[2..];
+                let close = self.skip_past(">")?;
+                if close.trim() != name {
+                    return Err(format!("mismatched </{}>, expected </{}>", close, name));
+                }
+                break;
+            } else if self.rest.starts_with('<') {
+                let (child, value) = self.element(depth + 1)?;
+                match children.get_mut(&child) {
+                    Some(Value::Array(items)) => items.push(value),
+                    Some(first) => *first = Value::Array(vec![first.take(), value]),
+                    None => {
+                        children.insert(child, value);
+                    }
+                }
+            } else if self.rest.is_empty() {
+                return Err(format!("missing </{}>", name));
+            } else {
+                let end = self.rest.find('<').unwrap_or(self.rest.len());
+                text.push_str(&unescape(&self.rest[..end]));
+                self.rest = &self.rest[end..];
+            }
+        }
+
+        if children.is_empty() {
+            Ok((name, Value::String(text)))
+        } else {
+            Ok((name, Value::Object(children)))
+        }
+    }
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_json;
+    use serde_json::json;
+
+    #[test]
+    fn push_xml() {
+        let xml = r#"<?xml version="1.0"?>
+            <xml>
+              <ToUserName><![CDATA[gh_123]]></ToUserName>
+              <CreateTime>1626959646</CreateTime>
+              <Content><![CDATA[a <b> & ]]>&amp;c</Content>
+              <!-- 注释 -->
+              <SubscribeMsgPopupEvent>
+                <List>
+                  <TemplateId><![CDATA[T1]]></TemplateId>
+                  <SubscribeStatusString><![CDATA[accept]]></SubscribeStatusString>
+                </List>
+                <List>
+                  <TemplateId><![CDATA[T2]]></TemplateId>
+                  <SubscribeStatusString><![CDATA[reject]]></SubscribeStatusString>
+                </List>
+              </SubscribeMsgPopupEvent>
+              <Empty/>
+            </xml>"#;
+        assert_eq!(
+            to_json(xml).unwrap(),
+            json!({
+                "ToUserName": "gh_123",
+                "CreateTime": "1626959646",
+                "Content": "a <b> & &c",
+                "SubscribeMsgPopupEvent": {
+                    "List": [
+                        {"TemplateId": "T1", "SubscribeStatusString": "accept"},
+                        {"TemplateId": "T2", "SubscribeStatusString": "reject"}
+                    ]
+                },
+                "Empty": ""
+            })
+        );
+        assert_eq!(to_json("<xml></xml>").unwrap(), json!({}));
+    }
+
+    #[test]
+    fn invalid_xml() {
+        assert!(to_json("<xml><a>1</b></xml>").is_err());
+        assert!(to_json("<xml><a>1</a>").is_err());
+        assert!(to_json("<xml></xml><xml></xml>").is_err());
+        assert!(to_json("{}").is_err());
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let nested = |depth: usize| format!("{}1{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        assert!(to_json(&nested(16)).is_ok());
+        assert!(to_json(&nested(17)).is_err());
+        // 不会栈溢出
+        assert!(to_json(&nested(1_000_000)).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! 通讯录读取
+//!
+//! 2022年6月后新建的自建应用，`user_info`不再返回手机号、邮箱等字段，
+//! 需通过网页授权的`user_detail`获取
+use super::super::client::{redact, ClientResult, Endpoint};
+use super::Wecom;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 成员
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct UserInfo {
+    pub userid: String,
+    pub name: String,
+    #[serde(default)]
+    pub department: Vec<i64>,
+    pub main_department: Option<i64>,
+    #[serde(default)]
+    pub position: String,
+    #[serde(default)]
+    pub mobile: String,
+    /// "0"未定义 "1"男性 "2"女性
+    #[serde(default)]
+    pub gender: String,
+    #[serde(default)]
+    pub email: String,
+    #[serde(default)]
+    pub avatar: String,
+    /// 1已激活 2已禁用 4未激活 5退出企业
+    #[serde(default)]
+    pub status: i32,
+    /// 对外职务
+    #[serde(default)]
+    pub external_position: String,
+}
+impl UserInfo {
+    pub fn is_active(&self) -> bool {
+        self.status == 1
+    }
+}
+// 手机号脱敏
+impl fmt::Debug for UserInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserInfo")
+            .field("userid", &self.userid)
+            .field("name", &self.name)
+            .field("department", &self.department)
+            .field("main_department", &self.main_department)
+            .field("position", &self.position)
+            .field("mobile", &redact::phone(&self.mobile))
+            .field("gender", &self.gender)
+            .field("email", &self.email)
+            .field("avatar", &self.avatar)
+            .field("status", &self.status)
+            .field("external_position", &self.external_position)
+            .finish()
+    }
+}
+
+/// 部门成员（只有基础信息）
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SimpleUser {
+    pub userid: String,
+    pub name: String,
+    #[serde(default)]
+    pub department: Vec<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Department {
+    pub id: i64,
+    #[serde(default)]
+    pub name: String,
+    /// 根部门为0
+    #[serde(default)]
+    pub parentid: i64,
+    /// 在父部门中的次序，值大的排序靠前
+    #[serde(default)]
+    pub order: i64,
+    /// 部门负责人的userid
+    #[serde(default)]
+    pub department_leader: Vec<String>,
+}
+
+impl Wecom {
+    pub async fn user_info(&self, userid: &str) -> ClientResult<UserInfo> {
+        let endpoint = Endpoint::new("https://qyapi.weixin.qq.com/cgi-bin/user/get")
+            .access_token()
+            .query("userid", userid);
+        self.0.client.get(endpoint).await
+    }
+
+    /// `id`为None时返回全部部门（应用可见范围内），否则返回该部门及其子部门
+    pub async fn department_list(&self, id: Option<i64>) -> ClientResult<Vec<Department>> {
+        let mut endpoint =
+            Endpoint::new("https://qyapi.weixin.qq.com/cgi-bin/department/list").access_token();
+        if let Some(id) = id {
+            endpoint = endpoint.query("id", id);
+        }
+
+        #[derive(Deserialize)]
+        struct ApiResult {
+            #[serde(default)]
+            department: Vec<Department>,
+        }
+        let resp: ApiResult = self.0.client.get(endpoint).await?;
+        Ok(resp.department)
+    }
+
+    /// 部门的直属成员
+    pub async fn department_users(&self, department_id: i64) -> ClientResult<Vec<SimpleUser>> {
+        let endpoint = Endpoint::new("https://qyapi.weixin.qq.com/cgi-bin/user/simplelist")
+            .access_token()
+            .query("department_id", department_id);
+
+        #[derive(Deserialize)]
+        struct ApiResult {
+            #[serde(default)]
+            userlist: Vec<SimpleUser>,
+        }
+        let resp: ApiResult = self.0.client.get(endpoint).await?;
+        Ok(resp.userlist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock_wecom;
+    use crate::core::testing::TestResult;
+    use serde_json::json;
+
+    #[async_std::test]
+    async fn user_info_offline() -> TestResult<()> {
+        let (app, mock) = mock_wecom();
+        mock.reply_json(
+            "/cgi-bin/user/get",
+            json!({
+                "errcode": 0,
+                "errmsg": "ok",
+                "userid": "zhangsan",
+                "name": "张三",
+                "department": [1, 2],
+                "main_department": 1,
+                "position": "产品经理",
+                "status": 1
+            }),
+        )
+        .reply_json(
+            "/cgi-bin/user/get",
+            json!({"errcode": 60111, "errmsg": "userid not found"}),
+        );
+
+        let user = app.user_info("zhangsan").await?;
+        assert!(user.is_active());
+        assert_eq!(user.department, vec![1, 2]);
+        assert_eq!(user.mobile, "");
+        let err = app.user_info("lisi").await.unwrap_err();
+        assert_eq!(err.code(), Some(60111));
+        assert!(mock.requests()[1]
+            .url
+            .ends_with("access_token=TOKEN&userid=zhangsan"));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn departments_offline() -> TestResult<()> {
+        let (app, mock) = mock_wecom();
+        mock.reply_json(
+            "/cgi-bin/department/list",
+            json!({
+                "errcode": 0,
+                "errmsg": "ok",
+                "department": [
+                    {"id": 1, "name": "广州研发中心", "parentid": 0, "order": 10, "department_leader": ["zhangsan"]},
+                    {"id": 2, "name": "邮箱产品部", "parentid": 1, "order": 40}
+                ]
+            }),
+        )
+        .reply_json(
+            "/cgi-bin/user/simplelist",
+            json!({
+                "errcode": 0,
+                "errmsg": "ok",
+                "userlist": [{"userid": "zhangsan", "name": "张三", "department": [1, 2]}]
+            }),
+        );
+
+        let departments = app.department_list(Some(1)).await?;
+        assert_eq!(departments.len(), 2);
+        assert_eq!(departments[1].parentid, 1);
+        assert!(departments[1].department_leader.is_empty());
+        let users = app.department_users(2).await?;
+        assert_eq!(users[0].userid, "zhangsan");
+
+        let requests = mock.requests();
+        assert!(requests[1].url.ends_with("access_token=TOKEN&id=1"));
+        assert!(requests[2]
+            .url
+            .ends_with("access_token=TOKEN&department_id=2"));
+        Ok(())
+    }
+}
@@ -0,0 +1,316 @@
+//! 应用消息
+//!
+//! 以应用的身份发给企业成员，接收人须在应用的可见范围内
+//!
+//! ```rs
+//! let message = AppMessage::new(MessageBody::text_card(
+//!     "预约成功",
+//!     "<div class=\"gray\">2030年12月17日</div>",
+//!     "https://h5.example.com/order?id=1",
+//! ))
+//! .to_users(&["zhangsan", "lisi"]);
+//! let result = wecom.send_app_message(&message).await?;
+//! ```
+use super::super::client::{ClientResult, Endpoint};
+use super::Wecom;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Content {
+    pub content: String,
+}
+
+/// 文本卡片，`description`支持`<div class="gray|normal|highlight">`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TextCard {
+    pub title: String,
+    pub description: String,
+    pub url: String,
+    /// 按钮文字，默认为“详情”
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub btntxt: Option<String>,
+}
+
+/// 图文消息的一条，最多8条
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Article {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub url: String,
+    /// 图片地址，大图1068*455，小图150*150
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picurl: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct News {
+    pub articles: Vec<Article>,
+}
+
+/// 消息内容，序列化为`{"msgtype": "text", "text": {...}}`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "msgtype", rename_all = "lowercase")]
+pub enum MessageBody {
+    Text {
+        text: Content,
+    },
+    /// 只有企业微信客户端能显示，微信插件里不支持
+    Markdown {
+        markdown: Content,
+    },
+    Textcard {
+        textcard: TextCard,
+    },
+    News {
+        news: News,
+    },
+}
+impl MessageBody {
+    pub fn text(content: &str) -> Self {
+        MessageBody::Text {
+            text: Content {
+                content: content.to_owned(),
+            },
+        }
+    }
+
+    pub fn markdown(content: &str) -> Self {
+        MessageBody::Markdown {
+            markdown: Content {
+                content: content.to_owned(),
+            },
+        }
+    }
+
+    pub fn text_card(title: &str, description: &str, url: &str) -> Self {
+        MessageBody::Textcard {
+            textcard: TextCard {
+                title: title.to_owned(),
+                description: description.to_owned(),
+                url: url.to_owned(),
+                btntxt: None,
+            },
+        }
+    }
+
+    pub fn news(articles: Vec<Article>) -> Self {
+        MessageBody::News {
+            news: News { articles },
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AppMessage {
+    /// 成员userid，多个用`|`分隔，`@all`为全部成员
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub touser: String,
+    /// 部门id，多个用`|`分隔
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub toparty: String,
+    /// 标签id，多个用`|`分隔
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub totag: String,
+    #[serde(flatten)]
+    pub body: MessageBody,
+    /// 1为保密消息（带水印、不能分享）
+    pub safe: i32,
+    /// 1为开启重复消息检查，间隔内内容相同的消息不再发送
+    pub enable_duplicate_check: i32,
+    /// 重复消息检查的时间间隔（秒），默认1800，最大4小时
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_check_interval: Option<i64>,
+}
+impl AppMessage {
+    pub fn new(body: MessageBody) -> Self {
+        Self {
+            touser: Default::default(),
+            toparty: Default::default(),
+            totag: Default::default(),
+            body,
+            safe: 0,
+            enable_duplicate_check: 0,
+            duplicate_check_interval: None,
+        }
+    }
+
+    pub fn to_users(mut self, userids: &[&str]) -> Self {
+        self.touser = userids.join("|");
+        self
+    }
+
+    pub fn to_all(mut self) -> Self {
+        self.touser = "@all".to_owned();
+        self
+    }
+
+    pub fn to_parties(mut self, ids: &[i64]) -> Self {
+        self.toparty = join_ids(ids);
+        self
+    }
+
+    pub fn to_tags(mut self, ids: &[i64]) -> Self {
+        self.totag = join_ids(ids);
+        self
+    }
+
+    pub fn safe(mut self) -> Self {
+        self.safe = 1;
+        self
+    }
+
+    pub fn duplicate_check(mut self, interval_secs: i64) -> Self {
+        self.enable_duplicate_check = 1;
+        self.duplicate_check_interval = Some(interval_secs);
+        self
+    }
+}
+
+fn join_ids(ids: &[i64]) -> String {
+    ids.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// 发送结果，部分接收人无效时接口仍返回成功
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SendResult {
+    #[serde(default)]
+    pub invaliduser: String,
+    #[serde(default)]
+    pub invalidparty: String,
+    #[serde(default)]
+    pub invalidtag: String,
+    /// 没有基础接口许可的成员
+    #[serde(default)]
+    pub unlicenseduser: String,
+    /// 用于撤回消息
+    #[serde(default)]
+    pub msgid: String,
+}
+impl SendResult {
+    /// 无效（不存在或不在可见范围内）的成员userid
+    pub fn invalid_users(&self) -> Vec<&str> {
+        self.invaliduser
+            .split('|')
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+}
+
+impl Wecom {
+    /// 以当前应用（`Config.agent_id`）发送消息
+    pub async fn send_app_message(&self, message: &AppMessage) -> ClientResult<SendResult> {
+        let endpoint =
+            Endpoint::new("https://qyapi.weixin.qq.com/cgi-bin/message/send").access_token();
+
+        #[derive(Serialize)]
+        struct SendRequest<'a> {
+            agentid: i64,
+            #[serde(flatten)]
+            message: &'a AppMessage,
+        }
+
+        let payload = SendRequest {
+            agentid: self.0.cfg.agent_id,
+            message,
+        };
+        let result: SendResult = self.0.client.post(endpoint, Some(&payload)).await?;
+        if !result.invaliduser.is_empty() {
+            warn!("send_app_message() invalid users: {}", result.invaliduser);
+        }
+        Ok(result)
+    }
+
+    /// 撤回24小时内发送的消息
+    pub async fn recall_app_message(&self, msgid: &str) -> ClientResult<()> {
+        let endpoint =
+            Endpoint::new("https://qyapi.weixin.qq.com/cgi-bin/message/recall").access_token();
+
+        #[derive(Serialize)]
+        struct RecallRequest<'a> {
+            msgid: &'a str,
+        }
+        #[derive(Deserialize)]
+        struct RecallResponse {}
+
+        let payload = RecallRequest { msgid };
+        self.0
+            .client
+            .post::<_, RecallResponse>(endpoint, Some(&payload))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock_wecom;
+    use super::{AppMessage, Article, MessageBody};
+    use crate::core::testing::TestResult;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn serialize_message() -> TestResult<()> {
+        let message = AppMessage::new(MessageBody::news(vec![Article {
+            title: "中秋节礼品领取".to_owned(),
+            url: "https://h5.example.com/gift".to_owned(),
+            ..Default::default()
+        }]))
+        .to_parties(&[1, 2])
+        .duplicate_check(600);
+        assert_eq!(
+            serde_json::to_value(&message)?,
+            json!({
+                "toparty": "1|2",
+                "msgtype": "news",
+                "news": {"articles": [{"title": "中秋节礼品领取", "url": "https://h5.example.com/gift"}]},
+                "safe": 0,
+                "enable_duplicate_check": 1,
+                "duplicate_check_interval": 600
+            })
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn send_app_message_offline() -> TestResult<()> {
+        let (app, mock) = mock_wecom();
+        mock.reply_json(
+            "/cgi-bin/message/send",
+            json!({
+                "errcode": 0,
+                "errmsg": "ok",
+                "invaliduser": "lisi",
+                "msgid": "MSGID"
+            }),
+        )
+        .reply_json(
+            "/cgi-bin/message/recall",
+            json!({"errcode": 0, "errmsg": "ok"}),
+        );
+
+        let message =
+            AppMessage::new(MessageBody::text("你的快递已到")).to_users(&["zhangsan", "lisi"]);
+        let result = app.send_app_message(&message).await?;
+        assert_eq!(result.invalid_users(), vec!["lisi"]);
+        app.recall_app_message(&result.msgid).await?;
+
+        let body: Value =
+            serde_json::from_slice(mock.requests()[1].body.as_deref().unwrap_or_default())?;
+        assert_eq!(
+            body,
+            json!({
+                "agentid": 1000002,
+                "touser": "zhangsan|lisi",
+                "msgtype": "text",
+                "text": {"content": "你的快递已到"},
+                "safe": 0,
+                "enable_duplicate_check": 0
+            })
+        );
+        Ok(())
+    }
+}
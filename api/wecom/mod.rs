@@ -0,0 +1,179 @@
+//! 企业微信（自建应用）
+//!
+//! * 网页授权登录（code换取成员userid），见`oauth`
+//! * 通讯录读取（成员、部门），见`contact`
+//! * 应用消息，见`message`
+//!
+//! 与钉钉一样，access_token按企业+应用的secret获取，每个自建应用各自一个
+use super::client::{
+    redact, CircuitBreaker, CircuitStatus, Client, ClientResult, Config as ClientConfig, Endpoint,
+    Platform,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+mod contact;
+pub use contact::{Department, SimpleUser, UserInfo};
+mod message;
+pub use message::{AppMessage, Article, Content, MessageBody, News, SendResult, TextCard};
+mod oauth;
+pub use oauth::{CodeUser, OauthScope, UserDetail};
+
+// wecom 配置
+#[derive(Serialize, Deserialize, Default)]
+pub struct Config {
+    pub corp_id: String,
+    /// 自建应用的AgentId
+    pub agent_id: i64,
+    /// 自建应用的Secret
+    pub corp_secret: String,
+    /// 超时、代理、重试等（token_url由此处自动设置）
+    #[serde(default)]
+    pub client: ClientConfig,
+}
+impl Config {
+    pub fn from_env() -> Config {
+        use dotenv::dotenv;
+        use std::env;
+
+        dotenv().ok();
+        let corp_id =
+            env::var("WECOM_CORP_ID").expect("value `WECOM_CORP_ID` not presented in .env file");
+        let agent_id = env::var("WECOM_AGENT_ID")
+            .expect("value `WECOM_AGENT_ID` not presented in .env file")
+            .parse()
+            .expect("value `WECOM_AGENT_ID` in .env file is not a valid integer");
+        let corp_secret = env::var("WECOM_CORP_SECRET")
+            .expect("value `WECOM_CORP_SECRET` not presented in .env file");
+
+        Config {
+            corp_id,
+            agent_id,
+            corp_secret,
+            ..Default::default()
+        }
+    }
+}
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("corp_id", &self.corp_id)
+            .field("agent_id", &self.agent_id)
+            .field("corp_secret", &redact::secret(&self.corp_secret))
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+// wecom 结构
+#[derive(Clone)]
+pub struct Wecom(Arc<WecomInner>);
+
+struct WecomInner {
+    cfg: Config,
+    client: Client,
+}
+impl Wecom {
+    pub fn new(cfg: Config) -> ClientResult<Self> {
+        let client = Client::new(Self::client_config(&cfg))?;
+        Ok(Self::with_client(cfg, client))
+    }
+
+    /// 使用自行构建的Client，可以同时设置token_store、metrics、breaker，测试时注入`MockTransport`
+    pub fn with_client(cfg: Config, client: Client) -> Self {
+        Self(Arc::new(WecomInner { cfg, client }))
+    }
+
+    /// Client的配置，token_url等由`cfg`生成
+    pub fn client_config(cfg: &Config) -> ClientConfig {
+        let token_url = Endpoint::new("https://qyapi.weixin.qq.com/cgi-bin/gettoken")
+            .query("corpid", &cfg.corp_id)
+            .query("corpsecret", &cfg.corp_secret);
+        ClientConfig {
+            token_url: token_url.to_string(),
+            platform: Platform::Wecom,
+            // 同一企业的不同应用，access_token互不通用
+            token_key: format!("wecom:{}:{}", cfg.corp_id, cfg.agent_id),
+            ..cfg.client.clone()
+        }
+    }
+
+    pub fn corp_id(&self) -> &str {
+        &self.0.cfg.corp_id
+    }
+
+    pub fn agent_id(&self) -> i64 {
+        self.0.cfg.agent_id
+    }
+
+    /// 各接口的熔断状态，见`client::breaker`
+    pub fn circuit_states(&self) -> Vec<CircuitStatus> {
+        self.0.client.circuit_states()
+    }
+
+    /// 熔断器，同一平台的其它Client可以共用，见`with_client`
+    pub fn circuit_breaker(&self) -> Arc<CircuitBreaker> {
+        self.0.client.circuit_breaker()
+    }
+
+    pub async fn access_token(&self) -> ClientResult<String> {
+        self.0.client.access_token().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::client::{transport::MockTransport, Client};
+    use super::{Config, Wecom};
+    use crate::core::testing::TestResult;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    pub(super) fn mock_wecom() -> (Wecom, Arc<MockTransport>) {
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json(
+            "/cgi-bin/gettoken",
+            json!({"errcode": 0, "errmsg": "ok", "access_token": "TOKEN", "expires_in": 7200}),
+        );
+        let cfg = Config {
+            corp_id: "CORPID".to_owned(),
+            agent_id: 1000002,
+            corp_secret: "SECRET".to_owned(),
+            ..Default::default()
+        };
+        let client = Client::with_transport(Wecom::client_config(&cfg), mock.clone());
+        (Wecom::with_client(cfg, client), mock)
+    }
+
+    #[test]
+    #[ignore = "需要提供WECOM_CORP_ID、WECOM_AGENT_ID与WECOM_CORP_SECRET"]
+    fn read_config_from_env() {
+        setup();
+
+        let cfg = Config::from_env();
+        info!("wecom cfg: {:#?}", cfg);
+    }
+
+    #[async_std::test]
+    async fn access_token_offline() -> TestResult<()> {
+        setup();
+
+        let (app, mock) = mock_wecom();
+        assert_eq!(app.access_token().await?, "TOKEN");
+        // 已缓存
+        assert_eq!(app.access_token().await?, "TOKEN");
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].url,
+            "https://qyapi.weixin.qq.com/cgi-bin/gettoken?corpid=CORPID&corpsecret=SECRET"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! 网页授权登录（在企业微信内打开应用页面）
+//!
+//! 1. 跳转到`authorize_url`，带着code回到redirect_uri
+//! 2. `user_by_code(code)`换取成员的userid（非企业成员只有openid）
+//! 3. `snsapi_privateinfo`授权时，可以再用user_ticket获取手机号等敏感信息
+use super::super::client::{redact, ClientResult, Endpoint};
+use super::Wecom;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OauthScope {
+    /// 静默授权，只能获取userid
+    Base,
+    /// 手动授权，可以获取手机号、邮箱等（需在管理后台开启）
+    PrivateInfo,
+}
+impl OauthScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OauthScope::Base => "snsapi_base",
+            OauthScope::PrivateInfo => "snsapi_privateinfo",
+        }
+    }
+}
+
+/// code对应的访问者，企业成员有userid，否则只有openid
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct CodeUser {
+    #[serde(alias = "UserId")]
+    pub userid: Option<String>,
+    /// 仅`snsapi_privateinfo`时返回，有效期1800秒
+    pub user_ticket: Option<String>,
+    #[serde(alias = "OpenId")]
+    pub openid: Option<String>,
+    pub external_userid: Option<String>,
+}
+impl CodeUser {
+    pub fn is_member(&self) -> bool {
+        self.userid.is_some()
+    }
+}
+impl fmt::Debug for CodeUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CodeUser")
+            .field("userid", &self.userid)
+            .field(
+                "user_ticket",
+                &self.user_ticket.as_deref().map(redact::secret),
+            )
+            .field("openid", &self.openid)
+            .field("external_userid", &self.external_userid)
+            .finish()
+    }
+}
+
+/// 成员的敏感信息
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct UserDetail {
+    pub userid: String,
+    /// "0"未定义 "1"男性 "2"女性
+    #[serde(default)]
+    pub gender: String,
+    #[serde(default)]
+    pub avatar: String,
+    #[serde(default)]
+    pub mobile: String,
+    #[serde(default)]
+    pub email: String,
+    #[serde(default)]
+    pub biz_mail: String,
+}
+// 手机号脱敏
+impl fmt::Debug for UserDetail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserDetail")
+            .field("userid", &self.userid)
+            .field("gender", &self.gender)
+            .field("avatar", &self.avatar)
+            .field("mobile", &redact::phone(&self.mobile))
+            .field("email", &self.email)
+            .field("biz_mail", &self.biz_mail)
+            .finish()
+    }
+}
+
+impl Wecom {
+    /// 授权页地址，`state`会原样带回redirect_uri（a-zA-Z0-9，最多128字节），用于防CSRF
+    pub fn authorize_url(&self, redirect_uri: &str, scope: OauthScope, state: &str) -> String {
+        let endpoint = Endpoint::new("https://open.weixin.qq.com/connect/oauth2/authorize")
+            .query("appid", &self.0.cfg.corp_id)
+            .query("redirect_uri", redirect_uri)
+            .query("response_type", "code")
+            .query("scope", scope.as_str())
+            .query("state", state)
+            .query("agentid", self.0.cfg.agent_id);
+        format!("{}#wechat_redirect", endpoint)
+    }
+
+    /// 用code换取访问者身份，code只能使用一次，5分钟内有效
+    pub async fn user_by_code(&self, code: &str) -> ClientResult<CodeUser> {
+        let endpoint = Endpoint::new("https://qyapi.weixin.qq.com/cgi-bin/auth/getuserinfo")
+            .access_token()
+            .query("code", code);
+        self.0.client.get(endpoint).await
+    }
+
+    /// 需要`snsapi_privateinfo`授权，且成员在应用的可见范围内
+    pub async fn user_detail(&self, user_ticket: &str) -> ClientResult<UserDetail> {
+        let endpoint =
+            Endpoint::new("https://qyapi.weixin.qq.com/cgi-bin/auth/getuserdetail").access_token();
+
+        #[derive(Serialize)]
+        struct DetailRequest<'a> {
+            user_ticket: &'a str,
+        }
+        let payload = DetailRequest { user_ticket };
+        self.0.client.post(endpoint, Some(&payload)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock_wecom;
+    use super::OauthScope;
+    use crate::core::testing::TestResult;
+    use serde_json::json;
+
+    #[test]
+    fn authorize_url() {
+        let (app, _) = mock_wecom();
+        assert_eq!(
+            app.authorize_url(
+                "https://h5.example.com/login",
+                OauthScope::PrivateInfo,
+                "STATE"
+            ),
+            "https://open.weixin.qq.com/connect/oauth2/authorize?appid=CORPID\
+             &redirect_uri=https%3A%2F%2Fh5.example.com%2Flogin&response_type=code\
+             &scope=snsapi_privateinfo&state=STATE&agentid=1000002#wechat_redirect"
+        );
+    }
+
+    #[async_std::test]
+    async fn user_by_code_offline() -> TestResult<()> {
+        let (app, mock) = mock_wecom();
+        mock.reply_json(
+            "/cgi-bin/auth/getuserinfo",
+            json!({"errcode": 0, "errmsg": "ok", "userid": "zhangsan", "user_ticket": "TICKET"}),
+        )
+        .reply_json(
+            "/cgi-bin/auth/getuserinfo",
+            json!({"errcode": 0, "errmsg": "ok", "openid": "OPENID", "external_userid": "EXTERNAL"}),
+        )
+        .reply_json(
+            "/cgi-bin/auth/getuserdetail",
+            json!({
+                "errcode": 0,
+                "errmsg": "ok",
+                "userid": "zhangsan",
+                "gender": "1",
+                "avatar": "https://wework.qpic.cn/AVATAR",
+                "mobile": "13800000000",
+                "email": "zhangsan@example.com"
+            }),
+        );
+
+        let user = app.user_by_code("CODE").await?;
+        assert!(user.is_member());
+        assert_eq!(user.userid.as_deref(), Some("zhangsan"));
+        assert!(!app.user_by_code("CODE").await?.is_member());
+
+        let detail = app.user_detail("TICKET").await?;
+        assert_eq!(detail.mobile, "13800000000");
+        assert!(!format!("{:?}", detail).contains("13800000000"));
+
+        let requests = mock.requests();
+        assert!(requests[1].url.ends_with("access_token=TOKEN&code=CODE"));
+        assert_eq!(
+            requests[3].body.as_deref(),
+            Some(&br#"{"user_ticket":"TICKET"}"#[..])
+        );
+        Ok(())
+    }
+}
@@ -1,14 +1,23 @@
 use crate::core::api::wechat_miniprogram as api_miniprogram;
+use crate::core::api::wechat_official as api_official;
+use crate::core::api::wecom as api_wecom;
 use crate::core::auth;
-use crate::core::wechat::miniprogram;
+use crate::core::auth::repository::Repositories;
+use crate::core::auth::service::Identity;
+use crate::core::http::session::Session;
+use crate::core::rate_limit::Key;
+use crate::core::wechat::{miniprogram, official, wecom};
 use crate::graphql::Context;
-use diesel::result::Error as DieselError;
 use juniper::{self, FieldResult};
+use rand::{rngs::OsRng, RngCore};
 use serde::{Deserialize, Serialize};
 
 const SESSION_KEY_OPENID: &str = "mp_openid";
 const SESSION_KEY_UNIONID: &str = "mp_unionid";
 const SESSION_KEY_SESSIONKEY: &str = "mp_session_key";
+const SESSION_KEY_OFFICIAL_STATE: &str = "official_oauth_state";
+const SESSION_KEY_WECOM_STATE: &str = "wecom_oauth_state";
+const UNKNOWN_CLIENT_IP: &str = "unknown";
 
 pub struct AuthResolver;
 #[juniper::graphql_object(Context = Context)]
@@ -24,8 +33,87 @@ impl AuthResolver {
 
     /// login 登录
     pub(crate) async fn login(js_code: String, context: &Context) -> FieldResult<LoginResult> {
+        // 先限流，再调用微信接口（消耗配额）
+        check_login_rate(&context.identity).await?;
         let miniprogram_session = context.miniprogram.code_to_session(&js_code).await?;
-        login_by_wechat_miniprogram_openid(miniprogram_session, context).await
+        login_by_wechat_miniprogram_openid(miniprogram_session, &context.identity, &context.session)
+            .await
+    }
+
+    /// officialAuthorizeUrl 公众号网页授权的跳转地址
+    ///
+    /// `userInfo`为true时是`snsapi_userinfo`授权（首次登陆按union_id关联需要），否则为静默授权；
+    /// 地址中的state同时记在session里，`officialLogin`时核对
+    pub(crate) async fn official_authorize_url(
+        redirect_uri: String,
+        user_info: bool,
+        context: &Context,
+    ) -> FieldResult<String> {
+        let scope = if user_info {
+            api_official::OauthScope::UserInfo
+        } else {
+            api_official::OauthScope::Base
+        };
+        let state = new_oauth_state(&context.session, SESSION_KEY_OFFICIAL_STATE).await?;
+        Ok(context.official.authorize_url(&redirect_uri, scope, &state))
+    }
+
+    /// officialLogin 公众号网页授权登录（H5）
+    ///
+    /// `code`、`state`为授权后回调页带回的参数，`state`须与`officialAuthorizeUrl`生成的一致；
+    /// 未绑定的用户按union_id关联到小程序里的同一个用户（需要`snsapi_userinfo`授权）
+    pub(crate) async fn official_login(
+        code: String,
+        state: String,
+        context: &Context,
+    ) -> FieldResult<LoginResult> {
+        check_oauth_state(&context.session, SESSION_KEY_OFFICIAL_STATE, &state).await?;
+        check_login_rate(&context.identity).await?;
+        let token = context.official.oauth_access_token(&code).await?;
+        let profile = if token.has_scope(api_official::OauthScope::UserInfo) {
+            Some(context.official.oauth_user_info(&token).await?)
+        } else {
+            None
+        };
+        login_by_wechat_official_openid(token, profile, &context.identity).await
+    }
+
+    /// wecomAuthorizeUrl 企业微信网页授权的跳转地址
+    ///
+    /// `privateInfo`为true时是`snsapi_privateinfo`授权（首次登陆按手机号关联需要），否则为静默授权；
+    /// 地址中的state同时记在session里，`wecomLogin`时核对
+    pub(crate) async fn wecom_authorize_url(
+        redirect_uri: String,
+        private_info: bool,
+        context: &Context,
+    ) -> FieldResult<String> {
+        let scope = if private_info {
+            api_wecom::OauthScope::PrivateInfo
+        } else {
+            api_wecom::OauthScope::Base
+        };
+        let state = new_oauth_state(&context.session, SESSION_KEY_WECOM_STATE).await?;
+        Ok(context.wecom.authorize_url(&redirect_uri, scope, &state))
+    }
+
+    /// wecomLogin 企业微信自建应用的网页授权登录
+    ///
+    /// `code`、`state`为授权后回调页带回的参数，`state`须与`wecomAuthorizeUrl`生成的一致；
+    /// 未绑定的成员按手机号关联到已登记的用户（需要`snsapi_privateinfo`授权）
+    pub(crate) async fn wecom_login(
+        code: String,
+        state: String,
+        context: &Context,
+    ) -> FieldResult<LoginResult> {
+        check_oauth_state(&context.session, SESSION_KEY_WECOM_STATE, &state).await?;
+        check_login_rate(&context.identity).await?;
+        let code_user = context.wecom.user_by_code(&code).await?;
+        let userid = code_user.userid.ok_or("非企业成员，无法登录")?;
+        let detail = match &code_user.user_ticket {
+            Some(ticket) => Some(context.wecom.user_detail(ticket).await?),
+            None => None,
+        };
+        login_by_wecom_userid(context.wecom.corp_id(), userid, detail, &context.identity).await
     }
 
     /// register 注册
@@ -36,6 +124,14 @@ impl AuthResolver {
         args: RegisterInput,
         context: &Context,
     ) -> FieldResult<LoginResult> {
+        let open_id = context
+            .session
+            .get::<String>(SESSION_KEY_OPENID)
+            .await?
+            .ok_or("open_id不存在session里")?;
+        let limiter = &context.identity.rate_limits().register;
+        limiter.check(Key::OpenId(&open_id)).await?;
+
         let phone_number = {
             let session_key = context
                 .session
@@ -46,14 +142,16 @@ impl AuthResolver {
             let data = args.encrypted_data;
             api_miniprogram::Miniprogram::get_phone_number(&session_key, &iv, &data)?.phone_number
         };
-        let open_id = context
-            .session
-            .get::<String>(SESSION_KEY_OPENID)
-            .await?
-            .ok_or("open_id不存在session里")?;
         let union_id = context.session.get::<String>(SESSION_KEY_UNIONID).await?;
 
-        register_by_wechat_miniprogram_phonenumber(phone_number, open_id, union_id, context).await
+        register_by_wechat_miniprogram_phonenumber(
+            phone_number,
+            open_id,
+            union_id,
+            &context.identity,
+            &context.session,
+        )
+        .await
     }
 
     pub(crate) async fn logout(context: &Context) -> FieldResult<bool> {
@@ -134,40 +232,88 @@ impl LoginResult {
     }
 }
 
+// 登录限流，按IP
+// 取不到客户端IP（例如unix socket、未配置的代理）时共用一个限额，而不是跳过
+async fn check_login_rate(identity: &Identity) -> FieldResult<()> {
+    let ip = identity.client_ip().await;
+    let key = Key::Ip(ip.as_deref().unwrap_or(UNKNOWN_CLIENT_IP));
+    identity.rate_limits().login.check(key).await?;
+    Ok(())
+}
+
+// 网页授权的state：随机生成并记在session里，回调时核对并作废
+// 防止登录CSRF（攻击者诱导受害者用攻击者的code完成登录，登录成攻击者的账号）
+async fn new_oauth_state(session: &Session, key: &str) -> FieldResult<String> {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    let state: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    session.set(key, state.clone()).await?;
+    Ok(state)
+}
+
+async fn check_oauth_state(session: &Session, key: &str, state: &str) -> FieldResult<()> {
+    let expected = session.get::<String>(key).await?;
+    // 只能使用一次
+    session.remove(key).await;
+    match expected {
+        Some(expected) if expected == state => Ok(()),
+        _ => Err("授权已失效，请重新登录".into()),
+    }
+}
+
 // 分离代码，方便测试
+// 只依赖identity/session，测试时无需构建完整的Context
 async fn login_by_wechat_miniprogram_openid(
     mp_session: api_miniprogram::Code2SessionResponse,
-    context: &Context,
+    identity: &Identity,
+    session: &Session,
 ) -> FieldResult<LoginResult> {
     // 设置session_key，后续登陆、解码用到
-    context
-        .session
+    session
         .set(SESSION_KEY_SESSIONKEY, mp_session.session_key)
         .await?;
 
     // 数据库查询是否有记录
-    match miniprogram::repository::find(mp_session.openid.clone(), context.db_pool.get()?).await {
+    let repos = identity.repositories();
+    match repos.miniprogram_users.find(&mp_session.openid).await? {
         // 顺利登陆
-        Ok(mp_user) => {
-            let user = auth::repository::find_user(mp_user.user_id, context.db_pool.get()?).await?;
-            context.identity.login(user.clone()).await?;
+        Some(mp_user) => {
+            let user = repos
+                .users
+                .find_user(mp_user.user_id)
+                .await?
+                .ok_or("小程序绑定的用户不存在")?;
+            identity.login(user.clone()).await?;
             Ok(LoginResult::success(user.into()))
         }
 
         // 此情况表示小程序首次登陆
-        // 记住openid/unionid，需前端补充提供手机号
+        // 如果已在H5登录过（同一个unionid），直接绑定并登陆
+        // 否则记住openid/unionid，需前端补充提供手机号
         // 下一步：如果手机号登陆成功，则绑定该openid至手机号，并从session清除该openid
-        Err(DieselError::NotFound) => {
-            context
-                .session
-                .set(SESSION_KEY_OPENID, mp_session.openid)
-                .await?;
+        None => {
+            let official_user = match &mp_session.unionid {
+                Some(union_id) => repos.official_users.find_by_union_id(union_id).await?,
+                None => None,
+            };
+            if let Some(official_user) = official_user {
+                let user = repos
+                    .users
+                    .find_user(official_user.user_id)
+                    .await?
+                    .ok_or("公众号绑定的用户不存在")?;
+                bind_miniprogram_user(repos, mp_session.openid, mp_session.unionid, user.id)
+                    .await?;
+                identity.login(user.clone()).await?;
+                return Ok(LoginResult::success(user.into()));
+            }
+
+            session.set(SESSION_KEY_OPENID, mp_session.openid).await?;
             if let Some(unionid) = mp_session.unionid {
-                context.session.set(SESSION_KEY_UNIONID, unionid).await?;
+                session.set(SESSION_KEY_UNIONID, unionid).await?;
             }
             Ok(LoginResult::failure())
         }
-        Err(e) => Err(e.into()),
     }
 }
 
@@ -175,54 +321,190 @@ async fn register_by_wechat_miniprogram_phonenumber(
     phone_number: String,
     open_id: String,
     union_id: Option<String>,
-    context: &Context,
+    identity: &Identity,
+    session: &Session,
 ) -> FieldResult<LoginResult> {
     // 根据电话查找exist_user
-    // todo: fallback - 根据union_id查找exist_user，暂时不做
-    match auth::repository::find_user_by_username(phone_number, context.db_pool.get()?).await {
-        Ok(exist_user) => {
+    // 按union_id查找已在`login`时处理（见`login_by_wechat_miniprogram_openid`）
+    let repos = identity.repositories();
+    match repos.users.find_user_by_username(&phone_number).await? {
+        Some(exist_user) => {
             // 关联exist_user与miniprogram_user
-            let mp_user =
-                miniprogram::repository::create(open_id, exist_user.id, context.db_pool.get()?)
-                    .await?;
-            if union_id.is_some() {
-                let update = miniprogram::models::MiniprogramUser {
-                    union_id,
-                    ..mp_user
-                };
-                miniprogram::repository::update(update, context.db_pool.get()?).await?;
-            }
+            bind_miniprogram_user(repos, open_id, union_id, exist_user.id).await?;
 
             // 清理session的openid/unionid
-            context.session.remove(SESSION_KEY_OPENID).await;
-            context.session.remove(SESSION_KEY_UNIONID).await;
+            session.remove(SESSION_KEY_OPENID).await;
+            session.remove(SESSION_KEY_UNIONID).await;
 
             // 设置identity为登陆态
-            context.identity.login(exist_user.clone()).await?;
+            identity.login(exist_user.clone()).await?;
 
             Ok(LoginResult::success(exist_user.into()))
         }
-        Err(DieselError::NotFound) => {
+        None => {
             // update: 管理员登记号码后，仍然可以再次注册，所以session不要清空
-            // context.session.purge().await;
+            // session.purge().await;
             Ok(LoginResult::failure())
         }
-        Err(e) => Err(e.into()),
     }
 }
 
+// 公众号网页授权后登陆
+// 1. openid已绑定，直接登陆
+// 2. 首次在H5登陆，按unionid找到小程序绑定的用户，绑定openid后登陆
+// 3. 都没有找到，需先在小程序登陆（注册）
+async fn login_by_wechat_official_openid(
+    token: api_official::OauthToken,
+    profile: Option<api_official::OauthUserInfo>,
+    identity: &Identity,
+) -> FieldResult<LoginResult> {
+    let repos = identity.repositories();
+    if let Some(official_user) = repos.official_users.find(&token.openid).await? {
+        let user = repos
+            .users
+            .find_user(official_user.user_id)
+            .await?
+            .ok_or("公众号绑定的用户不存在")?;
+        identity.login(user.clone()).await?;
+        return Ok(LoginResult::success(user.into()));
+    }
+
+    let union_id = token
+        .unionid
+        .or_else(|| profile.as_ref().and_then(|p| p.unionid.clone()));
+    let mp_user = match &union_id {
+        Some(union_id) => repos.miniprogram_users.find_by_union_id(union_id).await?,
+        None => None,
+    };
+    let user = match mp_user {
+        Some(mp_user) => repos
+            .users
+            .find_user(mp_user.user_id)
+            .await?
+            .ok_or("小程序绑定的用户不存在")?,
+        None => return Ok(LoginResult::failure()),
+    };
+
+    // create与update在同一个事务里，避免只写入一半（缺少union_id）的绑定
+    let tx = repos.begin().await?;
+    let official_user = tx.official_users().create(token.openid, user.id).await?;
+    let update = match profile {
+        Some(p) => official::models::OfficialUser {
+            union_id,
+            nick_name: Some(p.nickname),
+            gender: Some(p.sex),
+            city: Some(p.city),
+            province: Some(p.province),
+            country: Some(p.country),
+            avatar_url: Some(p.headimgurl),
+            ..official_user
+        },
+        None => official::models::OfficialUser {
+            union_id,
+            ..official_user
+        },
+    };
+    tx.official_users().update(update).await?;
+    tx.commit().await?;
+
+    identity.login(user.clone()).await?;
+    Ok(LoginResult::success(user.into()))
+}
+
+// 企业微信网页授权后登陆
+// 1. userid已绑定，直接登陆
+// 2. 首次登陆，按手机号找到已登记的用户，绑定userid后登陆
+// 3. 都没有找到（或没有手机号），需管理员先登记号码
+async fn login_by_wecom_userid(
+    corp_id: &str,
+    userid: String,
+    detail: Option<api_wecom::UserDetail>,
+    identity: &Identity,
+) -> FieldResult<LoginResult> {
+    let repos = identity.repositories();
+    if let Some(wecom_user) = repos.wecom_users.find(corp_id, &userid).await? {
+        let user = repos
+            .users
+            .find_user(wecom_user.user_id)
+            .await?
+            .ok_or("企业微信绑定的用户不存在")?;
+        identity.login(user.clone()).await?;
+        return Ok(LoginResult::success(user.into()));
+    }
+
+    let detail = match detail {
+        Some(detail) if !detail.mobile.is_empty() => detail,
+        _ => return Ok(LoginResult::failure()),
+    };
+    let user = match repos.users.find_user_by_username(&detail.mobile).await? {
+        Some(user) => user,
+        None => return Ok(LoginResult::failure()),
+    };
+
+    // create与update在同一个事务里，避免只写入一半（缺少手机号）的绑定
+    let tx = repos.begin().await?;
+    let wecom_user = tx
+        .wecom_users()
+        .create(corp_id.to_owned(), userid, user.id)
+        .await?;
+    let update = wecom::models::WecomUser {
+        mobile: Some(detail.mobile),
+        avatar: Some(detail.avatar),
+        ..wecom_user
+    };
+    tx.wecom_users().update(update).await?;
+    tx.commit().await?;
+
+    identity.login(user.clone()).await?;
+    Ok(LoginResult::success(user.into()))
+}
+
+// 关联user与miniprogram_user
+// create与update在同一个事务里，避免只写入一半（缺少union_id）的绑定
+async fn bind_miniprogram_user(
+    repos: &Repositories,
+    open_id: String,
+    union_id: Option<String>,
+    user_id: i32,
+) -> FieldResult<()> {
+    let tx = repos.begin().await?;
+    let mp_user = tx.miniprogram_users().create(open_id, user_id).await?;
+    if union_id.is_some() {
+        let update = miniprogram::models::MiniprogramUser {
+            union_id,
+            ..mp_user
+        };
+        tx.miniprogram_users().update(update).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{SESSION_KEY_OPENID, SESSION_KEY_SESSIONKEY};
+    use super::{
+        SESSION_KEY_OFFICIAL_STATE, SESSION_KEY_OPENID, SESSION_KEY_SESSIONKEY,
+        SESSION_KEY_WECOM_STATE,
+    };
     use crate::core::api::wechat_miniprogram::Code2SessionResponse;
+    use crate::core::api::wechat_official::{OauthToken, OauthUserInfo};
+    use crate::core::api::wecom::UserDetail;
+    use crate::core::auth::service::RateLimits;
     use crate::core::auth::tests;
     use crate::core::auth::tests::TestResult;
-    use crate::db_connection::tests as db_tests;
+    use crate::core::http::session::Session;
+    use crate::core::rate_limit::{Policy, RateLimiter};
+    use crate::core::wechat::miniprogram::models::MiniprogramUser;
+    use crate::core::wechat::official::models::OfficialUser;
 
     const MOCK_USERNAME: &str = "auth_mock_user_username";
     const MOCK_PHONE_NUMBER: &str = "18899990000";
     const MOCK_MP_OPENID: &str = "auth_mock_miniprogram_user_openid";
-    const MOCK_MP_OPENID_2: &str = "auth_mock_miniprogram_user_openid_2"; // 多线程测试中不可共用，所以需要区分不同的名字
+    const MOCK_MP_OPENID_2: &str = "auth_mock_miniprogram_user_openid_2";
+    const MOCK_OFFICIAL_OPENID: &str = "auth_mock_official_user_openid";
+    const MOCK_UNIONID: &str = "auth_mock_unionid";
+    const MOCK_CORPID: &str = "auth_mock_wecom_corpid";
+    const MOCK_WECOM_USERID: &str = "auth_mock_wecom_userid";
 
     fn setup() {
         // 为了在testing下看到logging
@@ -233,20 +515,15 @@ mod tests {
     async fn login_by_wechat_miniprogram_openid() -> TestResult<()> {
         setup();
 
-        let db_pool = db_tests::db_pool();
-        let sqlx_pool = db_tests::sqlx_pool().await;
-
-        // clear up for testing
-        tests::clear_mock_miniprogram_user(MOCK_MP_OPENID, db_pool.clone()).await?;
-        tests::clear_mock_user(MOCK_USERNAME, db_pool.clone()).await?;
+        let auth = tests::auth_service();
+        let identity = auth.get_identity("an invalid token").await?;
+        let repos = identity.repositories().clone();
+        let session = Session::default();
 
         // mock user
-        let user = tests::mock_user(MOCK_USERNAME, db_pool.clone()).await?;
+        let user = tests::mock_user(MOCK_USERNAME, &repos).await?;
         // mock miniprogram_user
-        let mp_user =
-            tests::mock_miniprogram_user(MOCK_MP_OPENID, user.id, db_pool.clone()).await?;
-        // mock context
-        let ctx = tests::mock_context(db_pool.clone(), sqlx_pool.clone()).await?;
+        let mp_user = tests::mock_miniprogram_user(MOCK_MP_OPENID, user.id, &repos).await?;
 
         // success
         let mock_session_key = "mock session_key";
@@ -255,44 +532,41 @@ mod tests {
             unionid: mp_user.union_id,
             session_key: mock_session_key.to_owned(),
         };
-        let result = super::login_by_wechat_miniprogram_openid(mp_session, &ctx)
+        let result = super::login_by_wechat_miniprogram_openid(mp_session, &identity, &session)
             .await
             .map_err(|e| format!("{:?}", e))?;
         assert_eq!(result.success, true);
-        assert_eq!(ctx.identity.is_login().await, true);
+        assert_eq!(identity.is_login().await, true);
         // update: 这里不应该再测试identity的内部状态，因为这个是auth.service做的事情，有service::tests负责测试，
         // 这个函数有设置session，应该测试是否正确的set session
         assert_eq!(
-            ctx.session.get::<String>(SESSION_KEY_SESSIONKEY).await?,
+            session.get::<String>(SESSION_KEY_SESSIONKEY).await?,
             Some(mock_session_key.to_owned())
         );
 
         // failure
-        let ctx = tests::mock_context(db_pool.clone(), sqlx_pool.clone()).await?;
+        let identity = auth.get_identity("an invalid token").await?;
+        let session = Session::default();
         let mock_openid = "mock openid not exist in database";
         let mp_session = Code2SessionResponse {
             openid: mock_openid.to_owned(),
             unionid: None,
             session_key: mock_session_key.to_owned(),
         };
-        let result = super::login_by_wechat_miniprogram_openid(mp_session, &ctx)
+        let result = super::login_by_wechat_miniprogram_openid(mp_session, &identity, &session)
             .await
             .map_err(|e| format!("{:?}", e))?;
         assert_eq!(result.success, false);
-        assert_eq!(ctx.identity.is_login().await, false);
+        assert_eq!(identity.is_login().await, false);
         assert_eq!(
-            ctx.session.get::<String>(SESSION_KEY_SESSIONKEY).await?,
+            session.get::<String>(SESSION_KEY_SESSIONKEY).await?,
             Some(mock_session_key.to_owned())
         );
         assert_eq!(
-            ctx.session.get::<String>(SESSION_KEY_OPENID).await?,
+            session.get::<String>(SESSION_KEY_OPENID).await?,
             Some(mock_openid.to_owned()),
         );
 
-        // clear up
-        tests::clear_mock_miniprogram_user(MOCK_MP_OPENID, db_pool.clone()).await?;
-        tests::clear_mock_user(MOCK_USERNAME, db_pool.clone()).await?;
-
         Ok(())
     }
 
@@ -300,31 +574,314 @@ mod tests {
     async fn register_by_wechat_miniprogram_phonenumber() -> TestResult<()> {
         setup();
 
-        let db_pool = db_tests::db_pool();
-        let sqlx_pool = db_tests::sqlx_pool().await;
-
-        // clear up for testing
-        tests::clear_mock_miniprogram_user(MOCK_MP_OPENID_2, db_pool.clone()).await?;
-        tests::clear_mock_user(MOCK_PHONE_NUMBER, db_pool.clone()).await?;
+        let auth = tests::auth_service();
+        let identity = auth.get_identity("an invalid token").await?;
+        let repos = identity.repositories().clone();
+        let session = Session::default();
 
         // mock user by phone number
-        tests::mock_user(MOCK_PHONE_NUMBER, db_pool.clone()).await?;
-        // mock context
-        let ctx = tests::mock_context(db_pool.clone(), sqlx_pool.clone()).await?;
+        tests::mock_user(MOCK_PHONE_NUMBER, &repos).await?;
 
         let phone_number = MOCK_PHONE_NUMBER.to_owned();
         let open_id = MOCK_MP_OPENID_2.to_owned();
-        let result =
-            super::register_by_wechat_miniprogram_phonenumber(phone_number, open_id, None, &ctx)
-                .await
-                .map_err(|e| e.message().to_owned())?;
+        let union_id = Some("mock union_id".to_owned());
+        let result = super::register_by_wechat_miniprogram_phonenumber(
+            phone_number,
+            open_id,
+            union_id.clone(),
+            &identity,
+            &session,
+        )
+        .await
+        .map_err(|e| e.message().to_owned())?;
         assert_eq!(result.success, true);
         assert!(result.user.is_some());
-        assert_eq!(ctx.identity.is_login().await, true);
+        assert_eq!(identity.is_login().await, true);
+        // 绑定了openid与unionid
+        let mp_user = repos.miniprogram_users.find(MOCK_MP_OPENID_2).await?;
+        assert_eq!(mp_user.map(|u| u.union_id), Some(union_id));
+
+        // 号码未登记
+        let identity = auth.get_identity("an invalid token").await?;
+        let result = super::register_by_wechat_miniprogram_phonenumber(
+            "not registered phone number".to_owned(),
+            "another openid".to_owned(),
+            None,
+            &identity,
+            &session,
+        )
+        .await
+        .map_err(|e| e.message().to_owned())?;
+        assert_eq!(result.success, false);
+        assert_eq!(identity.is_login().await, false);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn login_rate_without_client_ip() -> TestResult<()> {
+        setup();
+
+        let window = std::time::Duration::from_secs(60);
+        let auth = tests::auth_service().with_rate_limits(RateLimits {
+            login: RateLimiter::new("login", Policy::sliding_window(1, window)),
+            ..Default::default()
+        });
+        // 取不到IP的请求共用一个限额
+        let identity = auth.get_identity("an invalid token").await?;
+        assert_eq!(identity.client_ip().await, None);
+        assert!(super::check_login_rate(&identity).await.is_ok());
+        let identity = auth.get_identity("an invalid token").await?;
+        assert!(super::check_login_rate(&identity).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn oauth_state() -> TestResult<()> {
+        setup();
+
+        let session = Session::default();
+        let key = SESSION_KEY_OFFICIAL_STATE;
+        // 没有生成过state
+        assert!(super::check_oauth_state(&session, key, "").await.is_err());
+
+        let state = super::new_oauth_state(&session, key).await?;
+        assert_eq!(state.len(), 32);
+        assert!(super::check_oauth_state(&session, key, &state)
+            .await
+            .is_ok());
+        // 只能使用一次
+        assert!(super::check_oauth_state(&session, key, &state)
+            .await
+            .is_err());
+
+        // 不一致时也作废
+        let state = super::new_oauth_state(&session, key).await?;
+        assert!(super::check_oauth_state(&session, key, "forged")
+            .await
+            .is_err());
+        assert!(super::check_oauth_state(&session, key, &state)
+            .await
+            .is_err());
+
+        // 公众号与企业微信的state互不通用
+        let state = super::new_oauth_state(&session, key).await?;
+        assert!(
+            super::check_oauth_state(&session, SESSION_KEY_WECOM_STATE, &state)
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    fn oauth_token(openid: &str, unionid: Option<&str>) -> OauthToken {
+        OauthToken {
+            access_token: "mock oauth access_token".to_owned(),
+            expires_in: 7200,
+            refresh_token: "mock oauth refresh_token".to_owned(),
+            openid: openid.to_owned(),
+            scope: "snsapi_userinfo".to_owned(),
+            unionid: unionid.map(ToOwned::to_owned),
+            is_snapshotuser: 0,
+        }
+    }
+
+    #[async_std::test]
+    async fn login_by_wechat_official_openid() -> TestResult<()> {
+        setup();
+
+        let auth = tests::auth_service();
+        let identity = auth.get_identity("an invalid token").await?;
+        let repos = identity.repositories().clone();
+
+        // 已在小程序登陆过的用户
+        let user = tests::mock_user(MOCK_USERNAME, &repos).await?;
+        let mp_user = tests::mock_miniprogram_user(MOCK_MP_OPENID, user.id, &repos).await?;
+        repos
+            .miniprogram_users
+            .update(MiniprogramUser {
+                union_id: Some(MOCK_UNIONID.to_owned()),
+                ..mp_user
+            })
+            .await?;
+
+        // 首次在H5登陆，按unionid绑定
+        let profile = OauthUserInfo {
+            openid: MOCK_OFFICIAL_OPENID.to_owned(),
+            nickname: "mock nickname".to_owned(),
+            sex: 1,
+            province: Default::default(),
+            city: Default::default(),
+            country: Default::default(),
+            headimgurl: Default::default(),
+            unionid: Some(MOCK_UNIONID.to_owned()),
+        };
+        let result = super::login_by_wechat_official_openid(
+            oauth_token(MOCK_OFFICIAL_OPENID, Some(MOCK_UNIONID)),
+            Some(profile),
+            &identity,
+        )
+        .await
+        .map_err(|e| e.message().to_owned())?;
+        assert_eq!(result.success, true);
+        assert_eq!(identity.is_login().await, true);
+        let official_user = repos
+            .official_users
+            .find(MOCK_OFFICIAL_OPENID)
+            .await?
+            .ok_or("official user not bound")?;
+        assert_eq!(official_user.user_id, user.id);
+        assert_eq!(official_user.union_id.as_deref(), Some(MOCK_UNIONID));
+        assert_eq!(official_user.nick_name.as_deref(), Some("mock nickname"));
+
+        // 再次登陆（snsapi_base没有unionid）
+        let identity = auth.get_identity("an invalid token").await?;
+        let result = super::login_by_wechat_official_openid(
+            oauth_token(MOCK_OFFICIAL_OPENID, None),
+            None,
+            &identity,
+        )
+        .await
+        .map_err(|e| e.message().to_owned())?;
+        assert_eq!(result.success, true);
+        assert_eq!(identity.user_id().await, Some(user.id));
+
+        // 未在小程序登陆过
+        let identity = auth.get_identity("an invalid token").await?;
+        let result = super::login_by_wechat_official_openid(
+            oauth_token("another official openid", Some("another unionid")),
+            None,
+            &identity,
+        )
+        .await
+        .map_err(|e| e.message().to_owned())?;
+        assert_eq!(result.success, false);
+        assert_eq!(identity.is_login().await, false);
+        assert!(repos
+            .official_users
+            .find("another official openid")
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn login_by_wechat_miniprogram_unionid() -> TestResult<()> {
+        setup();
 
-        // clear up
-        tests::clear_mock_miniprogram_user(MOCK_MP_OPENID_2, db_pool.clone()).await?;
-        tests::clear_mock_user(MOCK_PHONE_NUMBER, db_pool.clone()).await?;
+        let auth = tests::auth_service();
+        let identity = auth.get_identity("an invalid token").await?;
+        let repos = identity.repositories().clone();
+        let session = Session::default();
+
+        // 已在H5登陆过的用户
+        let user = tests::mock_user(MOCK_USERNAME, &repos).await?;
+        let official_user = repos
+            .official_users
+            .create(MOCK_OFFICIAL_OPENID.to_owned(), user.id)
+            .await?;
+        repos
+            .official_users
+            .update(OfficialUser {
+                union_id: Some(MOCK_UNIONID.to_owned()),
+                ..official_user
+            })
+            .await?;
+
+        // 小程序首次登陆，无需手机号
+        let mp_session = Code2SessionResponse {
+            openid: MOCK_MP_OPENID.to_owned(),
+            unionid: Some(MOCK_UNIONID.to_owned()),
+            session_key: "mock session_key".to_owned(),
+        };
+        let result = super::login_by_wechat_miniprogram_openid(mp_session, &identity, &session)
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        assert_eq!(result.success, true);
+        assert_eq!(identity.user_id().await, Some(user.id));
+        let mp_user = repos.miniprogram_users.find(MOCK_MP_OPENID).await?;
+        assert_eq!(
+            mp_user.map(|u| (u.user_id, u.union_id)),
+            Some((user.id, Some(MOCK_UNIONID.to_owned())))
+        );
+        assert_eq!(session.get::<String>(SESSION_KEY_OPENID).await?, None);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn login_by_wecom_userid() -> TestResult<()> {
+        setup();
+
+        let auth = tests::auth_service();
+        let identity = auth.get_identity("an invalid token").await?;
+        let repos = identity.repositories().clone();
+
+        // 管理员已登记号码的用户
+        let user = tests::mock_user(MOCK_PHONE_NUMBER, &repos).await?;
+
+        // 没有授权手机号，无法匹配
+        let result = super::login_by_wecom_userid(
+            MOCK_CORPID,
+            MOCK_WECOM_USERID.to_owned(),
+            None,
+            &identity,
+        )
+        .await
+        .map_err(|e| e.message().to_owned())?;
+        assert_eq!(result.success, false);
+        assert_eq!(identity.is_login().await, false);
+
+        // 首次登陆，按手机号绑定
+        let detail = UserDetail {
+            userid: MOCK_WECOM_USERID.to_owned(),
+            mobile: MOCK_PHONE_NUMBER.to_owned(),
+            ..Default::default()
+        };
+        let result = super::login_by_wecom_userid(
+            MOCK_CORPID,
+            MOCK_WECOM_USERID.to_owned(),
+            Some(detail),
+            &identity,
+        )
+        .await
+        .map_err(|e| e.message().to_owned())?;
+        assert_eq!(result.success, true);
+        assert_eq!(identity.user_id().await, Some(user.id));
+        let wecom_user = repos
+            .wecom_users
+            .find(MOCK_CORPID, MOCK_WECOM_USERID)
+            .await?
+            .ok_or("wecom user not bound")?;
+        assert_eq!(wecom_user.user_id, user.id);
+        assert_eq!(wecom_user.mobile.as_deref(), Some(MOCK_PHONE_NUMBER));
+
+        // 再次登陆（snsapi_base没有手机号）
+        let identity = auth.get_identity("an invalid token").await?;
+        let result = super::login_by_wecom_userid(
+            MOCK_CORPID,
+            MOCK_WECOM_USERID.to_owned(),
+            None,
+            &identity,
+        )
+        .await
+        .map_err(|e| e.message().to_owned())?;
+        assert_eq!(result.success, true);
+        assert_eq!(identity.user_id().await, Some(user.id));
+
+        // 同一个userid在其它企业，不算已绑定
+        let identity = auth.get_identity("an invalid token").await?;
+        let result = super::login_by_wecom_userid(
+            "another corpid",
+            MOCK_WECOM_USERID.to_owned(),
+            None,
+            &identity,
+        )
+        .await
+        .map_err(|e| e.message().to_owned())?;
+        assert_eq!(result.success, false);
 
         Ok(())
     }
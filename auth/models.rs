@@ -1,8 +1,12 @@
-use crate::diesel_schema::{user_tokens, users};
+use crate::diesel_schema::users;
 use chrono::{DateTime, Utc};
+use sqlx::FromRow;
 
 // 用户
-#[derive(Identifiable, Queryable, Clone, PartialEq, Debug)]
+//
+// 停用的时间`users.disabled_at`不在这里（与diesel_schema保持一致），
+// 通过`UserRepository::is_user_disabled`查询，见migrations/2026-10-18-000006_users_disabled_at
+#[derive(Identifiable, Queryable, FromRow, Clone, PartialEq, Debug)]
 pub struct User {
     pub id: i32,
 
@@ -15,17 +19,20 @@ pub struct User {
 }
 
 // 用户登录时生成的token
-#[derive(Identifiable, Queryable, Associations, Clone)]
-#[belongs_to(User)]
-#[table_name = "user_tokens"]
+//
+// 续约宽限期（见`Token::verify_previous`）需要的字段，
+// 见migrations/2026-10-18-000001_user_tokens_renew_grace
+#[derive(FromRow, Clone, Debug, PartialEq)]
 pub struct UserToken {
     pub id: i32,
     pub user_id: i32,
 
     pub device: String,
     pub hash: String,
+    pub previous_hash: Option<String>, // 上一次续约前的hash
 
     pub created_at: DateTime<Utc>,
     pub issued_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>, // 上一次续约的时间
     pub deleted_at: Option<DateTime<Utc>>,
 }
@@ -1,157 +1,1066 @@
 use super::error::AuthResult;
 use super::models::{User, UserToken};
-use crate::db_connection::PgPooledConnection;
-use crate::diesel_schema::{user_tokens, users};
-use async_std::task;
+use crate::core::dingtalk::models::DingtalkUser;
+use crate::core::dingtalk::repository::{
+    queries as dingtalk_queries, DingtalkUserRepository,
+    MemoryRepository as MemoryDingtalkRepository, SqlxRepository as SqlxDingtalkRepository,
+};
+use crate::core::wechat::miniprogram::models::MiniprogramUser;
+use crate::core::wechat::miniprogram::repository::{
+    queries as miniprogram_queries, MemoryRepository as MemoryMiniprogramRepository,
+    MiniprogramUserRepository, SqlxRepository as SqlxMiniprogramRepository,
+};
+use crate::core::wechat::official::models::OfficialUser;
+use crate::core::wechat::official::repository::{
+    queries as official_queries, MemoryRepository as MemoryOfficialRepository,
+    OfficialUserRepository, SqlxRepository as SqlxOfficialRepository,
+};
+use crate::core::wechat::wecom::models::WecomUser;
+use crate::core::wechat::wecom::repository::{
+    queries as wecom_queries, MemoryRepository as MemoryWecomRepository,
+    SqlxRepository as SqlxWecomRepository, WecomUserRepository,
+};
+use async_std::sync::{Mutex, RwLock};
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use diesel::prelude::*;
+use sqlx::postgres::{PgPool, Postgres};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+/// 数据仓储集合
+///
+/// 由`AuthService`持有，并通过`Identity::repositories()`提供给resolver使用，
+/// 替换数据层（sqlx/内存/...）时无需改动`AuthService`
+#[derive(Clone)]
+pub struct Repositories {
+    pub users: Arc<dyn UserRepository>,
+    pub tokens: Arc<dyn TokenRepository>,
+    pub miniprogram_users: Arc<dyn MiniprogramUserRepository>,
+    pub official_users: Arc<dyn OfficialUserRepository>,
+    pub wecom_users: Arc<dyn WecomUserRepository>,
+    pub dingtalk_users: Arc<dyn DingtalkUserRepository>,
+    pub transactions: Arc<dyn UnitOfWork>,
+}
+impl Repositories {
+    /// 基于sqlx的实现，全程异步，不再占用blocking线程
+    pub fn sqlx(pool: PgPool) -> Self {
+        let repository = Arc::new(SqlxRepository::new(pool.clone()));
+        Self {
+            users: repository.clone(),
+            tokens: repository.clone(),
+            miniprogram_users: Arc::new(SqlxMiniprogramRepository::new(pool.clone())),
+            official_users: Arc::new(SqlxOfficialRepository::new(pool.clone())),
+            wecom_users: Arc::new(SqlxWecomRepository::new(pool.clone())),
+            dingtalk_users: Arc::new(SqlxDingtalkRepository::new(pool)),
+            transactions: repository,
+        }
+    }
+
+    /// 内存实现，无需数据库，一般用于测试
+    pub fn memory() -> Self {
+        let repository = Arc::new(MemoryRepository::default());
+        let miniprogram_repository = Arc::new(MemoryMiniprogramRepository::default());
+        let official_repository = Arc::new(MemoryOfficialRepository::default());
+        let wecom_repository = Arc::new(MemoryWecomRepository::default());
+        let dingtalk_repository = Arc::new(MemoryDingtalkRepository::default());
+        Self {
+            users: repository.clone(),
+            tokens: repository.clone(),
+            miniprogram_users: miniprogram_repository.clone(),
+            official_users: official_repository.clone(),
+            wecom_users: wecom_repository.clone(),
+            dingtalk_users: dingtalk_repository.clone(),
+            transactions: Arc::new(MemoryUnitOfWork {
+                repository,
+                miniprogram_repository,
+                official_repository,
+                wecom_repository,
+                dingtalk_repository,
+            }),
+        }
+    }
+
+    /// 开启事务
+    /// ```rs
+    /// let tx = repos.begin().await?;
+    /// let mp_user = tx.miniprogram_users().create(open_id, user_id).await?;
+    /// tx.miniprogram_users().update(mp_user).await?;
+    /// tx.commit().await?; // 未commit就drop，则回滚
+    /// ```
+    pub async fn begin(&self) -> AuthResult<Box<dyn Transaction>> {
+        self.transactions.begin().await
+    }
+}
 
 // user...
-pub async fn find_user(id: i32, conn: PgPooledConnection) -> QueryResult<User> {
-    task::spawn_blocking(move || users::table.find(id).first(&conn)).await
-}
-pub async fn find_user_by_username(
-    username: String,
-    conn: PgPooledConnection,
-) -> QueryResult<User> {
-    task::spawn_blocking(move || {
-        //use crate::diesel_schema::users;
-        users::table
-            .filter(users::username.eq(username))
-            .first(&conn)
-    })
-    .await
-}
-pub async fn find_user_by_token(_token: String) -> QueryResult<User> {
-    // let token = find_token(token_str);
-    // find_user(token.user_id)
-    todo!()
-}
-
-#[derive(Insertable, Default)]
-#[table_name = "users"]
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn find_user(&self, id: i32) -> AuthResult<Option<User>>;
+    async fn find_user_by_username(&self, username: &str) -> AuthResult<Option<User>>;
+    async fn create_user(&self, user: InsertUser) -> AuthResult<User>;
+    /// 更新username、name、avatar，updated_at自动设置为当前时间
+    async fn update_user(&self, user: User) -> AuthResult<User>;
+    /// 停用（`Some`）或恢复（`None`）用户，停用的用户不能登录、续约
+    async fn set_user_disabled(
+        &self,
+        id: i32,
+        disabled_at: Option<DateTime<Utc>>,
+    ) -> AuthResult<()>;
+    async fn is_user_disabled(&self, id: i32) -> AuthResult<bool>;
+}
+
+#[derive(Default)]
 pub struct InsertUser {
     pub username: String,
     pub name: String,
     pub avatar: String,
 }
-pub async fn create_user(user: InsertUser, conn: PgPooledConnection) -> AuthResult<User> {
-    task::spawn_blocking(move || {
-        use crate::diesel_schema::users::dsl::*;
-        diesel::insert_into(users)
-            .values(&user)
-            .get_result(&conn)
-            .map_err(Into::into)
-    })
-    .await
-}
-pub async fn update_user() -> AuthResult<()> {
-    todo!()
-}
-pub async fn update_user_name() -> AuthResult<()> {
-    todo!()
-}
-pub async fn list_user() -> AuthResult<Vec<User>> {
-    todo!()
-}
-pub async fn count_user() -> AuthResult<i32> {
-    todo!()
-}
 
-// 生存环境，是不允许删除用户的，
-// 所以这里限定只能在测试里面使用
-#[cfg(test)]
-use crate::db_connection::PgPool;
-#[cfg(test)]
-pub async fn delete_user_by_username(username: &str, pool: PgPool) -> AuthResult<()> {
-    let username = username.to_owned();
-    match find_user_by_username(username.to_owned(), pool.get()?).await {
-        Err(diesel::NotFound) => Ok(()),
-        Err(e) => Err(Box::new(e)),
-        Ok(user) => {
-            task::spawn_blocking(move || {
-                // 先删除tokens
-                use crate::diesel_schema::user_tokens::dsl::*;
-                diesel::delete(user_tokens.filter(user_id.eq(user.id)))
-                    .execute(&pool.get()?)
-                    .map_err(|e| format!("{}", e))?;
-
-                // 再删除users
-                //use crate::diesel_schema::users;
-                diesel::delete(users::table.filter(users::username.eq(username)))
-                    .execute(&pool.get()?)
-                    .map(|_| ())
-                    .map_err(|e| format!("{}", e).into())
-            })
-            .await
-        }
-    }
+// token...
+#[async_trait]
+pub trait TokenRepository: Send + Sync {
+    /// 只返回未被软删除的token
+    async fn find_refresh_token(&self, id: i32) -> AuthResult<Option<UserToken>>;
+    async fn create_refresh_token(&self, token: InsertToken) -> AuthResult<UserToken>;
+    async fn destroy_refresh_token(&self, id: i32) -> AuthResult<()>;
+    /// 续约：仅当当前hash仍为`current_hash`时，替换为`new_hash`，并保留旧hash用于宽限期
+    ///
+    /// 返回新的issued_at；并发续约时只有一个请求成功，其余返回None
+    async fn rotate_refresh_token(
+        &self,
+        id: i32,
+        current_hash: &str,
+        new_hash: String,
+    ) -> AuthResult<Option<DateTime<Utc>>>;
 }
 
-// token...
-pub async fn find_refresh_token(id: i32, conn: PgPooledConnection) -> QueryResult<UserToken> {
-    task::spawn_blocking(move || {
-        user_tokens::table
-            .find(id)
-            .filter(user_tokens::deleted_at.is_null()) // soft delete
-            // 另一种写法 .filter(id.eq(...).and(deleted_at.is_null()))
-            .first(&conn)
-    })
-    .await
-}
-#[derive(Insertable)]
-#[table_name = "user_tokens"]
 pub struct InsertToken {
     pub user_id: i32,
     pub device: String,
     pub hash: String,
 }
-pub async fn create_refresh_token(
-    token: InsertToken,
-    conn: PgPooledConnection,
-) -> AuthResult<UserToken> {
-    task::spawn_blocking(move || {
-        use crate::diesel_schema::user_tokens::dsl::*;
-        diesel::insert_into(user_tokens)
-            .values(&token)
-            .get_result(&conn)
+
+// 事务...
+/// 开启事务的入口
+#[async_trait]
+pub trait UnitOfWork: Send + Sync {
+    async fn begin(&self) -> AuthResult<Box<dyn Transaction>>;
+}
+
+/// 事务内的仓储共享同一个连接，
+/// 要么`commit()`全部生效，要么`rollback()`(或直接drop)全部撤销
+#[async_trait]
+pub trait Transaction: Send + Sync {
+    fn users(&self) -> &dyn UserRepository;
+    fn tokens(&self) -> &dyn TokenRepository;
+    fn miniprogram_users(&self) -> &dyn MiniprogramUserRepository;
+    fn official_users(&self) -> &dyn OfficialUserRepository;
+    fn wecom_users(&self) -> &dyn WecomUserRepository;
+    fn dingtalk_users(&self) -> &dyn DingtalkUserRepository;
+
+    async fn commit(self: Box<Self>) -> AuthResult<()>;
+    async fn rollback(self: Box<Self>) -> AuthResult<()>;
+}
+
+// sqlx 查询，连接池与事务共用
+mod queries {
+    use super::{InsertToken, InsertUser};
+    use crate::core::auth::error::AuthResult;
+    use crate::core::auth::models::{User, UserToken};
+    use chrono::{DateTime, Utc};
+    use sqlx::{postgres::Postgres, Executor};
+
+    pub async fn find_user<'e, E>(executor: E, id: i32) -> AuthResult<Option<User>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(executor)
+            .await
             .map_err(Into::into)
-    })
-    .await
-}
-pub async fn destroy_refresh_token(id: i32, conn: PgPooledConnection) -> AuthResult<()> {
-    task::spawn_blocking(move || {
-        diesel::update(
-            user_tokens::table
-                .find(id)
-                .filter(user_tokens::deleted_at.is_null()), // soft delete
+    }
+    pub async fn find_user_by_username<'e, E>(
+        executor: E,
+        username: &str,
+    ) -> AuthResult<Option<User>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_optional(executor)
+            .await
+            .map_err(Into::into)
+    }
+    pub async fn create_user<'e, E>(executor: E, user: InsertUser) -> AuthResult<User>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, User>(
+            "INSERT INTO users (username, name, avatar) VALUES ($1, $2, $3) RETURNING *",
+        )
+        .bind(user.username)
+        .bind(user.name)
+        .bind(user.avatar)
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn update_user<'e, E>(executor: E, user: User) -> AuthResult<User>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, User>(
+            "UPDATE users SET username = $2, name = $3, avatar = $4, updated_at = $5 \
+             WHERE id = $1 RETURNING *",
+        )
+        .bind(user.id)
+        .bind(user.username)
+        .bind(user.name)
+        .bind(user.avatar)
+        .bind(Utc::now())
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn set_user_disabled<'e, E>(
+        executor: E,
+        id: i32,
+        disabled_at: Option<DateTime<Utc>>,
+    ) -> AuthResult<()>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query("UPDATE users SET disabled_at = $2 WHERE id = $1")
+            .bind(id)
+            .bind(disabled_at)
+            .execute(executor)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    pub async fn is_user_disabled<'e, E>(executor: E, id: i32) -> AuthResult<bool>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, (bool,)>("SELECT disabled_at IS NOT NULL FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(executor)
+            .await
+            .map(|row| row.map_or(false, |(disabled,)| disabled))
+            .map_err(Into::into)
+    }
+
+    pub async fn find_refresh_token<'e, E>(executor: E, id: i32) -> AuthResult<Option<UserToken>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, UserToken>(
+            "SELECT * FROM user_tokens WHERE id = $1 AND deleted_at IS NULL", // soft delete
         )
-        .set(user_tokens::deleted_at.eq(Some(Utc::now())))
-        .execute(&conn)
-        .map(|_| ())
+        .bind(id)
+        .fetch_optional(executor)
+        .await
         .map_err(Into::into)
-    })
-    .await
-}
-pub async fn renew_refresh_token(
-    id: i32,
-    hash_str: String,
-    conn: PgPooledConnection,
-) -> AuthResult<DateTime<Utc>> {
-    task::spawn_blocking(move || {
+    }
+    pub async fn create_refresh_token<'e, E>(
+        executor: E,
+        token: InsertToken,
+    ) -> AuthResult<UserToken>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, UserToken>(
+            "INSERT INTO user_tokens (user_id, device, hash) VALUES ($1, $2, $3) RETURNING *",
+        )
+        .bind(token.user_id)
+        .bind(token.device)
+        .bind(token.hash)
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+    pub async fn destroy_refresh_token<'e, E>(executor: E, id: i32) -> AuthResult<()>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query("UPDATE user_tokens SET deleted_at = $2 WHERE id = $1 AND deleted_at IS NULL")
+            .bind(id)
+            .bind(Utc::now())
+            .execute(executor)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+    pub async fn rotate_refresh_token<'e, E>(
+        executor: E,
+        id: i32,
+        current_hash: &str,
+        new_hash: String,
+    ) -> AuthResult<Option<DateTime<Utc>>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
         let now: DateTime<Utc> = Utc::now();
-        diesel::update(
-            user_tokens::table
-                .find(id)
-                .filter(user_tokens::deleted_at.is_null()), // soft delete
+        sqlx::query(
+            "UPDATE user_tokens \
+             SET previous_hash = hash, hash = $3, issued_at = $4, rotated_at = $4 \
+             WHERE id = $1 AND hash = $2 AND deleted_at IS NULL",
         )
-        .set((
-            user_tokens::hash.eq(hash_str),
-            user_tokens::issued_at.eq(now),
-        ))
-        .execute(&conn)
-        .map(|_| now)
+        .bind(id)
+        .bind(current_hash)
+        .bind(new_hash)
+        .bind(now)
+        .execute(executor)
+        .await
+        .map(|result| {
+            if result.rows_affected() > 0 {
+                Some(now)
+            } else {
+                None
+            }
+        })
         .map_err(Into::into)
-    })
-    .await
+    }
+}
+
+// sqlx 实现
+pub struct SqlxRepository {
+    pool: PgPool,
+}
+impl SqlxRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl UserRepository for SqlxRepository {
+    async fn find_user(&self, id: i32) -> AuthResult<Option<User>> {
+        queries::find_user(&self.pool, id).await
+    }
+    async fn find_user_by_username(&self, username: &str) -> AuthResult<Option<User>> {
+        queries::find_user_by_username(&self.pool, username).await
+    }
+    async fn create_user(&self, user: InsertUser) -> AuthResult<User> {
+        queries::create_user(&self.pool, user).await
+    }
+    async fn update_user(&self, user: User) -> AuthResult<User> {
+        queries::update_user(&self.pool, user).await
+    }
+    async fn set_user_disabled(
+        &self,
+        id: i32,
+        disabled_at: Option<DateTime<Utc>>,
+    ) -> AuthResult<()> {
+        queries::set_user_disabled(&self.pool, id, disabled_at).await
+    }
+    async fn is_user_disabled(&self, id: i32) -> AuthResult<bool> {
+        queries::is_user_disabled(&self.pool, id).await
+    }
+}
+
+#[async_trait]
+impl TokenRepository for SqlxRepository {
+    async fn find_refresh_token(&self, id: i32) -> AuthResult<Option<UserToken>> {
+        queries::find_refresh_token(&self.pool, id).await
+    }
+    async fn create_refresh_token(&self, token: InsertToken) -> AuthResult<UserToken> {
+        queries::create_refresh_token(&self.pool, token).await
+    }
+    async fn destroy_refresh_token(&self, id: i32) -> AuthResult<()> {
+        queries::destroy_refresh_token(&self.pool, id).await
+    }
+    async fn rotate_refresh_token(
+        &self,
+        id: i32,
+        current_hash: &str,
+        new_hash: String,
+    ) -> AuthResult<Option<DateTime<Utc>>> {
+        queries::rotate_refresh_token(&self.pool, id, current_hash, new_hash).await
+    }
+}
+
+#[async_trait]
+impl UnitOfWork for SqlxRepository {
+    async fn begin(&self) -> AuthResult<Box<dyn Transaction>> {
+        Ok(Box::new(SqlxTransaction::begin(&self.pool).await?))
+    }
+}
+
+/// sqlx事务
+///
+/// 仓储方法只有`&self`，所以用Mutex包装，保证同一时间只有一条语句在执行
+pub struct SqlxTransaction {
+    tx: Mutex<sqlx::Transaction<'static, Postgres>>,
+}
+impl SqlxTransaction {
+    async fn begin(pool: &PgPool) -> AuthResult<Self> {
+        let tx = pool.begin().await?;
+        Ok(Self { tx: Mutex::new(tx) })
+    }
+}
+
+#[async_trait]
+impl Transaction for SqlxTransaction {
+    fn users(&self) -> &dyn UserRepository {
+        self
+    }
+    fn tokens(&self) -> &dyn TokenRepository {
+        self
+    }
+    fn miniprogram_users(&self) -> &dyn MiniprogramUserRepository {
+        self
+    }
+    fn official_users(&self) -> &dyn OfficialUserRepository {
+        self
+    }
+    fn wecom_users(&self) -> &dyn WecomUserRepository {
+        self
+    }
+    fn dingtalk_users(&self) -> &dyn DingtalkUserRepository {
+        self
+    }
+
+    async fn commit(self: Box<Self>) -> AuthResult<()> {
+        self.tx.into_inner().commit().await.map_err(Into::into)
+    }
+    async fn rollback(self: Box<Self>) -> AuthResult<()> {
+        self.tx.into_inner().rollback().await.map_err(Into::into)
+    }
+}
+
+#[async_trait]
+impl UserRepository for SqlxTransaction {
+    async fn find_user(&self, id: i32) -> AuthResult<Option<User>> {
+        let mut tx = self.tx.lock().await;
+        queries::find_user(&mut **tx, id).await
+    }
+    async fn find_user_by_username(&self, username: &str) -> AuthResult<Option<User>> {
+        let mut tx = self.tx.lock().await;
+        queries::find_user_by_username(&mut **tx, username).await
+    }
+    async fn create_user(&self, user: InsertUser) -> AuthResult<User> {
+        let mut tx = self.tx.lock().await;
+        queries::create_user(&mut **tx, user).await
+    }
+    async fn update_user(&self, user: User) -> AuthResult<User> {
+        let mut tx = self.tx.lock().await;
+        queries::update_user(&mut **tx, user).await
+    }
+    async fn set_user_disabled(
+        &self,
+        id: i32,
+        disabled_at: Option<DateTime<Utc>>,
+    ) -> AuthResult<()> {
+        let mut tx = self.tx.lock().await;
+        queries::set_user_disabled(&mut **tx, id, disabled_at).await
+    }
+    async fn is_user_disabled(&self, id: i32) -> AuthResult<bool> {
+        let mut tx = self.tx.lock().await;
+        queries::is_user_disabled(&mut **tx, id).await
+    }
+}
+
+#[async_trait]
+impl TokenRepository for SqlxTransaction {
+    async fn find_refresh_token(&self, id: i32) -> AuthResult<Option<UserToken>> {
+        let mut tx = self.tx.lock().await;
+        queries::find_refresh_token(&mut **tx, id).await
+    }
+    async fn create_refresh_token(&self, token: InsertToken) -> AuthResult<UserToken> {
+        let mut tx = self.tx.lock().await;
+        queries::create_refresh_token(&mut **tx, token).await
+    }
+    async fn destroy_refresh_token(&self, id: i32) -> AuthResult<()> {
+        let mut tx = self.tx.lock().await;
+        queries::destroy_refresh_token(&mut **tx, id).await
+    }
+    async fn rotate_refresh_token(
+        &self,
+        id: i32,
+        current_hash: &str,
+        new_hash: String,
+    ) -> AuthResult<Option<DateTime<Utc>>> {
+        let mut tx = self.tx.lock().await;
+        queries::rotate_refresh_token(&mut **tx, id, current_hash, new_hash).await
+    }
+}
+
+#[async_trait]
+impl MiniprogramUserRepository for SqlxTransaction {
+    async fn find(&self, open_id: &str) -> AuthResult<Option<MiniprogramUser>> {
+        let mut tx = self.tx.lock().await;
+        miniprogram_queries::find(&mut **tx, open_id).await
+    }
+    async fn find_by_user_id(&self, user_id: i32) -> AuthResult<Option<MiniprogramUser>> {
+        let mut tx = self.tx.lock().await;
+        miniprogram_queries::find_by_user_id(&mut **tx, user_id).await
+    }
+    async fn find_by_union_id(&self, union_id: &str) -> AuthResult<Option<MiniprogramUser>> {
+        let mut tx = self.tx.lock().await;
+        miniprogram_queries::find_by_union_id(&mut **tx, union_id).await
+    }
+    async fn create(&self, open_id: String, user_id: i32) -> AuthResult<MiniprogramUser> {
+        let mut tx = self.tx.lock().await;
+        miniprogram_queries::create(&mut **tx, open_id, user_id).await
+    }
+    async fn update(&self, u: MiniprogramUser) -> AuthResult<MiniprogramUser> {
+        let mut tx = self.tx.lock().await;
+        miniprogram_queries::update(&mut **tx, u).await
+    }
+}
+
+#[async_trait]
+impl OfficialUserRepository for SqlxTransaction {
+    async fn find(&self, open_id: &str) -> AuthResult<Option<OfficialUser>> {
+        let mut tx = self.tx.lock().await;
+        official_queries::find(&mut **tx, open_id).await
+    }
+    async fn find_by_user_id(&self, user_id: i32) -> AuthResult<Option<OfficialUser>> {
+        let mut tx = self.tx.lock().await;
+        official_queries::find_by_user_id(&mut **tx, user_id).await
+    }
+    async fn find_by_union_id(&self, union_id: &str) -> AuthResult<Option<OfficialUser>> {
+        let mut tx = self.tx.lock().await;
+        official_queries::find_by_union_id(&mut **tx, union_id).await
+    }
+    async fn create(&self, open_id: String, user_id: i32) -> AuthResult<OfficialUser> {
+        let mut tx = self.tx.lock().await;
+        official_queries::create(&mut **tx, open_id, user_id).await
+    }
+    async fn update(&self, u: OfficialUser) -> AuthResult<OfficialUser> {
+        let mut tx = self.tx.lock().await;
+        official_queries::update(&mut **tx, u).await
+    }
+}
+
+#[async_trait]
+impl WecomUserRepository for SqlxTransaction {
+    async fn find(&self, corp_id: &str, userid: &str) -> AuthResult<Option<WecomUser>> {
+        let mut tx = self.tx.lock().await;
+        wecom_queries::find(&mut **tx, corp_id, userid).await
+    }
+    async fn find_by_user_id(&self, corp_id: &str, user_id: i32) -> AuthResult<Option<WecomUser>> {
+        let mut tx = self.tx.lock().await;
+        wecom_queries::find_by_user_id(&mut **tx, corp_id, user_id).await
+    }
+    async fn create(&self, corp_id: String, userid: String, user_id: i32) -> AuthResult<WecomUser> {
+        let mut tx = self.tx.lock().await;
+        wecom_queries::create(&mut **tx, corp_id, userid, user_id).await
+    }
+    async fn update(&self, u: WecomUser) -> AuthResult<WecomUser> {
+        let mut tx = self.tx.lock().await;
+        wecom_queries::update(&mut **tx, u).await
+    }
+}
+
+#[async_trait]
+impl DingtalkUserRepository for SqlxTransaction {
+    async fn find(&self, union_id: &str) -> AuthResult<Option<DingtalkUser>> {
+        let mut tx = self.tx.lock().await;
+        dingtalk_queries::find(&mut **tx, union_id).await
+    }
+    async fn find_by_user_id(&self, user_id: i32) -> AuthResult<Option<DingtalkUser>> {
+        let mut tx = self.tx.lock().await;
+        dingtalk_queries::find_by_user_id(&mut **tx, user_id).await
+    }
+    async fn list_active(&self) -> AuthResult<Vec<DingtalkUser>> {
+        let mut tx = self.tx.lock().await;
+        dingtalk_queries::list_active(&mut **tx).await
+    }
+    async fn create(
+        &self,
+        union_id: String,
+        userid: String,
+        user_id: i32,
+    ) -> AuthResult<DingtalkUser> {
+        let mut tx = self.tx.lock().await;
+        dingtalk_queries::create(&mut **tx, union_id, userid, user_id).await
+    }
+    async fn update(&self, u: DingtalkUser) -> AuthResult<DingtalkUser> {
+        let mut tx = self.tx.lock().await;
+        dingtalk_queries::update(&mut **tx, u).await
+    }
+}
+
+// 内存 实现
+#[derive(Default)]
+pub struct MemoryRepository {
+    users: RwLock<Vec<User>>,
+    // users.disabled_at，不在`User`里（见models.rs）
+    disabled: RwLock<Vec<(i32, Option<DateTime<Utc>>)>>,
+    tokens: RwLock<Vec<UserToken>>,
+    // 与数据库的序列一样，事务快照与原仓储共用，回滚后id不会被重复使用
+    user_seq: Arc<AtomicI32>,
+    token_seq: Arc<AtomicI32>,
+}
+impl MemoryRepository {
+    async fn snapshot(&self) -> Self {
+        Self {
+            users: RwLock::new(self.users.read().await.clone()),
+            disabled: RwLock::new(self.disabled.read().await.clone()),
+            tokens: RwLock::new(self.tokens.read().await.clone()),
+            user_seq: self.user_seq.clone(),
+            token_seq: self.token_seq.clone(),
+        }
+    }
+    async fn merge(&self, base: Self, changed: Self) -> AuthResult<()> {
+        let mut users = self.users.write().await;
+        let mut disabled = self.disabled.write().await;
+        let mut tokens = self.tokens.write().await;
+        let merged_users = merge_rows(
+            users.as_slice(),
+            &base.users.into_inner(),
+            changed.users.into_inner(),
+            |u| u.id,
+        )?;
+        // 与数据库的unique约束保持一致
+        for (i, user) in merged_users.iter().enumerate() {
+            if merged_users[..i]
+                .iter()
+                .any(|u| u.username == user.username)
+            {
+                return Err(format!("duplicate username: {}", user.username).into());
+            }
+        }
+        let merged_disabled = merge_rows(
+            disabled.as_slice(),
+            &base.disabled.into_inner(),
+            changed.disabled.into_inner(),
+            |(id, _)| *id,
+        )?;
+        let merged_tokens = merge_rows(
+            tokens.as_slice(),
+            &base.tokens.into_inner(),
+            changed.tokens.into_inner(),
+            |t| t.id,
+        )?;
+        *users = merged_users;
+        *disabled = merged_disabled;
+        *tokens = merged_tokens;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    async fn find_user(&self, id: i32) -> AuthResult<Option<User>> {
+        let users = self.users.read().await;
+        Ok(users.iter().find(|u| u.id == id).cloned())
+    }
+    async fn find_user_by_username(&self, username: &str) -> AuthResult<Option<User>> {
+        let users = self.users.read().await;
+        Ok(users.iter().find(|u| u.username == username).cloned())
+    }
+    async fn create_user(&self, user: InsertUser) -> AuthResult<User> {
+        let mut users = self.users.write().await;
+        // 与数据库的unique约束保持一致
+        if users.iter().any(|u| u.username == user.username) {
+            return Err(format!("duplicate username: {}", user.username).into());
+        }
+        let now = Utc::now();
+        let user = User {
+            id: self.user_seq.fetch_add(1, Ordering::SeqCst) + 1,
+            username: user.username,
+            name: user.name,
+            avatar: user.avatar,
+            created_at: now,
+            updated_at: now,
+        };
+        users.push(user.clone());
+        Ok(user)
+    }
+    async fn update_user(&self, user: User) -> AuthResult<User> {
+        let mut users = self.users.write().await;
+        if users
+            .iter()
+            .any(|u| u.id != user.id && u.username == user.username)
+        {
+            return Err(format!("duplicate username: {}", user.username).into());
+        }
+        let exist = users
+            .iter_mut()
+            .find(|u| u.id == user.id)
+            .ok_or("user not found")?;
+        *exist = User {
+            updated_at: Utc::now(),
+            ..user
+        };
+        Ok(exist.clone())
+    }
+    async fn set_user_disabled(
+        &self,
+        id: i32,
+        disabled_at: Option<DateTime<Utc>>,
+    ) -> AuthResult<()> {
+        let mut disabled = self.disabled.write().await;
+        match disabled.iter_mut().find(|(user_id, _)| *user_id == id) {
+            Some(exist) => exist.1 = disabled_at,
+            None => disabled.push((id, disabled_at)),
+        }
+        Ok(())
+    }
+    async fn is_user_disabled(&self, id: i32) -> AuthResult<bool> {
+        let disabled = self.disabled.read().await;
+        Ok(disabled
+            .iter()
+            .any(|(user_id, disabled_at)| *user_id == id && disabled_at.is_some()))
+    }
+}
+
+#[async_trait]
+impl TokenRepository for MemoryRepository {
+    async fn find_refresh_token(&self, id: i32) -> AuthResult<Option<UserToken>> {
+        let tokens = self.tokens.read().await;
+        Ok(tokens
+            .iter()
+            .find(|t| t.id == id && t.deleted_at.is_none()) // soft delete
+            .cloned())
+    }
+    async fn create_refresh_token(&self, token: InsertToken) -> AuthResult<UserToken> {
+        let mut tokens = self.tokens.write().await;
+        let now = Utc::now();
+        let token = UserToken {
+            id: self.token_seq.fetch_add(1, Ordering::SeqCst) + 1,
+            user_id: token.user_id,
+            device: token.device,
+            hash: token.hash,
+            previous_hash: None,
+            created_at: now,
+            issued_at: now,
+            rotated_at: None,
+            deleted_at: None,
+        };
+        tokens.push(token.clone());
+        Ok(token)
+    }
+    async fn destroy_refresh_token(&self, id: i32) -> AuthResult<()> {
+        let mut tokens = self.tokens.write().await;
+        tokens
+            .iter_mut()
+            .filter(|t| t.id == id && t.deleted_at.is_none())
+            .for_each(|t| t.deleted_at = Some(Utc::now()));
+        Ok(())
+    }
+    async fn rotate_refresh_token(
+        &self,
+        id: i32,
+        current_hash: &str,
+        new_hash: String,
+    ) -> AuthResult<Option<DateTime<Utc>>> {
+        let mut tokens = self.tokens.write().await;
+        let now: DateTime<Utc> = Utc::now();
+        match tokens
+            .iter_mut()
+            .find(|t| t.id == id && t.hash == current_hash && t.deleted_at.is_none())
+        {
+            Some(t) => {
+                t.previous_hash = Some(std::mem::replace(&mut t.hash, new_hash));
+                t.issued_at = now;
+                t.rotated_at = Some(now);
+                Ok(Some(now))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// 内存事务合并：返回`current`合并事务内写入后的结果
+///
+/// 与事务开始时的`base`相比，只写回`changed`里新建、修改过的行，
+/// 事务外同时写入的其它行保留；新建的行已在事务外写入时报错（等同主键冲突）
+pub(crate) fn merge_rows<T, K>(
+    current: &[T],
+    base: &[T],
+    changed: Vec<T>,
+    key: impl Fn(&T) -> K,
+) -> AuthResult<Vec<T>>
+where
+    T: Clone + PartialEq,
+    K: PartialEq + std::fmt::Debug,
+{
+    let mut merged = current.to_vec();
+    for row in changed {
+        let k = key(&row);
+        match base.iter().find(|b| key(b) == k) {
+            // 事务内没有修改
+            Some(b) if *b == row => {}
+            Some(_) => {
+                let exist = merged
+                    .iter_mut()
+                    .find(|m| key(m) == k)
+                    .ok_or_else(|| format!("row not found: {:?}", k))?;
+                *exist = row;
+            }
+            None => {
+                if merged.iter().any(|m| key(m) == k) {
+                    return Err(format!("duplicate key: {:?}", k).into());
+                }
+                merged.push(row);
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// 内存事务：在快照上操作，commit时只写回事务内的修改
+///
+/// 仅用于测试；各仓储分别合并，合并冲突时已合并的仓储不会撤销
+#[derive(Clone)]
+struct MemoryUnitOfWork {
+    repository: Arc<MemoryRepository>,
+    miniprogram_repository: Arc<MemoryMiniprogramRepository>,
+    official_repository: Arc<MemoryOfficialRepository>,
+    wecom_repository: Arc<MemoryWecomRepository>,
+    dingtalk_repository: Arc<MemoryDingtalkRepository>,
+}
+impl MemoryUnitOfWork {
+    async fn snapshot(&self) -> MemorySnapshot {
+        MemorySnapshot {
+            repository: self.repository.snapshot().await,
+            miniprogram_repository: self.miniprogram_repository.snapshot().await,
+            official_repository: self.official_repository.snapshot().await,
+            wecom_repository: self.wecom_repository.snapshot().await,
+            dingtalk_repository: self.dingtalk_repository.snapshot().await,
+        }
+    }
+}
+
+struct MemorySnapshot {
+    repository: MemoryRepository,
+    miniprogram_repository: MemoryMiniprogramRepository,
+    official_repository: MemoryOfficialRepository,
+    wecom_repository: MemoryWecomRepository,
+    dingtalk_repository: MemoryDingtalkRepository,
+}
+impl MemorySnapshot {
+    async fn snapshot(&self) -> Self {
+        Self {
+            repository: self.repository.snapshot().await,
+            miniprogram_repository: self.miniprogram_repository.snapshot().await,
+            official_repository: self.official_repository.snapshot().await,
+            wecom_repository: self.wecom_repository.snapshot().await,
+            dingtalk_repository: self.dingtalk_repository.snapshot().await,
+        }
+    }
+}
+
+#[async_trait]
+impl UnitOfWork for MemoryUnitOfWork {
+    async fn begin(&self) -> AuthResult<Box<dyn Transaction>> {
+        let base = self.snapshot().await;
+        let changed = base.snapshot().await;
+        Ok(Box::new(MemoryTransaction {
+            target: self.clone(),
+            base,
+            changed,
+        }))
+    }
+}
+
+struct MemoryTransaction {
+    target: MemoryUnitOfWork,
+    // 事务开始时的快照，用于找出事务内的修改
+    base: MemorySnapshot,
+    changed: MemorySnapshot,
+}
+
+#[async_trait]
+impl Transaction for MemoryTransaction {
+    fn users(&self) -> &dyn UserRepository {
+        &self.changed.repository
+    }
+    fn tokens(&self) -> &dyn TokenRepository {
+        &self.changed.repository
+    }
+    fn miniprogram_users(&self) -> &dyn MiniprogramUserRepository {
+        &self.changed.miniprogram_repository
+    }
+    fn official_users(&self) -> &dyn OfficialUserRepository {
+        &self.changed.official_repository
+    }
+    fn wecom_users(&self) -> &dyn WecomUserRepository {
+        &self.changed.wecom_repository
+    }
+    fn dingtalk_users(&self) -> &dyn DingtalkUserRepository {
+        &self.changed.dingtalk_repository
+    }
+
+    async fn commit(self: Box<Self>) -> AuthResult<()> {
+        let Self {
+            target,
+            base,
+            changed,
+        } = *self;
+        target
+            .repository
+            .merge(base.repository, changed.repository)
+            .await?;
+        target
+            .miniprogram_repository
+            .merge(base.miniprogram_repository, changed.miniprogram_repository)
+            .await?;
+        target
+            .official_repository
+            .merge(base.official_repository, changed.official_repository)
+            .await?;
+        target
+            .wecom_repository
+            .merge(base.wecom_repository, changed.wecom_repository)
+            .await?;
+        target
+            .dingtalk_repository
+            .merge(base.dingtalk_repository, changed.dingtalk_repository)
+            .await?;
+        Ok(())
+    }
+    async fn rollback(self: Box<Self>) -> AuthResult<()> {
+        // 直接丢弃快照即可
+        Ok(())
+    }
+}
+
+// 生存环境，是不允许删除用户的，
+// 所以这里限定只能在测试里面使用
+#[cfg(test)]
+impl SqlxRepository {
+    pub async fn delete_user_by_username(&self, username: &str) -> AuthResult<()> {
+        let tx = SqlxTransaction::begin(&self.pool).await?;
+        let user = match tx.users().find_user_by_username(username).await? {
+            None => return Ok(()),
+            Some(user) => user,
+        };
+        tx.delete_user(user.id).await?;
+
+        // 两步操作同时生效
+        Box::new(tx).commit().await
+    }
+}
+#[cfg(test)]
+impl SqlxTransaction {
+    async fn delete_user(&self, id: i32) -> AuthResult<()> {
+        let mut tx = self.tx.lock().await;
+
+        // 先删除tokens
+        sqlx::query("DELETE FROM user_tokens WHERE user_id = $1")
+            .bind(id)
+            .execute(&mut **tx)
+            .await?;
+
+        // 再删除users
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(id)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InsertUser, Repositories, User};
+    use crate::core::auth::tests::TestResult;
+
+    #[async_std::test]
+    async fn memory_transaction_commit_and_rollback() -> TestResult<()> {
+        let repos = Repositories::memory();
+        let insert = |username: &str| InsertUser {
+            username: username.to_owned(),
+            ..Default::default()
+        };
+
+        // rollback：事务内可见，事务外不可见
+        let tx = repos.begin().await?;
+        tx.users().create_user(insert("rollback_user")).await?;
+        assert!(tx
+            .users()
+            .find_user_by_username("rollback_user")
+            .await?
+            .is_some());
+        assert!(repos
+            .users
+            .find_user_by_username("rollback_user")
+            .await?
+            .is_none());
+        tx.rollback().await?;
+        assert!(repos
+            .users
+            .find_user_by_username("rollback_user")
+            .await?
+            .is_none());
+
+        // drop 等同于 rollback
+        let tx = repos.begin().await?;
+        tx.users().create_user(insert("dropped_user")).await?;
+        drop(tx);
+        assert!(repos
+            .users
+            .find_user_by_username("dropped_user")
+            .await?
+            .is_none());
+
+        // commit：用户与小程序绑定同时生效
+        let tx = repos.begin().await?;
+        let user = tx.users().create_user(insert("commit_user")).await?;
+        tx.miniprogram_users()
+            .create("commit_openid".to_owned(), user.id)
+            .await?;
+        tx.commit().await?;
+        assert!(repos
+            .users
+            .find_user_by_username("commit_user")
+            .await?
+            .is_some());
+        assert!(repos
+            .miniprogram_users
+            .find("commit_openid")
+            .await?
+            .is_some());
+
+        // commit只写回事务内的修改，事务外同时写入的不会丢失
+        let tx = repos.begin().await?;
+        let renamed = User {
+            name: "renamed in tx".to_owned(),
+            ..user.clone()
+        };
+        tx.users().update_user(renamed).await?;
+        let tx_user = tx.users().create_user(insert("tx_user")).await?;
+        let outside_user = repos.users.create_user(insert("outside_user")).await?;
+        assert_ne!(tx_user.id, outside_user.id);
+        repos
+            .miniprogram_users
+            .create("outside_openid".to_owned(), outside_user.id)
+            .await?;
+        tx.commit().await?;
+        assert!(repos
+            .users
+            .find_user_by_username("outside_user")
+            .await?
+            .is_some());
+        assert!(repos
+            .users
+            .find_user_by_username("tx_user")
+            .await?
+            .is_some());
+        let found = repos.users.find_user(user.id).await?;
+        assert_eq!(found.map(|u| u.name), Some("renamed in tx".to_owned()));
+        assert!(repos
+            .miniprogram_users
+            .find("outside_openid")
+            .await?
+            .is_some());
+
+        // 事务内新建的与事务外同时新建的冲突（unique约束）
+        let tx = repos.begin().await?;
+        tx.users().create_user(insert("conflict_user")).await?;
+        repos.users.create_user(insert("conflict_user")).await?;
+        assert!(tx.commit().await.is_err());
+
+        Ok(())
+    }
 }
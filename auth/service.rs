@@ -1,52 +1,96 @@
 //#![allow(unused_imports)]
 use super::error::AuthResult;
-use super::models::User;
-use super::repository::{
-    create_refresh_token, destroy_refresh_token, find_refresh_token, find_user,
-    renew_refresh_token, InsertToken,
-};
-use super::token::{Token, KEY_LENGTH};
-use crate::db_connection::PgPool;
+use super::models::{User, UserToken};
+use super::repository::{InsertToken, Repositories};
+use super::token::{derive_renew_key, Token, KEY_LENGTH};
+use crate::core::rate_limit::{Key, Policy, RateLimiter};
 
 use async_std::sync::RwLock;
 use base64;
 use chrono::{DateTime, Utc};
-use diesel::result::Error::NotFound;
+use sqlx::postgres::PgPool;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 pub struct AuthService {
     config: Config,
 }
 #[derive(Clone)]
 pub struct Config {
-    pub db: PgPool,
+    pub repos: Repositories,
     pub cipher_key: [u8; KEY_LENGTH],
+    /// 由cipher_key派生，续约时生成新nonce用
+    pub renew_key: [u8; KEY_LENGTH],
+    pub rate_limits: RateLimits,
+}
+
+/// 登录相关的限流
+///
+/// 默认使用内存存储，多副本部署时可通过`RateLimiter::with_store`共享
+#[derive(Clone, Debug)]
+pub struct RateLimits {
+    /// 登录，按IP（调用jscode2session之前）
+    pub login: RateLimiter,
+    /// 注册，按openid
+    pub register: RateLimiter,
+    /// 续约，按user_id（refresh token校验通过、需要轮换时）
+    pub renew: RateLimiter,
+    /// 反向代理的地址，只有来自这些地址的请求才按Forwarded/X-Forwarded-For取客户端IP
+    pub trusted_proxies: Vec<IpAddr>,
+}
+impl Default for RateLimits {
+    fn default() -> Self {
+        let minute = Duration::from_secs(60);
+        Self {
+            login: RateLimiter::new("login", Policy::sliding_window(20, minute)),
+            register: RateLimiter::new("register", Policy::sliding_window(5, minute)),
+            renew: RateLimiter::new("renew", Policy::token_bucket(10, minute)),
+            trusted_proxies: Vec::new(),
+        }
+    }
 }
 impl AuthService {
     /// 初始化，一般在main.rs里
     /// Panics：1. 秘钥长度不对
     /// ```rs
-    /// let auth = ::authenticate::AuthService::new(db_pool, cipher_key);
+    /// let auth = ::authenticate::AuthService::new(sqlx_pool, cipher_key);
     /// ```
     pub fn new(db: PgPool, base64_encoded_key: &str) -> Self {
+        Self::with_repositories(Repositories::sqlx(db), base64_encoded_key)
+    }
+
+    /// 注入数据仓储，例如测试时使用`Repositories::memory()`
+    /// Panics：同`new()`
+    pub fn with_repositories(repos: Repositories, base64_encoded_key: &str) -> Self {
         use std::convert::TryInto;
         let cipher_key =
             base64::decode(base64_encoded_key).expect("CIPHER_KEY must be base64 encoded");
+        let cipher_key: [u8; KEY_LENGTH] = cipher_key[..]
+            .try_into()
+            .unwrap_or_else(|_| panic!("cipher key LENGTH should be {}", KEY_LENGTH));
 
         Self {
             config: Config {
-                db,
-                cipher_key: cipher_key[..]
-                    .try_into()
-                    .unwrap_or_else(|_| panic!("cipher key LENGTH should be {}", KEY_LENGTH)),
+                repos,
+                cipher_key,
+                renew_key: derive_renew_key(&cipher_key),
+                rate_limits: Default::default(),
             },
         }
     }
 
+    /// 替换默认的限流设置
+    pub fn with_rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.config.rate_limits = rate_limits;
+        self
+    }
+
     /// 实例化
     /// 一般是在 http handler里处理
     /// 此identity可以放入graphql的context参数结构里去
-    /// error: 一般是数据库连接问题，可以返回500
+    /// error: 一般是数据库连接问题，可以返回500；
+    ///        续约过于频繁时为`RateLimitError`，可以返回429
     pub async fn get_identity(&self, token_str: &str) -> AuthResult<Identity> {
         Identity::from_request(self.config.clone(), token_str).await
     }
@@ -63,6 +107,7 @@ struct IdentityInner {
     token: RwLock<Option<Token>>,
     user: RwLock<Option<User>>,
     response: RwLock<Option<TokenResponse>>,
+    client_ip: RwLock<Option<String>>,
 }
 // 开放api
 impl Identity {
@@ -105,8 +150,8 @@ impl Identity {
 
         if let Some(token) = self.get_token().await {
             let uid = token.user_id as i32;
-            let conn = self.0.config.db.get().ok()?;
-            let user: Option<User> = find_user(uid, conn).await.ok();
+            let users = &self.0.config.repos.users;
+            let user: Option<User> = users.find_user(uid).await.ok().flatten();
             self.set_user(user.clone()).await;
             user
         } else {
@@ -119,7 +164,11 @@ impl Identity {
     }
 
     // 登陆（在具体登陆的方式里调用该方法）
+    // 已停用的用户（例如钉钉通讯录同步为离职）返回错误
     pub async fn login(&self, user: User) -> AuthResult<()> {
+        if self.0.config.repos.users.is_user_disabled(user.id).await? {
+            return Err("用户已停用".into());
+        }
         let (nonce, hash) = Token::nonce_pair();
 
         // refresh token
@@ -129,7 +178,12 @@ impl Identity {
                 device: Default::default(),
                 hash,
             };
-            create_refresh_token(insert, self.0.config.db.get()?).await?
+            self.0
+                .config
+                .repos
+                .tokens
+                .create_refresh_token(insert)
+                .await?
         };
 
         // token
@@ -154,7 +208,10 @@ impl Identity {
     pub async fn logout(&self) -> AuthResult<()> {
         // 1. delete token in db
         if let Some(t) = self.get_token().await {
-            destroy_refresh_token(t.refresh_token_id as i32, self.0.config.db.get()?).await?
+            let tokens = &self.0.config.repos.tokens;
+            tokens
+                .destroy_refresh_token(t.refresh_token_id as i32)
+                .await?
         }
 
         // todo:
@@ -174,6 +231,21 @@ impl Identity {
         Ok(())
     }
 
+    // 数据仓储，供resolver等使用
+    pub fn repositories(&self) -> &Repositories {
+        &self.0.config.repos
+    }
+
+    // 限流器，供resolver等使用
+    pub fn rate_limits(&self) -> &RateLimits {
+        &self.0.config.rate_limits
+    }
+
+    // 客户端IP，由tide中间件设置
+    pub async fn client_ip(&self) -> Option<String> {
+        self.0.client_ip.read().await.clone()
+    }
+
     // 输出cookie
     //
     // update: 放在 mod integrate_with_actix_session 实现
@@ -195,7 +267,7 @@ macro_rules! pub_when_test {
 // 内部方法
 impl Identity {
     /// 解析token string获取Identity。
-    /// > 仅在数据库错误时候，返回Err
+    /// > 仅在数据库错误、续约过于频繁时候，返回Err
     async fn from_request(cfg: Config, token_str: &str) -> AuthResult<Self> {
         let key = cfg.cipher_key;
         match Token::from_string(token_str, &key) {
@@ -207,18 +279,41 @@ impl Identity {
 
             // 过期的token，需要数据库验证
             Ok(token) => {
-                let conn = cfg.db.get()?;
-                match find_refresh_token(token.refresh_token_id as i32, conn).await {
-                    Err(NotFound) => Ok(Self::with_invalid_token(cfg)),
-                    Err(e) => Err(e.into()),
-                    // 校验
-                    Ok(refresh_token) => {
-                        if token.verify(&refresh_token) {
-                            Self::with_renew(token, cfg).await
-                        } else {
-                            Ok(Self::with_invalid_token(cfg))
-                        }
-                    }
+                let tokens = &cfg.repos.tokens;
+                let refresh_token = match tokens
+                    .find_refresh_token(token.refresh_token_id as i32)
+                    .await?
+                {
+                    None => return Ok(Self::with_invalid_token(cfg)),
+                    Some(refresh_token) => refresh_token,
+                };
+
+                // 校验
+                let verified = token.verify(&refresh_token);
+                // 并发请求：其它请求刚刚完成了续约
+                if !verified && !token.verify_previous(&refresh_token) {
+                    return Ok(Self::with_invalid_token(cfg));
+                }
+
+                // 已停用的用户不再续约，refresh token一并作废
+                if cfg
+                    .repos
+                    .users
+                    .is_user_disabled(refresh_token.user_id)
+                    .await?
+                {
+                    tokens.destroy_refresh_token(refresh_token.id).await?;
+                    return Ok(Self::with_invalid_token(cfg));
+                }
+
+                if verified {
+                    // 校验通过后才限流：伪造、重放的token不消耗用户的额度，
+                    // 宽限期内的并发请求也不受影响
+                    let uid = Key::UserId(token.user_id as i32);
+                    cfg.rate_limits.renew.check(uid).await?;
+                    Self::with_renew(token, refresh_token, cfg).await
+                } else {
+                    Self::with_renewed(token, &refresh_token, cfg)
                 }
             }
         }
@@ -229,6 +324,7 @@ impl Identity {
             token: RwLock::new(None),
             user: RwLock::new(None),
             response: RwLock::new(None),
+            client_ip: RwLock::new(None),
         }))
     }
     fn with_invalid_token(config: Config) -> Self {
@@ -237,6 +333,7 @@ impl Identity {
             token: RwLock::new(None),
             user: RwLock::new(None),
             response: RwLock::new(Some(TokenResponse::Delete)),
+            client_ip: RwLock::new(None),
         }))
     }
     fn with_token(t: Token, config: Config) -> Self {
@@ -245,20 +342,48 @@ impl Identity {
             token: RwLock::new(Some(t)),
             user: RwLock::new(None),
             response: RwLock::new(None),
+            client_ip: RwLock::new(None),
         }))
     }
-    async fn with_renew(t: Token, config: Config) -> AuthResult<Self> {
+    async fn with_renew(t: Token, refresh_token: UserToken, config: Config) -> AuthResult<Self> {
         // 1. update refresh_token
-        let (nonce, hash) = Token::nonce_pair();
-        let iat = renew_refresh_token(t.refresh_token_id as i32, hash, config.db.get()?).await?;
-
-        // 2. create new token
+        // 同时把旧的bcrypt hash升级为sha256
+        let nonce = t.next_nonce(&config.renew_key);
+        let tokens = &config.repos.tokens;
+        let rotated = tokens
+            .rotate_refresh_token(refresh_token.id, &refresh_token.hash, Token::hash(&nonce))
+            .await?;
+
+        match rotated {
+            // 2. create new token
+            Some(iat) => {
+                let token = Token {
+                    nonce,
+                    issued_at: iat.timestamp(),
+                    ..t
+                };
+                Self::with_renewed_token(token, config)
+            }
+            // 并发续约，其它请求抢先了一步，
+            // 重新读取，按宽限期处理
+            None => match tokens.find_refresh_token(refresh_token.id).await? {
+                Some(refresh_token) if t.verify_previous(&refresh_token) => {
+                    Self::with_renewed(t, &refresh_token, config)
+                }
+                _ => Ok(Self::with_invalid_token(config)),
+            },
+        }
+    }
+    // 宽限期内的旧token：重现已经发放的新token
+    fn with_renewed(t: Token, refresh_token: &UserToken, config: Config) -> AuthResult<Self> {
         let token = Token {
-            nonce,
-            issued_at: iat.timestamp(),
+            nonce: t.next_nonce(&config.renew_key),
+            issued_at: refresh_token.issued_at.timestamp(),
             ..t
         };
-
+        Self::with_renewed_token(token, config)
+    }
+    fn with_renewed_token(token: Token, config: Config) -> AuthResult<Self> {
         // 3. set to response
         let (token_str, expires) = token.to_string(&config.cipher_key)?;
         Ok(Self(Arc::new(IdentityInner {
@@ -266,6 +391,7 @@ impl Identity {
             token: RwLock::new(Some(token)),
             user: RwLock::new(None),
             response: RwLock::new(Some(TokenResponse::Set(token_str, expires))),
+            client_ip: RwLock::new(None),
         })))
     }
 
@@ -283,6 +409,10 @@ impl Identity {
         *self.0.token.write().await = token;
     }
 
+    async fn set_client_ip(&self, ip: Option<String>) {
+        *self.0.client_ip.write().await = ip;
+    }
+
     // pub(super) 仅为了其它模块的test
     pub_when_test! {
         async fn get_response(&self) -> Option<TokenResponse> {
@@ -302,10 +432,10 @@ pub enum TokenResponse {
 
 #[cfg(test)]
 mod tests {
-    use super::super::repository::{create_refresh_token, InsertToken};
+    use super::super::repository::{InsertToken, Repositories};
     use super::super::token::{Token, TOKEN_LIFE_HOURS};
-    use super::{AuthService, TokenResponse};
-    use crate::db_connection::tests as db_tests;
+    use super::{AuthService, RateLimits, TokenResponse};
+    use crate::core::rate_limit::{Policy, RateLimitError, RateLimiter};
     use chrono::{Duration, Utc};
 
     use crate::core::auth::tests;
@@ -321,24 +451,19 @@ mod tests {
     #[test]
     #[should_panic]
     fn invalid_cipher_key() {
-        let pool = db_tests::db_pool();
         // should be panicked here
         // because of invalid key length
-        AuthService::new(pool, "invalid key length");
+        AuthService::with_repositories(Repositories::memory(), "invalid key length");
     }
 
     #[async_std::test]
     async fn login() -> TestResult<()> {
         setup();
 
-        let pool = db_tests::db_pool();
-
-        // clear up for testing
-        tests::clear_mock_user(MOCK_USERNAME, pool.clone()).await?;
-
-        let auth = tests::auth_service(pool.clone());
+        let auth = tests::auth_service();
+        let repos = auth.config.repos.clone();
         // 构建一个测试user
-        let user = tests::mock_user(MOCK_USERNAME, pool.clone()).await?;
+        let user = tests::mock_user(MOCK_USERNAME, &repos).await?;
 
         // todo 以下测试可以分拆到多个方法里面
 
@@ -409,7 +534,7 @@ mod tests {
                 device: Default::default(),
                 hash,
             };
-            let refresh_token = create_refresh_token(insert, pool.get()?).await?;
+            let refresh_token = repos.tokens.create_refresh_token(insert).await?;
 
             // pack token string
             // !故意提前，让token过期，为了让数据库验证token
@@ -431,22 +556,159 @@ mod tests {
             id.get_response().await,
             Some(TokenResponse::Set(_, _))
         ));
-        if let Some(TokenResponse::Set(new_token_str, _)) = id.get_response().await {
-            debug!("renew token:");
-            debug!("old token: {}", token_str);
-            debug!("new token: {}", new_token_str);
+        let new_token_str = match id.get_response().await {
+            Some(TokenResponse::Set(t, _)) => t,
+            _ => panic!("renew failed"),
+        };
+        debug!("renew token:");
+        debug!("old token: {}", token_str);
+        debug!("new token: {}", new_token_str);
+
+        // 测试七：再次使用renew前的token（并发续约）
+        // 宽限期内，应该得到同一个新token
+        let id = auth.get_identity(&token_str).await?;
+        assert_eq!(id.is_login().await, true);
+        assert_eq!(user.id, id.user_id().await.unwrap());
+        match id.get_response().await {
+            Some(TokenResponse::Set(t, _)) => assert_eq!(t, new_token_str),
+            r => panic!("unexpected response: {:?}", r),
         }
 
-        // 测试七：再次使用renew前的token
-        // 旧的token应该失效
+        // 测试八：新token再次续约后，最初的token应该失效
+        let renewed_token_str = {
+            let token = Token::from_string(&new_token_str, &auth.config.cipher_key)?;
+            let fake_issued_at = Utc::now() - Duration::hours(TOKEN_LIFE_HOURS + 1);
+            let token = Token {
+                issued_at: fake_issued_at.timestamp(),
+                ..token
+            };
+            let (token_str, _) = token.to_string(&auth.config.cipher_key)?;
+            token_str
+        };
+        let id = auth.get_identity(&renewed_token_str).await?;
+        assert_eq!(id.is_login().await, true);
         let id = auth.get_identity(&token_str).await?;
         assert_eq!(id.is_login().await, false);
         assert_eq!(id.user_id().await, None);
         assert_eq!(id.user().await, None);
         assert_eq!(id.get_response().await, Some(TokenResponse::Delete));
 
-        // clear up
-        tests::clear_mock_user(MOCK_USERNAME, pool.clone()).await?;
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn disabled_user() -> TestResult<()> {
+        setup();
+
+        let auth = tests::auth_service();
+        let repos = auth.config.repos.clone();
+        let user = tests::mock_user(MOCK_USERNAME, &repos).await?;
+
+        // 登录后停用
+        let id = auth.get_identity("an invalid token").await?;
+        id.login(user.clone()).await?;
+        let token_str = match id.get_response().await {
+            Some(TokenResponse::Set(t, _)) => t,
+            r => panic!("unexpected response: {:?}", r),
+        };
+        repos
+            .users
+            .set_user_disabled(user.id, Some(Utc::now()))
+            .await?;
+
+        // 不能登录
+        let id = auth.get_identity("an invalid token").await?;
+        assert!(id.login(user.clone()).await.is_err());
+        assert_eq!(id.is_login().await, false);
+
+        // 不能续约，refresh token被作废
+        let token = Token::from_string(&token_str, &auth.config.cipher_key)?;
+        let (expired_str, _) = Token {
+            issued_at: (Utc::now() - Duration::hours(TOKEN_LIFE_HOURS + 1)).timestamp(),
+            ..token
+        }
+        .to_string(&auth.config.cipher_key)?;
+        let id = auth.get_identity(&expired_str).await?;
+        assert_eq!(id.is_login().await, false);
+        assert_eq!(id.get_response().await, Some(TokenResponse::Delete));
+        let refresh_token = repos
+            .tokens
+            .find_refresh_token(token.refresh_token_id as i32)
+            .await?;
+        assert!(refresh_token.is_none());
+
+        // 恢复后可以再次登录
+        repos.users.set_user_disabled(user.id, None).await?;
+        let id = auth.get_identity("an invalid token").await?;
+        id.login(user.clone()).await?;
+        assert_eq!(id.user_id().await, Some(user.id));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn renew_rate_limited() -> TestResult<()> {
+        setup();
+
+        let window = std::time::Duration::from_secs(60);
+        let auth = tests::auth_service().with_rate_limits(RateLimits {
+            renew: RateLimiter::new("renew", Policy::sliding_window(1, window)),
+            ..Default::default()
+        });
+        let repos = auth.config.repos.clone();
+        let user = tests::mock_user(MOCK_USERNAME, &repos).await?;
+
+        // 过期的token
+        let (nonce, hash) = Token::nonce_pair();
+        let insert = InsertToken {
+            user_id: user.id,
+            device: Default::default(),
+            hash,
+        };
+        let refresh_token = repos.tokens.create_refresh_token(insert).await?;
+        let (token_str, _) = Token {
+            nonce,
+            user_id: user.id as i64,
+            refresh_token_id: refresh_token.id as i64,
+            issued_at: (Utc::now() - Duration::hours(TOKEN_LIFE_HOURS + 1)).timestamp(),
+        }
+        .to_string(&auth.config.cipher_key)?;
+
+        // 伪造的token不消耗额度
+        let (forged_str, _) = Token {
+            nonce: Token::nonce_pair().0,
+            ..Token::from_string(&token_str, &auth.config.cipher_key)?
+        }
+        .to_string(&auth.config.cipher_key)?;
+        for _ in 0..3 {
+            let id = auth.get_identity(&forged_str).await?;
+            assert_eq!(id.is_login().await, false);
+        }
+
+        // 第一次续约成功
+        let id = auth.get_identity(&token_str).await?;
+        assert_eq!(id.is_login().await, true);
+        let renewed_str = match id.get_response().await {
+            Some(TokenResponse::Set(t, _)) => t,
+            r => panic!("unexpected response: {:?}", r),
+        };
+
+        // 宽限期内的并发请求不受限流影响
+        let id = auth.get_identity(&token_str).await?;
+        assert_eq!(id.is_login().await, true);
+
+        // 再次轮换被限流
+        let (expired_str, _) = Token {
+            issued_at: (Utc::now() - Duration::hours(TOKEN_LIFE_HOURS + 1)).timestamp(),
+            ..Token::from_string(&renewed_str, &auth.config.cipher_key)?
+        }
+        .to_string(&auth.config.cipher_key)?;
+        let err = auth
+            .get_identity(&expired_str)
+            .await
+            .err()
+            .expect("rate limited");
+        assert!(err.downcast_ref::<RateLimitError>().is_some());
 
         Ok(())
     }
@@ -456,6 +718,7 @@ mod tests {
 pub mod integrate_with_tide {
     use super::{AuthService, Identity, TokenResponse};
     use crate::core::auth::token::REFRESH_TOKEN_LIFE_DAYS;
+    use crate::core::rate_limit::integrate_with_tide as rate_limit;
     use chrono::Duration;
     use futures::future::BoxFuture;
     use std::fmt;
@@ -477,10 +740,14 @@ pub mod integrate_with_tide {
                         .map(|c: Cookie| c.value().to_owned())
                         .unwrap_or_default();
                     debug!("token => {}", &token_str);
-                    self.get_identity(&token_str).await.map_err(|e| {
-                        tide::Error::from_str(tide::StatusCode::InternalServerError, e)
-                    })?
+                    self.get_identity(&token_str)
+                        .await
+                        .map_err(rate_limit::into_tide_error)?
                 };
+                let trusted_proxies = &self.config.rate_limits.trusted_proxies;
+                identity
+                    .set_client_ip(rate_limit::client_ip(&req, trusted_proxies))
+                    .await;
 
                 // handler run
                 let mut res = next.run(req.set_local(identity.clone())).await?;
@@ -511,7 +778,10 @@ pub mod integrate_with_tide {
     impl std::fmt::Debug for AuthService {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             f.debug_struct("AuthService")
-                .field("config", &Box::new("{db_pool, cipher_key}"))
+                .field(
+                    "config",
+                    &Box::new("{repos, cipher_key, renew_key, rate_limits}"),
+                )
                 .finish()
         }
     }
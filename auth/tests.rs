@@ -1,73 +1,47 @@
 use base_62;
 
 use crate::core::auth::models::User;
-use crate::core::auth::repository as user_repository;
+use crate::core::auth::repository::{self as user_repository, Repositories};
 use crate::core::auth::service::AuthService;
 use crate::core::wechat::miniprogram::models::MiniprogramUser;
-use crate::core::wechat::miniprogram::repository as miniprogram_repository;
 use crate::db_connection::tests as db_tests;
-use crate::db_connection::PgPool;
-use crate::graphql::Context;
-use sqlx::postgres::PgPool as SqlxPgPool;
 
-pub type TestResult<O> = Result<O, Box<dyn std::error::Error + Send + Sync>>;
+pub use crate::core::testing::TestResult;
 
-pub fn auth_service(pool: PgPool) -> AuthService {
+/// 使用内存数据仓储，测试无需数据库
+pub fn auth_service() -> AuthService {
     let cipher_key = "Q+mvRWovv4NHANIuevkXtAmC3r2wp8bjyrKCPTgm7m0=";
-    AuthService::new(pool, cipher_key)
+    AuthService::with_repositories(Repositories::memory(), cipher_key)
 }
 
-pub async fn mock_user(username: &str, pool: PgPool) -> TestResult<User> {
+pub async fn mock_user(username: &str, repos: &Repositories) -> TestResult<User> {
     let insert = user_repository::InsertUser {
         username: username.to_owned(),
         name: "for test".to_owned(),
         avatar: Default::default(),
     };
 
-    user_repository::create_user(insert, pool.get()?).await
-}
-
-pub async fn clear_mock_user(username: &str, pool: PgPool) -> TestResult<()> {
-    user_repository::delete_user_by_username(username, pool)
-        .await
-        .map_err(Into::into)
+    repos.users.create_user(insert).await
 }
 
 pub async fn mock_miniprogram_user(
     open_id: &str,
     user_id: i32,
-    pool: PgPool,
+    repos: &Repositories,
 ) -> TestResult<MiniprogramUser> {
-    miniprogram_repository::create(open_id.to_owned(), user_id, pool.get()?).await
-}
-
-pub async fn clear_mock_miniprogram_user(open_id: &str, pool: PgPool) -> TestResult<()> {
-    miniprogram_repository::delete(open_id, pool.get()?)
+    repos
+        .miniprogram_users
+        .create(open_id.to_owned(), user_id)
         .await
-        .map_err(Into::into)
-}
-
-pub async fn mock_context(db_pool: PgPool, sqlx_pool: SqlxPgPool) -> TestResult<Context> {
-    let auth = auth_service(db_pool.clone());
-    let identity = auth.get_identity("an invalid token").await?;
-
-    use crate::core::api::wechat_miniprogram::{Config, Miniprogram};
-    let miniprogram = Miniprogram::new(Config::from_env());
-
-    Ok(Context::new(
-        db_pool,
-        sqlx_pool,
-        identity,
-        miniprogram,
-        Default::default(),
-    ))
 }
 
 #[async_std::test]
+#[ignore = "需要数据库"]
 async fn clear_mock_user_test() {
-    let pool = db_tests::db_pool();
+    let pool = db_tests::sqlx_pool().await;
 
-    clear_mock_user("not_exist_users_username", pool)
+    user_repository::SqlxRepository::new(pool)
+        .delete_user_by_username("not_exist_users_username")
         .await
         .unwrap();
 }
@@ -4,8 +4,11 @@ use aead::{generic_array::GenericArray, Aead, NewAead};
 use aes_gcm_siv::Aes256GcmSiv;
 use bcrypt;
 use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac, NewMac};
 use rand::Rng;
+use sha2::{Digest, Sha256};
 use std::convert::TryInto;
+use subtle::ConstantTimeEq;
 
 // key&nonce length
 pub(crate) const KEY_LENGTH: usize = 32;
@@ -13,11 +16,26 @@ pub(crate) const NONCE_LENGTH: usize = 12;
 // life time
 pub(super) const TOKEN_LIFE_HOURS: i64 = 1;
 pub(super) const REFRESH_TOKEN_LIFE_DAYS: i64 = 30;
+// 续约后，旧token在这段时间内仍然有效（返回同一个新token），
+// 避免页面并发请求时，除第一个以外的请求都被登出
+pub(super) const RENEW_GRACE_SECONDS: i64 = 30;
 
-#[cfg(not(test))]
-const HASH_COST: u32 = bcrypt::DEFAULT_COST;
-#[cfg(test)]
-const HASH_COST: u32 = 4; // bcrypt::MIN_COST<private>
+// refresh token hash 格式：sha256$<base64(sha256(nonce))>
+// nonce本身是96位随机数，无需bcrypt这种慢hash；
+// 不带此前缀的是旧的bcrypt hash，仍然可以校验，续约时会被替换
+const HASH_PREFIX: &str = "sha256$";
+
+/// 续约nonce用的HMAC key，不与AES-GCM-SIV共用cipher_key
+///
+/// 即HKDF-Expand(cipher_key, "renew-nonce")，cipher_key本身是均匀随机的，省略Extract
+pub(crate) fn derive_renew_key(cipher_key: &[u8; KEY_LENGTH]) -> [u8; KEY_LENGTH] {
+    let mut mac = Hmac::<Sha256>::new_varkey(cipher_key).expect("HMAC accepts any key length");
+    mac.update(b"renew-nonce");
+    mac.update(&[1]);
+    let mut key = [0u8; KEY_LENGTH];
+    key.copy_from_slice(&mac.finalize().into_bytes());
+    key
+}
 
 #[derive(Copy, Clone)]
 pub(crate) struct Token {
@@ -87,31 +105,71 @@ impl Token {
     }
 
     pub fn nonce_pair() -> ([u8; NONCE_LENGTH], String) {
-        let nonce = loop {
-            let n = rand::thread_rng().gen::<[u8; NONCE_LENGTH]>();
-            if !n.contains(&0u8) {
-                break n;
-            }
-        };
-        // hash 函数有两个地方会Error：
-        // 1. COST值超出有效范围，这里使用bcrypt::DEFAULT_COST
-        // 2. password(即这里的nonce)包含\0字符，上面通过loop规避了，
-        // 因此这里可以安全的unwrap()
-        let hash = bcrypt::hash(nonce, HASH_COST).unwrap();
-        (nonce, hash)
+        let nonce = rand::thread_rng().gen::<[u8; NONCE_LENGTH]>();
+        (nonce, Self::hash(&nonce))
+    }
+
+    /// 续约时使用的新nonce
+    ///
+    /// 由旧nonce通过HMAC派生（不可预测，但可重现），
+    /// 宽限期内并发的续约请求可以算出同一个新token，而无需在数据库保存明文；
+    /// `renew_key`见`derive_renew_key`
+    pub fn next_nonce(&self, renew_key: &[u8; KEY_LENGTH]) -> [u8; NONCE_LENGTH] {
+        // HMAC可以接受任意长度的key，这里不会出错
+        let mut mac = Hmac::<Sha256>::new_varkey(renew_key).expect("HMAC accepts any key length");
+        mac.update(b"renew:");
+        mac.update(&self.nonce);
+        let digest = mac.finalize().into_bytes();
+
+        let mut nonce = [0u8; NONCE_LENGTH];
+        nonce.copy_from_slice(&digest[..NONCE_LENGTH]);
+        nonce
+    }
+
+    pub fn hash(nonce: &[u8; NONCE_LENGTH]) -> String {
+        format!("{}{}", HASH_PREFIX, base64::encode(Sha256::digest(nonce)))
     }
 
     pub fn verify(&self, refresh_token: &UserToken) -> bool {
-        let is_expired = refresh_token.deleted_at.is_some()
-            || Utc::now() > refresh_token.issued_at + Duration::days(REFRESH_TOKEN_LIFE_DAYS);
+        !Self::is_refresh_token_expired(refresh_token) && self.verify_hash(&refresh_token.hash)
+    }
+
+    /// 是否为刚刚被续约掉的旧token（宽限期内）
+    pub fn verify_previous(&self, refresh_token: &UserToken) -> bool {
+        let in_grace = refresh_token.rotated_at.map_or(false, |t| {
+            Utc::now() < t + Duration::seconds(RENEW_GRACE_SECONDS)
+        });
+
+        in_grace
+            && !Self::is_refresh_token_expired(refresh_token)
+            && refresh_token
+                .previous_hash
+                .as_ref()
+                .map_or(false, |hash| self.verify_hash(hash))
+    }
+
+    fn is_refresh_token_expired(refresh_token: &UserToken) -> bool {
+        refresh_token.deleted_at.is_some()
+            || Utc::now() > refresh_token.issued_at + Duration::days(REFRESH_TOKEN_LIFE_DAYS)
+    }
 
-        !is_expired && bcrypt::verify(self.nonce, &refresh_token.hash).unwrap_or(false)
+    fn verify_hash(&self, hash: &str) -> bool {
+        if hash.starts_with(HASH_PREFIX) {
+            // 固定时间比较，避免时序攻击
+            let expected = Self::hash(&self.nonce);
+            expected.as_bytes().ct_eq(hash.as_bytes()).into()
+        } else {
+            // 旧的bcrypt hash
+            bcrypt::verify(self.nonce, hash).unwrap_or(false)
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Token;
+    use super::super::models::UserToken;
+    use super::{Token, HASH_PREFIX};
+    use chrono::{Duration, Utc};
 
     fn setup() {
         // 为了在testing下看到logging
@@ -161,4 +219,99 @@ mod tests {
             user_id, refresh_token_id, issued_at, nonce
         );
     }
+
+    fn mock_refresh_token(hash: String) -> UserToken {
+        UserToken {
+            id: 12,
+            user_id: 1005,
+            device: Default::default(),
+            hash,
+            previous_hash: None,
+            created_at: Utc::now(),
+            issued_at: Utc::now(),
+            rotated_at: None,
+            deleted_at: None,
+        }
+    }
+    fn mock_token(nonce: [u8; 12]) -> Token {
+        Token {
+            nonce,
+            user_id: 1005i64,
+            refresh_token_id: 12i64,
+            issued_at: Utc::now().timestamp(),
+        }
+    }
+
+    #[test]
+    fn verify_sha256_hash() {
+        setup();
+
+        let (nonce, hash) = Token::nonce_pair();
+        assert!(hash.starts_with(HASH_PREFIX));
+        let refresh_token = mock_refresh_token(hash);
+        assert!(mock_token(nonce).verify(&refresh_token));
+
+        // nonce不匹配
+        let (other_nonce, _) = Token::nonce_pair();
+        assert!(!mock_token(other_nonce).verify(&refresh_token));
+
+        // 已删除
+        let deleted = UserToken {
+            deleted_at: Some(Utc::now()),
+            ..refresh_token.clone()
+        };
+        assert!(!mock_token(nonce).verify(&deleted));
+
+        // 已过期
+        let expired = UserToken {
+            issued_at: Utc::now() - Duration::days(super::REFRESH_TOKEN_LIFE_DAYS + 1),
+            ..refresh_token
+        };
+        assert!(!mock_token(nonce).verify(&expired));
+    }
+
+    #[test]
+    fn verify_legacy_bcrypt_hash() {
+        setup();
+
+        // 旧版本生成的hash（不含\0的nonce）
+        let nonce = *b"12345678_234";
+        let hash = bcrypt::hash(nonce, 4).unwrap();
+        let refresh_token = mock_refresh_token(hash);
+        assert!(mock_token(nonce).verify(&refresh_token));
+        assert!(!mock_token(*b"12345678_235").verify(&refresh_token));
+    }
+
+    #[test]
+    fn verify_previous_in_grace_window() {
+        setup();
+
+        let cipher_key = b"12345678_2345678_2345678_2345678";
+        let key = &super::derive_renew_key(cipher_key);
+        assert_ne!(key, cipher_key);
+        let (old_nonce, old_hash) = Token::nonce_pair();
+        let old_token = mock_token(old_nonce);
+        let new_nonce = old_token.next_nonce(key);
+        assert_ne!(old_nonce, new_nonce);
+        // 可重现
+        assert_eq!(new_nonce, old_token.next_nonce(key));
+
+        let rotated = UserToken {
+            hash: Token::hash(&new_nonce),
+            previous_hash: Some(old_hash),
+            rotated_at: Some(Utc::now()),
+            ..mock_refresh_token(Default::default())
+        };
+        assert!(!old_token.verify(&rotated));
+        assert!(old_token.verify_previous(&rotated));
+        assert!(mock_token(new_nonce).verify(&rotated));
+        assert!(!mock_token(new_nonce).verify_previous(&rotated));
+
+        // 宽限期已过
+        let rotated_long_ago = UserToken {
+            rotated_at: Some(Utc::now() - Duration::seconds(super::RENEW_GRACE_SECONDS + 1)),
+            ..rotated
+        };
+        assert!(!old_token.verify_previous(&rotated_long_ago));
+    }
 }
@@ -0,0 +1,25 @@
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+
+pub(super) type AnyResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// 钉钉员工，由通讯录同步写入（见`sync`），按unionid关联到users
+//
+// 表结构见migrations/2026-10-18-000005_create_dingtalk_users
+#[derive(FromRow, Clone, Debug, Default, PartialEq)]
+pub struct DingtalkUser {
+    pub union_id: String,
+    pub userid: String, // 企业内的员工id，管理员可以修改，所以不作为主键
+    pub name: String,
+    pub mobile: Option<String>,
+    pub avatar: Option<String>,
+    pub position: Option<String>,
+    pub department_ids: Vec<i64>,
+    pub user_id: i32,                       // 关联users表
+    pub disabled_at: Option<DateTime<Utc>>, // 已离职（通讯录里不再存在）
+}
+impl DingtalkUser {
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
+}
@@ -0,0 +1,243 @@
+use super::models::{AnyResult, DingtalkUser};
+use crate::core::auth::repository::merge_rows;
+use async_std::sync::RwLock;
+use async_trait::async_trait;
+use sqlx::postgres::PgPool;
+
+#[async_trait]
+pub trait DingtalkUserRepository: Send + Sync {
+    async fn find(&self, union_id: &str) -> AnyResult<Option<DingtalkUser>>;
+    async fn find_by_user_id(&self, user_id: i32) -> AnyResult<Option<DingtalkUser>>;
+    /// 未离职的员工，同步时用于找出已离职的人
+    async fn list_active(&self) -> AnyResult<Vec<DingtalkUser>>;
+    async fn create(
+        &self,
+        union_id: String,
+        userid: String,
+        user_id: i32,
+    ) -> AnyResult<DingtalkUser>;
+    async fn update(&self, u: DingtalkUser) -> AnyResult<DingtalkUser>;
+}
+
+// sqlx 查询，连接池与事务共用（见`auth::repository::SqlxTransaction`）
+pub(crate) mod queries {
+    use super::super::models::{AnyResult, DingtalkUser};
+    use sqlx::{postgres::Postgres, Executor};
+
+    pub async fn find<'e, E>(executor: E, union_id: &str) -> AnyResult<Option<DingtalkUser>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, DingtalkUser>("SELECT * FROM dingtalk_users WHERE union_id = $1")
+            .bind(union_id)
+            .fetch_optional(executor)
+            .await
+            .map_err(Into::into)
+    }
+
+    pub async fn find_by_user_id<'e, E>(
+        executor: E,
+        user_id: i32,
+    ) -> AnyResult<Option<DingtalkUser>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, DingtalkUser>(
+            "SELECT * FROM dingtalk_users WHERE user_id = $1 ORDER BY union_id LIMIT 1",
+        )
+        .bind(user_id)
+        .fetch_optional(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn list_active<'e, E>(executor: E) -> AnyResult<Vec<DingtalkUser>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, DingtalkUser>(
+            "SELECT * FROM dingtalk_users WHERE disabled_at IS NULL ORDER BY union_id",
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn create<'e, E>(
+        executor: E,
+        union_id: String,
+        userid: String,
+        user_id: i32,
+    ) -> AnyResult<DingtalkUser>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, DingtalkUser>(
+            "INSERT INTO dingtalk_users (union_id, userid, user_id) VALUES ($1, $2, $3) RETURNING *",
+        )
+        .bind(union_id)
+        .bind(userid)
+        .bind(user_id)
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn update<'e, E>(executor: E, u: DingtalkUser) -> AnyResult<DingtalkUser>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, DingtalkUser>(
+            "UPDATE dingtalk_users SET \
+             userid = $2, name = $3, mobile = $4, avatar = $5, position = $6, \
+             department_ids = $7, user_id = $8, disabled_at = $9 \
+             WHERE union_id = $1 RETURNING *",
+        )
+        .bind(u.union_id)
+        .bind(u.userid)
+        .bind(u.name)
+        .bind(u.mobile)
+        .bind(u.avatar)
+        .bind(u.position)
+        .bind(u.department_ids)
+        .bind(u.user_id)
+        .bind(u.disabled_at)
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+}
+
+// sqlx 实现
+pub struct SqlxRepository {
+    pool: PgPool,
+}
+impl SqlxRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl DingtalkUserRepository for SqlxRepository {
+    async fn find(&self, union_id: &str) -> AnyResult<Option<DingtalkUser>> {
+        queries::find(&self.pool, union_id).await
+    }
+
+    async fn find_by_user_id(&self, user_id: i32) -> AnyResult<Option<DingtalkUser>> {
+        queries::find_by_user_id(&self.pool, user_id).await
+    }
+
+    async fn list_active(&self) -> AnyResult<Vec<DingtalkUser>> {
+        queries::list_active(&self.pool).await
+    }
+
+    async fn create(
+        &self,
+        union_id: String,
+        userid: String,
+        user_id: i32,
+    ) -> AnyResult<DingtalkUser> {
+        queries::create(&self.pool, union_id, userid, user_id).await
+    }
+
+    async fn update(&self, u: DingtalkUser) -> AnyResult<DingtalkUser> {
+        queries::update(&self.pool, u).await
+    }
+}
+
+// 内存 实现
+#[derive(Default)]
+pub struct MemoryRepository {
+    users: RwLock<Vec<DingtalkUser>>,
+}
+impl MemoryRepository {
+    // 供内存事务使用
+    pub(crate) async fn snapshot(&self) -> Self {
+        Self {
+            users: RwLock::new(self.users.read().await.clone()),
+        }
+    }
+    // 只写回事务内新建、修改过的行，见`auth::repository::merge_rows`
+    pub(crate) async fn merge(&self, base: Self, changed: Self) -> AnyResult<()> {
+        let mut users = self.users.write().await;
+        *users = merge_rows(
+            users.as_slice(),
+            &base.users.into_inner(),
+            changed.users.into_inner(),
+            |u| u.union_id.clone(),
+        )?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DingtalkUserRepository for MemoryRepository {
+    async fn find(&self, union_id: &str) -> AnyResult<Option<DingtalkUser>> {
+        let users = self.users.read().await;
+        Ok(users.iter().find(|u| u.union_id == union_id).cloned())
+    }
+
+    async fn find_by_user_id(&self, user_id: i32) -> AnyResult<Option<DingtalkUser>> {
+        let users = self.users.read().await;
+        let mut found: Vec<_> = users.iter().filter(|u| u.user_id == user_id).collect();
+        found.sort_by(|a, b| a.union_id.cmp(&b.union_id));
+        Ok(found.first().cloned().cloned())
+    }
+
+    async fn list_active(&self) -> AnyResult<Vec<DingtalkUser>> {
+        let users = self.users.read().await;
+        let mut found: Vec<_> = users
+            .iter()
+            .filter(|u| u.disabled_at.is_none())
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| a.union_id.cmp(&b.union_id));
+        Ok(found)
+    }
+
+    async fn create(
+        &self,
+        union_id: String,
+        userid: String,
+        user_id: i32,
+    ) -> AnyResult<DingtalkUser> {
+        let mut users = self.users.write().await;
+        // union_id为主键
+        if users.iter().any(|u| u.union_id == union_id) {
+            return Err(format!("duplicate union_id: {}", union_id).into());
+        }
+        let user = DingtalkUser {
+            union_id,
+            userid,
+            user_id,
+            ..Default::default()
+        };
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn update(&self, u: DingtalkUser) -> AnyResult<DingtalkUser> {
+        let mut users = self.users.write().await;
+        let exist = users
+            .iter_mut()
+            .find(|e| e.union_id == u.union_id)
+            .ok_or("dingtalk user not found")?;
+        *exist = u.clone();
+        Ok(u)
+    }
+}
+
+// 生存环境，是不允许删除用户资料的，
+// 所以这里限定只能在测试里面使用
+#[cfg(test)]
+impl SqlxRepository {
+    pub async fn delete(&self, union_id: &str) -> AnyResult<()> {
+        sqlx::query("DELETE FROM dingtalk_users WHERE union_id = $1")
+            .bind(union_id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+}
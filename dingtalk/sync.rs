@@ -0,0 +1,429 @@
+//! 通讯录同步
+//!
+//! 遍历钉钉的部门与成员，写入`dingtalk_users`与`users`：
+//! * 按unionid找到已同步的员工，资料有变化则更新
+//! * 首次出现的员工，按手机号（即`users.username`）关联已有用户，没有则新建
+//! * 通讯录里不再存在的员工，标记为已离职（`disabled_at`），同时停用关联的用户（不能登录、续约）；
+//!   重新出现在通讯录里时恢复
+//!
+//! 重复执行结果不变，可以手动触发，也可以定时执行。
+//! 同一时间只允许一个同步的限制只在进程内有效，多副本部署时只在一个副本上调用`schedule`；
+//! 即使多个副本同时执行，`dingtalk_users`的主键也会让重复建档的一方失败（记为`failed`，下次同步重试）：
+//! ```rs
+//! let sync = Arc::new(DirectorySync::new(dingtalk, repositories));
+//! let report = sync.run().await?; // 手动
+//! sync.clone().schedule(Duration::from_secs(3600)); // 每小时
+//! ```
+use super::models::{AnyResult, DingtalkUser};
+use crate::core::api::dingtalk::{DepartmentMember, Dingtalk, ROOT_DEPARTMENT_ID};
+use crate::core::auth::models::User;
+use crate::core::auth::repository::{InsertUser, Repositories};
+use async_std::sync::Mutex;
+use async_std::task::{self, JoinHandle};
+use chrono::Utc;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+// `user/listbypage`每页最多100
+const PAGE_SIZE: u32 = 100;
+
+/// 同步结果，内容为钉钉的userid
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncReport {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub disabled: Vec<String>,
+    /// 没有手机号（缺少通讯录手机号权限），无法关联users
+    pub skipped: Vec<String>,
+    /// 写入失败，下次同步时重试
+    pub failed: Vec<String>,
+}
+impl SyncReport {
+    /// 没有任何变化
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty()
+            && self.updated.is_empty()
+            && self.disabled.is_empty()
+            && self.failed.is_empty()
+    }
+}
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "created {}, updated {}, disabled {}, skipped {}, failed {}",
+            self.created.len(),
+            self.updated.len(),
+            self.disabled.len(),
+            self.skipped.len(),
+            self.failed.len()
+        )
+    }
+}
+
+pub struct DirectorySync {
+    dingtalk: Arc<Dingtalk>,
+    repos: Repositories,
+    // 手动与定时同步可能重叠，同一时间只允许一个（仅限本进程，见模块说明）
+    running: Mutex<()>,
+}
+impl DirectorySync {
+    pub fn new(dingtalk: Arc<Dingtalk>, repos: Repositories) -> Self {
+        Self {
+            dingtalk,
+            repos,
+            running: Mutex::new(()),
+        }
+    }
+
+    /// 执行一次同步；正在同步时返回错误
+    pub async fn run(&self) -> AnyResult<SyncReport> {
+        let _running = self.running.try_lock().ok_or("钉钉通讯录正在同步")?;
+
+        // 先完整拉取通讯录，中途出错则不做任何修改，避免把没拉到的员工误判为离职
+        let members = self.fetch_members().await?;
+        if members.is_empty() {
+            return Err("钉钉通讯录为空（应用的通讯录权限范围？），跳过同步".into());
+        }
+
+        let mut report = SyncReport::default();
+        for member in &members {
+            if let Err(e) = self.sync_member(member, &mut report).await {
+                warn!("sync dingtalk user {} failed: {}", member.userid, e);
+                report.failed.push(member.userid.clone());
+            }
+        }
+
+        let seen: HashSet<&str> = members.iter().map(|m| m.unionid.as_str()).collect();
+        for user in self.repos.dingtalk_users.list_active().await? {
+            if seen.contains(user.union_id.as_str()) {
+                continue;
+            }
+            let userid = user.userid.clone();
+            match self.disable_member(user).await {
+                Ok(()) => report.disabled.push(userid),
+                Err(e) => {
+                    warn!("disable dingtalk user {} failed: {}", userid, e);
+                    report.failed.push(userid);
+                }
+            }
+        }
+
+        info!("sync dingtalk users: {}", report);
+        Ok(report)
+    }
+
+    /// 每隔`every`同步一次（启动后先等待一个间隔）
+    pub fn schedule(self: Arc<Self>, every: Duration) -> JoinHandle<()> {
+        task::spawn(async move {
+            loop {
+                task::sleep(every).await;
+                if let Err(e) = self.run().await {
+                    warn!("sync dingtalk users failed: {}", e);
+                }
+            }
+        })
+    }
+
+    // 全部部门的成员，按unionid去重（同一员工可以在多个部门）
+    async fn fetch_members(&self) -> AnyResult<Vec<DepartmentMember>> {
+        let mut department_ids = vec![ROOT_DEPARTMENT_ID];
+        let departments = self
+            .dingtalk
+            .department_list(ROOT_DEPARTMENT_ID, true)
+            .await?;
+        department_ids.extend(departments.into_iter().map(|d| d.id));
+
+        let mut members = BTreeMap::new();
+        for department_id in department_ids {
+            let mut offset = 0;
+            loop {
+                let page = self
+                    .dingtalk
+                    .department_members(department_id, offset, PAGE_SIZE)
+                    .await?;
+                let count = page.userlist.len() as u32;
+                for member in page.userlist {
+                    members.entry(member.unionid.clone()).or_insert(member);
+                }
+                if !page.has_more || count == 0 {
+                    break;
+                }
+                offset += count;
+            }
+        }
+        Ok(members.into_iter().map(|(_, member)| member).collect())
+    }
+
+    // 离职：标记dingtalk_user并停用关联的用户
+    async fn disable_member(&self, user: DingtalkUser) -> AnyResult<()> {
+        let user_id = user.user_id;
+        let now = Utc::now();
+        let update = DingtalkUser {
+            disabled_at: Some(now),
+            ..user
+        };
+        let tx = self.repos.begin().await?;
+        tx.dingtalk_users().update(update).await?;
+        tx.users().set_user_disabled(user_id, Some(now)).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn sync_member(
+        &self,
+        member: &DepartmentMember,
+        report: &mut SyncReport,
+    ) -> AnyResult<()> {
+        let repos = &self.repos;
+        match repos.dingtalk_users.find(&member.unionid).await? {
+            Some(exist) => {
+                let user = repos
+                    .users
+                    .find_user(exist.user_id)
+                    .await?
+                    .ok_or("钉钉员工关联的用户不存在")?;
+                let dingtalk_update = merge_dingtalk_user(&exist, member);
+                let user_update = merge_user(&user, member);
+                if dingtalk_update == exist && user_update == user {
+                    return Ok(());
+                }
+
+                let tx = repos.begin().await?;
+                if dingtalk_update != exist {
+                    tx.dingtalk_users().update(dingtalk_update).await?;
+                }
+                // 离职后重新入职
+                if exist.is_disabled() {
+                    tx.users().set_user_disabled(user.id, None).await?;
+                }
+                if user_update != user {
+                    tx.users().update_user(user_update).await?;
+                }
+                tx.commit().await?;
+                report.updated.push(member.userid.clone());
+            }
+            None => {
+                if member.mobile.is_empty() {
+                    report.skipped.push(member.userid.clone());
+                    return Ok(());
+                }
+
+                // 关联用户与新建dingtalk_user在同一个事务里
+                let tx = repos.begin().await?;
+                let user = match tx.users().find_user_by_username(&member.mobile).await? {
+                    Some(user) => {
+                        let update = merge_user(&user, member);
+                        if update != user {
+                            tx.users().update_user(update).await?
+                        } else {
+                            user
+                        }
+                    }
+                    None => {
+                        let insert = InsertUser {
+                            username: member.mobile.clone(),
+                            name: member.name.clone(),
+                            avatar: member.avatar.clone(),
+                        };
+                        tx.users().create_user(insert).await?
+                    }
+                };
+                let dingtalk_user = tx
+                    .dingtalk_users()
+                    .create(member.unionid.clone(), member.userid.clone(), user.id)
+                    .await?;
+                tx.dingtalk_users()
+                    .update(merge_dingtalk_user(&dingtalk_user, member))
+                    .await?;
+                tx.commit().await?;
+                report.created.push(member.userid.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+// 钉钉里的最新资料；没有手机号权限时保留原来的手机号
+fn merge_dingtalk_user(exist: &DingtalkUser, member: &DepartmentMember) -> DingtalkUser {
+    let mut department_ids = member.department.clone();
+    department_ids.sort_unstable();
+    let non_empty = |s: &str| Some(s.to_owned()).filter(|s| !s.is_empty());
+    DingtalkUser {
+        userid: member.userid.clone(),
+        name: member.name.clone(),
+        mobile: non_empty(&member.mobile).or_else(|| exist.mobile.clone()),
+        avatar: non_empty(&member.avatar),
+        position: non_empty(&member.position),
+        department_ids,
+        disabled_at: None,
+        ..exist.clone()
+    }
+}
+
+// 只更新姓名、头像，username（手机号）保持不变；钉钉里为空时不覆盖
+fn merge_user(user: &User, member: &DepartmentMember) -> User {
+    let mut user = user.clone();
+    if !member.name.is_empty() {
+        user.name = member.name.clone();
+    }
+    if !member.avatar.is_empty() {
+        user.avatar = member.avatar.clone();
+    }
+    user
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DirectorySync;
+    use crate::core::api::client::{transport::MockTransport, Client};
+    use crate::core::api::dingtalk::Dingtalk;
+    use crate::core::auth::repository::{InsertUser, Repositories};
+    use crate::core::testing::TestResult;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn setup() {
+        // 为了在testing下看到logging
+        env_logger::try_init().ok();
+    }
+
+    fn member(userid: &str, mobile: &str, name: &str) -> Value {
+        json!({
+            "userid": userid,
+            "unionid": format!("union_{}", userid),
+            "name": name,
+            "mobile": mobile,
+            "active": true,
+            "department": [2]
+        })
+    }
+
+    // 根部门没有直属成员，研发部(2)的成员分两页
+    fn reply_directory(mock: &MockTransport, first_page: Vec<Value>, second_page: Vec<Value>) {
+        mock.reply_json(
+            "/department/list",
+            json!({"errcode": 0, "department": [{"id": 2, "name": "研发部", "parentid": 1}]}),
+        )
+        .reply_json(
+            "/user/listbypage",
+            json!({"errcode": 0, "hasMore": false, "userlist": []}),
+        )
+        .reply_json(
+            "/user/listbypage",
+            json!({"errcode": 0, "hasMore": true, "userlist": first_page}),
+        )
+        .reply_json(
+            "/user/listbypage",
+            json!({"errcode": 0, "hasMore": false, "userlist": second_page}),
+        );
+    }
+
+    #[async_std::test]
+    async fn sync_directory_offline() -> TestResult<()> {
+        setup();
+
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json("/gettoken", json!({"errcode": 0, "access_token": "TOKEN"}));
+        let cfg = Default::default();
+        let client = Client::with_transport(Dingtalk::client_config(&cfg), mock.clone());
+        let dingtalk = Arc::new(Dingtalk::with_client(cfg, client));
+        let repos = Repositories::memory();
+        let sync = DirectorySync::new(dingtalk, repos.clone());
+
+        // 已在小程序注册过的用户
+        let exist_user = repos
+            .users
+            .create_user(InsertUser {
+                username: "13800000001".to_owned(),
+                name: "小张".to_owned(),
+                avatar: Default::default(),
+            })
+            .await?;
+
+        // 首次同步
+        reply_directory(
+            &mock,
+            vec![member("zhangsan", "13800000001", "张三")],
+            vec![
+                member("lisi", "13800000002", "李四"),
+                member("nomobile", "", "没有手机号"),
+            ],
+        );
+        let report = sync.run().await?;
+        assert_eq!(report.created, vec!["lisi", "zhangsan"]);
+        assert_eq!(report.skipped, vec!["nomobile"]);
+        let zhangsan = repos
+            .dingtalk_users
+            .find("union_zhangsan")
+            .await?
+            .ok_or("zhangsan not synced")?;
+        assert_eq!(zhangsan.user_id, exist_user.id);
+        assert_eq!(zhangsan.department_ids, vec![2]);
+        let user = repos.users.find_user(exist_user.id).await?;
+        assert_eq!(user.map(|u| u.name), Some("张三".to_owned()));
+        assert!(repos
+            .users
+            .find_user_by_username("13800000002")
+            .await?
+            .is_some());
+        // 分页参数
+        let requests = mock.requests();
+        assert!(requests[4]
+            .url
+            .ends_with("department_id=2&offset=1&size=100"));
+
+        // 再次同步，没有变化
+        reply_directory(
+            &mock,
+            vec![member("zhangsan", "13800000001", "张三")],
+            vec![
+                member("lisi", "13800000002", "李四"),
+                member("nomobile", "", "没有手机号"),
+            ],
+        );
+        let report = sync.run().await?;
+        assert!(report.is_empty(), "{:?}", report);
+
+        // 李四离职，张三改名
+        reply_directory(
+            &mock,
+            vec![member("zhangsan", "13800000001", "张三丰")],
+            vec![],
+        );
+        let report = sync.run().await?;
+        assert_eq!(report.updated, vec!["zhangsan"]);
+        assert_eq!(report.disabled, vec!["lisi"]);
+        let lisi = repos
+            .dingtalk_users
+            .find("union_lisi")
+            .await?
+            .ok_or("lisi not synced")?;
+        assert!(lisi.is_disabled());
+        // 关联的用户被停用，不能再登录
+        assert!(repos.users.is_user_disabled(lisi.user_id).await?);
+        assert!(!repos.users.is_user_disabled(zhangsan.user_id).await?);
+
+        // 接口出错时不做任何修改
+        mock.reply_json(
+            "/department/list",
+            json!({"errcode": 60011, "errmsg": "no permission"}),
+        );
+        assert!(sync.run().await.is_err());
+        assert_eq!(repos.dingtalk_users.list_active().await?.len(), 1);
+
+        // 李四重新入职，恢复关联的用户
+        reply_directory(
+            &mock,
+            vec![member("zhangsan", "13800000001", "张三丰")],
+            vec![member("lisi", "13800000002", "李四")],
+        );
+        let report = sync.run().await?;
+        assert_eq!(report.updated, vec!["lisi"]);
+        assert!(!repos.users.is_user_disabled(lisi.user_id).await?);
+        assert_eq!(mock.pending(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,3 @@
+ALTER TABLE user_tokens
+    DROP COLUMN rotated_at,
+    DROP COLUMN previous_hash;
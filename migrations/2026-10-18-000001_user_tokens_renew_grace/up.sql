@@ -0,0 +1,4 @@
+-- 续约宽限期：保存上一次续约前的hash和续约时间
+ALTER TABLE user_tokens
+    ADD COLUMN previous_hash VARCHAR,
+    ADD COLUMN rotated_at TIMESTAMPTZ;
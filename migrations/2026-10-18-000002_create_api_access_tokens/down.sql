@@ -0,0 +1 @@
+DROP TABLE api_access_tokens;
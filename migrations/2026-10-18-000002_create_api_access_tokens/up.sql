@@ -0,0 +1,7 @@
+-- 多副本共享的access_token，见api/client/token_store.rs
+CREATE TABLE api_access_tokens (
+    key VARCHAR PRIMARY KEY,
+    access_token VARCHAR NOT NULL,
+    expired_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,2 @@
+DROP INDEX wechat_miniprogram_users_union_id;
+DROP TABLE wechat_official_users;
@@ -0,0 +1,16 @@
+-- 公众号用户，与小程序用户通过union_id关联到同一个users，见wechat/official/models.rs
+CREATE TABLE wechat_official_users (
+    open_id VARCHAR PRIMARY KEY,
+    union_id VARCHAR,
+    nick_name VARCHAR,
+    gender SMALLINT,
+    city VARCHAR,
+    province VARCHAR,
+    country VARCHAR,
+    avatar_url VARCHAR,
+    user_id INTEGER NOT NULL REFERENCES users (id)
+);
+CREATE INDEX wechat_official_users_union_id ON wechat_official_users (union_id);
+CREATE INDEX wechat_official_users_user_id ON wechat_official_users (user_id);
+-- 公众号首次登陆时按union_id查找小程序用户
+CREATE INDEX wechat_miniprogram_users_union_id ON wechat_miniprogram_users (union_id);
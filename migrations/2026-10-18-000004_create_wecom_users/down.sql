@@ -0,0 +1 @@
+DROP TABLE wecom_users;
@@ -0,0 +1,10 @@
+-- 企业微信成员，按(corp_id, userid)绑定到users，见wechat/wecom/models.rs
+CREATE TABLE wecom_users (
+    corp_id VARCHAR NOT NULL,
+    userid VARCHAR NOT NULL,
+    mobile VARCHAR,
+    avatar VARCHAR,
+    user_id INTEGER NOT NULL REFERENCES users (id),
+    PRIMARY KEY (corp_id, userid)
+);
+CREATE INDEX wecom_users_user_id ON wecom_users (user_id);
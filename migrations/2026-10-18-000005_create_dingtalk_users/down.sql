@@ -0,0 +1 @@
+DROP TABLE dingtalk_users;
@@ -0,0 +1,13 @@
+-- 钉钉员工，由通讯录同步写入，按unionid关联到users，见dingtalk/models.rs
+CREATE TABLE dingtalk_users (
+    union_id VARCHAR PRIMARY KEY,
+    userid VARCHAR NOT NULL,
+    name VARCHAR NOT NULL DEFAULT '',
+    mobile VARCHAR,
+    avatar VARCHAR,
+    position VARCHAR,
+    department_ids BIGINT[] NOT NULL DEFAULT '{}',
+    user_id INTEGER NOT NULL REFERENCES users (id),
+    disabled_at TIMESTAMPTZ
+);
+CREATE INDEX dingtalk_users_user_id ON dingtalk_users (user_id);
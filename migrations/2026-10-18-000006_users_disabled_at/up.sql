@@ -0,0 +1,2 @@
+-- 停用的用户不能登录、续约（例如钉钉通讯录同步为离职），见auth/service.rs
+ALTER TABLE users ADD COLUMN disabled_at TIMESTAMPTZ;
@@ -1,4 +1,8 @@
 pub mod api;
 pub mod auth;
+pub mod dingtalk;
 pub mod http;
+pub mod rate_limit;
+#[cfg(test)]
+pub(crate) mod testing;
 pub mod wechat;
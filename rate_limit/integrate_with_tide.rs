@@ -0,0 +1,200 @@
+/// 集成到tide
+///
+/// 按客户端IP限流，超出限制时返回429
+///
+/// ```rs
+/// use crate::core::rate_limit::{integrate_with_tide::RateLimitMiddleware, Policy, RateLimiter};
+/// let limiter = RateLimiter::new("graphql", Policy::token_bucket(60, Duration::from_secs(1)));
+/// app.at("/graphql").middleware(RateLimitMiddleware::new(limiter)).post(handle_graphql);
+/// ```
+///
+/// 部署在反向代理之后时，需要用`trust_proxies`指定代理的地址，否则所有请求都按代理的IP计数
+use super::{Key, RateLimitError, RateLimiter};
+use futures::future::BoxFuture;
+use std::net::{IpAddr, SocketAddr};
+use tide::{Next, Request, StatusCode};
+
+#[derive(Debug)]
+pub struct RateLimitMiddleware {
+    limiter: RateLimiter,
+    trusted_proxies: Vec<IpAddr>,
+}
+impl RateLimitMiddleware {
+    pub fn new(limiter: RateLimiter) -> Self {
+        Self {
+            limiter,
+            trusted_proxies: Vec::new(),
+        }
+    }
+
+    /// 来自这些地址的连接，使用Forwarded/X-Forwarded-For里的客户端IP
+    pub fn trust_proxies(mut self, proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = proxies;
+        self
+    }
+}
+
+impl<State: Send + Sync + 'static> tide::Middleware<State> for RateLimitMiddleware {
+    fn handle<'a>(
+        &'a self,
+        req: Request<State>,
+        next: Next<'a, State>,
+    ) -> BoxFuture<'a, tide::Result> {
+        Box::pin(async move {
+            // 取不到IP时不限流，交给后续的resolver按openid/user_id限流
+            if let Some(ip) = client_ip(&req, &self.trusted_proxies) {
+                self.limiter
+                    .check(Key::Ip(&ip))
+                    .await
+                    .map_err(into_tide_error)?;
+            }
+            next.run(req).await
+        })
+    }
+}
+
+/// 客户端IP
+///
+/// 默认使用连接的对端地址；Forwarded/X-Forwarded-For可以由客户端随意填写，
+/// 只有对端是`trusted_proxies`里的反向代理时才使用
+pub fn client_ip<State>(req: &Request<State>, trusted_proxies: &[IpAddr]) -> Option<String> {
+    let header = |name: &str| {
+        req.header(name).map(|values| {
+            values
+                .iter()
+                .map(|v| v.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+    };
+    resolve_client_ip(
+        req.peer_addr(),
+        header("Forwarded").as_deref(),
+        header("X-Forwarded-For").as_deref(),
+        trusted_proxies,
+    )
+}
+
+fn resolve_client_ip(
+    peer_addr: Option<&str>,
+    forwarded: Option<&str>,
+    x_forwarded_for: Option<&str>,
+    trusted_proxies: &[IpAddr],
+) -> Option<String> {
+    let peer = parse_ip(peer_addr?)?;
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer.to_string());
+    }
+
+    // 每经过一层代理在末尾追加一个地址，从右往左跳过受信任的代理，第一个即为客户端
+    let chain: Vec<&str> = match forwarded {
+        Some(forwarded) => forwarded
+            .split(',')
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let mut kv = pair.trim().splitn(2, '=');
+                    match (kv.next(), kv.next()) {
+                        (Some(k), Some(v)) if k.eq_ignore_ascii_case("for") => {
+                            Some(v.trim_matches('"'))
+                        }
+                        _ => None,
+                    }
+                })
+            })
+            .collect(),
+        None => x_forwarded_for
+            .map(|v| v.split(',').map(str::trim).collect())
+            .unwrap_or_default(),
+    };
+    let mut client = peer;
+    for addr in chain.iter().rev() {
+        // 格式不对（例如"unknown"、被篡改）时，停在最后一个可信的地址
+        match parse_ip(addr) {
+            Some(ip) => {
+                client = ip;
+                if !trusted_proxies.contains(&ip) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    Some(client.to_string())
+}
+
+// "1.2.3.4"、"1.2.3.4:5678"、"::1"、"[::1]"、"[::1]:5678"
+fn parse_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<SocketAddr>()
+        .map(|socket| socket.ip())
+        .or_else(|_| {
+            addr.trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+        })
+        .ok()
+}
+
+/// 限流错误转换为429，其它错误（例如store不可用）为500
+pub fn into_tide_error(e: Box<dyn std::error::Error + Send + Sync>) -> tide::Error {
+    match e.downcast_ref::<RateLimitError>() {
+        Some(limited) => tide::Error::from_str(StatusCode::TooManyRequests, limited.to_string()),
+        None => tide::Error::from_str(StatusCode::InternalServerError, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve_client_ip;
+    use std::net::IpAddr;
+
+    #[test]
+    fn client_ip() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+
+        // 没有配置代理时忽略转发头
+        assert_eq!(
+            resolve_client_ip(Some("1.2.3.4:5678"), None, Some("9.9.9.9"), &[]).as_deref(),
+            Some("1.2.3.4")
+        );
+        assert_eq!(
+            resolve_client_ip(Some("[::1]:5678"), Some("for=9.9.9.9"), None, &[proxy]).as_deref(),
+            Some("::1")
+        );
+
+        // 客户端自己填写的地址在左边，取代理追加的最右一个
+        assert_eq!(
+            resolve_client_ip(
+                Some("10.0.0.1:80"),
+                None,
+                Some("9.9.9.9, 1.2.3.4"),
+                &[proxy]
+            )
+            .as_deref(),
+            Some("1.2.3.4")
+        );
+        assert_eq!(
+            resolve_client_ip(
+                Some("10.0.0.1:80"),
+                Some(r#"for=9.9.9.9, for="[2001:db8::1]:4711";proto=https"#),
+                Some("8.8.8.8"),
+                &[proxy]
+            )
+            .as_deref(),
+            Some("2001:db8::1")
+        );
+
+        // 没有转发头时为代理自己
+        assert_eq!(
+            resolve_client_ip(Some("10.0.0.1:80"), None, None, &[proxy]).as_deref(),
+            Some("10.0.0.1")
+        );
+        assert_eq!(
+            resolve_client_ip(Some("10.0.0.1:80"), None, Some("unknown"), &[proxy]).as_deref(),
+            Some("10.0.0.1")
+        );
+        assert_eq!(
+            resolve_client_ip(None, None, Some("1.2.3.4"), &[proxy]),
+            None
+        );
+    }
+}
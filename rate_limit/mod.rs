@@ -0,0 +1,314 @@
+//! 限流
+//!
+//! 用于保护登录、注册、续约等开销较大的接口（微信jscode2session配额、bcrypt计算）
+//!
+//! ```rs
+//! let limiter = RateLimiter::new("login", Policy::sliding_window(10, Duration::from_secs(60)));
+//! limiter.check(Key::Ip(ip)).await?; // 超出限制时返回 RateLimitError::TooManyAttempts
+//! ```
+//!
+//! 默认使用内存存储（单进程），多副本部署时可实现`RateLimitStore`接入redis等共享存储
+
+use async_std::sync::Mutex;
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error as ThisError;
+
+pub mod integrate_with_tide;
+
+pub type RateLimitResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// 错误类型
+#[derive(ThisError, Debug, Clone, PartialEq)]
+pub enum RateLimitError {
+    #[error("尝试次数过多(too many attempts)，请{}秒后重试", retry_after_secs(.retry_after))]
+    TooManyAttempts { retry_after: Duration },
+}
+fn retry_after_secs(d: &Duration) -> u64 {
+    // 向上取整，避免提示“0秒后重试”
+    d.as_secs() + if d.subsec_nanos() > 0 { 1 } else { 0 }
+}
+
+/// 限流策略
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// 令牌桶：允许突发`capacity`次，之后每`refill_every`补充一个令牌
+    TokenBucket {
+        capacity: u32,
+        refill_every: Duration,
+    },
+    /// 滑动窗口：任意`window`时间内最多`limit`次
+    SlidingWindow { limit: u32, window: Duration },
+}
+impl Policy {
+    pub fn token_bucket(capacity: u32, refill_every: Duration) -> Self {
+        Policy::TokenBucket {
+            capacity,
+            refill_every,
+        }
+    }
+    pub fn sliding_window(limit: u32, window: Duration) -> Self {
+        Policy::SlidingWindow { limit, window }
+    }
+}
+
+/// 限流的维度
+#[derive(Debug, Clone, Copy)]
+pub enum Key<'a> {
+    Ip(&'a str),
+    OpenId(&'a str),
+    UserId(i32),
+}
+impl fmt::Display for Key<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Ip(ip) => write!(f, "ip:{}", ip),
+            Key::OpenId(open_id) => write!(f, "openid:{}", open_id),
+            Key::UserId(id) => write!(f, "uid:{}", id),
+        }
+    }
+}
+
+/// 单次检查的结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    Allowed { remaining: u32 },
+    Limited { retry_after: Duration },
+}
+
+/// 限流状态的存储
+///
+/// `acquire`须是原子的：检查并记录一次请求
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    async fn acquire(&self, key: &str, policy: &Policy) -> RateLimitResult<Decision>;
+}
+
+// 限流器
+#[derive(Clone)]
+pub struct RateLimiter {
+    name: &'static str,
+    policy: Policy,
+    store: Arc<dyn RateLimitStore>,
+}
+impl RateLimiter {
+    /// 使用内存存储
+    pub fn new(name: &'static str, policy: Policy) -> Self {
+        Self::with_store(name, policy, Arc::new(MemoryStore::default()))
+    }
+
+    /// `name`作为key的前缀，多个限流器可以共用一个store
+    pub fn with_store(name: &'static str, policy: Policy, store: Arc<dyn RateLimitStore>) -> Self {
+        Self {
+            name,
+            policy,
+            store,
+        }
+    }
+
+    pub async fn acquire(&self, key: Key<'_>) -> RateLimitResult<Decision> {
+        let key = format!("{}:{}", self.name, key);
+        self.store.acquire(&key, &self.policy).await
+    }
+
+    /// 超出限制时返回`RateLimitError::TooManyAttempts`
+    pub async fn check(&self, key: Key<'_>) -> RateLimitResult<()> {
+        match self.acquire(key).await? {
+            Decision::Allowed { .. } => Ok(()),
+            Decision::Limited { retry_after } => {
+                warn!("rate limited: {} {} ({:?})", self.name, key, retry_after);
+                Err(RateLimitError::TooManyAttempts { retry_after }.into())
+            }
+        }
+    }
+}
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("name", &self.name)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+// 内存存储
+enum State {
+    Bucket { tokens: f64, updated_at: Instant },
+    Window { hits: VecDeque<Instant> },
+}
+impl State {
+    fn new(policy: &Policy, now: Instant) -> Self {
+        match *policy {
+            Policy::TokenBucket { capacity, .. } => State::Bucket {
+                tokens: capacity as f64,
+                updated_at: now,
+            },
+            Policy::SlidingWindow { .. } => State::Window {
+                hits: VecDeque::new(),
+            },
+        }
+    }
+
+    fn acquire(&mut self, policy: &Policy, now: Instant) -> Decision {
+        match (self, *policy) {
+            (
+                State::Bucket { tokens, updated_at },
+                Policy::TokenBucket {
+                    capacity,
+                    refill_every,
+                },
+            ) => {
+                let refill = refill_every.as_secs_f64();
+                let elapsed = now.duration_since(*updated_at).as_secs_f64();
+                *tokens = (*tokens + elapsed / refill).min(capacity as f64);
+                *updated_at = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    Decision::Allowed {
+                        remaining: *tokens as u32,
+                    }
+                } else {
+                    Decision::Limited {
+                        retry_after: Duration::from_secs_f64((1.0 - *tokens) * refill),
+                    }
+                }
+            }
+            (State::Window { hits }, Policy::SlidingWindow { limit, window }) => {
+                while let Some(first) = hits.front() {
+                    if now.duration_since(*first) >= window {
+                        hits.pop_front();
+                    } else {
+                        break;
+                    }
+                }
+                match hits.front().copied() {
+                    Some(first) if hits.len() as u32 >= limit => Decision::Limited {
+                        retry_after: window - now.duration_since(first),
+                    },
+                    // limit为0时，永远拒绝
+                    None if limit == 0 => Decision::Limited {
+                        retry_after: window,
+                    },
+                    _ => {
+                        hits.push_back(now);
+                        Decision::Allowed {
+                            remaining: limit - hits.len() as u32,
+                        }
+                    }
+                }
+            }
+            // 由MemoryStore保证state与policy匹配
+            _ => unreachable!("rate limit state does not match policy"),
+        }
+    }
+
+    // 是否已经恢复到初始状态，可以从内存里移除
+    fn is_idle(&self, policy: &Policy, now: Instant) -> bool {
+        match (self, *policy) {
+            (
+                State::Bucket { tokens, updated_at },
+                Policy::TokenBucket {
+                    capacity,
+                    refill_every,
+                },
+            ) => {
+                let elapsed = now.duration_since(*updated_at).as_secs_f64();
+                tokens + elapsed / refill_every.as_secs_f64() >= capacity as f64
+            }
+            (State::Window { hits }, Policy::SlidingWindow { window, .. }) => hits
+                .back()
+                .map_or(true, |last| now.duration_since(*last) >= window),
+            _ => true,
+        }
+    }
+}
+
+// 每这么多次请求清理一次内存
+const SWEEP_INTERVAL: u64 = 1024;
+
+#[derive(Default)]
+pub struct MemoryStore {
+    inner: Mutex<MemoryStoreInner>,
+}
+#[derive(Default)]
+struct MemoryStoreInner {
+    // 记住每个key的策略，多个限流器共用store时互不干扰
+    states: HashMap<String, (Policy, State)>,
+    counter: u64,
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn acquire(&self, key: &str, policy: &Policy) -> RateLimitResult<Decision> {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().await;
+
+        inner.counter += 1;
+        if inner.counter % SWEEP_INTERVAL == 0 {
+            inner.states.retain(|_, (p, s)| !s.is_idle(p, now));
+        }
+
+        let (stored_policy, state) = inner
+            .states
+            .entry(key.to_owned())
+            .or_insert_with(|| (*policy, State::new(policy, now)));
+        // 策略变化（例如调整了配置），重新计数
+        if stored_policy != policy {
+            *stored_policy = *policy;
+            *state = State::new(policy, now);
+        }
+        Ok(state.acquire(policy, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decision, Key, Policy, RateLimitError, RateLimiter};
+    use crate::core::testing::TestResult;
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn token_bucket() -> TestResult<()> {
+        let policy = Policy::token_bucket(3, Duration::from_millis(50));
+        let limiter = RateLimiter::new("test", policy);
+
+        for remaining in (0..3).rev() {
+            let decision = limiter.acquire(Key::Ip("127.0.0.1")).await?;
+            assert_eq!(decision, Decision::Allowed { remaining });
+        }
+        assert!(matches!(
+            limiter.acquire(Key::Ip("127.0.0.1")).await?,
+            Decision::Limited { .. }
+        ));
+
+        // 其它key不受影响
+        assert!(limiter.check(Key::Ip("127.0.0.2")).await.is_ok());
+
+        // 补充令牌
+        async_std::task::sleep(Duration::from_millis(60)).await;
+        assert!(limiter.check(Key::Ip("127.0.0.1")).await.is_ok());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn sliding_window() -> TestResult<()> {
+        let policy = Policy::sliding_window(2, Duration::from_millis(50));
+        let limiter = RateLimiter::new("test", policy);
+
+        limiter.check(Key::UserId(1)).await?;
+        limiter.check(Key::UserId(1)).await?;
+        let err = limiter.check(Key::UserId(1)).await.unwrap_err();
+        let err = err
+            .downcast_ref::<RateLimitError>()
+            .expect("RateLimitError");
+        let RateLimitError::TooManyAttempts { retry_after } = err;
+        assert!(*retry_after <= Duration::from_millis(50));
+
+        async_std::task::sleep(Duration::from_millis(60)).await;
+        limiter.check(Key::UserId(1)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,4 @@
+//! 测试共用的类型
+
+/// 测试函数的返回值，测试里可以直接用`?`
+pub type TestResult<O> = Result<O, Box<dyn std::error::Error + Send + Sync>>;
@@ -1,2 +1,4 @@
 pub mod models;
+pub mod pay;
 pub mod repository;
+pub mod subscribe;
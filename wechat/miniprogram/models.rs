@@ -1,9 +1,21 @@
 use crate::core::auth::models::User;
 use crate::diesel_schema::wechat_miniprogram_users;
+use sqlx::FromRow;
 
 pub(super) type AnyResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
-#[derive(Identifiable, Queryable, Associations, Insertable, AsChangeset, Clone, Debug, Default)]
+#[derive(
+    Identifiable,
+    Queryable,
+    Associations,
+    Insertable,
+    AsChangeset,
+    FromRow,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+)]
 #[belongs_to(User)]
 #[primary_key(open_id)]
 #[table_name = "wechat_miniprogram_users"]
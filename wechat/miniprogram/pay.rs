@@ -0,0 +1,75 @@
+//! 小程序支付，付款人为user_id
+//!
+//! ```rs
+//! let payments = PaymentService::new(pay, repositories.miniprogram_users.clone());
+//! let params = payments.create_payment(order.user_id, &order.no, "体检套餐", order.total).await?;
+//! ```
+use super::models::AnyResult;
+use super::repository::MiniprogramUserRepository;
+use crate::core::api::wechat_pay::{JsapiOrder, RequestPayment, WechatPay};
+use std::sync::Arc;
+
+pub struct PaymentService {
+    pay: WechatPay,
+    users: Arc<dyn MiniprogramUserRepository>,
+}
+impl PaymentService {
+    pub fn new(pay: WechatPay, users: Arc<dyn MiniprogramUserRepository>) -> Self {
+        Self { pay, users }
+    }
+
+    /// 下单并返回`wx.requestPayment`的参数，用户没有关联小程序时返回错误
+    pub async fn create_payment(
+        &self,
+        user_id: i32,
+        out_trade_no: &str,
+        description: &str,
+        total: i64,
+    ) -> AnyResult<RequestPayment> {
+        let open_id = self.users.open_id_of(user_id).await?;
+        let order = JsapiOrder::new(out_trade_no, description, total, &open_id);
+        self.pay
+            .create_jsapi_payment(&order)
+            .await
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::repository::{MemoryRepository, MiniprogramUserRepository};
+    use super::PaymentService;
+    use crate::core::api::wechat_pay::testing::{mock_pay, reply_signed};
+    use crate::core::testing::TestResult;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    #[async_std::test]
+    async fn pay_by_user_id() -> TestResult<()> {
+        let (pay, mock) = mock_pay();
+        reply_signed(
+            &mock,
+            "/v3/pay/transactions/jsapi",
+            200,
+            r#"{"prepay_id":"wx201410272009395522657a690389285100"}"#,
+        );
+
+        let users = Arc::new(MemoryRepository::default());
+        users.create("OPENID".to_owned(), 1).await?;
+        let payments = PaymentService::new(pay, users);
+
+        let params = payments
+            .create_payment(1, "20301217001", "体检套餐", 100)
+            .await?;
+        assert_eq!(
+            params.package,
+            "prepay_id=wx201410272009395522657a690389285100"
+        );
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        let body: Value = serde_json::from_slice(requests[0].body.as_deref().unwrap_or_default())?;
+        assert_eq!(body["payer"]["openid"], "OPENID");
+        Ok(())
+    }
+}
@@ -1,53 +1,259 @@
 use super::models::{AnyResult, MiniprogramUser};
-use crate::db_connection::PgPooledConnection;
-use async_std::task;
-use diesel::prelude::*;
-
-pub async fn find(open_id: String, conn: PgPooledConnection) -> QueryResult<MiniprogramUser> {
-    task::spawn_blocking(move || {
-        use crate::diesel_schema::wechat_miniprogram_users;
-        wechat_miniprogram_users::table
-            .filter(wechat_miniprogram_users::open_id.eq(open_id))
-            .first(&conn)
-    })
-    .await
-}
-
-pub async fn create(
-    open_id: String,
-    user_id: i32,
-    conn: PgPooledConnection,
-) -> AnyResult<MiniprogramUser> {
-    task::spawn_blocking(move || {
-        use crate::diesel_schema::wechat_miniprogram_users;
-        let insert = MiniprogramUser {
+use crate::core::auth::repository::merge_rows;
+use async_std::sync::RwLock;
+use async_trait::async_trait;
+use sqlx::postgres::PgPool;
+
+#[async_trait]
+pub trait MiniprogramUserRepository: Send + Sync {
+    async fn find(&self, open_id: &str) -> AnyResult<Option<MiniprogramUser>>;
+    /// 用户关联的小程序用户，见`open_id_of`
+    async fn find_by_user_id(&self, user_id: i32) -> AnyResult<Option<MiniprogramUser>>;
+    /// H5登录时，按union_id找到已在小程序登录过的用户
+    async fn find_by_union_id(&self, union_id: &str) -> AnyResult<Option<MiniprogramUser>>;
+    async fn create(&self, open_id: String, user_id: i32) -> AnyResult<MiniprogramUser>;
+    async fn update(&self, u: MiniprogramUser) -> AnyResult<MiniprogramUser>;
+
+    /// 业务里只有user_id时（例如下单、发送订阅消息），找到关联的open_id；没有关联小程序时返回错误
+    async fn open_id_of(&self, user_id: i32) -> AnyResult<String> {
+        self.find_by_user_id(user_id)
+            .await?
+            .map(|u| u.open_id)
+            .ok_or_else(|| format!("miniprogram user not found: user_id={}", user_id).into())
+    }
+}
+
+// sqlx 查询，连接池与事务共用（见`auth::repository::SqlxTransaction`）
+pub(crate) mod queries {
+    use super::super::models::{AnyResult, MiniprogramUser};
+    use sqlx::{postgres::Postgres, Executor};
+
+    pub async fn find<'e, E>(executor: E, open_id: &str) -> AnyResult<Option<MiniprogramUser>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, MiniprogramUser>(
+            "SELECT * FROM wechat_miniprogram_users WHERE open_id = $1",
+        )
+        .bind(open_id)
+        .fetch_optional(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn find_by_user_id<'e, E>(
+        executor: E,
+        user_id: i32,
+    ) -> AnyResult<Option<MiniprogramUser>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, MiniprogramUser>(
+            "SELECT * FROM wechat_miniprogram_users WHERE user_id = $1 ORDER BY open_id LIMIT 1",
+        )
+        .bind(user_id)
+        .fetch_optional(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn find_by_union_id<'e, E>(
+        executor: E,
+        union_id: &str,
+    ) -> AnyResult<Option<MiniprogramUser>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, MiniprogramUser>(
+            "SELECT * FROM wechat_miniprogram_users WHERE union_id = $1 ORDER BY open_id LIMIT 1",
+        )
+        .bind(union_id)
+        .fetch_optional(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn create<'e, E>(
+        executor: E,
+        open_id: String,
+        user_id: i32,
+    ) -> AnyResult<MiniprogramUser>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, MiniprogramUser>(
+            "INSERT INTO wechat_miniprogram_users (open_id, user_id) VALUES ($1, $2) RETURNING *",
+        )
+        .bind(open_id)
+        .bind(user_id)
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn update<'e, E>(executor: E, u: MiniprogramUser) -> AnyResult<MiniprogramUser>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, MiniprogramUser>(
+            "UPDATE wechat_miniprogram_users SET \
+             union_id = $2, nick_name = $3, gender = $4, language = $5, city = $6, \
+             province = $7, country = $8, avatar_url = $9, user_id = $10 \
+             WHERE open_id = $1 RETURNING *",
+        )
+        .bind(u.open_id)
+        .bind(u.union_id)
+        .bind(u.nick_name)
+        .bind(u.gender)
+        .bind(u.language)
+        .bind(u.city)
+        .bind(u.province)
+        .bind(u.country)
+        .bind(u.avatar_url)
+        .bind(u.user_id)
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+}
+
+// sqlx 实现
+pub struct SqlxRepository {
+    pool: PgPool,
+}
+impl SqlxRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl MiniprogramUserRepository for SqlxRepository {
+    async fn find(&self, open_id: &str) -> AnyResult<Option<MiniprogramUser>> {
+        queries::find(&self.pool, open_id).await
+    }
+
+    async fn find_by_user_id(&self, user_id: i32) -> AnyResult<Option<MiniprogramUser>> {
+        queries::find_by_user_id(&self.pool, user_id).await
+    }
+
+    async fn find_by_union_id(&self, union_id: &str) -> AnyResult<Option<MiniprogramUser>> {
+        queries::find_by_union_id(&self.pool, union_id).await
+    }
+
+    async fn create(&self, open_id: String, user_id: i32) -> AnyResult<MiniprogramUser> {
+        queries::create(&self.pool, open_id, user_id).await
+    }
+
+    async fn update(&self, u: MiniprogramUser) -> AnyResult<MiniprogramUser> {
+        queries::update(&self.pool, u).await
+    }
+}
+
+// 内存 实现
+#[derive(Default)]
+pub struct MemoryRepository {
+    users: RwLock<Vec<MiniprogramUser>>,
+}
+impl MemoryRepository {
+    // 供内存事务使用
+    pub(crate) async fn snapshot(&self) -> Self {
+        Self {
+            users: RwLock::new(self.users.read().await.clone()),
+        }
+    }
+    // 只写回事务内新建、修改过的行，见`auth::repository::merge_rows`
+    pub(crate) async fn merge(&self, base: Self, changed: Self) -> AnyResult<()> {
+        let mut users = self.users.write().await;
+        *users = merge_rows(
+            users.as_slice(),
+            &base.users.into_inner(),
+            changed.users.into_inner(),
+            |u| u.open_id.clone(),
+        )?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MiniprogramUserRepository for MemoryRepository {
+    async fn find(&self, open_id: &str) -> AnyResult<Option<MiniprogramUser>> {
+        let users = self.users.read().await;
+        Ok(users.iter().find(|u| u.open_id == open_id).cloned())
+    }
+
+    async fn find_by_user_id(&self, user_id: i32) -> AnyResult<Option<MiniprogramUser>> {
+        let users = self.users.read().await;
+        let mut found: Vec<_> = users.iter().filter(|u| u.user_id == user_id).collect();
+        found.sort_by(|a, b| a.open_id.cmp(&b.open_id));
+        Ok(found.first().cloned().cloned())
+    }
+
+    async fn find_by_union_id(&self, union_id: &str) -> AnyResult<Option<MiniprogramUser>> {
+        let users = self.users.read().await;
+        let mut found: Vec<_> = users
+            .iter()
+            .filter(|u| u.union_id.as_deref() == Some(union_id))
+            .collect();
+        found.sort_by(|a, b| a.open_id.cmp(&b.open_id));
+        Ok(found.first().cloned().cloned())
+    }
+
+    async fn create(&self, open_id: String, user_id: i32) -> AnyResult<MiniprogramUser> {
+        let mut users = self.users.write().await;
+        // open_id为主键
+        if users.iter().any(|u| u.open_id == open_id) {
+            return Err(format!("duplicate open_id: {}", open_id).into());
+        }
+        let user = MiniprogramUser {
             open_id,
             user_id,
             ..Default::default()
         };
-        diesel::insert_into(wechat_miniprogram_users::table)
-            .values(&insert)
-            .get_result(&conn)
-            .map_err(Into::into)
-    })
-    .await
-}
-pub async fn update(u: MiniprogramUser, conn: PgPooledConnection) -> QueryResult<MiniprogramUser> {
-    task::spawn_blocking(move || diesel::update(&u).set(&u).get_result(&conn)).await
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn update(&self, u: MiniprogramUser) -> AnyResult<MiniprogramUser> {
+        let mut users = self.users.write().await;
+        let exist = users
+            .iter_mut()
+            .find(|e| e.open_id == u.open_id)
+            .ok_or("miniprogram user not found")?;
+        *exist = u.clone();
+        Ok(u)
+    }
 }
 
 // 生存环境，是不允许删除用户资料的，
 // 所以这里限定只能在测试里面使用
 #[cfg(test)]
-pub async fn delete(open_id: &str, conn: PgPooledConnection) -> QueryResult<()> {
-    let open_id = open_id.to_owned();
-    task::spawn_blocking(move || {
-        use crate::diesel_schema::wechat_miniprogram_users;
-        diesel::delete(
-            wechat_miniprogram_users::table.filter(wechat_miniprogram_users::open_id.eq(open_id)),
-        )
-        .execute(&conn)
-        .map(|_| ())
-    })
-    .await
+impl SqlxRepository {
+    pub async fn delete(&self, open_id: &str) -> AnyResult<()> {
+        sqlx::query("DELETE FROM wechat_miniprogram_users WHERE open_id = $1")
+            .bind(open_id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryRepository, MiniprogramUserRepository};
+    use crate::core::testing::TestResult;
+
+    #[async_std::test]
+    async fn open_id_of() -> TestResult<()> {
+        let users = MemoryRepository::default();
+        users.create("OPENID_B".to_owned(), 1).await?;
+        users.create("OPENID_A".to_owned(), 1).await?;
+
+        // 同一用户关联多个open_id时，取第一个
+        assert_eq!(users.open_id_of(1).await?, "OPENID_A");
+        // 没有关联小程序
+        assert!(users.open_id_of(2).await.is_err());
+        Ok(())
+    }
 }
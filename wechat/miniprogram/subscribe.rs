@@ -0,0 +1,66 @@
+//! 订阅消息通知，接收人为user_id
+//!
+//! ```rs
+//! let notifier = SubscribeNotifier::new(app, repositories.miniprogram_users.clone());
+//! notifier.notify(order.user_id, &message).await?;
+//! ```
+use super::models::AnyResult;
+use super::repository::MiniprogramUserRepository;
+use crate::core::api::wechat_miniprogram::{Miniprogram, SubscribeMessage};
+use std::sync::Arc;
+
+pub struct SubscribeNotifier {
+    app: Miniprogram,
+    users: Arc<dyn MiniprogramUserRepository>,
+}
+impl SubscribeNotifier {
+    pub fn new(app: Miniprogram, users: Arc<dyn MiniprogramUserRepository>) -> Self {
+        Self { app, users }
+    }
+
+    /// 用户没有关联小程序时返回错误
+    pub async fn notify(&self, user_id: i32, message: &SubscribeMessage) -> AnyResult<()> {
+        let open_id = self.users.open_id_of(user_id).await?;
+        self.app
+            .send_subscribe_message(&open_id, message)
+            .await
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::repository::{MemoryRepository, MiniprogramUserRepository};
+    use super::SubscribeNotifier;
+    use crate::core::api::client::{transport::MockTransport, Client};
+    use crate::core::api::wechat_miniprogram::{Config, Field, Miniprogram, SubscribeMessage};
+    use crate::core::testing::TestResult;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    #[async_std::test]
+    async fn notify_by_user_id() -> TestResult<()> {
+        let mock = Arc::new(MockTransport::new());
+        mock.reply_json(
+            "/cgi-bin/token",
+            json!({"access_token": "TOKEN", "expires_in": 7200}),
+        )
+        .reply_json("/message/subscribe/send", json!({"errcode": 0}));
+        let cfg = Config::default();
+        let client = Client::with_transport(Miniprogram::client_config(&cfg), mock.clone());
+        let app = Miniprogram::with_client(cfg, client);
+
+        let users = Arc::new(MemoryRepository::default());
+        users.create("OPENID".to_owned(), 1).await?;
+        let notifier = SubscribeNotifier::new(app, users);
+
+        let message = SubscribeMessage::new("TEMPLATE_ID").field(Field::Thing, 1, "预约成功");
+        notifier.notify(1, &message).await?;
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        let body: Value = serde_json::from_slice(requests[1].body.as_deref().unwrap_or_default())?;
+        assert_eq!(body["touser"], "OPENID");
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
 #![allow(dead_code)]
 
 pub mod miniprogram;
+pub mod official;
+pub mod wecom;
@@ -0,0 +1,19 @@
+use sqlx::FromRow;
+
+pub(super) type AnyResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// 公众号用户，与小程序用户通过union_id关联到同一个users
+//
+// 表结构见migrations/2026-10-18-000003_create_wechat_official_users
+#[derive(FromRow, Clone, Debug, Default, PartialEq)]
+pub struct OfficialUser {
+    pub open_id: String,
+    pub union_id: Option<String>,
+    pub nick_name: Option<String>,
+    pub gender: Option<i16>, // 0未知 1男性 2女性
+    pub city: Option<String>,
+    pub province: Option<String>,
+    pub country: Option<String>,
+    pub avatar_url: Option<String>,
+    pub user_id: i32, // 关联users表
+}
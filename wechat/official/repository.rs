@@ -0,0 +1,229 @@
+use super::models::{AnyResult, OfficialUser};
+use crate::core::auth::repository::merge_rows;
+use async_std::sync::RwLock;
+use async_trait::async_trait;
+use sqlx::postgres::PgPool;
+
+#[async_trait]
+pub trait OfficialUserRepository: Send + Sync {
+    async fn find(&self, open_id: &str) -> AnyResult<Option<OfficialUser>>;
+    /// 用户关联的公众号用户，例如发送模板消息时查找open_id
+    async fn find_by_user_id(&self, user_id: i32) -> AnyResult<Option<OfficialUser>>;
+    /// 小程序登录时，按union_id找到已在H5登录过的用户
+    async fn find_by_union_id(&self, union_id: &str) -> AnyResult<Option<OfficialUser>>;
+    async fn create(&self, open_id: String, user_id: i32) -> AnyResult<OfficialUser>;
+    async fn update(&self, u: OfficialUser) -> AnyResult<OfficialUser>;
+}
+
+// sqlx 查询，连接池与事务共用（见`auth::repository::SqlxTransaction`）
+pub(crate) mod queries {
+    use super::super::models::{AnyResult, OfficialUser};
+    use sqlx::{postgres::Postgres, Executor};
+
+    pub async fn find<'e, E>(executor: E, open_id: &str) -> AnyResult<Option<OfficialUser>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, OfficialUser>("SELECT * FROM wechat_official_users WHERE open_id = $1")
+            .bind(open_id)
+            .fetch_optional(executor)
+            .await
+            .map_err(Into::into)
+    }
+
+    pub async fn find_by_user_id<'e, E>(
+        executor: E,
+        user_id: i32,
+    ) -> AnyResult<Option<OfficialUser>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, OfficialUser>(
+            "SELECT * FROM wechat_official_users WHERE user_id = $1 ORDER BY open_id LIMIT 1",
+        )
+        .bind(user_id)
+        .fetch_optional(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn find_by_union_id<'e, E>(
+        executor: E,
+        union_id: &str,
+    ) -> AnyResult<Option<OfficialUser>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, OfficialUser>(
+            "SELECT * FROM wechat_official_users WHERE union_id = $1 ORDER BY open_id LIMIT 1",
+        )
+        .bind(union_id)
+        .fetch_optional(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn create<'e, E>(
+        executor: E,
+        open_id: String,
+        user_id: i32,
+    ) -> AnyResult<OfficialUser>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, OfficialUser>(
+            "INSERT INTO wechat_official_users (open_id, user_id) VALUES ($1, $2) RETURNING *",
+        )
+        .bind(open_id)
+        .bind(user_id)
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn update<'e, E>(executor: E, u: OfficialUser) -> AnyResult<OfficialUser>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, OfficialUser>(
+            "UPDATE wechat_official_users SET \
+             union_id = $2, nick_name = $3, gender = $4, city = $5, \
+             province = $6, country = $7, avatar_url = $8, user_id = $9 \
+             WHERE open_id = $1 RETURNING *",
+        )
+        .bind(u.open_id)
+        .bind(u.union_id)
+        .bind(u.nick_name)
+        .bind(u.gender)
+        .bind(u.city)
+        .bind(u.province)
+        .bind(u.country)
+        .bind(u.avatar_url)
+        .bind(u.user_id)
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+}
+
+// sqlx 实现
+pub struct SqlxRepository {
+    pool: PgPool,
+}
+impl SqlxRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl OfficialUserRepository for SqlxRepository {
+    async fn find(&self, open_id: &str) -> AnyResult<Option<OfficialUser>> {
+        queries::find(&self.pool, open_id).await
+    }
+
+    async fn find_by_user_id(&self, user_id: i32) -> AnyResult<Option<OfficialUser>> {
+        queries::find_by_user_id(&self.pool, user_id).await
+    }
+
+    async fn find_by_union_id(&self, union_id: &str) -> AnyResult<Option<OfficialUser>> {
+        queries::find_by_union_id(&self.pool, union_id).await
+    }
+
+    async fn create(&self, open_id: String, user_id: i32) -> AnyResult<OfficialUser> {
+        queries::create(&self.pool, open_id, user_id).await
+    }
+
+    async fn update(&self, u: OfficialUser) -> AnyResult<OfficialUser> {
+        queries::update(&self.pool, u).await
+    }
+}
+
+// 内存 实现
+#[derive(Default)]
+pub struct MemoryRepository {
+    users: RwLock<Vec<OfficialUser>>,
+}
+impl MemoryRepository {
+    // 供内存事务使用
+    pub(crate) async fn snapshot(&self) -> Self {
+        Self {
+            users: RwLock::new(self.users.read().await.clone()),
+        }
+    }
+    // 只写回事务内新建、修改过的行，见`auth::repository::merge_rows`
+    pub(crate) async fn merge(&self, base: Self, changed: Self) -> AnyResult<()> {
+        let mut users = self.users.write().await;
+        *users = merge_rows(
+            users.as_slice(),
+            &base.users.into_inner(),
+            changed.users.into_inner(),
+            |u| u.open_id.clone(),
+        )?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OfficialUserRepository for MemoryRepository {
+    async fn find(&self, open_id: &str) -> AnyResult<Option<OfficialUser>> {
+        let users = self.users.read().await;
+        Ok(users.iter().find(|u| u.open_id == open_id).cloned())
+    }
+
+    async fn find_by_user_id(&self, user_id: i32) -> AnyResult<Option<OfficialUser>> {
+        let users = self.users.read().await;
+        let mut found: Vec<_> = users.iter().filter(|u| u.user_id == user_id).collect();
+        found.sort_by(|a, b| a.open_id.cmp(&b.open_id));
+        Ok(found.first().cloned().cloned())
+    }
+
+    async fn find_by_union_id(&self, union_id: &str) -> AnyResult<Option<OfficialUser>> {
+        let users = self.users.read().await;
+        let mut found: Vec<_> = users
+            .iter()
+            .filter(|u| u.union_id.as_deref() == Some(union_id))
+            .collect();
+        found.sort_by(|a, b| a.open_id.cmp(&b.open_id));
+        Ok(found.first().cloned().cloned())
+    }
+
+    async fn create(&self, open_id: String, user_id: i32) -> AnyResult<OfficialUser> {
+        let mut users = self.users.write().await;
+        // open_id为主键
+        if users.iter().any(|u| u.open_id == open_id) {
+            return Err(format!("duplicate open_id: {}", open_id).into());
+        }
+        let user = OfficialUser {
+            open_id,
+            user_id,
+            ..Default::default()
+        };
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn update(&self, u: OfficialUser) -> AnyResult<OfficialUser> {
+        let mut users = self.users.write().await;
+        let exist = users
+            .iter_mut()
+            .find(|e| e.open_id == u.open_id)
+            .ok_or("official user not found")?;
+        *exist = u.clone();
+        Ok(u)
+    }
+}
+
+// 生存环境，是不允许删除用户资料的，
+// 所以这里限定只能在测试里面使用
+#[cfg(test)]
+impl SqlxRepository {
+    pub async fn delete(&self, open_id: &str) -> AnyResult<()> {
+        sqlx::query("DELETE FROM wechat_official_users WHERE open_id = $1")
+            .bind(open_id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+}
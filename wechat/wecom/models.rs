@@ -0,0 +1,15 @@
+use sqlx::FromRow;
+
+pub(super) type AnyResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// 企业微信成员，按(corp_id, userid)绑定到users
+//
+// 表结构见migrations/2026-10-18-000004_create_wecom_users
+#[derive(FromRow, Clone, Debug, Default, PartialEq)]
+pub struct WecomUser {
+    pub corp_id: String,
+    pub userid: String, // 企业微信的成员账号，企业内唯一
+    pub mobile: Option<String>,
+    pub avatar: Option<String>,
+    pub user_id: i32, // 关联users表
+}
@@ -0,0 +1,213 @@
+use super::models::{AnyResult, WecomUser};
+use crate::core::auth::repository::merge_rows;
+use async_std::sync::RwLock;
+use async_trait::async_trait;
+use sqlx::postgres::PgPool;
+
+#[async_trait]
+pub trait WecomUserRepository: Send + Sync {
+    async fn find(&self, corp_id: &str, userid: &str) -> AnyResult<Option<WecomUser>>;
+    /// 用户关联的企业微信成员，例如发送应用消息时查找userid
+    async fn find_by_user_id(&self, corp_id: &str, user_id: i32) -> AnyResult<Option<WecomUser>>;
+    async fn create(&self, corp_id: String, userid: String, user_id: i32) -> AnyResult<WecomUser>;
+    async fn update(&self, u: WecomUser) -> AnyResult<WecomUser>;
+}
+
+// sqlx 查询，连接池与事务共用（见`auth::repository::SqlxTransaction`）
+pub(crate) mod queries {
+    use super::super::models::{AnyResult, WecomUser};
+    use sqlx::{postgres::Postgres, Executor};
+
+    pub async fn find<'e, E>(
+        executor: E,
+        corp_id: &str,
+        userid: &str,
+    ) -> AnyResult<Option<WecomUser>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, WecomUser>(
+            "SELECT * FROM wecom_users WHERE corp_id = $1 AND userid = $2",
+        )
+        .bind(corp_id)
+        .bind(userid)
+        .fetch_optional(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn find_by_user_id<'e, E>(
+        executor: E,
+        corp_id: &str,
+        user_id: i32,
+    ) -> AnyResult<Option<WecomUser>>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, WecomUser>(
+            "SELECT * FROM wecom_users WHERE corp_id = $1 AND user_id = $2 ORDER BY userid LIMIT 1",
+        )
+        .bind(corp_id)
+        .bind(user_id)
+        .fetch_optional(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn create<'e, E>(
+        executor: E,
+        corp_id: String,
+        userid: String,
+        user_id: i32,
+    ) -> AnyResult<WecomUser>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, WecomUser>(
+            "INSERT INTO wecom_users (corp_id, userid, user_id) VALUES ($1, $2, $3) RETURNING *",
+        )
+        .bind(corp_id)
+        .bind(userid)
+        .bind(user_id)
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn update<'e, E>(executor: E, u: WecomUser) -> AnyResult<WecomUser>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        sqlx::query_as::<_, WecomUser>(
+            "UPDATE wecom_users SET mobile = $3, avatar = $4, user_id = $5 \
+             WHERE corp_id = $1 AND userid = $2 RETURNING *",
+        )
+        .bind(u.corp_id)
+        .bind(u.userid)
+        .bind(u.mobile)
+        .bind(u.avatar)
+        .bind(u.user_id)
+        .fetch_one(executor)
+        .await
+        .map_err(Into::into)
+    }
+}
+
+// sqlx 实现
+pub struct SqlxRepository {
+    pool: PgPool,
+}
+impl SqlxRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl WecomUserRepository for SqlxRepository {
+    async fn find(&self, corp_id: &str, userid: &str) -> AnyResult<Option<WecomUser>> {
+        queries::find(&self.pool, corp_id, userid).await
+    }
+
+    async fn find_by_user_id(&self, corp_id: &str, user_id: i32) -> AnyResult<Option<WecomUser>> {
+        queries::find_by_user_id(&self.pool, corp_id, user_id).await
+    }
+
+    async fn create(&self, corp_id: String, userid: String, user_id: i32) -> AnyResult<WecomUser> {
+        queries::create(&self.pool, corp_id, userid, user_id).await
+    }
+
+    async fn update(&self, u: WecomUser) -> AnyResult<WecomUser> {
+        queries::update(&self.pool, u).await
+    }
+}
+
+// 内存 实现
+#[derive(Default)]
+pub struct MemoryRepository {
+    users: RwLock<Vec<WecomUser>>,
+}
+impl MemoryRepository {
+    // 供内存事务使用
+    pub(crate) async fn snapshot(&self) -> Self {
+        Self {
+            users: RwLock::new(self.users.read().await.clone()),
+        }
+    }
+    // 只写回事务内新建、修改过的行，见`auth::repository::merge_rows`
+    pub(crate) async fn merge(&self, base: Self, changed: Self) -> AnyResult<()> {
+        let mut users = self.users.write().await;
+        *users = merge_rows(
+            users.as_slice(),
+            &base.users.into_inner(),
+            changed.users.into_inner(),
+            |u| (u.corp_id.clone(), u.userid.clone()),
+        )?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WecomUserRepository for MemoryRepository {
+    async fn find(&self, corp_id: &str, userid: &str) -> AnyResult<Option<WecomUser>> {
+        let users = self.users.read().await;
+        Ok(users
+            .iter()
+            .find(|u| u.corp_id == corp_id && u.userid == userid)
+            .cloned())
+    }
+
+    async fn find_by_user_id(&self, corp_id: &str, user_id: i32) -> AnyResult<Option<WecomUser>> {
+        let users = self.users.read().await;
+        let mut found: Vec<_> = users
+            .iter()
+            .filter(|u| u.corp_id == corp_id && u.user_id == user_id)
+            .collect();
+        found.sort_by(|a, b| a.userid.cmp(&b.userid));
+        Ok(found.first().cloned().cloned())
+    }
+
+    async fn create(&self, corp_id: String, userid: String, user_id: i32) -> AnyResult<WecomUser> {
+        let mut users = self.users.write().await;
+        // (corp_id, userid)为主键
+        if users
+            .iter()
+            .any(|u| u.corp_id == corp_id && u.userid == userid)
+        {
+            return Err(format!("duplicate wecom userid: {}/{}", corp_id, userid).into());
+        }
+        let user = WecomUser {
+            corp_id,
+            userid,
+            user_id,
+            ..Default::default()
+        };
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn update(&self, u: WecomUser) -> AnyResult<WecomUser> {
+        let mut users = self.users.write().await;
+        let exist = users
+            .iter_mut()
+            .find(|e| e.corp_id == u.corp_id && e.userid == u.userid)
+            .ok_or("wecom user not found")?;
+        *exist = u.clone();
+        Ok(u)
+    }
+}
+
+// 生存环境，是不允许删除用户资料的，
+// 所以这里限定只能在测试里面使用
+#[cfg(test)]
+impl SqlxRepository {
+    pub async fn delete(&self, corp_id: &str, userid: &str) -> AnyResult<()> {
+        sqlx::query("DELETE FROM wecom_users WHERE corp_id = $1 AND userid = $2")
+            .bind(corp_id)
+            .bind(userid)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+}